@@ -0,0 +1,114 @@
+use tokio::io::{AsyncRead, AsyncReadExt};
+
+use crate::v5::ErrorV5;
+use crate::{check_body_end, decode_raw_header, read_bytes, read_u8, v3, v5, Error, Protocol, QoS};
+
+/// CONNECT packet of any protocol version, see `decode_connect()`.
+// Only one is decoded for each connection
+#[allow(clippy::large_enum_variant)]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum AnyConnect {
+    /// MQTT v3.1 or v3.1.1 (including the bridge protocol levels)
+    V3(v3::Connect),
+    /// MQTT v5.0
+    V5(v5::Connect),
+}
+
+impl AnyConnect {
+    pub fn protocol(&self) -> Protocol {
+        match self {
+            AnyConnect::V3(connect) => connect.protocol,
+            AnyConnect::V5(connect) => connect.protocol,
+        }
+    }
+}
+
+/// Decode the first packet of a connection, for servers which don't know the
+/// protocol version of the client before the CONNECT packet is decoded.
+///
+/// The protocol name and level are read first, then the rest of the packet
+/// is decoded as a v3 or v5 CONNECT. An unsupported protocol version is
+/// reported as `Error::InvalidProtocol` with the name and level, the server
+/// may reply a CONNACK with "unacceptable protocol version" before closing
+/// the connection. A packet other than CONNECT is an
+/// `Error::InvalidPacket`.
+pub async fn decode_connect<T: AsyncRead + Unpin>(reader: &mut T) -> Result<AnyConnect, ErrorV5> {
+    let (typ, remaining_len) = decode_raw_header(reader).await?;
+    if typ >> 4 != 1 {
+        return Err(Error::InvalidPacket("first packet is not CONNECT").into());
+    }
+    if typ & 0b1111 != 0 {
+        return Err(Error::InvalidHeader.into());
+    }
+    let mut body = reader.take(u64::from(remaining_len));
+    let result = decode_connect_body(&mut body, remaining_len).await;
+    check_body_end(body.limit(), result, ErrorV5::is_eof)
+}
+
+async fn decode_connect_body<T: AsyncRead + Unpin>(
+    reader: &mut T,
+    remaining_len: u32,
+) -> Result<AnyConnect, ErrorV5> {
+    let name = read_bytes(reader).await?;
+    let (protocol, is_bridge) = Protocol::new_bridge(&name, read_u8(reader).await?)?;
+    Ok(match protocol {
+        Protocol::V310 | Protocol::V311 => {
+            let mut connect = v3::Connect::decode_with_protocol(reader, protocol).await?;
+            connect.is_bridge = is_bridge;
+            AnyConnect::V3(connect)
+        }
+        Protocol::V500 => {
+            let header = v5::Header::new(
+                v5::PacketType::Connect,
+                false,
+                QoS::Level0,
+                false,
+                remaining_len,
+            );
+            AnyConnect::V5(v5::Connect::decode_with_protocol(reader, header, protocol).await?)
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use futures_lite::future::block_on;
+
+    use super::*;
+
+    #[test]
+    fn test_decode_connect() {
+        let v3_connect = v3::Connect::new("c3", 30);
+        let data = v3::Packet::from(v3_connect.clone()).encode().unwrap();
+        let mut reader = data.as_ref();
+        assert_eq!(
+            block_on(decode_connect(&mut reader)).unwrap(),
+            AnyConnect::V3(v3_connect.clone())
+        );
+
+        let mut bridge = v3_connect;
+        bridge.is_bridge = true;
+        let data = v3::Packet::from(bridge.clone()).encode().unwrap();
+        let result = block_on(decode_connect(&mut data.as_ref())).unwrap();
+        assert_eq!(result.protocol(), Protocol::V311);
+        assert_eq!(result, AnyConnect::V3(bridge));
+
+        let v5_connect = v5::Connect::new("c5", 30);
+        let data = v5::Packet::from(v5_connect.clone()).encode().unwrap();
+        assert_eq!(
+            block_on(decode_connect(&mut data.as_ref())).unwrap(),
+            AnyConnect::V5(v5_connect)
+        );
+
+        let data = [0x10, 7, 0, 4, b'M', b'Q', b'T', b'T', 6];
+        assert_eq!(
+            block_on(decode_connect(&mut &data[..])),
+            Err(Error::InvalidProtocol("MQTT".to_owned(), 6).into())
+        );
+        let data = v3::Packet::Pingreq.encode().unwrap();
+        assert_eq!(
+            block_on(decode_connect(&mut data.as_ref())),
+            Err(Error::InvalidPacket("first packet is not CONNECT").into())
+        );
+    }
+}
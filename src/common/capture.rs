@@ -0,0 +1,224 @@
+use std::io::{self, Read, Write};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use bytes::Bytes;
+
+use crate::Error;
+
+/// The first bytes of a capture file.
+const CAPTURE_MAGIC: &[u8; 8] = b"MQTTCAP1";
+
+/// Direction of a captured packet.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum Direction {
+    /// Received from the peer.
+    Inbound = 0,
+    /// Sent to the peer.
+    Outbound = 1,
+}
+
+/// A captured packet.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CaptureRecord {
+    /// The time the packet is received or sent, stored in microseconds.
+    pub timestamp: SystemTime,
+    pub direction: Direction,
+    /// The raw packet bytes (include the fixed header).
+    pub data: Bytes,
+}
+
+/// Write packets in the capture format, so the session can be replayed by
+/// `CaptureReader` later.
+///
+/// The format is the magic bytes `MQTTCAP1` followed by the records, each
+/// record is:
+///   * timestamp: microseconds since UNIX epoch (8 bytes, big endian)
+///   * direction: 0 is inbound, 1 is outbound (1 byte)
+///   * length of data (4 bytes, big endian)
+///   * data
+#[derive(Debug)]
+pub struct CaptureWriter<W> {
+    writer: W,
+}
+
+impl<W: Write> CaptureWriter<W> {
+    /// Create the writer, the magic bytes are written immediately.
+    pub fn new(mut writer: W) -> Result<Self, Error> {
+        writer.write_all(CAPTURE_MAGIC)?;
+        Ok(CaptureWriter { writer })
+    }
+
+    /// Write a packet with current time as the timestamp.
+    pub fn record(&mut self, direction: Direction, data: &[u8]) -> Result<(), Error> {
+        self.write_parts(SystemTime::now(), direction, data)
+    }
+
+    pub fn write_record(&mut self, record: &CaptureRecord) -> Result<(), Error> {
+        self.write_parts(record.timestamp, record.direction, &record.data)
+    }
+
+    pub fn flush(&mut self) -> Result<(), Error> {
+        Ok(self.writer.flush()?)
+    }
+
+    pub fn get_ref(&self) -> &W {
+        &self.writer
+    }
+
+    pub fn into_inner(self) -> W {
+        self.writer
+    }
+
+    fn write_parts(
+        &mut self,
+        timestamp: SystemTime,
+        direction: Direction,
+        data: &[u8],
+    ) -> Result<(), Error> {
+        let len = u32::try_from(data.len()).map_err(|_| Error::ValueTooLong(data.len()))?;
+        // Time before UNIX epoch is recorded as 0
+        let micros = timestamp
+            .duration_since(UNIX_EPOCH)
+            .map(|value| value.as_micros() as u64)
+            .unwrap_or(0);
+        let mut header = [0u8; 13];
+        header[..8].copy_from_slice(&micros.to_be_bytes());
+        header[8] = direction as u8;
+        header[9..].copy_from_slice(&len.to_be_bytes());
+        self.writer.write_all(&header)?;
+        self.writer.write_all(data)?;
+        Ok(())
+    }
+}
+
+/// Read the packets written by `CaptureWriter`, the data can be decoded by
+/// `Packet::decode()` of the protocol version of the session.
+#[derive(Debug)]
+pub struct CaptureReader<R> {
+    reader: R,
+}
+
+impl<R: Read> CaptureReader<R> {
+    /// Create the reader, the magic bytes are checked immediately.
+    pub fn new(mut reader: R) -> Result<Self, Error> {
+        let mut magic = [0u8; 8];
+        reader.read_exact(&mut magic)?;
+        if &magic != CAPTURE_MAGIC {
+            return Err(Error::InvalidCapture("magic"));
+        }
+        Ok(CaptureReader { reader })
+    }
+
+    /// Read next record, return `Ok(None)` at the end of the capture.
+    pub fn read_record(&mut self) -> Result<Option<CaptureRecord>, Error> {
+        let mut header = [0u8; 13];
+        let mut filled = 0;
+        while filled < header.len() {
+            match self.reader.read(&mut header[filled..]) {
+                Ok(0) if filled == 0 => return Ok(None),
+                Ok(0) => return Err(io::Error::from(io::ErrorKind::UnexpectedEof).into()),
+                Ok(size) => filled += size,
+                Err(err) if err.kind() == io::ErrorKind::Interrupted => {}
+                Err(err) => return Err(err.into()),
+            }
+        }
+        let mut micros = [0u8; 8];
+        micros.copy_from_slice(&header[..8]);
+        let direction = match header[8] {
+            0 => Direction::Inbound,
+            1 => Direction::Outbound,
+            _ => return Err(Error::InvalidCapture("direction")),
+        };
+        let len = u32::from_be_bytes([header[9], header[10], header[11], header[12]]);
+        let mut data = Vec::new();
+        (&mut self.reader).take(len as u64).read_to_end(&mut data)?;
+        if data.len() != len as usize {
+            return Err(io::Error::from(io::ErrorKind::UnexpectedEof).into());
+        }
+        Ok(Some(CaptureRecord {
+            timestamp: UNIX_EPOCH + Duration::from_micros(u64::from_be_bytes(micros)),
+            direction,
+            data: data.into(),
+        }))
+    }
+
+    pub fn into_inner(self) -> R {
+        self.reader
+    }
+}
+
+impl<R: Read> Iterator for CaptureReader<R> {
+    type Item = Result<CaptureRecord, Error>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.read_record().transpose()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::v3::Packet;
+    use crate::{Pid, QoS, TopicFilter};
+
+    #[test]
+    fn test_capture_replay() {
+        let subscribe: Packet = crate::v3::Subscribe::new(
+            Pid::try_from(1).unwrap(),
+            vec![(
+                TopicFilter::try_from("a/+".to_owned()).unwrap(),
+                QoS::Level1,
+            )],
+        )
+        .into();
+        let timestamp = UNIX_EPOCH + Duration::from_micros(1_700_000_000_123_456);
+
+        let mut writer = CaptureWriter::new(Vec::new()).unwrap();
+        writer
+            .write_record(&CaptureRecord {
+                timestamp,
+                direction: Direction::Outbound,
+                data: subscribe.encode().unwrap().as_ref().to_vec().into(),
+            })
+            .unwrap();
+        writer
+            .record(
+                Direction::Inbound,
+                Packet::Pingresp.encode().unwrap().as_ref(),
+            )
+            .unwrap();
+        let data = writer.into_inner();
+        assert_eq!(&data[..8], CAPTURE_MAGIC);
+
+        let records = CaptureReader::new(&data[..])
+            .unwrap()
+            .collect::<Result<Vec<_>, _>>()
+            .unwrap();
+        assert_eq!(records.len(), 2);
+        assert_eq!(records[0].timestamp, timestamp);
+        assert_eq!(records[0].direction, Direction::Outbound);
+        assert_eq!(records[1].direction, Direction::Inbound);
+        let packets = records
+            .iter()
+            .map(|record| Packet::decode(&record.data).unwrap().unwrap())
+            .collect::<Vec<_>>();
+        assert_eq!(packets, vec![subscribe, Packet::Pingresp]);
+
+        // Truncated record
+        let mut reader = CaptureReader::new(&data[..data.len() - 1]).unwrap();
+        assert!(reader.read_record().unwrap().is_some());
+        assert!(reader.read_record().unwrap_err().is_eof());
+
+        assert_eq!(
+            CaptureReader::new(&b"MQTTCAP0"[..]).unwrap_err(),
+            Error::InvalidCapture("magic")
+        );
+        let mut data = data;
+        data[8 + 8] = 2;
+        assert_eq!(
+            CaptureReader::new(&data[..]).unwrap().read_record(),
+            Err(Error::InvalidCapture("direction"))
+        );
+    }
+}
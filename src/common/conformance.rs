@@ -0,0 +1,434 @@
+//! Run externally defined test vectors through all the decode paths, enabled
+//! by the `conformance` feature.
+//!
+//! The vectors are a JSON array, each vector is an object:
+//!
+//! ```json
+//! [
+//!   { "name": "pingreq", "version": "v3", "hex": "c0 00", "packet": { "type": "PINGREQ" } },
+//!   { "name": "bad length", "version": "v5", "hex": "30 ff ff ff ff 01", "error": "Common(InvalidVarByteInt" },
+//!   { "name": "partial", "version": "v3", "hex": "30 05 00", "incomplete": true }
+//! ]
+//! ```
+//!
+//! * `packet` is the expected `Packet::to_json()` of the decoded packet.
+//! * `error` is the expected prefix of the `Debug` output of the decode error.
+//! * `incomplete` means the bytes are a valid prefix of a packet.
+
+use std::fmt;
+use std::fmt::Debug;
+use std::slice;
+
+use bytes::{Bytes, BytesMut};
+use futures_lite::future::block_on;
+use serde_json::Value;
+
+use crate::{v3, v5, DecodeLimits, Error, FeedStatus, GenericPacketDecoder, PollHeader};
+
+/// The protocol version a vector is decoded with.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TestVersion {
+    /// MQTT v3.1 and v3.1.1
+    V3,
+    /// MQTT v5.0
+    V5,
+}
+
+/// Expected decode result of a test vector.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Expected {
+    /// The `Packet::to_json()` of the decoded packet.
+    Packet(Value),
+    /// Prefix of the `Debug` output of the error.
+    Error(String),
+    /// The bytes are not a complete packet.
+    Incomplete,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct TestVector {
+    pub name: String,
+    pub version: TestVersion,
+    pub data: Vec<u8>,
+    pub expected: Expected,
+}
+
+/// Error of loading the test vectors.
+#[derive(thiserror::Error, Debug)]
+pub enum LoadError {
+    #[error("invalid JSON: {0}")]
+    Json(#[from] serde_json::Error),
+
+    #[error("invalid test vector #{index}: {reason}")]
+    InvalidVector { index: usize, reason: &'static str },
+}
+
+/// A decode path returned an unexpected result.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Mismatch {
+    /// Name of the test vector.
+    pub name: String,
+    /// The decode path, such as `"decode_async"`.
+    pub path: &'static str,
+    /// What is expected and what is returned.
+    pub message: String,
+}
+
+impl fmt::Display for Mismatch {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} ({}): {}", self.name, self.path, self.message)
+    }
+}
+
+/// Load the test vectors from JSON text.
+pub fn load_vectors(text: &str) -> Result<Vec<TestVector>, LoadError> {
+    let value: Value = serde_json::from_str(text)?;
+    let items = value.as_array().ok_or(LoadError::InvalidVector {
+        index: 0,
+        reason: "expected an array of vectors",
+    })?;
+    items
+        .iter()
+        .enumerate()
+        .map(|(index, item)| {
+            parse_vector(item).map_err(|reason| LoadError::InvalidVector { index, reason })
+        })
+        .collect()
+}
+
+fn parse_vector(item: &Value) -> Result<TestVector, &'static str> {
+    let name = item
+        .get("name")
+        .and_then(Value::as_str)
+        .ok_or("missing name")?
+        .to_owned();
+    let version = match item.get("version").and_then(Value::as_str) {
+        Some("v3") => TestVersion::V3,
+        Some("v5") => TestVersion::V5,
+        _ => return Err("version must be \"v3\" or \"v5\""),
+    };
+    let data = parse_hex(
+        item.get("hex")
+            .and_then(Value::as_str)
+            .ok_or("missing hex")?,
+    )?;
+    let expected = match (
+        item.get("packet"),
+        item.get("error"),
+        item.get("incomplete"),
+    ) {
+        (Some(packet), None, None) => Expected::Packet(packet.clone()),
+        (None, Some(Value::String(error)), None) => Expected::Error(error.clone()),
+        (None, None, Some(Value::Bool(true))) => Expected::Incomplete,
+        _ => return Err("expected exactly one of packet, error or incomplete"),
+    };
+    Ok(TestVector {
+        name,
+        version,
+        data,
+        expected,
+    })
+}
+
+/// Hex digits, whitespace between the bytes is ignored.
+fn parse_hex(text: &str) -> Result<Vec<u8>, &'static str> {
+    let digits = text
+        .chars()
+        .filter(|c| !c.is_ascii_whitespace())
+        .map(|c| c.to_digit(16).map(|digit| digit as u8))
+        .collect::<Option<Vec<u8>>>()
+        .ok_or("invalid hex digit")?;
+    if digits.len() % 2 != 0 {
+        return Err("odd number of hex digits");
+    }
+    Ok(digits
+        .chunks(2)
+        .map(|pair| (pair[0] << 4) | pair[1])
+        .collect())
+}
+
+/// Run the vectors through all the decode paths of the version, return the
+/// mismatches (empty means all the vectors pass). The paths which decoded a
+/// packet must also consume the same number of bytes.
+pub fn run_vectors(vectors: &[TestVector]) -> Vec<Mismatch> {
+    let mut mismatches = Vec::new();
+    for vector in vectors {
+        let outputs = match vector.version {
+            TestVersion::V3 => decode_v3(&vector.data),
+            TestVersion::V5 => decode_v5(&vector.data),
+        };
+        let mut first_consumed: Option<(&'static str, usize)> = None;
+        for (path, result, consumed) in outputs {
+            let decoded = matches!(result, Ok(Some(_)));
+            if let Err(message) = check(&vector.expected, result) {
+                mismatches.push(Mismatch {
+                    name: vector.name.clone(),
+                    path,
+                    message,
+                });
+                continue;
+            }
+            let Some(consumed) = consumed.filter(|_| decoded) else {
+                continue;
+            };
+            match first_consumed {
+                None => first_consumed = Some((path, consumed)),
+                Some((first_path, first)) if first != consumed => mismatches.push(Mismatch {
+                    name: vector.name.clone(),
+                    path,
+                    message: format!("consumed {consumed} bytes, {first_path} consumed {first}"),
+                }),
+                Some(_) => {}
+            }
+        }
+    }
+    mismatches
+}
+
+/// Result of a decode path, `Ok(None)` is an incomplete packet.
+type PathResult = Result<Option<Value>, String>;
+
+/// A decode path, its result and the number of bytes it consumed (`None` if
+/// the path does not report it).
+type PathOutput = (&'static str, PathResult, Option<usize>);
+
+fn decode_v3(data: &[u8]) -> Vec<PathOutput> {
+    let mut shared = Bytes::copy_from_slice(data);
+    let shared_result = v3::Packet::decode_shared(&mut shared);
+    let mut buf = BytesMut::from(data);
+    let buf_result = v3::Packet::decode_buf(&mut buf);
+    let mut lazy = Bytes::copy_from_slice(data);
+    let lazy_result = v3::LazyPacket::decode(&mut lazy)
+        .and_then(|packet| packet.map(v3::LazyPacket::into_packet).transpose());
+    let mut ref_reader = data;
+    let packet_ref =
+        v3::PacketRef::decode(&mut ref_reader).map(|packet| packet.map(|p| p.to_packet()));
+    let mut async_reader = data;
+    let decoded = block_on(v3::Packet::decode_async(&mut async_reader));
+    let mut poll_reader = data;
+    let mut state = v3::PollPacketState::default();
+    let poll =
+        block_on(v3::PollPacket::new(&mut state, &mut poll_reader)).map(|(_, _, packet)| packet);
+    let (feed_result, feed_consumed) = feed_bytes::<v3::Header>(data);
+    vec![
+        (
+            "decode",
+            path_result(v3::Packet::decode(data), v3::Packet::to_json),
+            None,
+        ),
+        (
+            "decode_with_limits",
+            path_result(
+                v3::Packet::decode_with_limits(data, &DecodeLimits::default()),
+                v3::Packet::to_json,
+            ),
+            None,
+        ),
+        (
+            "decode_shared",
+            path_result(shared_result, v3::Packet::to_json),
+            Some(data.len() - shared.len()),
+        ),
+        (
+            "decode_buf",
+            path_result(buf_result, v3::Packet::to_json),
+            Some(data.len() - buf.len()),
+        ),
+        (
+            "LazyPacket",
+            path_result(lazy_result, v3::Packet::to_json),
+            Some(data.len() - lazy.len()),
+        ),
+        (
+            "PacketRef::decode",
+            path_result(packet_ref, v3::Packet::to_json),
+            Some(data.len() - ref_reader.len()),
+        ),
+        (
+            "PacketDecoder::feed",
+            path_result(feed_result, v3::Packet::to_json),
+            Some(feed_consumed),
+        ),
+        (
+            "decode_async",
+            async_result(decoded, |err| err.is_eof(), v3::Packet::to_json),
+            Some(data.len() - async_reader.len()),
+        ),
+        (
+            "PollPacket",
+            async_result(poll, |err| err.is_eof(), v3::Packet::to_json),
+            Some(data.len() - poll_reader.len()),
+        ),
+    ]
+}
+
+fn decode_v5(data: &[u8]) -> Vec<PathOutput> {
+    let mut shared = Bytes::copy_from_slice(data);
+    let shared_result = v5::Packet::decode_shared(&mut shared);
+    let mut buf = BytesMut::from(data);
+    let buf_result = v5::Packet::decode_buf(&mut buf);
+    let mut lazy = Bytes::copy_from_slice(data);
+    let lazy_result = v5::LazyPacket::decode(&mut lazy)
+        .and_then(|packet| packet.map(v5::LazyPacket::into_packet).transpose());
+    let mut async_reader = data;
+    let decoded = block_on(v5::Packet::decode_async(&mut async_reader));
+    let mut poll_reader = data;
+    let mut state = v5::PollPacketState::default();
+    let poll =
+        block_on(v5::PollPacket::new(&mut state, &mut poll_reader)).map(|(_, _, packet)| packet);
+    let (feed_result, feed_consumed) = feed_bytes::<v5::Header>(data);
+    vec![
+        (
+            "decode",
+            path_result(v5::Packet::decode(data), v5::Packet::to_json),
+            None,
+        ),
+        (
+            "decode_with_limits",
+            path_result(
+                v5::Packet::decode_with_limits(data, &DecodeLimits::default()),
+                v5::Packet::to_json,
+            ),
+            None,
+        ),
+        (
+            "decode_shared",
+            path_result(shared_result, v5::Packet::to_json),
+            Some(data.len() - shared.len()),
+        ),
+        (
+            "decode_buf",
+            path_result(buf_result, v5::Packet::to_json),
+            Some(data.len() - buf.len()),
+        ),
+        (
+            "LazyPacket",
+            path_result(lazy_result, v5::Packet::to_json),
+            Some(data.len() - lazy.len()),
+        ),
+        (
+            "PacketDecoder::feed",
+            path_result(feed_result, v5::Packet::to_json),
+            Some(feed_consumed),
+        ),
+        (
+            "decode_async",
+            async_result(decoded, |err| err.is_eof(), v5::Packet::to_json),
+            Some(data.len() - async_reader.len()),
+        ),
+        (
+            "PollPacket",
+            async_result(poll, |err| err.is_eof(), v5::Packet::to_json),
+            Some(data.len() - poll_reader.len()),
+        ),
+    ]
+}
+
+/// Feed the bytes one by one, so the packet goes through the buffer of the
+/// decoder. Return the result and the number of bytes consumed.
+fn feed_bytes<H>(data: &[u8]) -> (Result<Option<H::Packet>, H::Error>, usize)
+where
+    H: PollHeader + Copy,
+    H::Error: From<Error>,
+{
+    let mut decoder = GenericPacketDecoder::<H>::new();
+    for (idx, byte) in data.iter().enumerate() {
+        match decoder.feed(slice::from_ref(byte)) {
+            Ok(FeedStatus::Packet(packet, len)) => return (Ok(Some(packet)), idx + len),
+            Ok(FeedStatus::NeedMore) => {}
+            Err(err) => return (Err(err), idx + 1),
+        }
+    }
+    (Ok(None), data.len())
+}
+
+fn path_result<P, E: Debug>(
+    result: Result<Option<P>, E>,
+    to_json: impl Fn(&P) -> Value,
+) -> PathResult {
+    result
+        .map(|packet| packet.as_ref().map(to_json))
+        .map_err(|err| format!("{err:?}"))
+}
+
+/// The async decoders return an EOF error on incomplete packet.
+fn async_result<P, E: Debug>(
+    result: Result<P, E>,
+    is_eof: impl Fn(&E) -> bool,
+    to_json: impl Fn(&P) -> Value,
+) -> PathResult {
+    match result {
+        Ok(packet) => Ok(Some(to_json(&packet))),
+        Err(err) if is_eof(&err) => Ok(None),
+        Err(err) => Err(format!("{err:?}")),
+    }
+}
+
+fn check(expected: &Expected, result: PathResult) -> Result<(), String> {
+    match (expected, result) {
+        (Expected::Packet(expected), Ok(Some(packet))) if *expected == packet => Ok(()),
+        (Expected::Error(expected), Err(err)) if err.starts_with(expected.as_str()) => Ok(()),
+        (Expected::Incomplete, Ok(None)) => Ok(()),
+        (expected, result) => {
+            let expected = match expected {
+                Expected::Packet(packet) => format!("packet {packet}"),
+                Expected::Error(err) => format!("error {err}.."),
+                Expected::Incomplete => "incomplete".to_owned(),
+            };
+            let result = match result {
+                Ok(Some(packet)) => format!("packet {packet}"),
+                Ok(None) => "incomplete".to_owned(),
+                Err(err) => format!("error {err}"),
+            };
+            Err(format!("expected {expected}, got {result}"))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const VECTORS: &str = r#"[
+        { "name": "v3 pingreq", "version": "v3", "hex": "c0 00", "packet": { "type": "PINGREQ" } },
+        { "name": "v3 puback", "version": "v3", "hex": "40 02 00 07", "packet": { "type": "PUBACK", "pid": 7 } },
+        { "name": "v3 zero pid", "version": "v3", "hex": "40 02 00 00", "error": "ZeroPid" },
+        { "name": "v3 partial", "version": "v3", "hex": "30 05 00", "incomplete": true },
+        { "name": "v5 bad var int", "version": "v5", "hex": "30 ff ff ff ff 01", "error": "Common(InvalidVarByteInt" },
+        { "name": "v5 pingresp", "version": "v5", "hex": "d0 00", "packet": { "type": "PINGRESP" } },
+        { "name": "v3 trailing bytes", "version": "v3", "hex": "40 02 00 07 c0", "packet": { "type": "PUBACK", "pid": 7 } },
+        { "name": "v5 trailing bytes", "version": "v5", "hex": "d0 80 00 d0 00", "packet": { "type": "PINGRESP" } }
+    ]"#;
+
+    #[test]
+    fn test_conformance_vectors() {
+        let vectors = load_vectors(VECTORS).unwrap();
+        assert_eq!(vectors.len(), 8);
+        assert_eq!(vectors[1].data, vec![0x40, 0x02, 0x00, 0x07]);
+        assert_eq!(run_vectors(&vectors), Vec::new());
+
+        let wrong = load_vectors(
+            r#"[{ "name": "wrong", "version": "v3", "hex": "c000", "packet": { "type": "PINGRESP" } }]"#,
+        )
+        .unwrap();
+        let mismatches = run_vectors(&wrong);
+        assert_eq!(mismatches.len(), 9);
+        assert_eq!(
+            mismatches[0].to_string(),
+            r#"wrong (decode): expected packet {"type":"PINGRESP"}, got packet {"type":"PINGREQ"}"#
+        );
+
+        assert!(matches!(
+            load_vectors(r#"[{ "name": "x", "version": "v4", "hex": "", "incomplete": true }]"#),
+            Err(LoadError::InvalidVector { index: 0, .. })
+        ));
+        assert!(matches!(
+            load_vectors(r#"[{ "name": "x", "version": "v3", "hex": "c", "incomplete": true }]"#),
+            Err(LoadError::InvalidVector {
+                reason: "odd number of hex digits",
+                ..
+            })
+        ));
+        assert!(matches!(load_vectors("{"), Err(LoadError::Json(_))));
+    }
+}
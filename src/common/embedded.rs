@@ -0,0 +1,73 @@
+use std::io;
+
+use embedded_io_async::{Read, ReadExactError, Write};
+
+use crate::{block_decode_exact, Error, PollHeader, MAX_RESERVE};
+
+/// Convert an `embedded-io` error, the kind is kept.
+pub(crate) fn embedded_error<E: embedded_io_async::Error>(err: E) -> Error {
+    let kind: io::ErrorKind = err.kind().into();
+    Error::IoError(kind, format!("{err:?}"))
+}
+
+fn read_exact_error<E: embedded_io_async::Error>(err: ReadExactError<E>) -> Error {
+    match err {
+        ReadExactError::UnexpectedEof => io::Error::from(io::ErrorKind::UnexpectedEof).into(),
+        ReadExactError::Other(err) => embedded_error(err),
+    }
+}
+
+/// Decode a packet from an `embedded_io_async::Read`.
+///
+/// The fixed header is read byte by byte, then the body is read into a
+/// buffer in chunks of at most `MAX_RESERVE` bytes and decoded in one go.
+pub(crate) async fn read_packet_embedded<R, H>(reader: &mut R) -> Result<H::Packet, H::Error>
+where
+    R: Read,
+    H: PollHeader,
+    H::Error: From<Error>,
+{
+    let mut byte = [0u8];
+    reader
+        .read_exact(&mut byte)
+        .await
+        .map_err(read_exact_error)?;
+    let control_byte = byte[0];
+    let mut remaining_len: u32 = 0;
+    let mut i = 0;
+    loop {
+        reader
+            .read_exact(&mut byte)
+            .await
+            .map_err(read_exact_error)?;
+        remaining_len |= (u32::from(byte[0]) & 0x7F) << (7 * i);
+        if byte[0] & 0x80 == 0 {
+            break;
+        } else if i < 3 {
+            i += 1;
+        } else {
+            return Err(Error::InvalidVarByteInt.into());
+        }
+    }
+    let header = H::new_with(control_byte, remaining_len)?;
+    if let Some(packet) = header.build_empty_packet() {
+        return Ok(packet);
+    }
+    // The length comes from the peer, grow the buffer as the bytes arrive
+    let len = header.remaining_len();
+    let mut body = Vec::new();
+    while body.len() < len {
+        let start = body.len();
+        body.resize(start + (len - start).min(MAX_RESERVE), 0);
+        reader
+            .read_exact(&mut body[start..])
+            .await
+            .map_err(read_exact_error)?;
+    }
+    block_decode_exact(header, &body)
+}
+
+/// Write an encoded packet to an `embedded_io_async::Write`.
+pub(crate) async fn write_all_embedded<W: Write>(writer: &mut W, data: &[u8]) -> Result<(), Error> {
+    writer.write_all(data).await.map_err(embedded_error)
+}
@@ -0,0 +1,217 @@
+use std::io;
+
+use bytes::{Buf, Bytes};
+use tokio::io::{AsyncWrite, AsyncWriteExt};
+
+use crate::{
+    decode_raw_header_slice, total_len, write_u16, write_var_int, Error, PollEncodable,
+    PollEncodeState, QosPid, VarBytes,
+};
+
+/// A packet encoded once and written to many connections, such as a PUBLISH
+/// forwarded to the subscribers. Cloning it only increases the reference
+/// counts of the encoded bytes.
+///
+/// The PUBLISH payload is kept separately (see `Packet::encode_vectored()`),
+/// so `with_qos_pid()` creates the variant of a subscriber by encoding the
+/// fixed header and packet identifier again while the payload is shared.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct EncodedPacket {
+    head: Bytes,
+    payload: Bytes,
+    publish: Option<PublishLayout>,
+}
+
+/// Offsets in the head of an encoded PUBLISH packet.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct PublishLayout {
+    header_end: usize,
+    topic_end: usize,
+    pid_end: usize,
+}
+
+impl PublishLayout {
+    fn parse(head: &[u8]) -> Option<Self> {
+        if head.first()? >> 4 != 3 {
+            return None;
+        }
+        let (_, _, header_end) = decode_raw_header_slice(head).ok()??;
+        let topic_len = head.get(header_end..header_end + 2)?;
+        let topic_end = header_end + 2 + u16::from_be_bytes([topic_len[0], topic_len[1]]) as usize;
+        let pid_end = if head[0] & 0b110 == 0 {
+            topic_end
+        } else {
+            topic_end + 2
+        };
+        (pid_end <= head.len()).then_some(PublishLayout {
+            header_end,
+            topic_end,
+            pid_end,
+        })
+    }
+}
+
+impl EncodedPacket {
+    /// Create from the encoded packet and the payload not included in it
+    /// (see `Packet::encode_vectored()`).
+    pub(crate) fn new(data: VarBytes, payload: Bytes) -> Self {
+        let head = match data {
+            VarBytes::Dynamic(data) => Bytes::from(data),
+            data => Bytes::copy_from_slice(data.as_ref()),
+        };
+        let publish = PublishLayout::parse(&head);
+        EncodedPacket {
+            head,
+            payload,
+            publish,
+        }
+    }
+
+    /// The encoded packet except the PUBLISH payload.
+    pub fn head(&self) -> &Bytes {
+        &self.head
+    }
+
+    /// The PUBLISH payload, it is empty for other packets.
+    pub fn payload(&self) -> &Bytes {
+        &self.payload
+    }
+
+    /// Packet total size (include header)
+    pub fn total(&self) -> usize {
+        self.head.len() + self.payload.len()
+    }
+
+    pub fn is_publish(&self) -> bool {
+        self.publish.is_some()
+    }
+
+    /// Create the PUBLISH packet with another QoS and packet identifier, the
+    /// topic name, properties and payload are shared with this packet. The
+    /// DUP flag is cleared for QoS 0 ([MQTT-3.3.1-2]).
+    ///
+    /// Return `Error::InvalidPacket` if this is not a PUBLISH packet.
+    pub fn with_qos_pid(&self, qos_pid: QosPid) -> Result<Self, Error> {
+        let layout = self
+            .publish
+            .ok_or(Error::InvalidPacket("not a PUBLISH packet"))?;
+        let pid = qos_pid.pid();
+        let mut control_byte = (self.head[0] & !0b110) | ((qos_pid.qos() as u8) << 1);
+        if pid.is_none() {
+            control_byte &= !0b1000;
+        }
+        let remaining_len = self.total() - layout.header_end - (layout.pid_end - layout.topic_end)
+            + if pid.is_some() { 2 } else { 0 };
+        let total = total_len(remaining_len)?;
+        let mut head = Vec::with_capacity(total - self.payload.len());
+        head.push(control_byte);
+        write_var_int(&mut head, remaining_len)?;
+        let header_end = head.len();
+        head.extend_from_slice(&self.head[layout.header_end..layout.topic_end]);
+        let topic_end = head.len();
+        if let Some(pid) = pid {
+            write_u16(&mut head, pid.value())?;
+        }
+        let pid_end = head.len();
+        head.extend_from_slice(&self.head[layout.pid_end..]);
+        Ok(EncodedPacket {
+            head: Bytes::from(head),
+            payload: self.payload.clone(),
+            publish: Some(PublishLayout {
+                header_end,
+                topic_end,
+                pid_end,
+            }),
+        })
+    }
+
+    /// Write the whole packet to `writer`.
+    pub fn write_to<W: io::Write>(&self, writer: &mut W) -> io::Result<()> {
+        writer.write_all(&self.head)?;
+        writer.write_all(&self.payload)
+    }
+
+    /// Write the whole packet to `writer`, the payload is not copied.
+    pub async fn write_async<T: AsyncWrite + Unpin>(&self, writer: &mut T) -> Result<(), Error> {
+        let mut buf = self.head.as_ref().chain(self.payload.as_ref());
+        writer.write_all_buf(&mut buf).await?;
+        Ok(())
+    }
+}
+
+impl PollEncodable for EncodedPacket {
+    fn encode_poll_state(&self) -> Result<PollEncodeState, Error> {
+        Ok(PollEncodeState::shared(
+            self.head.clone(),
+            self.payload.clone(),
+        ))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use futures_lite::future::block_on;
+
+    use super::*;
+    use crate::{v3, v5, Pid, TopicName};
+
+    fn encoded(packet: &EncodedPacket) -> Vec<u8> {
+        let mut data = Vec::new();
+        packet.write_to(&mut data).unwrap();
+        data
+    }
+
+    #[test]
+    fn test_encoded_packet() {
+        let pid = Pid::try_from(10).unwrap();
+        let qos_pids = [QosPid::Level0, QosPid::Level1(pid), QosPid::Level2(pid)];
+        let topic_name = TopicName::try_from("a/b").unwrap();
+        for qos_pid in qos_pids {
+            let mut publish = v3::Publish::new(qos_pid, topic_name.clone(), vec![7u8; 200]);
+            publish.dup = qos_pid != QosPid::Level0;
+            publish.retain = true;
+            let packet = v3::Packet::from(publish.clone()).encode_shared().unwrap();
+            assert!(packet.is_publish());
+            assert_eq!(
+                encoded(&packet),
+                v3::Packet::from(publish.clone()).encode().unwrap().as_ref()
+            );
+
+            for other in qos_pids {
+                let variant = packet.with_qos_pid(other).unwrap();
+                let expected = v3::Publish {
+                    qos_pid: other,
+                    dup: publish.dup && other != QosPid::Level0,
+                    ..publish.clone()
+                };
+                assert_eq!(
+                    encoded(&variant),
+                    v3::Packet::from(expected).encode().unwrap().as_ref()
+                );
+                assert_eq!(variant.payload().as_ptr(), packet.payload().as_ptr());
+            }
+
+            let mut publish = v5::Publish::new(qos_pid, topic_name.clone(), vec![7u8; 200]);
+            publish.properties.content_type = Some("text".into());
+            let packet = v5::Packet::from(publish.clone()).encode_shared().unwrap();
+            for other in qos_pids {
+                let variant = packet.with_qos_pid(other).unwrap();
+                let mut data = Vec::new();
+                block_on(variant.write_async(&mut data)).unwrap();
+                let expected = v5::Publish {
+                    qos_pid: other,
+                    ..publish.clone()
+                };
+                assert_eq!(data, v5::Packet::from(expected).encode().unwrap().as_ref());
+            }
+        }
+
+        let packet = v5::Packet::Pingreq.encode_shared().unwrap();
+        assert!(!packet.is_publish());
+        assert_eq!(packet.total(), 2);
+        assert_eq!(
+            packet.with_qos_pid(QosPid::Level0),
+            Err(Error::InvalidPacket("not a PUBLISH packet"))
+        );
+    }
+}
@@ -7,7 +7,8 @@ use crate::Protocol;
 /// Errors returned by encoding and decoding process.
 #[derive(Error, Debug, Clone, PartialEq, Eq)]
 pub enum Error {
-    /// Invalid remaining length.
+    /// Invalid remaining length, also returned when a field of the packet
+    /// body exceeds the remaining length (the body is malformed).
     #[error("invalid remaining length")]
     InvalidRemainingLength,
 
@@ -55,6 +56,11 @@ pub enum Error {
     #[error("invalid topic name: {0}")]
     InvalidTopicName(String),
 
+    /// Invalid level of a topic name built by `TopicName::from_levels()`,
+    /// the level contains a wildcard, a NUL or a level separator.
+    #[error("invalid topic level #{index}: {level:?}")]
+    InvalidTopicLevel { index: usize, level: String },
+
     /// Invalid topic filter
     #[error("invalid topic filter: {0}")]
     InvalidTopicFilter(String),
@@ -63,19 +69,83 @@ pub enum Error {
     #[error("invalid string")]
     InvalidString,
 
+    /// Packet size (include header) exceeds the max packet size, the value
+    /// is the packet size. Map it to `PacketTooLarge` reason code in v5.0.
+    #[error("packet too large: {0}")]
+    PacketTooLarge(usize),
+
+    /// All the packet identifiers are in-flight.
+    #[error("packet identifier exhausted")]
+    PidExhausted,
+
+    /// A limit in `DecodeLimits` is exceeded, the value is the field name.
+    #[error("decode limit exceeded: {0}")]
+    DecodeLimitExceeded(&'static str),
+
+    /// A string contains a character forbidden by the strict UTF-8
+    /// validation, see `check_utf8_strict()`.
+    #[error("forbidden character in string: {0:?}")]
+    ForbiddenChar(char),
+
+    /// A hand-built packet fails `Packet::validate()`, the value describes
+    /// the violated rule.
+    #[error("invalid packet: {0}")]
+    InvalidPacket(&'static str),
+
+    /// A string or binary data to encode is longer than 65,535 bytes, the
+    /// value is the length.
+    #[error("value too long to encode: {0} bytes")]
+    ValueTooLong(usize),
+
+    /// A packet is not fully received within the `PollTimeout`.
+    #[error("decode timeout")]
+    DecodeTimeout,
+
+    /// Invalid PROXY protocol header.
+    #[error("invalid proxy header: {0}")]
+    InvalidProxyHeader(&'static str),
+
+    /// Invalid packet capture file, see `CaptureReader`.
+    #[error("invalid capture: {0}")]
+    InvalidCapture(&'static str),
+
     /// Catch-all error when converting from `std::io::Error`.
     #[error("io error: {0}, {1}")]
     IoError(io::ErrorKind, String),
 }
 
+/// A decode error with the byte offset (from the start of the packet) where
+/// the decoding failed. The offset is the number of bytes consumed when the
+/// error is detected, so the invalid field ends at this offset.
+#[derive(Error, Debug, Clone, PartialEq, Eq)]
+#[error("{error} (at byte offset {offset})")]
+pub struct DecodeError<E> {
+    pub offset: usize,
+    #[source]
+    pub error: E,
+}
+
 impl Error {
+    /// The input ends before the whole packet (header and remaining length
+    /// bytes) is received, so more data is needed. The slice decoders return
+    /// `Ok(None)` instead.
     pub fn is_eof(&self) -> bool {
         matches!(self, Error::IoError(kind, _) if *kind == io::ErrorKind::UnexpectedEof)
     }
+
+    /// The non-blocking reader or writer is not ready, the operation can be
+    /// retried when it is ready instead of treated as a failure.
+    pub fn is_would_block(&self) -> bool {
+        matches!(self, Error::IoError(kind, _) if *kind == io::ErrorKind::WouldBlock)
+    }
 }
 
 impl From<io::Error> for Error {
     fn from(err: io::Error) -> Error {
+        // The errors of `Encodable::encode()` are wrapped in `io::Error`
+        if let Some(err) = err.get_ref().and_then(|err| err.downcast_ref::<Error>()) {
+            return err.clone();
+        }
         Error::IoError(err.kind(), err.to_string())
     }
 }
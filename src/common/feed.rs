@@ -0,0 +1,174 @@
+use std::mem;
+
+use crate::{block_decode_exact, decode_raw_header_slice, Error, PollHeader, MAX_RESERVE};
+
+/// The result of `GenericPacketDecoder::feed()`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum FeedStatus<P> {
+    /// A packet is decoded, with the number of bytes consumed from the input.
+    /// The bytes after them are not consumed, feed them again.
+    Packet(P, usize),
+    /// All the input is buffered, more bytes are needed.
+    NeedMore,
+}
+
+/// A resumable decoder fed with byte fragments, the sync analogue of
+/// `GenericPollPacket` for readiness based event loops (epoll, mio) and
+/// C embedding.
+///
+/// A packet fully contained in the input is decoded from the input
+/// directly, only the fragments of a split packet are buffered.
+#[derive(Debug, Clone)]
+pub struct GenericPacketDecoder<H> {
+    /// The header and the header length of the buffered packet.
+    header: Option<(H, usize)>,
+    buf: Vec<u8>,
+    max_packet_size: Option<u32>,
+}
+
+impl<H> Default for GenericPacketDecoder<H> {
+    fn default() -> Self {
+        GenericPacketDecoder {
+            header: None,
+            buf: Vec::new(),
+            max_packet_size: None,
+        }
+    }
+}
+
+impl<H> GenericPacketDecoder<H> {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Create a decoder which rejects packets larger than `max_packet_size`
+    /// (include header) with `Error::PacketTooLarge` before buffering the
+    /// body.
+    pub fn with_max_packet_size(max_packet_size: u32) -> Self {
+        GenericPacketDecoder {
+            max_packet_size: Some(max_packet_size),
+            ..Default::default()
+        }
+    }
+
+    /// Number of bytes of the current packet buffered so far.
+    pub fn buffered(&self) -> usize {
+        self.buf.len()
+    }
+
+    /// Drop the buffered bytes, the next input must start with a packet.
+    pub fn reset(&mut self) {
+        self.header = None;
+        self.buf.clear();
+    }
+}
+
+impl<H> GenericPacketDecoder<H>
+where
+    H: PollHeader + Copy,
+    H::Error: From<Error>,
+{
+    /// Feed the next fragment of the input. After an error the buffered bytes
+    /// are dropped.
+    pub fn feed(&mut self, data: &[u8]) -> Result<FeedStatus<H::Packet>, H::Error> {
+        let result = self.feed_inner(data);
+        if result.is_err() {
+            self.reset();
+        }
+        result
+    }
+
+    fn feed_inner(&mut self, mut data: &[u8]) -> Result<FeedStatus<H::Packet>, H::Error> {
+        let input_len = data.len();
+        if self.buf.is_empty() {
+            if let Some((typ, remaining_len, header_len)) = decode_raw_header_slice(data)? {
+                let header = self.check_header(typ, remaining_len, header_len)?;
+                let total = header_len + header.remaining_len();
+                if data.len() >= total {
+                    let packet = decode_body(header, &data[header_len..total])?;
+                    return Ok(FeedStatus::Packet(packet, total));
+                }
+            }
+        }
+        loop {
+            let (header, header_len) = match self.header {
+                Some(value) => value,
+                None => match decode_raw_header_slice(&self.buf)? {
+                    Some((typ, remaining_len, header_len)) => {
+                        let header = self.check_header(typ, remaining_len, header_len)?;
+                        // The length comes from the peer, grow as the bytes arrive
+                        self.buf.reserve(header.remaining_len().min(MAX_RESERVE));
+                        self.header = Some((header, header_len));
+                        (header, header_len)
+                    }
+                    None => {
+                        // The fixed header is at most 5 bytes, take it byte by byte
+                        let Some((byte, rest)) = data.split_first() else {
+                            return Ok(FeedStatus::NeedMore);
+                        };
+                        self.buf.push(*byte);
+                        data = rest;
+                        continue;
+                    }
+                },
+            };
+            let total = header_len + header.remaining_len();
+            let size = (total - self.buf.len()).min(data.len());
+            self.buf.extend_from_slice(&data[..size]);
+            data = &data[size..];
+            if self.buf.len() < total {
+                return Ok(FeedStatus::NeedMore);
+            }
+            let buf = mem::take(&mut self.buf);
+            let result = decode_body(header, &buf[header_len..]);
+            self.reset();
+            // Keep the allocation for the next packet
+            self.buf = buf;
+            self.buf.clear();
+            return result.map(|packet| FeedStatus::Packet(packet, input_len - data.len()));
+        }
+    }
+
+    fn check_header(&self, typ: u8, remaining_len: u32, header_len: usize) -> Result<H, H::Error> {
+        let header = H::new_with(typ, remaining_len)?;
+        let total = header_len + header.remaining_len();
+        if let Some(max_packet_size) = self.max_packet_size {
+            if total > max_packet_size as usize {
+                return Err(Error::PacketTooLarge(total).into());
+            }
+        }
+        Ok(header)
+    }
+}
+
+fn decode_body<H>(header: H, body: &[u8]) -> Result<H::Packet, H::Error>
+where
+    H: PollHeader,
+    H::Error: From<Error>,
+{
+    if let Some(packet) = header.build_empty_packet() {
+        Ok(packet)
+    } else if body.is_empty() {
+        Err(Error::InvalidRemainingLength.into())
+    } else {
+        block_decode_exact(header, body)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::v3::PacketDecoder;
+    use crate::{FeedStatus, MAX_RESERVE};
+
+    #[test]
+    fn test_feed_reserve_capped() {
+        // PUBLISH with remaining length = 268,435,455
+        let mut decoder = PacketDecoder::new();
+        assert_eq!(
+            decoder.feed(&[0x30, 0xff, 0xff, 0xff, 0x7f, 0x00]),
+            Ok(FeedStatus::NeedMore)
+        );
+        assert!(decoder.buf.capacity() <= 5 + MAX_RESERVE);
+        assert_eq!(decoder.buffered(), 6);
+    }
+}
@@ -0,0 +1,217 @@
+use std::fmt::Write;
+
+use simdutf8::basic::from_utf8;
+
+const BYTES_PER_LINE: usize = 16;
+
+/// Name of the packet type in the first byte of the fixed header.
+const PACKET_TYPE_NAMES: [&str; 16] = [
+    "RESERVED",
+    "CONNECT",
+    "CONNACK",
+    "PUBLISH",
+    "PUBACK",
+    "PUBREC",
+    "PUBREL",
+    "PUBCOMP",
+    "SUBSCRIBE",
+    "SUBACK",
+    "UNSUBSCRIBE",
+    "UNSUBACK",
+    "PINGREQ",
+    "PINGRESP",
+    "DISCONNECT",
+    "AUTH",
+];
+
+/// Writer of an annotated hexdump, each field is written as the hex of its
+/// bytes followed by the annotation:
+///
+/// ```text
+/// 000000  32                                               PUBLISH flags=0b0010
+/// 000001  0c                                               remaining length: 12
+/// 000002  00 03 61 2f 62                                   topic name: "a/b"
+/// ```
+///
+/// The field readers return `None` when the bytes of current packet body are
+/// not enough, the rest is annotated by the caller.
+pub(crate) struct HexdumpWriter<'a> {
+    data: &'a [u8],
+    pos: usize,
+    end: usize,
+    out: String,
+}
+
+impl<'a> HexdumpWriter<'a> {
+    /// Annotate all the packets in `data`, the body of each packet is
+    /// annotated by `body` with the control byte.
+    pub(crate) fn annotate(
+        data: &'a [u8],
+        mut body: impl FnMut(&mut Self, u8) -> Option<()>,
+    ) -> String {
+        let mut writer = HexdumpWriter {
+            data,
+            pos: 0,
+            end: data.len(),
+            out: String::new(),
+        };
+        while writer.pos < data.len() {
+            writer.end = data.len();
+            let Some(control_byte) = writer.field(1, |bytes| {
+                format!(
+                    "{} flags=0b{:04b}",
+                    PACKET_TYPE_NAMES[(bytes[0] >> 4) as usize],
+                    bytes[0] & 0b1111
+                )
+            }) else {
+                break;
+            };
+            let Some(remaining_len) = writer.var_int("remaining length") else {
+                writer.rest("malformed remaining length");
+                break;
+            };
+            let body_end = writer.pos + remaining_len as usize;
+            writer.end = body_end.min(data.len());
+            if body(&mut writer, control_byte[0]).is_none() {
+                writer.rest("malformed");
+            } else {
+                writer.rest("unexpected bytes");
+            }
+            if body_end > data.len() {
+                let _ = writeln!(
+                    writer.out,
+                    "        incomplete packet: {} more bytes expected",
+                    body_end - data.len()
+                );
+                break;
+            }
+        }
+        writer.out
+    }
+
+    /// Number of bytes left in current packet body.
+    pub(crate) fn remaining(&self) -> usize {
+        self.end - self.pos
+    }
+
+    /// Write a field of `len` bytes with the annotation built from its bytes.
+    pub(crate) fn field(
+        &mut self,
+        len: usize,
+        label: impl FnOnce(&'a [u8]) -> String,
+    ) -> Option<&'a [u8]> {
+        if self.remaining() < len {
+            return None;
+        }
+        let bytes = &self.data[self.pos..self.pos + len];
+        self.write_lines(bytes, &label(bytes));
+        self.pos += len;
+        Some(bytes)
+    }
+
+    pub(crate) fn u8(&mut self, name: &str) -> Option<u8> {
+        self.field(1, |bytes| format!("{name}: {}", bytes[0]))
+            .map(|bytes| bytes[0])
+    }
+
+    pub(crate) fn u16(&mut self, name: &str) -> Option<u16> {
+        self.field(2, |bytes| {
+            format!("{name}: {}", u16::from_be_bytes([bytes[0], bytes[1]]))
+        })
+        .map(|bytes| u16::from_be_bytes([bytes[0], bytes[1]]))
+    }
+
+    pub(crate) fn u32(&mut self, name: &str) -> Option<u32> {
+        let read = |bytes: &[u8]| u32::from_be_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]);
+        self.field(4, |bytes| format!("{name}: {}", read(bytes)))
+            .map(read)
+    }
+
+    /// Variable byte integer, the invalid encoding (more than 4 bytes) is
+    /// treated as malformed.
+    pub(crate) fn var_int(&mut self, name: &str) -> Option<u32> {
+        let rest = &self.data[self.pos..self.end];
+        let len = rest.iter().take(4).position(|byte| byte & 0x80 == 0)? + 1;
+        let value = rest[..len]
+            .iter()
+            .enumerate()
+            .fold(0, |value, (idx, byte)| {
+                value | ((u32::from(*byte) & 0x7F) << (7 * idx))
+            });
+        self.field(len, |_| format!("{name}: {value}"));
+        Some(value)
+    }
+
+    /// UTF-8 string with two bytes length prefix.
+    pub(crate) fn string(&mut self, name: &str) -> Option<&'a str> {
+        let len = self.peek_len()?;
+        let bytes = self.field(2 + len, |bytes| match from_utf8(&bytes[2..]) {
+            Ok(value) => format!("{name}: {value:?}"),
+            Err(_) => format!("{name}: invalid UTF-8"),
+        })?;
+        from_utf8(&bytes[2..]).ok()
+    }
+
+    /// Binary data with two bytes length prefix.
+    pub(crate) fn binary(&mut self, name: &str) -> Option<&'a [u8]> {
+        let len = self.peek_len()?;
+        self.field(2 + len, |_| format!("{name}: {len} bytes"))
+            .map(|bytes| &bytes[2..])
+    }
+
+    /// The connect flags of CONNECT packet, the names of the set flags are
+    /// annotated.
+    pub(crate) fn connect_flags(&mut self, clean_flag: &str) -> Option<u8> {
+        self.field(1, |bytes| {
+            let flags = bytes[0];
+            let mut label = format!("connect flags: 0b{flags:08b}");
+            for (mask, name) in [
+                (0b10000000, "username"),
+                (0b01000000, "password"),
+                (0b00100000, "will_retain"),
+                (0b00000100, "will"),
+                (0b00000010, clean_flag),
+            ] {
+                if flags & mask != 0 {
+                    label.push(' ');
+                    label.push_str(name);
+                }
+            }
+            if flags & 0b100 != 0 {
+                let _ = write!(label, " will_qos={}", (flags & 0b11000) >> 3);
+            }
+            label
+        })
+        .map(|bytes| bytes[0])
+    }
+
+    /// The rest of current packet body.
+    pub(crate) fn rest(&mut self, name: &str) {
+        let len = self.remaining();
+        if len > 0 {
+            self.field(len, |_| format!("{name}: {len} bytes"));
+        }
+    }
+
+    fn peek_len(&self) -> Option<usize> {
+        let bytes = self.data.get(self.pos..self.pos + 2)?;
+        (self.remaining() >= 2).then(|| u16::from_be_bytes([bytes[0], bytes[1]]) as usize)
+    }
+
+    fn write_lines(&mut self, bytes: &[u8], label: &str) {
+        for (idx, chunk) in bytes.chunks(BYTES_PER_LINE).enumerate() {
+            let offset = self.pos + idx * BYTES_PER_LINE;
+            let mut hex = String::with_capacity(BYTES_PER_LINE * 3);
+            for byte in chunk {
+                let _ = write!(hex, "{byte:02x} ");
+            }
+            let label = if idx == 0 { label } else { "" };
+            let line = format!(
+                "{offset:06x}  {hex:<width$} {label}",
+                width = BYTES_PER_LINE * 3
+            );
+            self.out.push_str(line.trim_end());
+            self.out.push('\n');
+        }
+    }
+}
@@ -0,0 +1,98 @@
+use std::collections::HashSet;
+use std::sync::Mutex;
+
+use super::{TopicFilter, TopicName};
+
+/// Deduplicate the `TopicName` and `TopicFilter` values, so the topics
+/// received again and again share one allocation.
+///
+/// Interning a topic returns the instance already in the interner if there
+/// is one (the given value is dropped), otherwise the given value is kept.
+/// The interner holds a reference to every topic, call `purge()`
+/// periodically to release the topics not used anywhere else.
+#[derive(Debug, Default)]
+pub struct TopicInterner {
+    names: Mutex<HashSet<TopicName>>,
+    filters: Mutex<HashSet<TopicFilter>>,
+}
+
+impl TopicInterner {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Return the interned instance of the topic name.
+    pub fn intern_name(&self, name: TopicName) -> TopicName {
+        let mut names = self.names.lock().expect("topic interner lock");
+        if let Some(interned) = names.get(&name) {
+            return interned.clone();
+        }
+        names.insert(name.clone());
+        name
+    }
+
+    /// Return the interned instance of the topic filter.
+    pub fn intern_filter(&self, filter: TopicFilter) -> TopicFilter {
+        let mut filters = self.filters.lock().expect("topic interner lock");
+        if let Some(interned) = filters.get(&filter) {
+            return interned.clone();
+        }
+        filters.insert(filter.clone());
+        filter
+    }
+
+    /// Release the topics only referenced by the interner.
+    ///
+    /// Topics built from a `&'static str` never own their memory, they are
+    /// kept until the interner is dropped.
+    pub fn purge(&self) {
+        self.names
+            .lock()
+            .expect("topic interner lock")
+            .retain(|name| !name.is_unique());
+        self.filters
+            .lock()
+            .expect("topic interner lock")
+            .retain(|filter| !filter.is_unique());
+    }
+
+    /// Number of interned topic names and topic filters.
+    pub fn len(&self) -> usize {
+        self.names.lock().expect("topic interner lock").len()
+            + self.filters.lock().expect("topic interner lock").len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_topic_interner() {
+        let interner = TopicInterner::new();
+        let first = interner.intern_name(TopicName::try_from("a/b".to_owned()).unwrap());
+        let second = interner.intern_name(TopicName::try_from("a/b".to_owned()).unwrap());
+        assert_eq!(first.as_ptr(), second.as_ptr());
+        let other = interner.intern_name(TopicName::try_from("a/c".to_owned()).unwrap());
+        assert_ne!(first.as_ptr(), other.as_ptr());
+
+        let filter =
+            interner.intern_filter(TopicFilter::try_from("$share/g/a/+".to_owned()).unwrap());
+        let same =
+            interner.intern_filter(TopicFilter::try_from("$share/g/a/+".to_owned()).unwrap());
+        assert_eq!(filter.as_ptr(), same.as_ptr());
+        assert_eq!(same.shared_filter(), Some("a/+"));
+        assert_eq!(interner.len(), 3);
+
+        drop((second, other, same));
+        interner.purge();
+        assert_eq!(interner.len(), 2);
+        drop((first, filter));
+        interner.purge();
+        assert!(interner.is_empty());
+    }
+}
@@ -0,0 +1,12 @@
+use std::fmt::Write;
+
+use serde_json::Value;
+
+/// Hex encode the binary field.
+pub(crate) fn json_hex(bytes: &[u8]) -> Value {
+    let mut output = String::with_capacity(bytes.len() * 2);
+    for byte in bytes {
+        let _ = write!(output, "{byte:02x}");
+    }
+    Value::String(output)
+}
@@ -0,0 +1,98 @@
+use crate::{check_utf8_strict, var_int_len, Error};
+
+/// Hard caps applied when decoding packets from an untrusted peer.
+///
+/// The remaining length is checked right after the fixed header is decoded,
+/// before the packet body is read. The other limits are checked after the
+/// body is decoded, the memory used by the body is already bounded by the
+/// remaining length. The default value is the maximum the protocol allows.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct DecodeLimits {
+    /// Max remaining length of a packet.
+    pub max_remaining_len: u32,
+    /// Max encoded length of a property list (v5.0 only).
+    pub max_properties_len: usize,
+    /// Max number of user properties in a property list (v5.0 only).
+    pub max_user_properties: usize,
+    /// Max number of topic filters in a SUBSCRIBE/UNSUBSCRIBE packet.
+    pub max_topics: usize,
+    /// Max length of client identifier, username, topic name, topic filter
+    /// and user property.
+    pub max_string_len: usize,
+    /// Reject the control characters and non-characters in the strings
+    /// checked by `max_string_len`, see `check_utf8_strict()`. Default is
+    /// `false` for compatibility with lenient peers.
+    pub strict_utf8: bool,
+    /// The "Topic Alias Maximum" sent to the peer (v5.0 only). When set, a
+    /// PUBLISH with topic alias 0 or above the maximum is rejected with
+    /// `ErrorV5::InvalidTopicAlias` [MQTT-3.3.2-8] [MQTT-3.3.2-9].
+    pub topic_alias_max: Option<u16>,
+    /// Reject a remaining length encoded in more bytes than needed (e.g.
+    /// `0x80 0x00` for 0) with `Error::InvalidVarByteInt`. Default is
+    /// `false` for compatibility with lenient peers.
+    pub strict_var_int: bool,
+}
+
+impl Default for DecodeLimits {
+    fn default() -> Self {
+        DecodeLimits {
+            max_remaining_len: 268435455,
+            max_properties_len: 268435455,
+            max_user_properties: usize::MAX,
+            max_topics: usize::MAX,
+            max_string_len: u16::MAX as usize,
+            strict_utf8: false,
+            topic_alias_max: None,
+            strict_var_int: false,
+        }
+    }
+}
+
+impl DecodeLimits {
+    /// Check the remaining length, `var_int_bytes` is the number of bytes
+    /// it is encoded in.
+    pub(crate) fn check_remaining_len(
+        &self,
+        value: u32,
+        var_int_bytes: usize,
+    ) -> Result<(), Error> {
+        if self.strict_var_int && var_int_len(value as usize)? != var_int_bytes {
+            return Err(Error::InvalidVarByteInt);
+        }
+        if value > self.max_remaining_len {
+            return Err(Error::DecodeLimitExceeded("max_remaining_len"));
+        }
+        Ok(())
+    }
+
+    pub(crate) fn check_properties(
+        &self,
+        properties_len: usize,
+        user_properties: usize,
+    ) -> Result<(), Error> {
+        if properties_len > self.max_properties_len {
+            return Err(Error::DecodeLimitExceeded("max_properties_len"));
+        }
+        if user_properties > self.max_user_properties {
+            return Err(Error::DecodeLimitExceeded("max_user_properties"));
+        }
+        Ok(())
+    }
+
+    pub(crate) fn check_topics(&self, value: usize) -> Result<(), Error> {
+        if value > self.max_topics {
+            return Err(Error::DecodeLimitExceeded("max_topics"));
+        }
+        Ok(())
+    }
+
+    pub(crate) fn check_str(&self, value: &str) -> Result<(), Error> {
+        if value.len() > self.max_string_len {
+            return Err(Error::DecodeLimitExceeded("max_string_len"));
+        }
+        if self.strict_utf8 {
+            check_utf8_strict(value)?;
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,166 @@
+use std::io;
+use std::pin::Pin;
+use std::sync::{Arc, Mutex};
+use std::task::{Context, Poll};
+
+use tokio::io::{AsyncRead, ReadBuf};
+
+/// An in-memory `AsyncRead` with fault injection, for testing partial
+/// reads, `Pending` and errors in the decoding code (such as
+/// `GenericPollPacket`) deterministically.
+///
+/// By default all the data is returned by the first read. The read pattern
+/// is recorded and can be inspected by the handle returned by `handle()`,
+/// also after the buffer is moved.
+#[derive(Debug)]
+pub struct MockBuffer {
+    data: Vec<u8>,
+    chunk_size: usize,
+    interleave_pending: bool,
+    errors: Vec<(usize, io::ErrorKind)>,
+    // If `Pending` is returned by last poll
+    pending: bool,
+    record: Arc<Mutex<MockRecord>>,
+}
+
+/// Shared view of the read pattern of a [`MockBuffer`].
+#[derive(Debug, Clone)]
+pub struct MockBufferHandle {
+    record: Arc<Mutex<MockRecord>>,
+}
+
+#[derive(Debug, Default)]
+struct MockRecord {
+    position: usize,
+    reads: Vec<usize>,
+    pending: usize,
+    errors: usize,
+}
+
+impl MockBuffer {
+    pub fn new(data: impl Into<Vec<u8>>) -> Self {
+        MockBuffer {
+            data: data.into(),
+            chunk_size: usize::MAX,
+            interleave_pending: false,
+            errors: Vec::new(),
+            pending: false,
+            record: Default::default(),
+        }
+    }
+
+    /// Deliver at most `size` bytes per poll.
+    pub fn chunk_size(mut self, size: usize) -> Self {
+        assert!(size > 0, "chunk size must be positive");
+        self.chunk_size = size;
+        self
+    }
+
+    /// Return `Pending` (and wake the task) before every read.
+    pub fn interleave_pending(mut self, enabled: bool) -> Self {
+        self.interleave_pending = enabled;
+        self
+    }
+
+    /// Return an error of `kind` once when the read position reaches
+    /// `offset`, the bytes before the offset are delivered first.
+    pub fn error_at(mut self, offset: usize, kind: io::ErrorKind) -> Self {
+        self.errors.push((offset, kind));
+        self.errors.sort_by_key(|(offset, _)| *offset);
+        self
+    }
+
+    pub fn handle(&self) -> MockBufferHandle {
+        MockBufferHandle {
+            record: Arc::clone(&self.record),
+        }
+    }
+}
+
+impl MockBufferHandle {
+    /// Number of bytes delivered.
+    pub fn position(&self) -> usize {
+        self.record.lock().expect("mock buffer lock").position
+    }
+
+    /// Number of bytes delivered by each successful read.
+    pub fn reads(&self) -> Vec<usize> {
+        self.record.lock().expect("mock buffer lock").reads.clone()
+    }
+
+    /// Number of `Pending` returned.
+    pub fn pending(&self) -> usize {
+        self.record.lock().expect("mock buffer lock").pending
+    }
+
+    /// Number of errors returned.
+    pub fn errors(&self) -> usize {
+        self.record.lock().expect("mock buffer lock").errors
+    }
+}
+
+impl AsyncRead for MockBuffer {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        let this = self.get_mut();
+        let mut record = this.record.lock().expect("mock buffer lock");
+        if this.interleave_pending && !this.pending {
+            this.pending = true;
+            record.pending += 1;
+            cx.waker().wake_by_ref();
+            return Poll::Pending;
+        }
+        this.pending = false;
+
+        let position = record.position;
+        if let Some(&(offset, kind)) = this.errors.first() {
+            if offset <= position {
+                this.errors.remove(0);
+                record.errors += 1;
+                return Poll::Ready(Err(kind.into()));
+            }
+        }
+        let end = this.errors.first().map_or(this.data.len(), |(offset, _)| {
+            (*offset).min(this.data.len())
+        });
+        let size = (end - position.min(end))
+            .min(this.chunk_size)
+            .min(buf.remaining());
+        buf.put_slice(&this.data[position..position + size]);
+        record.position += size;
+        record.reads.push(size);
+        Poll::Ready(Ok(()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use futures_lite::future::block_on;
+    use tokio::io::AsyncReadExt;
+
+    use super::*;
+
+    #[test]
+    fn test_mock_buffer() {
+        let mut buffer = MockBuffer::new(b"hello world".to_vec())
+            .chunk_size(4)
+            .interleave_pending(true)
+            .error_at(6, io::ErrorKind::ConnectionReset);
+        let handle = buffer.handle();
+        let mut data = [0u8; 11];
+        let err = block_on(buffer.read_exact(&mut data)).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::ConnectionReset);
+        assert_eq!(handle.reads(), vec![4, 2]);
+        assert_eq!(handle.position(), 6);
+        assert_eq!(handle.pending(), 3);
+        assert_eq!(handle.errors(), 1);
+
+        let mut rest = Vec::new();
+        block_on(buffer.read_to_end(&mut rest)).unwrap();
+        assert_eq!(rest, b"world");
+        assert_eq!(handle.reads(), vec![4, 2, 4, 1, 0]);
+    }
+}
@@ -1,19 +1,85 @@
+mod any_connect;
+mod capture;
+#[cfg(feature = "conformance")]
+pub mod conformance;
+#[cfg(feature = "embedded-io")]
+mod embedded;
+mod encoded;
 mod error;
+mod feed;
+mod hexdump;
+mod interner;
+#[cfg(feature = "json")]
+mod json;
+mod limits;
+#[cfg(any(test, feature = "testing"))]
+mod mock;
+mod owned;
+mod pid_allocator;
 mod poll;
+mod pool;
+#[cfg(feature = "proxy-protocol")]
+mod proxy;
+mod retained;
+mod slice;
+mod sniff;
+mod stats;
+#[cfg(feature = "proptest")]
+pub mod strategy;
+mod stream;
+mod topic_tree;
 mod types;
 mod utils;
+#[cfg(feature = "websocket")]
+mod websocket;
 
+pub(crate) use types::BRIDGE_BIT;
 pub(crate) use utils::{
-    decode_var_int, encode_packet, packet_from, read_bytes, read_string, read_u16, read_u32,
-    read_u8, write_bytes, write_u16, write_u32, write_u8, write_var_int,
+    capacity_hint, check_body_end, copy_exact, decode_raw_header_len, decode_var_int,
+    encode_packet_to, fmt_list, fmt_publish, packet_from, peek_str, peek_u16, read_bytes,
+    read_exact_capped, read_string, read_u16, read_u8, redacted, split_by_packet_size, write_bytes,
+    write_u16, write_u32, write_u8, write_var_int, MAX_RESERVE,
 };
 
-pub use error::Error;
+pub use any_connect::{decode_connect, AnyConnect};
+pub use capture::{CaptureReader, CaptureRecord, CaptureWriter, Direction};
+#[cfg(feature = "embedded-io")]
+pub(crate) use embedded::{read_packet_embedded, write_all_embedded};
+pub use encoded::EncodedPacket;
+pub use error::{DecodeError, Error};
+pub use feed::{FeedStatus, GenericPacketDecoder};
+pub(crate) use hexdump::HexdumpWriter;
+pub use interner::TopicInterner;
+#[cfg(feature = "json")]
+pub(crate) use json::json_hex;
+pub use limits::DecodeLimits;
+#[cfg(any(test, feature = "testing"))]
+pub use mock::{MockBuffer, MockBufferHandle};
+pub use owned::{GenericOwnedPacketReader, OwnedRead};
+pub use pid_allocator::PidAllocator;
+pub(crate) use poll::block_decode_exact;
 pub use poll::{
-    GenericPollBodyState, GenericPollPacket, GenericPollPacketState, PollHeader, PollHeaderState,
+    GenericPollBodyState, GenericPollEncode, GenericPollPacket, GenericPollPacketState,
+    PollEncodeState, PollHeader, PollHeaderState, PollTimeout,
 };
-pub use types::{Encodable, Pid, Protocol, QoS, QosPid, TopicFilter, TopicName, VarBytes};
-pub use utils::{decode_raw_header, header_len, remaining_len, total_len, var_int_len};
+pub use pool::ScratchBufferPool;
+#[cfg(feature = "proxy-protocol")]
+pub use proxy::ProxyHeader;
+pub use retained::{RetainedStore, RetainedTree};
+pub(crate) use slice::{decode_exact, decode_frame, decode_raw_header_slice, SliceReader};
+pub use sniff::{sniff_first_bytes, SniffedProtocol};
+pub use stats::{CodecCounters, CodecStats};
+pub use stream::{GenericPacketSink, GenericPacketStream, PollEncodable};
+pub use topic_tree::TopicTree;
+pub use types::{
+    effective_qos, Encodable, Pid, Protocol, QoS, QosPid, SharedStr, TopicFilter, TopicName,
+    VarBytes,
+};
+pub use utils::{
+    check_utf8_strict, decode_raw_header, header_len, remaining_len, total_len, var_int_len,
+};
+#[cfg(feature = "websocket")]
+pub use websocket::{split_frames, WsFrames, WsPacketDecoder};
 
 /// Character used to separate each level within a topic tree and provide a hierarchical structure.
 pub const LEVEL_SEP: char = '/';
@@ -0,0 +1,93 @@
+use std::future::Future;
+use std::io;
+use std::marker::PhantomData;
+use std::mem;
+
+use crate::{block_decode_exact, decode_frame, Error, PollHeader};
+
+/// A reader of completion based runtimes (such as monoio and glommio),
+/// which takes the ownership of the buffer while reading.
+///
+/// The data is read into the spare capacity of `buf` (after `buf.len()`),
+/// the buffer is returned with the length extended by the read size. Zero
+/// size means EOF.
+pub trait OwnedRead {
+    fn read_owned(&mut self, buf: Vec<u8>) -> impl Future<Output = (io::Result<usize>, Vec<u8>)>;
+}
+
+/// Decode packets from an `OwnedRead`, the bytes after the decoded packet
+/// are kept in the buffer for the next packet.
+///
+/// The buffer is owned by the reader while reading, if the future of
+/// `read_packet()` is dropped in the middle the buffered bytes are lost.
+pub struct GenericOwnedPacketReader<R, H> {
+    reader: R,
+    buf: Vec<u8>,
+    read_size: usize,
+    _header: PhantomData<fn() -> H>,
+}
+
+impl<R, H> GenericOwnedPacketReader<R, H> {
+    pub fn new(reader: R) -> Self {
+        Self::with_read_size(reader, 4096)
+    }
+
+    /// Reserve at least `read_size` bytes in the buffer before each read.
+    pub fn with_read_size(reader: R, read_size: usize) -> Self {
+        GenericOwnedPacketReader {
+            reader,
+            buf: Vec::new(),
+            read_size,
+            _header: PhantomData,
+        }
+    }
+
+    /// The received bytes not decoded yet.
+    pub fn buffered(&self) -> &[u8] {
+        &self.buf
+    }
+
+    pub fn into_inner(self) -> (R, Vec<u8>) {
+        (self.reader, self.buf)
+    }
+}
+
+impl<R, H> GenericOwnedPacketReader<R, H>
+where
+    R: OwnedRead,
+    H: PollHeader,
+    H::Error: From<io::Error> + From<Error>,
+{
+    /// Read the next packet, return `Ok(None)` when the reader reaches EOF
+    /// between two packets.
+    pub async fn read_packet(&mut self) -> Result<Option<H::Packet>, H::Error> {
+        loop {
+            if let Some(packet) = self.decode_buffered()? {
+                return Ok(Some(packet));
+            }
+            let mut buf = mem::take(&mut self.buf);
+            buf.reserve(self.read_size);
+            let (result, buf) = self.reader.read_owned(buf).await;
+            self.buf = buf;
+            if result? == 0 {
+                if self.buf.is_empty() {
+                    return Ok(None);
+                }
+                return Err(io::Error::from(io::ErrorKind::UnexpectedEof).into());
+            }
+        }
+    }
+
+    fn decode_buffered(&mut self) -> Result<Option<H::Packet>, H::Error> {
+        let Some((header, header_len)) = decode_frame::<H>(&self.buf)? else {
+            return Ok(None);
+        };
+        let total = header_len + header.remaining_len();
+        let result = match header.build_empty_packet() {
+            Some(packet) => Ok(packet),
+            None => block_decode_exact(header, &self.buf[header_len..total]),
+        };
+        self.buf.drain(..total);
+        result.map(Some)
+    }
+}
@@ -0,0 +1,98 @@
+use std::collections::HashSet;
+
+use super::{Error, Pid};
+
+/// Allocate packet identifiers for QoS 1/2 PUBLISH, SUBSCRIBE and UNSUBSCRIBE
+/// packets, an allocated identifier is in-flight until it is released (when
+/// the packet is acknowledged).
+#[derive(Debug, Clone, Default)]
+pub struct PidAllocator {
+    next: Pid,
+    in_flight: HashSet<Pid>,
+}
+
+impl PidAllocator {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Allocate a packet identifier which is not in-flight. Return
+    /// `Error::PidExhausted` if all the 65535 identifiers are in-flight.
+    pub fn alloc(&mut self) -> Result<Pid, Error> {
+        if self.in_flight.len() >= u16::MAX as usize {
+            return Err(Error::PidExhausted);
+        }
+        let mut pid = self.next;
+        while self.in_flight.contains(&pid) {
+            pid += 1;
+        }
+        self.in_flight.insert(pid);
+        self.next = pid + 1;
+        Ok(pid)
+    }
+
+    /// Mark a packet identifier in-flight (e.g. restored from a session),
+    /// return false if it is already in-flight.
+    pub fn reserve(&mut self, pid: Pid) -> bool {
+        self.in_flight.insert(pid)
+    }
+
+    /// Release a packet identifier, return false if it is not in-flight.
+    pub fn release(&mut self, pid: Pid) -> bool {
+        self.in_flight.remove(&pid)
+    }
+
+    pub fn is_in_flight(&self, pid: Pid) -> bool {
+        self.in_flight.contains(&pid)
+    }
+
+    /// Number of in-flight packet identifiers.
+    pub fn in_flight(&self) -> usize {
+        self.in_flight.len()
+    }
+
+    /// Release all the packet identifiers.
+    pub fn clear(&mut self) {
+        self.in_flight.clear();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::convert::TryFrom;
+
+    use super::*;
+
+    #[test]
+    fn test_alloc_release() {
+        let mut allocator = PidAllocator::new();
+        let pid1 = allocator.alloc().unwrap();
+        let pid2 = allocator.alloc().unwrap();
+        assert_eq!(pid1.value(), 1);
+        assert_eq!(pid2.value(), 2);
+        assert!(allocator.is_in_flight(pid1));
+
+        assert!(allocator.release(pid1));
+        assert!(!allocator.release(pid1));
+        assert!(!allocator.is_in_flight(pid1));
+        assert_eq!(allocator.in_flight(), 1);
+
+        // Skip the in-flight identifiers
+        assert!(allocator.reserve(Pid::try_from(3).unwrap()));
+        assert!(!allocator.reserve(Pid::try_from(3).unwrap()));
+        assert_eq!(allocator.alloc().unwrap().value(), 4);
+    }
+
+    #[test]
+    fn test_exhausted() {
+        let mut allocator = PidAllocator::new();
+        for value in 1..=u16::MAX {
+            assert_eq!(allocator.alloc().unwrap().value(), value);
+        }
+        assert_eq!(allocator.alloc(), Err(Error::PidExhausted));
+
+        let pid = Pid::try_from(100).unwrap();
+        allocator.release(pid);
+        assert_eq!(allocator.alloc(), Ok(pid));
+    }
+}
@@ -3,10 +3,12 @@ use std::io;
 use std::mem::{self, MaybeUninit};
 use std::pin::Pin;
 use std::task::{Context, Poll};
+use std::time::{Duration, Instant};
 
-use tokio::io::{AsyncRead, ReadBuf};
+use bytes::Bytes;
+use tokio::io::{AsyncRead, AsyncWrite, ReadBuf};
 
-use crate::Error;
+use crate::{CodecStats, DecodeLimits, Error, ScratchBufferPool, VarBytes, MAX_RESERVE};
 
 #[derive(Debug, Clone)]
 pub enum GenericPollPacketState<H> {
@@ -19,6 +21,10 @@ pub struct PollHeaderState {
     pub control_byte: Option<u8>,
     pub var_idx: u8,
     pub var_int: u32,
+    /// When the first byte of the packet is received
+    pub started: Option<Instant>,
+    /// Number of polls since the first byte of the packet is received
+    pub polls: u32,
 }
 
 #[derive(Debug, Clone)]
@@ -27,7 +33,36 @@ pub struct GenericPollBodyState<H> {
     /// Packet total size (include header)
     pub total: usize,
     pub idx: usize,
+    /// The body buffer, it grows by at most 64KB at a time as the bytes
+    /// arrive, the bytes after `idx` are uninitialized.
     pub buf: Vec<MaybeUninit<u8>>,
+    pub started: Option<Instant>,
+    pub polls: u32,
+}
+
+/// Abort a half-received packet with `Error::DecodeTimeout`, see
+/// `GenericPollPacket::with_timeout()`.
+///
+/// Both limits count from the first byte of the packet, so an idle
+/// connection between two packets never times out. The limits are checked
+/// when the future is polled: a reader which never wakes the task again
+/// still needs a timer to poll it.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct PollTimeout {
+    pub duration: Option<Duration>,
+    pub max_polls: Option<u32>,
+}
+
+impl PollTimeout {
+    fn is_expired(&self, started: Option<Instant>, polls: u32) -> bool {
+        let Some(started) = started else {
+            return false;
+        };
+        self.max_polls.is_some_and(|max_polls| polls > max_polls)
+            || self
+                .duration
+                .is_some_and(|duration| started.elapsed() > duration)
+    }
 }
 
 pub trait PollHeader {
@@ -37,11 +72,29 @@ pub trait PollHeader {
     fn new_with(hd: u8, remaining_len: u32) -> Result<Self, Self::Error>
     where
         Self: Sized;
-    /// Packet without body is empty packet
+    /// Packet without body is empty packet, it is only built when the
+    /// remaining length is 0, otherwise `block_decode()` rejects the body.
     fn build_empty_packet(&self) -> Option<Self::Packet>;
     fn block_decode(self, reader: &mut &[u8]) -> Result<Self::Packet, Self::Error>;
+    /// Decode a fully received body, the decoded packet may share the body
+    /// (such as the PUBLISH payload). By default the body is copied by
+    /// `block_decode()`.
+    fn decode_shared(self, body: Bytes) -> Result<Self::Packet, Self::Error>
+    where
+        Self: Sized,
+        Self::Error: From<Error>,
+    {
+        block_decode_exact(self, &body)
+    }
     fn remaining_len(&self) -> usize;
+    /// The first byte of the fixed header (packet type and flags).
+    fn control_byte(&self) -> u8;
     fn is_eof_error(err: &Self::Error) -> bool;
+    /// Check the decoded packet against the limits, the remaining length is
+    /// already checked.
+    fn check_limits(_packet: &Self::Packet, _limits: &DecodeLimits) -> Result<(), Self::Error> {
+        Ok(())
+    }
 }
 
 impl<H> Default for GenericPollPacketState<H> {
@@ -53,11 +106,60 @@ impl<H> Default for GenericPollPacketState<H> {
 pub struct GenericPollPacket<'a, T, H> {
     state: &'a mut GenericPollPacketState<H>,
     reader: &'a mut T,
+    limits: Option<DecodeLimits>,
+    stats: Option<&'a dyn CodecStats>,
+    pool: Option<&'a ScratchBufferPool>,
+    timeout: Option<PollTimeout>,
+    max_packet_size: Option<u32>,
 }
 
 impl<'a, T, H> GenericPollPacket<'a, T, H> {
     pub fn new(state: &'a mut GenericPollPacketState<H>, reader: &'a mut T) -> Self {
-        GenericPollPacket { state, reader }
+        GenericPollPacket {
+            state,
+            reader,
+            limits: None,
+            stats: None,
+            pool: None,
+            timeout: None,
+            max_packet_size: None,
+        }
+    }
+
+    /// The decoded packet must not exceed the limits.
+    pub fn with_limits(mut self, limits: DecodeLimits) -> Self {
+        self.limits = Some(limits);
+        self
+    }
+
+    /// Report the decoded and malformed packets to `stats`.
+    pub fn with_stats(mut self, stats: &'a dyn CodecStats) -> Self {
+        self.stats = Some(stats);
+        self
+    }
+
+    /// Take the buffer of the packet body from `pool`, it is returned to the
+    /// pool when dropped: after decoding, or when the payload of a decoded
+    /// PUBLISH packet (a slice of the buffer) is dropped. The buffer in the
+    /// output is empty.
+    pub fn with_scratch_pool(mut self, pool: &'a ScratchBufferPool) -> Self {
+        self.pool = Some(pool);
+        self
+    }
+
+    /// Abort a packet which is not fully received within the timeout, the
+    /// progress is kept in the state so it also works when the future is
+    /// dropped and created again.
+    pub fn with_timeout(mut self, timeout: PollTimeout) -> Self {
+        self.timeout = Some(timeout);
+        self
+    }
+
+    /// Reject packets larger than `max_packet_size` (include header) with
+    /// `Error::PacketTooLarge` before reading the body.
+    pub fn with_max_packet_size(mut self, max_packet_size: u32) -> Self {
+        self.max_packet_size = Some(max_packet_size);
+        self
     }
 }
 
@@ -73,13 +175,37 @@ where
         let GenericPollPacket {
             ref mut state,
             ref mut reader,
+            limits,
+            stats,
+            pool,
+            timeout,
+            max_packet_size,
         } = self.get_mut();
+        let stats = *stats;
+        if let Some(timeout) = timeout {
+            let (started, polls) = match state {
+                GenericPollPacketState::Header(PollHeaderState { started, polls, .. }) => {
+                    (*started, polls)
+                }
+                GenericPollPacketState::Body(GenericPollBodyState { started, polls, .. }) => {
+                    (*started, polls)
+                }
+            };
+            if started.is_some() {
+                *polls += 1;
+            }
+            if timeout.is_expired(started, *polls) {
+                return Poll::Ready(Err(Error::DecodeTimeout.into()));
+            }
+        }
         loop {
             match state {
                 GenericPollPacketState::Header(PollHeaderState {
                     control_byte,
                     var_idx,
                     var_int,
+                    started,
+                    polls,
                 }) => {
                     let mut buf = [0u8; 1];
                     loop {
@@ -103,6 +229,9 @@ where
                         let byte = readbuf.filled()[0];
                         if control_byte.is_none() {
                             *control_byte = Some(byte);
+                            if timeout.is_some() {
+                                *started = Some(Instant::now());
+                            }
                         } else {
                             *var_int |= (u32::from(byte) & 0x7F) << (7 * u32::from(*var_idx));
                             if byte & 0x80 == 0 {
@@ -110,30 +239,57 @@ where
                             } else if *var_idx < 3 {
                                 *var_idx += 1;
                             } else {
-                                return Poll::Ready(Err(Error::InvalidVarByteInt.into()));
+                                return Poll::Ready(Err(malformed(
+                                    stats,
+                                    Error::InvalidVarByteInt.into(),
+                                )));
                             }
                         }
                     }
 
                     let header = match H::new_with(control_byte.unwrap(), *var_int) {
                         Ok(header) => header,
-                        Err(err) => return Poll::Ready(Err(err)),
+                        Err(err) => return Poll::Ready(Err(malformed(stats, err))),
                     };
+                    let total = 1 + 1 + *var_idx as usize + header.remaining_len();
+                    if let Some(max_packet_size) = max_packet_size {
+                        if total > *max_packet_size as usize {
+                            let err = Error::PacketTooLarge(total).into();
+                            return Poll::Ready(Err(malformed(stats, err)));
+                        }
+                    }
+                    if let Some(limits) = limits {
+                        if let Err(err) = limits.check_remaining_len(
+                            header.remaining_len() as u32,
+                            *var_idx as usize + 1,
+                        ) {
+                            return Poll::Ready(Err(malformed(stats, err.into())));
+                        }
+                    }
                     if let Some(empty_packet) = header.build_empty_packet() {
+                        if let Some(stats) = stats {
+                            stats.on_decode(header.control_byte() >> 4, 2);
+                        }
                         return Poll::Ready(Ok((2, Vec::new(), empty_packet)));
                     }
                     if header.remaining_len() == 0 {
-                        return Poll::Ready(Err(Error::InvalidRemainingLength.into()));
-                    }
-                    let mut buf: Vec<MaybeUninit<u8>> = Vec::with_capacity(header.remaining_len());
-                    unsafe {
-                        buf.set_len(header.remaining_len());
+                        let err = Error::InvalidRemainingLength.into();
+                        return Poll::Ready(Err(malformed(stats, err)));
                     }
+                    // The length comes from the peer, the buffer grows as the
+                    // bytes arrive
+                    let mut buf = match pool {
+                        Some(pool) => pool.take(0),
+                        None => Vec::new(),
+                    };
+                    grow_body(&mut buf, header.remaining_len());
                     **state = GenericPollPacketState::Body(GenericPollBodyState {
                         header,
-                        total: 1 + 1 + *var_idx as usize + header.remaining_len(),
+                        total,
                         idx: 0,
                         buf,
+                        started: *started,
+                        polls: *polls,
                     });
                 }
                 GenericPollPacketState::Body(GenericPollBodyState {
@@ -141,7 +297,11 @@ where
                     idx,
                     buf,
                     total,
+                    ..
                 }) => loop {
+                    if *idx == buf.len() {
+                        grow_body(buf, header.remaining_len());
+                    }
                     let buf_refmut: &mut [u8] = unsafe { mem::transmute(&mut buf[*idx..]) };
                     let mut readbuf_refmut = ReadBuf::new(buf_refmut);
                     let size = match Pin::new(&mut *reader).poll_read(cx, &mut readbuf_refmut) {
@@ -163,21 +323,228 @@ where
                     *idx += size;
                     debug_assert!(*idx <= buf.len());
 
-                    if *idx == buf.len() {
-                        let mut buf_ref: &[u8] = unsafe { mem::transmute(&buf[..]) };
-                        let result = header.block_decode(&mut buf_ref);
-                        if result.is_ok() && !buf_ref.is_empty() {
-                            return Poll::Ready(Err(Error::InvalidRemainingLength.into()));
+                    if *idx == header.remaining_len() {
+                        let mut result = match pool {
+                            // SAFETY: the whole body is received
+                            Some(pool) => {
+                                header.decode_shared(unsafe { pool.freeze(mem::take(buf)) })
+                            }
+                            None => {
+                                let buf_ref: &[u8] = unsafe { mem::transmute(&buf[..]) };
+                                block_decode_exact(*header, buf_ref)
+                            }
+                        };
+                        if let (Ok(packet), Some(limits)) = (&result, limits) {
+                            if let Err(err) = H::check_limits(packet, limits) {
+                                result = Err(err);
+                            }
                         }
-                        if let Err(err) = &result {
-                            if H::is_eof_error(err) {
-                                return Poll::Ready(Err(Error::InvalidRemainingLength.into()));
+                        if let Some(stats) = stats {
+                            match result {
+                                Ok(_) => stats.on_decode(header.control_byte() >> 4, *total),
+                                Err(_) => stats.on_malformed(),
                             }
                         }
-                        return Poll::Ready(result.map(|packet| (*total, mem::take(buf), packet)));
+                        let buf = mem::take(buf);
+                        return Poll::Ready(result.map(|packet| (*total, buf, packet)));
                     }
                 },
             }
         }
     }
 }
+
+/// Extend the uninitialized body buffer by at most `MAX_RESERVE` bytes,
+/// without exceeding `remaining_len`.
+fn grow_body(buf: &mut Vec<MaybeUninit<u8>>, remaining_len: usize) {
+    let len = buf.len();
+    let additional = (remaining_len - len).min(MAX_RESERVE);
+    buf.reserve(additional);
+    // SAFETY: the capacity is reserved and the items are `MaybeUninit`
+    unsafe {
+        buf.set_len(len + additional);
+    }
+}
+
+/// The state of writing an encoded packet, it records how many bytes are
+/// already written so the writing can be resumed after a partial write.
+#[derive(Debug, Clone)]
+pub struct PollEncodeState {
+    data: EncodedHead,
+    payload: Bytes,
+    idx: usize,
+}
+
+/// The encoded packet except the payload, shared with the `EncodedPacket` it
+/// comes from.
+#[derive(Debug, Clone)]
+enum EncodedHead {
+    Owned(VarBytes),
+    Shared(Bytes),
+}
+
+impl AsRef<[u8]> for EncodedHead {
+    fn as_ref(&self) -> &[u8] {
+        match self {
+            EncodedHead::Owned(data) => data.as_ref(),
+            EncodedHead::Shared(data) => data,
+        }
+    }
+}
+
+impl PollEncodeState {
+    /// Create the state from the encoded packet and the payload not included
+    /// in it (see `Packet::encode_vectored()`).
+    pub fn new(data: VarBytes, payload: Bytes) -> Self {
+        PollEncodeState {
+            data: EncodedHead::Owned(data),
+            payload,
+            idx: 0,
+        }
+    }
+
+    /// Same as `new()`, but the encoded bytes are shared instead of owned.
+    pub(crate) fn shared(head: Bytes, payload: Bytes) -> Self {
+        PollEncodeState {
+            data: EncodedHead::Shared(head),
+            payload,
+            idx: 0,
+        }
+    }
+
+    /// Packet total size (include header)
+    pub fn total(&self) -> usize {
+        self.data.as_ref().len() + self.payload.len()
+    }
+
+    /// Number of bytes already written
+    pub fn written(&self) -> usize {
+        self.idx
+    }
+
+    pub fn is_done(&self) -> bool {
+        self.idx == self.total()
+    }
+
+    fn remaining(&self) -> [io::IoSlice<'_>; 2] {
+        let data = self.data.as_ref();
+        if self.idx < data.len() {
+            [
+                io::IoSlice::new(&data[self.idx..]),
+                io::IoSlice::new(&self.payload),
+            ]
+        } else {
+            [
+                io::IoSlice::new(&self.payload[self.idx - data.len()..]),
+                io::IoSlice::new(&[]),
+            ]
+        }
+    }
+
+    fn advance(&mut self, size: usize) -> io::Result<()> {
+        if size == 0 {
+            return Err(io::ErrorKind::WriteZero.into());
+        }
+        self.idx += size;
+        debug_assert!(self.idx <= self.total());
+        Ok(())
+    }
+
+    /// Write the remaining bytes to a (non-blocking) writer. When the writer
+    /// returns an error (include `WouldBlock`) the progress is kept, call it
+    /// again to resume the writing.
+    pub fn write_to<W: io::Write>(&mut self, writer: &mut W) -> io::Result<()> {
+        while !self.is_done() {
+            match writer.write_vectored(&self.remaining()) {
+                Ok(size) => self.advance(size)?,
+                Err(err) if err.kind() == io::ErrorKind::Interrupted => {}
+                Err(err) => return Err(err),
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Write an encoded packet to an `AsyncWrite`, the state can be kept and
+/// polled again after the future is dropped.
+pub struct GenericPollEncode<'a, T> {
+    state: &'a mut PollEncodeState,
+    writer: &'a mut T,
+    stats: Option<&'a dyn CodecStats>,
+}
+
+impl<'a, T> GenericPollEncode<'a, T> {
+    pub fn new(state: &'a mut PollEncodeState, writer: &'a mut T) -> Self {
+        GenericPollEncode {
+            state,
+            writer,
+            stats: None,
+        }
+    }
+
+    /// Report the packet to `stats` when it is fully written.
+    pub fn with_stats(mut self, stats: &'a dyn CodecStats) -> Self {
+        self.stats = Some(stats);
+        self
+    }
+}
+
+impl<'a, T> Future for GenericPollEncode<'a, T>
+where
+    T: AsyncWrite + Unpin,
+{
+    /// The packet total size
+    type Output = Result<usize, Error>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let GenericPollEncode {
+            ref mut state,
+            ref mut writer,
+            stats,
+        } = self.get_mut();
+        if state.is_done() {
+            return Poll::Ready(Ok(state.total()));
+        }
+        while !state.is_done() {
+            let result = Pin::new(&mut **writer).poll_write_vectored(cx, &state.remaining());
+            match result {
+                Poll::Ready(Ok(size)) => {
+                    if let Err(err) = state.advance(size) {
+                        return Poll::Ready(Err(err.into()));
+                    }
+                }
+                Poll::Ready(Err(err)) => return Poll::Ready(Err(err.into())),
+                Poll::Pending => return Poll::Pending,
+            }
+        }
+        if let Some(stats) = stats {
+            stats.on_encode(state.data.as_ref()[0] >> 4, state.total());
+        }
+        Poll::Ready(Ok(state.total()))
+    }
+}
+
+fn malformed<E>(stats: Option<&dyn CodecStats>, err: E) -> E {
+    if let Some(stats) = stats {
+        stats.on_malformed();
+    }
+    err
+}
+
+/// Decode a fully received packet body, the body must be consumed exactly.
+pub(crate) fn block_decode_exact<H>(header: H, mut body: &[u8]) -> Result<H::Packet, H::Error>
+where
+    H: PollHeader,
+    H::Error: From<Error>,
+{
+    let result = header.block_decode(&mut body);
+    if result.is_ok() && !body.is_empty() {
+        return Err(Error::InvalidRemainingLength.into());
+    }
+    if let Err(err) = &result {
+        if H::is_eof_error(err) {
+            return Err(Error::InvalidRemainingLength.into());
+        }
+    }
+    result
+}
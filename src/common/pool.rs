@@ -0,0 +1,150 @@
+use std::mem::{self, MaybeUninit};
+use std::slice;
+use std::sync::{Arc, Mutex};
+
+use bytes::Bytes;
+
+/// A pool of the buffers which receive the packet bodies, shared by the
+/// connections, see `GenericPollPacket::with_scratch_pool()`. Cloning it
+/// only increases the reference count of the pool.
+///
+/// Without a pool every received packet allocates a buffer for its body and
+/// drops it after decoding. With a pool the buffer is taken from the pool
+/// and returned to it when it is dropped: right after decoding for most
+/// packets, when the payload is dropped for PUBLISH packets (the payload is
+/// a slice of the buffer). The strings of the decoded packet are still
+/// allocated as usual. Buffers larger than `max_capacity` are not kept, so a
+/// few large packets do not pin the memory.
+#[derive(Debug, Clone)]
+pub struct ScratchBufferPool {
+    inner: Arc<PoolInner>,
+}
+
+#[derive(Debug)]
+struct PoolInner {
+    buffers: Mutex<Vec<Vec<MaybeUninit<u8>>>>,
+    max_buffers: usize,
+    max_capacity: usize,
+}
+
+impl ScratchBufferPool {
+    /// Create a pool keeping at most `max_buffers` idle buffers, each with
+    /// at most `max_capacity` bytes.
+    pub fn new(max_buffers: usize, max_capacity: usize) -> Self {
+        ScratchBufferPool {
+            inner: Arc::new(PoolInner {
+                buffers: Mutex::new(Vec::new()),
+                max_buffers,
+                max_capacity,
+            }),
+        }
+    }
+
+    /// Take a buffer with length `len`, the content is uninitialized. The
+    /// whole `len` is reserved, so do not pass a length declared by the
+    /// peer, grow the buffer as the bytes arrive instead.
+    pub fn take(&self, len: usize) -> Vec<MaybeUninit<u8>> {
+        let mut buf = self
+            .inner
+            .buffers
+            .lock()
+            .expect("buffer pool lock")
+            .pop()
+            .unwrap_or_default();
+        buf.reserve(len);
+        // SAFETY: the capacity is reserved and the items are `MaybeUninit`
+        unsafe {
+            buf.set_len(len);
+        }
+        buf
+    }
+
+    /// Return a buffer to the pool.
+    pub fn recycle(&self, mut buf: Vec<MaybeUninit<u8>>) {
+        if buf.capacity() == 0 || buf.capacity() > self.inner.max_capacity {
+            return;
+        }
+        buf.clear();
+        let mut buffers = self.inner.buffers.lock().expect("buffer pool lock");
+        if buffers.len() < self.inner.max_buffers {
+            buffers.push(buf);
+        }
+    }
+
+    /// Share a filled buffer as `Bytes`, the buffer is returned to the pool
+    /// when the last `Bytes` sharing it is dropped.
+    ///
+    /// # Safety
+    ///
+    /// All the `buf.len()` bytes must be initialized.
+    pub(crate) unsafe fn freeze(&self, buf: Vec<MaybeUninit<u8>>) -> Bytes {
+        Bytes::from_owner(PooledBuffer {
+            buf,
+            pool: self.clone(),
+        })
+    }
+
+    /// Number of idle buffers in the pool.
+    pub fn idle(&self) -> usize {
+        self.inner.buffers.lock().expect("buffer pool lock").len()
+    }
+}
+
+/// A filled buffer shared by `Bytes`, see `ScratchBufferPool::freeze()`.
+struct PooledBuffer {
+    buf: Vec<MaybeUninit<u8>>,
+    pool: ScratchBufferPool,
+}
+
+impl AsRef<[u8]> for PooledBuffer {
+    fn as_ref(&self) -> &[u8] {
+        // SAFETY: the buffer is fully initialized, see `freeze()`
+        unsafe { slice::from_raw_parts(self.buf.as_ptr().cast(), self.buf.len()) }
+    }
+}
+
+impl Drop for PooledBuffer {
+    fn drop(&mut self) {
+        self.pool.recycle(mem::take(&mut self.buf));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_buffer_pool() {
+        let pool = ScratchBufferPool::new(1, 64);
+        let buf = pool.take(16);
+        assert_eq!(buf.len(), 16);
+        let ptr = buf.as_ptr();
+        pool.recycle(buf);
+        assert_eq!(pool.idle(), 1);
+
+        // The buffer is reused
+        let buf = pool.take(8);
+        assert_eq!(buf.len(), 8);
+        assert_eq!(buf.as_ptr(), ptr);
+        assert_eq!(pool.idle(), 0);
+
+        pool.recycle(buf);
+        pool.recycle(pool.take(4));
+        pool.recycle(Vec::with_capacity(8));
+        assert_eq!(pool.idle(), 1);
+        // The buffer grows too large and is dropped
+        pool.recycle(pool.take(128));
+        assert_eq!(pool.idle(), 0);
+
+        // The shared buffer is returned when the last `Bytes` is dropped
+        let mut buf = pool.take(0);
+        buf.extend(b"abc".iter().map(|byte| MaybeUninit::new(*byte)));
+        let data = unsafe { pool.clone().freeze(buf) };
+        let slice = data.slice(1..);
+        drop(data);
+        assert_eq!(pool.idle(), 0);
+        assert_eq!(slice, &b"bc"[..]);
+        drop(slice);
+        assert_eq!(pool.idle(), 1);
+    }
+}
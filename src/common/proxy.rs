@@ -0,0 +1,231 @@
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+
+use futures_lite::future::block_on;
+use tokio::io::{AsyncRead, AsyncReadExt};
+
+use super::{read_u16, read_u8, Error};
+
+const V2_SIGNATURE: [u8; 12] = *b"\r\n\r\n\0\r\nQUIT\n";
+/// Max length of a v1 header line (including the CRLF)
+const V1_MAX_LEN: usize = 107;
+
+/// The [HAProxy PROXY protocol] header sent by a load balancer before the
+/// MQTT stream.
+///
+/// The addresses are `None` for a v1 `UNKNOWN` header, a v2 `LOCAL` command
+/// and the address families other than TCP/UDP over IPv4/IPv6.
+///
+/// [HAProxy PROXY protocol]: https://www.haproxy.org/download/2.8/doc/proxy-protocol.txt
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct ProxyHeader {
+    /// The protocol version, 1 or 2.
+    pub version: u8,
+    /// The address of the real client.
+    pub source: Option<SocketAddr>,
+    /// The address the client connected to.
+    pub destination: Option<SocketAddr>,
+}
+
+impl ProxyHeader {
+    /// Decode a header from bytes, return the header and the number of bytes
+    /// consumed, or `None` if more bytes are needed.
+    pub fn decode(bytes: &[u8]) -> Result<Option<(Self, usize)>, Error> {
+        let mut reader = bytes;
+        match block_on(Self::decode_async(&mut reader)) {
+            Ok(header) => Ok(Some((header, bytes.len() - reader.len()))),
+            Err(err) if err.is_eof() => Ok(None),
+            Err(err) => Err(err),
+        }
+    }
+
+    /// Decode a header (v1 or v2) from the reader. Exactly the header bytes
+    /// are read, the rest of the stream can be handed to `PollPacket` or
+    /// `decode_raw_header`.
+    pub async fn decode_async<T: AsyncRead + Unpin>(reader: &mut T) -> Result<Self, Error> {
+        // The shortest v1 header ("PROXY UNKNOWN\r\n") is longer than 12 bytes
+        let mut prefix = [0u8; 12];
+        reader.read_exact(&mut prefix).await?;
+        if prefix == V2_SIGNATURE {
+            Self::decode_v2(reader).await
+        } else if prefix.starts_with(b"PROXY ") {
+            let mut line = prefix.to_vec();
+            while !line.ends_with(b"\r\n") {
+                if line.len() >= V1_MAX_LEN {
+                    return Err(Error::InvalidProxyHeader("v1 header too long"));
+                }
+                line.push(read_u8(reader).await?);
+            }
+            Self::parse_v1(&line[..line.len() - 2])
+        } else {
+            Err(Error::InvalidProxyHeader("invalid signature"))
+        }
+    }
+
+    fn parse_v1(line: &[u8]) -> Result<Self, Error> {
+        let line = std::str::from_utf8(line)
+            .map_err(|_| Error::InvalidProxyHeader("invalid v1 header"))?;
+        let mut parts = line.split(' ').skip(1);
+        let mut header = ProxyHeader {
+            version: 1,
+            source: None,
+            destination: None,
+        };
+        let is_ipv6 = match parts.next() {
+            Some("UNKNOWN") => return Ok(header),
+            Some("TCP4") => false,
+            Some("TCP6") => true,
+            _ => return Err(Error::InvalidProxyHeader("invalid v1 protocol")),
+        };
+        let mut next = || {
+            parts
+                .next()
+                .ok_or(Error::InvalidProxyHeader("missing v1 address"))
+        };
+        let invalid_addr = || Error::InvalidProxyHeader("invalid v1 address");
+        // The addresses must be of the family of the protocol
+        let parse_ip = |value: &str| {
+            if is_ipv6 {
+                value.parse::<Ipv6Addr>().map(IpAddr::from)
+            } else {
+                value.parse::<Ipv4Addr>().map(IpAddr::from)
+            }
+            .map_err(|_| invalid_addr())
+        };
+        let src_ip = parse_ip(next()?)?;
+        let dst_ip = parse_ip(next()?)?;
+        let src_port: u16 = next()?.parse().map_err(|_| invalid_addr())?;
+        let dst_port: u16 = next()?.parse().map_err(|_| invalid_addr())?;
+        if parts.next().is_some() {
+            return Err(Error::InvalidProxyHeader("invalid v1 header"));
+        }
+        header.source = Some(SocketAddr::new(src_ip, src_port));
+        header.destination = Some(SocketAddr::new(dst_ip, dst_port));
+        Ok(header)
+    }
+
+    async fn decode_v2<T: AsyncRead + Unpin>(reader: &mut T) -> Result<Self, Error> {
+        let ver_cmd = read_u8(reader).await?;
+        let family = read_u8(reader).await?;
+        let len = read_u16(reader).await? as usize;
+        if ver_cmd >> 4 != 2 {
+            return Err(Error::InvalidProxyHeader("invalid v2 version"));
+        }
+        let mut data = vec![0u8; len];
+        reader.read_exact(&mut data).await?;
+
+        let mut header = ProxyHeader {
+            version: 2,
+            source: None,
+            destination: None,
+        };
+        match ver_cmd & 0x0F {
+            // LOCAL
+            0 => return Ok(header),
+            // PROXY
+            1 => {}
+            _ => return Err(Error::InvalidProxyHeader("invalid v2 command")),
+        }
+        // The TLVs after the addresses are ignored
+        let (source, destination) = match family >> 4 {
+            1 if len >= 12 => {
+                let src_ip = Ipv4Addr::from(<[u8; 4]>::try_from(&data[0..4]).unwrap());
+                let dst_ip = Ipv4Addr::from(<[u8; 4]>::try_from(&data[4..8]).unwrap());
+                (
+                    SocketAddr::new(src_ip.into(), port(&data[8..10])),
+                    SocketAddr::new(dst_ip.into(), port(&data[10..12])),
+                )
+            }
+            2 if len >= 36 => {
+                let src_ip = Ipv6Addr::from(<[u8; 16]>::try_from(&data[0..16]).unwrap());
+                let dst_ip = Ipv6Addr::from(<[u8; 16]>::try_from(&data[16..32]).unwrap());
+                (
+                    SocketAddr::new(src_ip.into(), port(&data[32..34])),
+                    SocketAddr::new(dst_ip.into(), port(&data[34..36])),
+                )
+            }
+            1 | 2 => return Err(Error::InvalidProxyHeader("v2 address too short")),
+            // AF_UNSPEC, AF_UNIX
+            _ => return Ok(header),
+        };
+        header.source = Some(source);
+        header.destination = Some(destination);
+        Ok(header)
+    }
+}
+
+fn port(data: &[u8]) -> u16 {
+    u16::from_be_bytes([data[0], data[1]])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_v1() {
+        let data = b"PROXY TCP4 192.168.0.1 192.168.0.11 56324 1883\r\n\x10";
+        let (header, len) = ProxyHeader::decode(data).unwrap().unwrap();
+        assert_eq!(len, data.len() - 1);
+        assert_eq!(header.version, 1);
+        assert_eq!(header.source, Some("192.168.0.1:56324".parse().unwrap()));
+        assert_eq!(
+            header.destination,
+            Some("192.168.0.11:1883".parse().unwrap())
+        );
+
+        let data = b"PROXY TCP6 ::1 ::2 56324 1883\r\n";
+        let (header, _) = ProxyHeader::decode(data).unwrap().unwrap();
+        assert_eq!(header.source, Some("[::1]:56324".parse().unwrap()));
+
+        let (header, _) = ProxyHeader::decode(b"PROXY UNKNOWN\r\n").unwrap().unwrap();
+        assert_eq!(header.source, None);
+
+        assert_eq!(ProxyHeader::decode(b"PROXY TCP4 192.168.0.1"), Ok(None));
+        assert!(ProxyHeader::decode(b"PROXY TCP4 192.168.0.1 x 1 2\r\n").is_err());
+        assert_eq!(
+            ProxyHeader::decode(b"PROXY TCP4 ::1 ::2 56324 1883\r\n"),
+            Err(Error::InvalidProxyHeader("invalid v1 address"))
+        );
+        assert_eq!(
+            ProxyHeader::decode(b"PROXY TCP6 192.168.0.1 ::2 56324 1883\r\n"),
+            Err(Error::InvalidProxyHeader("invalid v1 address"))
+        );
+        assert!(ProxyHeader::decode(&[&b"PROXY "[..], &[b'a'; 120]].concat()).is_err());
+        assert_eq!(
+            ProxyHeader::decode(b"\x10\x0c\x00\x04MQTT\x04\x02\x00\x3c"),
+            Err(Error::InvalidProxyHeader("invalid signature"))
+        );
+    }
+
+    #[test]
+    fn test_v2() {
+        let mut data = V2_SIGNATURE.to_vec();
+        // PROXY command, TCP over IPv4, 12 bytes addresses and a 4 bytes TLV
+        data.extend([0x21, 0x11, 0x00, 0x10]);
+        data.extend([192, 168, 0, 1, 192, 168, 0, 11, 0xDC, 0x04, 0x07, 0x5B]);
+        data.extend([0x04, 0x00, 0x01, 0x00]);
+        let (header, len) = ProxyHeader::decode(&data).unwrap().unwrap();
+        assert_eq!(len, data.len());
+        assert_eq!(header.version, 2);
+        assert_eq!(header.source, Some("192.168.0.1:56324".parse().unwrap()));
+        assert_eq!(
+            header.destination,
+            Some("192.168.0.11:1883".parse().unwrap())
+        );
+        assert_eq!(ProxyHeader::decode(&data[..20]), Ok(None));
+
+        // LOCAL command
+        let mut data = V2_SIGNATURE.to_vec();
+        data.extend([0x20, 0x00, 0x00, 0x00]);
+        let (header, _) = ProxyHeader::decode(&data).unwrap().unwrap();
+        assert_eq!(header.source, None);
+
+        let mut data = V2_SIGNATURE.to_vec();
+        data.extend([0x21, 0x21, 0x00, 0x0C]);
+        data.extend([0u8; 12]);
+        assert_eq!(
+            ProxyHeader::decode(&data),
+            Err(Error::InvalidProxyHeader("v2 address too short"))
+        );
+    }
+}
@@ -0,0 +1,245 @@
+use std::collections::HashMap;
+
+use super::{TopicFilter, TopicName, MATCH_ALL_STR, MATCH_ONE_STR};
+
+/// Storage of the retained messages, one message per topic name.
+///
+/// A PUBLISH with retain flag and empty payload removes the retained message
+/// of the topic, the caller should call `remove()` for it instead of
+/// `insert()`.
+pub trait RetainedStore<T> {
+    /// Insert or replace the retained message, return the old message.
+    fn insert(&mut self, topic_name: TopicName, message: T) -> Option<T>;
+
+    fn remove(&mut self, topic_name: &TopicName) -> Option<T>;
+
+    fn get(&self, topic_name: &TopicName) -> Option<&T>;
+
+    /// Find all retained messages matching the filter, to be sent for a new
+    /// subscription. Shared filters are matched by the `match_filter()` part.
+    ///
+    /// Topic names start with `$` are not matched by filters start with a
+    /// wildcard character ([MQTT 4.7.2]).
+    ///
+    /// [MQTT 4.7.2]: http://docs.oasis-open.org/mqtt/mqtt/v3.1.1/os/mqtt-v3.1.1-os.html#_Toc398718109
+    fn matches(&self, filter: &TopicFilter) -> Vec<(&TopicName, &T)>;
+
+    /// Number of retained messages.
+    fn len(&self) -> usize;
+
+    fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+/// An in-memory `RetainedStore`, a trie of topic names keyed by topic levels.
+#[derive(Debug, Clone)]
+pub struct RetainedTree<T> {
+    root: Node<T>,
+    len: usize,
+}
+
+#[derive(Debug, Clone)]
+struct Node<T> {
+    value: Option<(TopicName, T)>,
+    children: HashMap<String, Node<T>>,
+}
+
+impl<T> Default for Node<T> {
+    fn default() -> Self {
+        Node {
+            value: None,
+            children: HashMap::new(),
+        }
+    }
+}
+
+impl<T> Default for RetainedTree<T> {
+    fn default() -> Self {
+        RetainedTree {
+            root: Node::default(),
+            len: 0,
+        }
+    }
+}
+
+impl<T> RetainedTree<T> {
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+impl<T> RetainedStore<T> for RetainedTree<T> {
+    fn insert(&mut self, topic_name: TopicName, message: T) -> Option<T> {
+        let mut node = &mut self.root;
+        for level in topic_name.levels() {
+            node = node.children.entry(level.to_owned()).or_default();
+        }
+        let old = node.value.replace((topic_name, message));
+        if old.is_none() {
+            self.len += 1;
+        }
+        old.map(|(_, message)| message)
+    }
+
+    fn remove(&mut self, topic_name: &TopicName) -> Option<T> {
+        let levels: Vec<&str> = topic_name.levels().collect();
+        let message = self.root.remove(&levels);
+        if message.is_some() {
+            self.len -= 1;
+        }
+        message
+    }
+
+    fn get(&self, topic_name: &TopicName) -> Option<&T> {
+        let mut node = &self.root;
+        for level in topic_name.levels() {
+            node = node.children.get(level)?;
+        }
+        node.value.as_ref().map(|(_, message)| message)
+    }
+
+    fn matches(&self, filter: &TopicFilter) -> Vec<(&TopicName, &T)> {
+        let levels: Vec<&str> = filter.levels().collect();
+        let mut output = Vec::new();
+        self.root.collect_matches(&levels, true, &mut output);
+        output
+    }
+
+    fn len(&self) -> usize {
+        self.len
+    }
+}
+
+impl<T> Node<T> {
+    fn is_empty(&self) -> bool {
+        self.value.is_none() && self.children.is_empty()
+    }
+
+    fn remove(&mut self, levels: &[&str]) -> Option<T> {
+        match levels.split_first() {
+            Some((level, rest)) => {
+                let child = self.children.get_mut(*level)?;
+                let message = child.remove(rest);
+                if child.is_empty() {
+                    self.children.remove(*level);
+                }
+                message
+            }
+            None => self.value.take().map(|(_, message)| message),
+        }
+    }
+
+    /// The wildcards of the first level do not match the `$` topics.
+    fn wildcard_children(&self, is_root: bool) -> impl Iterator<Item = &Node<T>> {
+        self.children
+            .iter()
+            .filter(move |(level, _)| !(is_root && level.starts_with('$')))
+            .map(|(_, child)| child)
+    }
+
+    fn collect_all<'a>(&'a self, output: &mut Vec<(&'a TopicName, &'a T)>) {
+        output.extend(self.value.as_ref().map(|(name, message)| (name, message)));
+        for child in self.children.values() {
+            child.collect_all(output);
+        }
+    }
+
+    fn collect_matches<'a>(
+        &'a self,
+        levels: &[&str],
+        is_root: bool,
+        output: &mut Vec<(&'a TopicName, &'a T)>,
+    ) {
+        match levels.split_first() {
+            // "#" also matches the parent level
+            Some((&MATCH_ALL_STR, _)) => {
+                output.extend(self.value.as_ref().map(|(name, message)| (name, message)));
+                for child in self.wildcard_children(is_root) {
+                    child.collect_all(output);
+                }
+            }
+            Some((&MATCH_ONE_STR, rest)) => {
+                for child in self.wildcard_children(is_root) {
+                    child.collect_matches(rest, false, output);
+                }
+            }
+            Some((level, rest)) => {
+                if let Some(child) = self.children.get(*level) {
+                    child.collect_matches(rest, false, output);
+                }
+            }
+            None => output.extend(self.value.as_ref().map(|(name, message)| (name, message))),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn topic(value: &str) -> TopicName {
+        TopicName::try_from(value.to_owned()).unwrap()
+    }
+
+    fn matches(store: &RetainedTree<u32>, filter: &str) -> Vec<u32> {
+        let filter = TopicFilter::try_from(filter.to_owned()).unwrap();
+        let mut values: Vec<u32> = store
+            .matches(&filter)
+            .into_iter()
+            .map(|(_, v)| *v)
+            .collect();
+        values.sort_unstable();
+        values
+    }
+
+    #[test]
+    fn test_retained_tree_matches() {
+        let mut store = RetainedTree::new();
+        for (idx, value) in ["a", "a/b", "a/b/c", "a/", "x/b", "$SYS/info", "$SYS"]
+            .iter()
+            .enumerate()
+        {
+            assert_eq!(store.insert(topic(value), idx as u32), None);
+        }
+        assert_eq!(store.len(), 7);
+
+        assert_eq!(matches(&store, "a/b"), vec![1]);
+        assert_eq!(matches(&store, "a/#"), vec![0, 1, 2, 3]);
+        assert_eq!(matches(&store, "a/+"), vec![1, 3]);
+        assert_eq!(matches(&store, "+/b"), vec![1, 4]);
+        assert_eq!(matches(&store, "#"), vec![0, 1, 2, 3, 4]);
+        assert_eq!(matches(&store, "+"), vec![0]);
+        assert_eq!(matches(&store, "$SYS/#"), vec![5, 6]);
+        assert_eq!(matches(&store, "$SYS/+"), vec![5]);
+        assert_eq!(matches(&store, "$share/g/a/+"), vec![1, 3]);
+        assert_eq!(matches(&store, "b"), Vec::<u32>::new());
+
+        // Every filter matches the same messages as `TopicFilter::matches()`
+        for filter in ["a/b", "a/#", "a/+", "+/b", "#", "+", "$SYS/#", "+/+/+"] {
+            let filter = TopicFilter::try_from(filter.to_owned()).unwrap();
+            for (name, _) in store.matches(&filter) {
+                assert!(filter.matches(name), "{filter:?} {name:?}");
+            }
+        }
+    }
+
+    #[test]
+    fn test_retained_tree_insert_remove() {
+        let mut store = RetainedTree::new();
+        assert_eq!(store.insert(topic("a/b"), 1), None);
+        assert_eq!(store.insert(topic("a/b"), 2), Some(1));
+        assert_eq!(store.insert(topic("a"), 3), None);
+        assert_eq!(store.len(), 2);
+        assert_eq!(store.get(&topic("a/b")), Some(&2));
+        assert_eq!(store.get(&topic("a/c")), None);
+
+        assert_eq!(store.remove(&topic("a/c")), None);
+        assert_eq!(store.remove(&topic("a/b")), Some(2));
+        assert_eq!(store.remove(&topic("a/b")), None);
+        assert_eq!(matches(&store, "#"), vec![3]);
+        assert_eq!(store.remove(&topic("a")), Some(3));
+        assert!(store.is_empty());
+        assert!(store.root.is_empty());
+    }
+}
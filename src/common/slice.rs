@@ -0,0 +1,154 @@
+use simdutf8::basic::from_utf8;
+
+use crate::{Error, Pid, PollHeader};
+
+/// Decode the fixed header from the start of a slice, return the control
+/// byte, the remaining length and the header length.
+///
+/// If the slice ends in the middle of the header, it will return `Ok(None)`.
+#[inline]
+pub(crate) fn decode_raw_header_slice(data: &[u8]) -> Result<Option<(u8, u32, usize)>, Error> {
+    let Some((&typ, rest)) = data.split_first() else {
+        return Ok(None);
+    };
+    let mut var_int: u32 = 0;
+    for (i, byte) in rest.iter().enumerate() {
+        var_int |= (u32::from(*byte) & 0x7F) << (7 * i);
+        if byte & 0x80 == 0 {
+            return Ok(Some((typ, var_int, i + 2)));
+        } else if i >= 3 {
+            return Err(Error::InvalidVarByteInt);
+        }
+    }
+    Ok(None)
+}
+
+/// Decode the fixed header of the first packet in a slice, return the header
+/// and the header length.
+///
+/// If the slice does not contain the whole packet, it will return `Ok(None)`.
+#[inline]
+pub(crate) fn decode_frame<H>(data: &[u8]) -> Result<Option<(H, usize)>, H::Error>
+where
+    H: PollHeader,
+    H::Error: From<Error>,
+{
+    let Some((control_byte, remaining_len, header_len)) = decode_raw_header_slice(data)? else {
+        return Ok(None);
+    };
+    let header = H::new_with(control_byte, remaining_len)?;
+    if data.len() < header_len + header.remaining_len() {
+        return Ok(None);
+    }
+    Ok(Some((header, header_len)))
+}
+
+/// Decode a complete packet body, the body must be consumed exactly.
+#[inline]
+pub(crate) fn decode_exact<P, E: From<Error>>(
+    body: &[u8],
+    decode: impl FnOnce(&mut SliceReader<'_>) -> Result<P, E>,
+) -> Result<P, E> {
+    let mut reader = SliceReader(body);
+    let packet = decode(&mut reader)?;
+    if reader.0.is_empty() {
+        Ok(packet)
+    } else {
+        Err(Error::InvalidRemainingLength.into())
+    }
+}
+
+/// Reader of a packet body, the body is complete so running out of bytes
+/// means the remaining length is invalid.
+pub(crate) struct SliceReader<'a>(pub(crate) &'a [u8]);
+
+impl<'a> SliceReader<'a> {
+    #[inline]
+    pub(crate) fn take(&mut self, len: usize) -> Result<&'a [u8], Error> {
+        if self.0.len() < len {
+            return Err(Error::InvalidRemainingLength);
+        }
+        let (value, rest) = self.0.split_at(len);
+        self.0 = rest;
+        Ok(value)
+    }
+
+    #[inline]
+    pub(crate) fn read_u8(&mut self) -> Result<u8, Error> {
+        Ok(self.take(1)?[0])
+    }
+
+    #[inline]
+    pub(crate) fn read_u16(&mut self) -> Result<u16, Error> {
+        let value = self.take(2)?;
+        Ok(u16::from_be_bytes([value[0], value[1]]))
+    }
+
+    #[inline]
+    pub(crate) fn read_u32(&mut self) -> Result<u32, Error> {
+        let value = self.take(4)?;
+        Ok(u32::from_be_bytes([value[0], value[1], value[2], value[3]]))
+    }
+
+    /// Read a variable byte integer (4 bytes max).
+    #[inline]
+    pub(crate) fn read_var_int(&mut self) -> Result<u32, Error> {
+        let mut var_int: u32 = 0;
+        for i in 0..4 {
+            let byte = self.read_u8()?;
+            var_int |= (u32::from(byte) & 0x7F) << (7 * i);
+            if byte & 0x80 == 0 {
+                return Ok(var_int);
+            }
+        }
+        Err(Error::InvalidVarByteInt)
+    }
+
+    #[inline]
+    pub(crate) fn read_pid(&mut self) -> Result<Pid, Error> {
+        Pid::try_from(self.read_u16()?)
+    }
+
+    #[inline]
+    pub(crate) fn read_bytes(&mut self) -> Result<&'a [u8], Error> {
+        let len = self.read_u16()?;
+        self.take(len as usize)
+    }
+
+    #[inline]
+    pub(crate) fn read_str(&mut self) -> Result<&'a str, Error> {
+        from_utf8(self.read_bytes()?).map_err(|_| Error::InvalidString)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use futures_lite::future::block_on;
+
+    use super::*;
+    use crate::decode_raw_header_len;
+
+    #[test]
+    fn test_decode_raw_header_slice() {
+        for data in [
+            &[0x30, 0x00][..],
+            &[0x30, 0x7f, 0xff],
+            &[0x30, 0x80, 0x01],
+            &[0x30, 0xff, 0xff, 0xff, 0x7f, 0x00],
+        ] {
+            let (typ, remaining_len, bytes) =
+                block_on(decode_raw_header_len(&mut &data[..])).expect("async header");
+            assert_eq!(
+                decode_raw_header_slice(data).unwrap(),
+                Some((typ, remaining_len, bytes + 1))
+            );
+        }
+        assert_eq!(decode_raw_header_slice(&[]).unwrap(), None);
+        assert_eq!(decode_raw_header_slice(&[0x30]).unwrap(), None);
+        assert_eq!(decode_raw_header_slice(&[0x30, 0x80, 0x80]).unwrap(), None);
+        assert!(matches!(
+            decode_raw_header_slice(&[0x30, 0xff, 0xff, 0xff, 0xff, 0x01]),
+            Err(Error::InvalidVarByteInt)
+        ));
+    }
+}
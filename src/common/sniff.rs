@@ -0,0 +1,139 @@
+/// The protocol detected from the first bytes of a connection.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum SniffedProtocol {
+    /// MQTT CONNECT packet (any protocol level).
+    Mqtt,
+    /// TLS ClientHello record.
+    Tls,
+    /// HTTP request, e.g. the WebSocket upgrade request.
+    Http,
+    /// None of the above.
+    Unknown,
+}
+
+const TLS_PREFIX_LEN: usize = 6;
+const HTTP_METHODS: [&[u8]; 9] = [
+    b"GET ",
+    b"POST ",
+    b"PUT ",
+    b"HEAD ",
+    b"DELETE ",
+    b"OPTIONS ",
+    b"PATCH ",
+    b"CONNECT ",
+    b"TRACE ",
+];
+
+/// Classify the first bytes of a connection, so a listener can dispatch
+/// MQTT, MQTT over TLS and MQTT over WebSocket on a single port.
+///
+/// Return `None` if more bytes are needed, at most 13 bytes (for an MQTT
+/// CONNECT with "MQIsdp" protocol name) are inspected.
+pub fn sniff_first_bytes(bytes: &[u8]) -> Option<SniffedProtocol> {
+    let first = *bytes.first()?;
+    let protocol = match first {
+        0x10 => sniff_mqtt(bytes)?,
+        0x16 => {
+            if bytes.len() < TLS_PREFIX_LEN {
+                return None;
+            }
+            // Record type handshake, version 3.x, handshake type ClientHello
+            if bytes[1] == 0x03 && bytes[2] <= 0x04 && bytes[5] == 0x01 {
+                SniffedProtocol::Tls
+            } else {
+                SniffedProtocol::Unknown
+            }
+        }
+        _ => {
+            let mut incomplete = false;
+            for method in HTTP_METHODS {
+                match match_prefix(bytes, method) {
+                    Some(true) => return Some(SniffedProtocol::Http),
+                    Some(false) => {}
+                    None => incomplete = true,
+                }
+            }
+            if incomplete {
+                return None;
+            }
+            SniffedProtocol::Unknown
+        }
+    };
+    Some(protocol)
+}
+
+fn sniff_mqtt(bytes: &[u8]) -> Option<SniffedProtocol> {
+    // Skip the remaining length
+    let mut idx = 1;
+    loop {
+        let byte = *bytes.get(idx)?;
+        idx += 1;
+        if byte & 0x80 == 0 {
+            break;
+        }
+        if idx > 4 {
+            return Some(SniffedProtocol::Unknown);
+        }
+    }
+    let name = &bytes[idx..];
+    let mut incomplete = false;
+    for pattern in [&b"\x00\x04MQTT"[..], &b"\x00\x06MQIsdp"[..]] {
+        match match_prefix(name, pattern) {
+            Some(true) => return Some(SniffedProtocol::Mqtt),
+            Some(false) => {}
+            None => incomplete = true,
+        }
+    }
+    if incomplete {
+        None
+    } else {
+        Some(SniffedProtocol::Unknown)
+    }
+}
+
+/// Return `None` if `bytes` is a proper prefix of `pattern`.
+fn match_prefix(bytes: &[u8], pattern: &[u8]) -> Option<bool> {
+    if bytes.len() < pattern.len() {
+        if pattern.starts_with(bytes) {
+            None
+        } else {
+            Some(false)
+        }
+    } else {
+        Some(bytes.starts_with(pattern))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_sniff() {
+        let connect = b"\x10\x0c\x00\x04MQTT\x04\x02\x00\x3c";
+        assert_eq!(sniff_first_bytes(connect), Some(SniffedProtocol::Mqtt));
+        assert_eq!(sniff_first_bytes(&connect[..5]), None);
+        let connect = b"\x10\x82\x01\x00\x06MQIsdp\x03";
+        assert_eq!(sniff_first_bytes(connect), Some(SniffedProtocol::Mqtt));
+        assert_eq!(
+            sniff_first_bytes(b"\x10\x0c\x00\x04MQTX"),
+            Some(SniffedProtocol::Unknown)
+        );
+
+        let client_hello = [0x16, 0x03, 0x01, 0x02, 0x00, 0x01, 0x00];
+        assert_eq!(sniff_first_bytes(&client_hello), Some(SniffedProtocol::Tls));
+        assert_eq!(sniff_first_bytes(&client_hello[..3]), None);
+
+        assert_eq!(
+            sniff_first_bytes(b"GET /mqtt HTTP/1.1\r\n"),
+            Some(SniffedProtocol::Http)
+        );
+        assert_eq!(sniff_first_bytes(b"GE"), None);
+        assert_eq!(sniff_first_bytes(b"GEX"), Some(SniffedProtocol::Unknown));
+        assert_eq!(sniff_first_bytes(b""), None);
+        assert_eq!(
+            sniff_first_bytes(b"\xC0\x00"),
+            Some(SniffedProtocol::Unknown)
+        );
+    }
+}
@@ -0,0 +1,77 @@
+use std::sync::atomic::{AtomicU64, Ordering};
+
+/// Hooks to collect codec metrics, see `GenericPollPacket::with_stats()` and
+/// `GenericPollEncode::with_stats()`.
+///
+/// The `packet_type` argument is the 4 bits packet type in the fixed header
+/// (1 for CONNECT, 2 for CONNACK, ..., 15 for AUTH), the `size` argument is
+/// the packet total size (include header). All methods do nothing by default.
+pub trait CodecStats: Send + Sync {
+    /// A packet is decoded.
+    fn on_decode(&self, _packet_type: u8, _size: usize) {}
+
+    /// A packet is encoded and fully written.
+    fn on_encode(&self, _packet_type: u8, _size: usize) {}
+
+    /// A malformed packet (or a packet exceeding the limits) is received,
+    /// I/O errors are not counted.
+    fn on_malformed(&self) {}
+}
+
+/// Lock-free counters implementing [`CodecStats`], share one per listener
+/// and export the values periodically.
+#[derive(Debug, Default)]
+pub struct CodecCounters {
+    decoded: [AtomicU64; 16],
+    encoded: [AtomicU64; 16],
+    bytes_in: AtomicU64,
+    bytes_out: AtomicU64,
+    malformed: AtomicU64,
+}
+
+impl CodecCounters {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Number of decoded packets of the packet type.
+    pub fn decoded(&self, packet_type: u8) -> u64 {
+        self.decoded[packet_type as usize & 0x0F].load(Ordering::Relaxed)
+    }
+
+    /// Number of encoded packets of the packet type.
+    pub fn encoded(&self, packet_type: u8) -> u64 {
+        self.encoded[packet_type as usize & 0x0F].load(Ordering::Relaxed)
+    }
+
+    /// Total size of the decoded packets.
+    pub fn bytes_in(&self) -> u64 {
+        self.bytes_in.load(Ordering::Relaxed)
+    }
+
+    /// Total size of the encoded packets.
+    pub fn bytes_out(&self) -> u64 {
+        self.bytes_out.load(Ordering::Relaxed)
+    }
+
+    /// Number of malformed packets.
+    pub fn malformed(&self) -> u64 {
+        self.malformed.load(Ordering::Relaxed)
+    }
+}
+
+impl CodecStats for CodecCounters {
+    fn on_decode(&self, packet_type: u8, size: usize) {
+        self.decoded[packet_type as usize & 0x0F].fetch_add(1, Ordering::Relaxed);
+        self.bytes_in.fetch_add(size as u64, Ordering::Relaxed);
+    }
+
+    fn on_encode(&self, packet_type: u8, size: usize) {
+        self.encoded[packet_type as usize & 0x0F].fetch_add(1, Ordering::Relaxed);
+        self.bytes_out.fetch_add(size as u64, Ordering::Relaxed);
+    }
+
+    fn on_malformed(&self) {
+        self.malformed.fetch_add(1, Ordering::Relaxed);
+    }
+}
@@ -0,0 +1,69 @@
+//! `proptest` strategies of the common types.
+//!
+//! All the generated values are valid, the packet strategies are in
+//! `v3::strategy` and `v5::strategy`.
+
+use bytes::Bytes;
+use proptest::prelude::*;
+
+use crate::{Pid, QoS, QosPid, SharedStr, TopicFilter, TopicName, SHARED_PREFIX};
+
+/// Any valid packet identifier.
+pub fn pid() -> impl Strategy<Value = Pid> {
+    (1..=u16::MAX).prop_map(|value| Pid::try_from(value).expect("non-zero pid"))
+}
+
+pub fn qos() -> impl Strategy<Value = QoS> {
+    prop_oneof![Just(QoS::Level0), Just(QoS::Level1), Just(QoS::Level2)]
+}
+
+pub fn qos_pid() -> impl Strategy<Value = QosPid> {
+    prop_oneof![
+        Just(QosPid::Level0),
+        pid().prop_map(QosPid::Level1),
+        pid().prop_map(QosPid::Level2),
+    ]
+}
+
+/// Non-empty topic names of up to 4 levels.
+pub fn topic_name() -> impl Strategy<Value = TopicName> {
+    prop::collection::vec("[a-zA-Z0-9_-]{0,6}", 1..=4)
+        .prop_map(|levels| levels.join("/"))
+        .prop_filter("empty topic name", |name| !name.is_empty())
+        .prop_map(|name| TopicName::try_from(name).expect("valid topic name"))
+}
+
+/// Topic filters of up to 4 levels, with wildcards and shared subscriptions.
+pub fn topic_filter() -> impl Strategy<Value = TopicFilter> {
+    let level = prop_oneof![
+        3 => "[a-zA-Z0-9_-]{0,6}",
+        1 => Just("+".to_owned()),
+    ];
+    (
+        prop::option::weighted(0.2, "[a-z0-9]{1,6}"),
+        prop::collection::vec(level, 1..=4),
+        any::<bool>(),
+    )
+        .prop_map(|(group, mut levels, match_all)| {
+            if match_all {
+                levels.push("#".to_owned());
+            }
+            (group, levels.join("/"))
+        })
+        // The filter part of a shared subscription can not be empty either
+        .prop_filter("empty topic filter", |(_, filter)| !filter.is_empty())
+        .prop_map(|(group, filter)| match group {
+            Some(group) => format!("{SHARED_PREFIX}{group}/{filter}"),
+            None => filter,
+        })
+        .prop_map(|filter| TopicFilter::try_from(filter).expect("valid topic filter"))
+}
+
+/// Client identifiers allowed by every server ([MQTT-3.1.3-5]).
+pub fn client_id() -> impl Strategy<Value = SharedStr> {
+    "[a-zA-Z0-9]{0,23}".prop_map(SharedStr::from)
+}
+
+pub fn payload() -> impl Strategy<Value = Bytes> {
+    prop::collection::vec(any::<u8>(), 0..64).prop_map(Bytes::from)
+}
@@ -0,0 +1,209 @@
+use std::future::Future;
+use std::io;
+use std::marker::PhantomData;
+use std::pin::Pin;
+use std::sync::Arc;
+use std::task::{Context, Poll};
+
+use futures_lite::{ready, Stream};
+use futures_sink::Sink;
+use tokio::io::{AsyncRead, AsyncWrite};
+
+use crate::{
+    DecodeLimits, Error, GenericPollEncode, GenericPollPacket, GenericPollPacketState,
+    PollEncodeState, PollHeader,
+};
+
+/// A packet which can be written by `GenericPacketSink`.
+///
+/// It is also implemented for `&P` and `Arc<P>`, so a shared packet can be
+/// sent to many sinks (such as the subscribers of a publish) without cloning.
+pub trait PollEncodable {
+    fn encode_poll_state(&self) -> Result<PollEncodeState, Error>;
+}
+
+impl<P: PollEncodable + ?Sized> PollEncodable for &P {
+    fn encode_poll_state(&self) -> Result<PollEncodeState, Error> {
+        (**self).encode_poll_state()
+    }
+}
+
+impl<P: PollEncodable + ?Sized> PollEncodable for Arc<P> {
+    fn encode_poll_state(&self) -> Result<PollEncodeState, Error> {
+        (**self).encode_poll_state()
+    }
+}
+
+/// Decode packets from an `AsyncRead` as a `Stream`.
+///
+/// The stream ends when the reader reaches EOF between two packets, EOF in
+/// the middle of a packet is an error. The stream also ends after the first
+/// error.
+pub struct GenericPacketStream<R, H> {
+    reader: R,
+    state: GenericPollPacketState<H>,
+    limits: Option<DecodeLimits>,
+    max_packet_size: Option<u32>,
+    done: bool,
+}
+
+impl<R, H> GenericPacketStream<R, H> {
+    pub fn new(reader: R) -> Self {
+        GenericPacketStream {
+            reader,
+            state: GenericPollPacketState::default(),
+            limits: None,
+            max_packet_size: None,
+            done: false,
+        }
+    }
+
+    /// Same as `new()`, but the decoded packets must not exceed the limits.
+    pub fn with_limits(reader: R, limits: DecodeLimits) -> Self {
+        GenericPacketStream {
+            reader,
+            state: GenericPollPacketState::default(),
+            limits: Some(limits),
+            max_packet_size: None,
+            done: false,
+        }
+    }
+
+    /// Reject packets larger than `max_packet_size` (include header), see
+    /// `GenericPollPacket::with_max_packet_size()`.
+    pub fn with_max_packet_size(mut self, max_packet_size: u32) -> Self {
+        self.max_packet_size = Some(max_packet_size);
+        self
+    }
+
+    pub fn get_ref(&self) -> &R {
+        &self.reader
+    }
+
+    pub fn get_mut(&mut self) -> &mut R {
+        &mut self.reader
+    }
+
+    /// Take the reader, a partially received packet is dropped.
+    pub fn into_inner(self) -> R {
+        self.reader
+    }
+}
+
+impl<R, H> Stream for GenericPacketStream<R, H>
+where
+    R: AsyncRead + Unpin,
+    H: PollHeader + Copy + Unpin,
+    H::Error: From<io::Error> + From<Error>,
+{
+    type Item = Result<H::Packet, H::Error>;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        let this = self.get_mut();
+        if this.done {
+            return Poll::Ready(None);
+        }
+        let mut fut = GenericPollPacket::new(&mut this.state, &mut this.reader);
+        if let Some(limits) = this.limits {
+            fut = fut.with_limits(limits);
+        }
+        if let Some(max_packet_size) = this.max_packet_size {
+            fut = fut.with_max_packet_size(max_packet_size);
+        }
+        let result = ready!(Pin::new(&mut fut).poll(cx));
+        // No byte of the next packet is received
+        let at_boundary = matches!(
+            &this.state,
+            GenericPollPacketState::Header(state) if state.control_byte.is_none()
+        );
+        this.state = GenericPollPacketState::default();
+        match result {
+            Ok((_, _, packet)) => Poll::Ready(Some(Ok(packet))),
+            Err(err) => {
+                this.done = true;
+                if at_boundary && H::is_eof_error(&err) {
+                    Poll::Ready(None)
+                } else {
+                    Poll::Ready(Some(Err(err)))
+                }
+            }
+        }
+    }
+}
+
+/// Encode packets to an `AsyncWrite` as a `Sink`.
+///
+/// Only one packet is buffered, `poll_ready()` writes the pending packet
+/// before the next one is accepted.
+pub struct GenericPacketSink<W, P> {
+    writer: W,
+    state: Option<PollEncodeState>,
+    _packet: PhantomData<fn(P)>,
+}
+
+impl<W, P> GenericPacketSink<W, P> {
+    pub fn new(writer: W) -> Self {
+        GenericPacketSink {
+            writer,
+            state: None,
+            _packet: PhantomData,
+        }
+    }
+
+    pub fn get_ref(&self) -> &W {
+        &self.writer
+    }
+
+    pub fn get_mut(&mut self) -> &mut W {
+        &mut self.writer
+    }
+
+    /// Take the writer, a partially written packet is dropped.
+    pub fn into_inner(self) -> W {
+        self.writer
+    }
+}
+
+impl<W: AsyncWrite + Unpin, P> GenericPacketSink<W, P> {
+    fn poll_write_pending(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Error>> {
+        if let Some(state) = self.state.as_mut() {
+            ready!(Pin::new(&mut GenericPollEncode::new(state, &mut self.writer)).poll(cx))?;
+            self.state = None;
+        }
+        Poll::Ready(Ok(()))
+    }
+}
+
+impl<W, P> Sink<P> for GenericPacketSink<W, P>
+where
+    W: AsyncWrite + Unpin,
+    P: PollEncodable,
+{
+    type Error = Error;
+
+    fn poll_ready(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), Error>> {
+        self.get_mut().poll_write_pending(cx)
+    }
+
+    fn start_send(self: Pin<&mut Self>, packet: P) -> Result<(), Error> {
+        let this = self.get_mut();
+        debug_assert!(this.state.is_none(), "poll_ready() must be called first");
+        this.state = Some(packet.encode_poll_state()?);
+        Ok(())
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), Error>> {
+        let this = self.get_mut();
+        ready!(this.poll_write_pending(cx))?;
+        Pin::new(&mut this.writer)
+            .poll_flush(cx)
+            .map_err(Into::into)
+    }
+
+    fn poll_close(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), Error>> {
+        ready!(self.as_mut().poll_flush(cx))?;
+        Pin::new(&mut self.get_mut().writer)
+            .poll_shutdown(cx)
+            .map_err(Into::into)
+    }
+}
@@ -0,0 +1,218 @@
+use std::collections::HashMap;
+use std::mem;
+
+use super::{TopicFilter, TopicName, MATCH_ALL_STR, MATCH_ONE_STR};
+
+/// A trie of topic filters keyed by topic levels.
+///
+/// Shared filters (`$share/{group}/{filter}`) are indexed by the filter part,
+/// so they are matched like normal filters. Every filter is associated with a
+/// value, such as the subscriber.
+#[derive(Debug, Clone)]
+pub struct TopicTree<T> {
+    root: Node<T>,
+    len: usize,
+}
+
+#[derive(Debug, Clone)]
+struct Node<T> {
+    values: Vec<(TopicFilter, T)>,
+    children: HashMap<String, Node<T>>,
+}
+
+impl<T> Default for Node<T> {
+    fn default() -> Self {
+        Node {
+            values: Vec::new(),
+            children: HashMap::new(),
+        }
+    }
+}
+
+impl<T> Default for TopicTree<T> {
+    fn default() -> Self {
+        TopicTree {
+            root: Node::default(),
+            len: 0,
+        }
+    }
+}
+
+impl<T> TopicTree<T> {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Number of filters in the tree.
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// Insert a filter, return the old value if the filter already exists.
+    pub fn insert(&mut self, filter: TopicFilter, value: T) -> Option<T> {
+        let mut node = &mut self.root;
+        for level in filter.levels() {
+            node = node.children.entry(level.to_owned()).or_default();
+        }
+        if let Some((_, old)) = node.values.iter_mut().find(|(f, _)| f == &filter) {
+            return Some(mem::replace(old, value));
+        }
+        node.values.push((filter, value));
+        self.len += 1;
+        None
+    }
+
+    /// Remove a filter, return its value if the filter exists.
+    pub fn remove(&mut self, filter: &TopicFilter) -> Option<T> {
+        let levels: Vec<&str> = filter.levels().collect();
+        let value = self.root.remove(&levels, filter);
+        if value.is_some() {
+            self.len -= 1;
+        }
+        value
+    }
+
+    pub fn get(&self, filter: &TopicFilter) -> Option<&T> {
+        let mut node = &self.root;
+        for level in filter.levels() {
+            node = node.children.get(level)?;
+        }
+        node.values
+            .iter()
+            .find(|(f, _)| f == filter)
+            .map(|(_, value)| value)
+    }
+
+    /// Find all filters matching the topic name.
+    ///
+    /// Topic names start with `$` are not matched by filters start with a
+    /// wildcard character ([MQTT 4.7.2]).
+    ///
+    /// [MQTT 4.7.2]: http://docs.oasis-open.org/mqtt/mqtt/v3.1.1/os/mqtt-v3.1.1-os.html#_Toc398718109
+    pub fn matches(&self, topic_name: &TopicName) -> Vec<(&TopicFilter, &T)> {
+        let levels: Vec<&str> = topic_name.levels().collect();
+        let mut output = Vec::new();
+        self.root
+            .collect_matches(&levels, !topic_name.starts_with('$'), &mut output);
+        output
+    }
+}
+
+impl<T> Node<T> {
+    fn is_empty(&self) -> bool {
+        self.values.is_empty() && self.children.is_empty()
+    }
+
+    fn remove(&mut self, levels: &[&str], filter: &TopicFilter) -> Option<T> {
+        match levels.split_first() {
+            Some((level, rest)) => {
+                let child = self.children.get_mut(*level)?;
+                let value = child.remove(rest, filter);
+                if child.is_empty() {
+                    self.children.remove(*level);
+                }
+                value
+            }
+            None => {
+                let idx = self.values.iter().position(|(f, _)| f == filter)?;
+                Some(self.values.swap_remove(idx).1)
+            }
+        }
+    }
+
+    fn collect_matches<'a>(
+        &'a self,
+        levels: &[&str],
+        allow_wildcard: bool,
+        output: &mut Vec<(&'a TopicFilter, &'a T)>,
+    ) {
+        if allow_wildcard {
+            // "#" also matches the parent level
+            if let Some(child) = self.children.get(MATCH_ALL_STR) {
+                output.extend(child.values.iter().map(|(f, v)| (f, v)));
+            }
+        }
+        match levels.split_first() {
+            Some((level, rest)) => {
+                if let Some(child) = self.children.get(*level) {
+                    child.collect_matches(rest, true, output);
+                }
+                if allow_wildcard {
+                    if let Some(child) = self.children.get(MATCH_ONE_STR) {
+                        child.collect_matches(rest, true, output);
+                    }
+                }
+            }
+            None => output.extend(self.values.iter().map(|(f, v)| (f, v))),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn filter(value: &str) -> TopicFilter {
+        TopicFilter::try_from(value.to_owned()).unwrap()
+    }
+
+    fn matches(tree: &TopicTree<u32>, topic: &str) -> Vec<u32> {
+        let topic = TopicName::try_from(topic.to_owned()).unwrap();
+        let mut values: Vec<u32> = tree.matches(&topic).into_iter().map(|(_, v)| *v).collect();
+        values.sort_unstable();
+        values
+    }
+
+    #[test]
+    fn test_topic_tree_matches() {
+        let mut tree = TopicTree::new();
+        for (idx, value) in [
+            "a/b",
+            "a/+",
+            "a/#",
+            "#",
+            "+/b",
+            "+",
+            "a/b/c",
+            "$SYS/#",
+            "$share/g/a/b",
+        ]
+        .iter()
+        .enumerate()
+        {
+            assert_eq!(tree.insert(filter(value), idx as u32), None);
+        }
+        assert_eq!(tree.len(), 9);
+
+        assert_eq!(matches(&tree, "a/b"), vec![0, 1, 2, 3, 4, 8]);
+        assert_eq!(matches(&tree, "a"), vec![2, 3, 5]);
+        assert_eq!(matches(&tree, "a/b/c"), vec![2, 3, 6]);
+        assert_eq!(matches(&tree, "a/"), vec![1, 2, 3]);
+        assert_eq!(matches(&tree, "x/b"), vec![3, 4]);
+        assert_eq!(matches(&tree, "$SYS/info"), vec![7]);
+        assert_eq!(matches(&tree, "$SYS"), vec![7]);
+    }
+
+    #[test]
+    fn test_topic_tree_insert_remove() {
+        let mut tree = TopicTree::new();
+        assert_eq!(tree.insert(filter("a/+/c"), 1), None);
+        assert_eq!(tree.insert(filter("a/+/c"), 2), Some(1));
+        assert_eq!(tree.insert(filter("$share/g/a/+/c"), 3), None);
+        assert_eq!(tree.len(), 2);
+        assert_eq!(tree.get(&filter("a/+/c")), Some(&2));
+        assert_eq!(tree.get(&filter("a/+")), None);
+
+        assert_eq!(tree.remove(&filter("a/+")), None);
+        assert_eq!(tree.remove(&filter("a/+/c")), Some(2));
+        assert_eq!(tree.remove(&filter("a/+/c")), None);
+        assert_eq!(matches(&tree, "a/b/c"), vec![3]);
+        assert_eq!(tree.remove(&filter("$share/g/a/+/c")), Some(3));
+        assert!(tree.is_empty());
+        assert!(tree.root.is_empty());
+    }
+}
@@ -1,3 +1,4 @@
+use std::borrow::Borrow;
 use std::cmp::{Eq, Ord, Ordering, PartialEq, PartialOrd};
 use std::convert::TryFrom;
 use std::fmt;
@@ -5,16 +6,23 @@ use std::hash::{Hash, Hasher};
 use std::io;
 use std::ops::Deref;
 use std::slice;
+use std::str;
 use std::sync::Arc;
 
+use bytes::Bytes;
 use simdutf8::basic::from_utf8;
 use tokio::io::AsyncRead;
 
-use super::{read_bytes, read_u8};
-use crate::{Error, LEVEL_SEP, MATCH_ALL_CHAR, MATCH_ONE_CHAR, SHARED_PREFIX, SYS_PREFIX};
+use super::{check_utf8_strict, read_bytes, read_u8};
+use crate::{
+    Error, LEVEL_SEP, MATCH_ALL_CHAR, MATCH_ALL_STR, MATCH_ONE_CHAR, MATCH_ONE_STR, SHARED_PREFIX,
+    SYS_PREFIX,
+};
 
 pub const MQISDP: &[u8] = b"MQIsdp";
 pub const MQTT: &[u8] = b"MQTT";
+/// The bit of the protocol level set by bridges, see `Protocol::new_bridge()`.
+pub(crate) const BRIDGE_BIT: u8 = 0x80;
 
 /// The ability of encoding type into `io::Write`, and calculating encoded size.
 pub trait Encodable {
@@ -24,9 +32,30 @@ pub trait Encodable {
     fn encode_len(&self) -> usize;
 }
 
+impl<T: Encodable + ?Sized> Encodable for &T {
+    fn encode<W: io::Write>(&self, writer: &mut W) -> io::Result<()> {
+        (**self).encode(writer)
+    }
+
+    fn encode_len(&self) -> usize {
+        (**self).encode_len()
+    }
+}
+
+impl<T: Encodable + ?Sized> Encodable for Arc<T> {
+    fn encode<W: io::Write>(&self, writer: &mut W) -> io::Result<()> {
+        (**self).encode(writer)
+    }
+
+    fn encode_len(&self) -> usize {
+        (**self).encode_len()
+    }
+}
+
 /// Protocol version.
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
 #[cfg_attr(feature = "arbitrary", derive(arbitrary::Arbitrary))]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum Protocol {
     /// [MQTT 3.1]
     ///
@@ -57,6 +86,20 @@ impl Protocol {
         }
     }
 
+    /// Same as `new()`, but the protocol levels with the bridge bit (0x83
+    /// and 0x84, sent by Mosquitto style bridges) are also accepted. Return
+    /// the protocol and whether the bridge bit is set.
+    pub fn new_bridge(name: &[u8], level: u8) -> Result<(Protocol, bool), Error> {
+        if level & BRIDGE_BIT != 0 {
+            if let Ok(protocol @ (Protocol::V310 | Protocol::V311)) =
+                Protocol::new(name, level & !BRIDGE_BIT)
+            {
+                return Ok((protocol, true));
+            }
+        }
+        Protocol::new(name, level).map(|protocol| (protocol, false))
+    }
+
     pub fn to_pair(self) -> (&'static [u8], u8) {
         match self {
             Self::V310 => (MQISDP, 3),
@@ -104,6 +147,11 @@ impl Encodable for Protocol {
 /// Packet identifier
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Ord, PartialOrd)]
 #[cfg_attr(feature = "arbitrary", derive(arbitrary::Arbitrary))]
+#[cfg_attr(
+    feature = "serde",
+    derive(serde::Serialize, serde::Deserialize),
+    serde(try_from = "u16")
+)]
 pub struct Pid(u16);
 
 impl Pid {
@@ -155,7 +203,7 @@ impl core::ops::Sub<u16> for Pid {
     /// Subing a `u16` to a `Pid` will wrap around and avoid 0.
     fn sub(self, u: u16) -> Pid {
         let n = match self.0.overflowing_sub(u) {
-            (0, _) => core::u16::MAX,
+            (0, _) => u16::MAX,
             (n, false) => n,
             (n, true) => n - 1,
         };
@@ -175,6 +223,7 @@ impl core::ops::SubAssign<u16> for Pid {
 #[repr(u8)]
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
 #[cfg_attr(feature = "arbitrary", derive(arbitrary::Arbitrary))]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum QoS {
     /// `QoS 0`. At most once. No ack needed.
     Level0 = 0,
@@ -195,6 +244,15 @@ impl QoS {
     }
 }
 
+/// The QoS to forward a message to a subscriber: the minimum of the QoS of
+/// the PUBLISH and the maximum QoS granted to the subscription. The same as
+/// `publish_qos.min(max_qos)`. See [MQTT 3.8.4].
+///
+/// [MQTT 3.8.4]: https://docs.oasis-open.org/mqtt/mqtt/v5.0/os/mqtt-v5.0-os.html#_Toc3901175
+pub fn effective_qos(publish_qos: QoS, max_qos: QoS) -> QoS {
+    publish_qos.min(max_qos)
+}
+
 /// Combined [`QoS`] and [`Pid`].
 ///
 /// Used only in [`Publish`] packets.
@@ -204,6 +262,7 @@ impl QoS {
 /// [`Pid`]: struct.Pid.html
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
 #[cfg_attr(feature = "arbitrary", derive(arbitrary::Arbitrary))]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum QosPid {
     Level0,
     Level1(Pid),
@@ -232,29 +291,219 @@ impl QosPid {
             QosPid::Level2(_) => QoS::Level2,
         }
     }
+
+    /// The `QosPid` to forward a received PUBLISH to a subscription with
+    /// `max_qos`, the QoS is downgraded by [`effective_qos`]. The packet
+    /// identifier belongs to the session of the sender, so a new one is
+    /// allocated by `alloc_pid` when the forwarded QoS is 1 or 2.
+    pub fn forward<E>(
+        self,
+        max_qos: QoS,
+        alloc_pid: impl FnOnce() -> Result<Pid, E>,
+    ) -> Result<QosPid, E> {
+        Ok(match effective_qos(self.qos(), max_qos) {
+            QoS::Level0 => QosPid::Level0,
+            QoS::Level1 => QosPid::Level1(alloc_pid()?),
+            QoS::Level2 => QosPid::Level2(alloc_pid()?),
+        })
+    }
+}
+
+/// An immutable UTF-8 string backed by `Bytes`, used for the client
+/// identifier, username, topic names and topic filters.
+///
+/// Cloning it only increases a reference count, and a `&'static str` (such as
+/// a string literal) is used without allocation.
+#[derive(Clone, Default, PartialEq, Eq, PartialOrd, Ord)]
+pub struct SharedStr(Bytes);
+
+impl SharedStr {
+    /// Create from a static string without allocation.
+    pub const fn from_static(value: &'static str) -> Self {
+        SharedStr(Bytes::from_static(value.as_bytes()))
+    }
+
+    /// Create from UTF-8 bytes, the bytes are shared instead of copied.
+    pub fn from_utf8(value: Bytes) -> Result<Self, Error> {
+        from_utf8(&value).map_err(|_| Error::InvalidString)?;
+        Ok(SharedStr(value))
+    }
+
+    pub fn as_str(&self) -> &str {
+        // SAFETY: the bytes are checked or built from a `str`
+        unsafe { str::from_utf8_unchecked(&self.0) }
+    }
+
+    pub fn into_bytes(self) -> Bytes {
+        self.0
+    }
+
+    /// If the string memory is not shared with any other value.
+    pub(crate) fn is_unique(&self) -> bool {
+        self.0.is_unique()
+    }
+}
+
+impl From<&'static str> for SharedStr {
+    fn from(value: &'static str) -> Self {
+        Self::from_static(value)
+    }
+}
+
+impl From<String> for SharedStr {
+    fn from(value: String) -> Self {
+        SharedStr(Bytes::from(value.into_bytes()))
+    }
+}
+
+impl Deref for SharedStr {
+    type Target = str;
+    fn deref(&self) -> &str {
+        self.as_str()
+    }
+}
+
+impl AsRef<str> for SharedStr {
+    fn as_ref(&self) -> &str {
+        self.as_str()
+    }
+}
+
+impl Borrow<str> for SharedStr {
+    fn borrow(&self) -> &str {
+        self.as_str()
+    }
+}
+
+impl Hash for SharedStr {
+    // Same as `str`, as required by `Borrow<str>`
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.as_str().hash(state);
+    }
+}
+
+impl PartialEq<str> for SharedStr {
+    fn eq(&self, other: &str) -> bool {
+        self.as_str() == other
+    }
+}
+
+impl PartialEq<&str> for SharedStr {
+    fn eq(&self, other: &&str) -> bool {
+        self.as_str() == *other
+    }
+}
+
+impl fmt::Debug for SharedStr {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Debug::fmt(self.as_str(), f)
+    }
+}
+
+impl fmt::Display for SharedStr {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Display::fmt(self.as_str(), f)
+    }
+}
+
+#[cfg(feature = "serde")]
+impl serde::Serialize for SharedStr {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(self.as_str())
+    }
+}
+
+#[cfg(feature = "serde")]
+impl<'de> serde::Deserialize<'de> for SharedStr {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        String::deserialize(deserializer).map(Self::from)
+    }
+}
+
+#[cfg(feature = "arbitrary")]
+impl<'a> arbitrary::Arbitrary<'a> for SharedStr {
+    fn arbitrary(u: &mut arbitrary::Unstructured<'a>) -> arbitrary::Result<Self> {
+        String::arbitrary(u).map(Self::from)
+    }
 }
 
 /// Topic name.
 ///
-/// See [MQTT 4.7]. The internal value is `Arc<String>`.
+/// See [MQTT 4.7]. The internal value is `SharedStr`.
 ///
 /// [MQTT 4.7]: http://docs.oasis-open.org/mqtt/mqtt/v3.1.1/os/mqtt-v3.1.1-os.html#_Toc398718106
 #[derive(Debug, Clone, PartialEq, Eq, Hash, PartialOrd, Ord)]
 #[cfg_attr(feature = "arbitrary", derive(arbitrary::Arbitrary))]
-pub struct TopicName(Arc<String>);
+#[cfg_attr(
+    feature = "serde",
+    derive(serde::Serialize, serde::Deserialize),
+    serde(try_from = "String")
+)]
+pub struct TopicName(SharedStr);
 
 impl TopicName {
     /// Check if the topic name is invalid.
-    pub fn is_invalid(value: &str) -> bool {
-        if value.len() > u16::max_value() as usize {
+    pub const fn is_invalid(value: &str) -> bool {
+        let bytes = value.as_bytes();
+        if bytes.len() > u16::MAX as usize {
             return true;
         }
-        value.contains(|c| c == MATCH_ONE_CHAR || c == MATCH_ALL_CHAR || c == '\0')
+        let mut idx = 0;
+        while idx < bytes.len() {
+            let c = bytes[idx];
+            if c == MATCH_ONE_CHAR as u8 || c == MATCH_ALL_CHAR as u8 || c == b'\0' {
+                return true;
+            }
+            idx += 1;
+        }
+        false
+    }
+
+    /// Create a topic name and also reject the characters forbidden by
+    /// `check_utf8_strict()`.
+    pub fn try_from_strict(value: String) -> Result<Self, Error> {
+        check_utf8_strict(&value)?;
+        Self::try_from(value)
+    }
+
+    /// Create a topic name by joining the levels with `/`. A level containing
+    /// `+`, `#`, `\0` or `/` is reported as `Error::InvalidTopicLevel`, a
+    /// topic name longer than 65,535 bytes as `Error::InvalidTopicName`.
+    pub fn from_levels<I, S>(levels: I) -> Result<Self, Error>
+    where
+        I: IntoIterator<Item = S>,
+        S: AsRef<str>,
+    {
+        let mut value = String::new();
+        for (index, level) in levels.into_iter().enumerate() {
+            let level = level.as_ref();
+            if level.contains([MATCH_ONE_CHAR, MATCH_ALL_CHAR, '\0', LEVEL_SEP]) {
+                return Err(Error::InvalidTopicLevel {
+                    index,
+                    level: level.to_owned(),
+                });
+            }
+            if index > 0 {
+                value.push(LEVEL_SEP);
+            }
+            value.push_str(level);
+        }
+        Self::try_from(value)
     }
 
     pub fn is_shared(&self) -> bool {
         self.0.starts_with(SHARED_PREFIX)
     }
+
+    /// The levels separated by `/`, an empty level is yielded for a leading,
+    /// trailing or repeated separator (e.g. `"a//b"` has 3 levels).
+    pub fn levels(&self) -> str::Split<'_, char> {
+        self.0.split(LEVEL_SEP)
+    }
+
+    pub(crate) fn is_unique(&self) -> bool {
+        self.0.is_unique()
+    }
     pub fn is_sys(&self) -> bool {
         self.0.starts_with(SYS_PREFIX)
     }
@@ -272,11 +521,28 @@ impl TryFrom<String> for TopicName {
         if TopicName::is_invalid(value.as_str()) {
             Err(Error::InvalidTopicName(value))
         } else {
-            Ok(TopicName(Arc::new(value)))
+            Ok(TopicName(value.into()))
+        }
+    }
+}
+
+impl TryFrom<&'static str> for TopicName {
+    type Error = Error;
+    fn try_from(value: &'static str) -> Result<Self, Error> {
+        if TopicName::is_invalid(value) {
+            Err(Error::InvalidTopicName(value.to_owned()))
+        } else {
+            Ok(TopicName(value.into()))
         }
     }
 }
 
+impl From<TopicName> for SharedStr {
+    fn from(value: TopicName) -> Self {
+        value.0
+    }
+}
+
 impl Deref for TopicName {
     type Target = str;
     fn deref(&self) -> &str {
@@ -286,7 +552,7 @@ impl Deref for TopicName {
 
 /// Topic filter.
 ///
-/// See [MQTT 4.7]. The internal value is `Arc<String>` and a cache value for
+/// See [MQTT 4.7]. The internal value is `SharedStr` and a cache value for
 /// where shared filter byte index started. The traits:
 /// `Hash`/`Ord`/`PartialOrd`/`Eq`/`PartialEq` are all manually implemented for
 /// only contains the string value.
@@ -294,8 +560,13 @@ impl Deref for TopicName {
 /// [MQTT 4.7]: http://docs.oasis-open.org/mqtt/mqtt/v3.1.1/os/mqtt-v3.1.1-os.html#_Toc398718106
 #[derive(Debug, Clone)]
 #[cfg_attr(feature = "arbitrary", derive(arbitrary::Arbitrary))]
+#[cfg_attr(
+    feature = "serde",
+    derive(serde::Deserialize),
+    serde(try_from = "String")
+)]
 pub struct TopicFilter {
-    inner: Arc<String>,
+    inner: SharedStr,
     shared_filter_sep: u16,
 }
 
@@ -303,27 +574,31 @@ impl TopicFilter {
     /// Check if the topic filter is invalid.
     ///
     ///   * The u16 returned is where the bytes index of '/' char before shared topic filter
-    pub fn is_invalid(value: &str) -> (bool, u16) {
-        if value.len() > u16::max_value() as usize {
+    pub const fn is_invalid(value: &str) -> (bool, u16) {
+        let bytes = value.as_bytes();
+        if bytes.len() > u16::MAX as usize {
             return (true, 0);
         }
 
-        const SHARED_PREFIX_CHARS: [char; 7] = ['$', 's', 'h', 'a', 'r', 'e', '/'];
+        const SHARED_PREFIX_BYTES: &[u8] = SHARED_PREFIX.as_bytes();
 
         // v5.0 [MQTT-4.7.3-1]
-        if value.is_empty() {
+        if bytes.is_empty() {
             return (true, 0);
         }
 
+        // The special chars are all ASCII and never part of a multi-byte
+        // char, so checking the bytes is the same as checking the chars.
         let mut last_sep: Option<usize> = None;
         let mut has_all = false;
         let mut has_one = false;
-        let mut byte_idx = 0;
         let mut is_shared = true;
         let mut shared_group_sep = 0;
         let mut shared_filter_sep = 0;
-        for (char_idx, c) in value.chars().enumerate() {
-            if c == '\0' {
+        let mut idx = 0;
+        while idx < bytes.len() {
+            let c = bytes[idx];
+            if c == b'\0' {
                 return (true, 0);
             }
             // "#" must be last char
@@ -331,25 +606,25 @@ impl TopicFilter {
                 return (true, 0);
             }
 
-            if is_shared && char_idx < 7 && c != SHARED_PREFIX_CHARS[char_idx] {
+            if is_shared && idx < SHARED_PREFIX_BYTES.len() && c != SHARED_PREFIX_BYTES[idx] {
                 is_shared = false;
             }
 
-            if c == LEVEL_SEP {
+            if c == LEVEL_SEP as u8 {
                 if is_shared {
                     if shared_group_sep == 0 {
-                        shared_group_sep = byte_idx as u16;
+                        shared_group_sep = idx as u16;
                     } else if shared_filter_sep == 0 {
-                        shared_filter_sep = byte_idx as u16;
+                        shared_filter_sep = idx as u16;
                     }
                 }
                 // "+" must occupy an entire level of the filter
-                if has_one && Some(char_idx) != last_sep.map(|v| v + 2) && char_idx != 1 {
+                if has_one && !follows_sep(last_sep, idx, 2) && idx != 1 {
                     return (true, 0);
                 }
-                last_sep = Some(char_idx);
+                last_sep = Some(idx);
                 has_one = false;
-            } else if c == MATCH_ALL_CHAR {
+            } else if c == MATCH_ALL_CHAR as u8 {
                 // v5.0 [MQTT-4.8.2-2]
                 if shared_group_sep > 0 && shared_filter_sep == 0 {
                     return (true, 0);
@@ -357,13 +632,13 @@ impl TopicFilter {
                 if has_one {
                     // invalid topic filter: "/+#"
                     return (true, 0);
-                } else if Some(char_idx) == last_sep.map(|v| v + 1) || char_idx == 0 {
+                } else if follows_sep(last_sep, idx, 1) || idx == 0 {
                     has_all = true;
                 } else {
                     // invalid topic filter: "/ab#"
                     return (true, 0);
                 }
-            } else if c == MATCH_ONE_CHAR {
+            } else if c == MATCH_ONE_CHAR as u8 {
                 // v5.0 [MQTT-4.8.2-2]
                 if shared_group_sep > 0 && shared_filter_sep == 0 {
                     return (true, 0);
@@ -371,18 +646,18 @@ impl TopicFilter {
                 if has_one {
                     // invalid topic filter: "/++"
                     return (true, 0);
-                } else if Some(char_idx) == last_sep.map(|v| v + 1) || char_idx == 0 {
+                } else if follows_sep(last_sep, idx, 1) || idx == 0 {
                     has_one = true;
                 } else {
                     return (true, 0);
                 }
             }
 
-            byte_idx += c.len_utf8();
+            idx += 1;
         }
 
         // v5.0 [MQTT-4.7.3-1]
-        if shared_filter_sep > 0 && shared_filter_sep as usize == value.len() - 1 {
+        if shared_filter_sep > 0 && shared_filter_sep as usize == bytes.len() - 1 {
             return (true, 0);
         }
         // v5.0 [MQTT-4.8.2-2]
@@ -399,9 +674,20 @@ impl TopicFilter {
         (false, shared_filter_sep)
     }
 
+    /// Create a topic filter and also reject the characters forbidden by
+    /// `check_utf8_strict()`.
+    pub fn try_from_strict(value: String) -> Result<Self, Error> {
+        check_utf8_strict(&value)?;
+        Self::try_from(value)
+    }
+
     pub fn is_shared(&self) -> bool {
         self.shared_filter_sep > 0
     }
+
+    pub(crate) fn is_unique(&self) -> bool {
+        self.inner.is_unique()
+    }
     pub fn is_sys(&self) -> bool {
         self.inner.starts_with(SYS_PREFIX)
     }
@@ -424,6 +710,69 @@ impl TopicFilter {
         }
     }
 
+    /// The filter used for matching topic names, it is the shared filter
+    /// part of a shared subscription, otherwise the whole filter.
+    pub fn match_filter(&self) -> &str {
+        self.shared_filter().unwrap_or(&self.inner)
+    }
+
+    /// The levels of `match_filter()` separated by `/`, the `$share/{group}`
+    /// prefix of a shared subscription is skipped.
+    pub fn levels(&self) -> str::Split<'_, char> {
+        self.match_filter().split(LEVEL_SEP)
+    }
+
+    /// Check if the topic name matches the filter, shared filters are
+    /// matched by the `match_filter()` part.
+    ///
+    /// Topic names start with `$` are not matched by filters start with a
+    /// wildcard character ([MQTT 4.7.2]).
+    ///
+    /// [MQTT 4.7.2]: http://docs.oasis-open.org/mqtt/mqtt/v3.1.1/os/mqtt-v3.1.1-os.html#_Toc398718109
+    pub fn matches(&self, topic_name: &str) -> bool {
+        let filter = self.match_filter();
+        if topic_name.starts_with('$') && filter.starts_with([MATCH_ONE_CHAR, MATCH_ALL_CHAR]) {
+            return false;
+        }
+        let mut topic_levels = topic_name.split(LEVEL_SEP);
+        for level in self.levels() {
+            if level == MATCH_ALL_STR {
+                return true;
+            }
+            match topic_levels.next() {
+                Some(topic_level) if level == MATCH_ONE_STR || level == topic_level => {}
+                _ => return false,
+            }
+        }
+        topic_levels.next().is_none()
+    }
+
+    /// Check if every topic name matched by `other` is also matched by the
+    /// filter (e.g. `a/#` covers `a/+/b`), shared filters are compared by
+    /// the `match_filter()` part. A filter covers itself.
+    pub fn covers(&self, other: &TopicFilter) -> bool {
+        if self
+            .match_filter()
+            .starts_with([MATCH_ONE_CHAR, MATCH_ALL_CHAR])
+            && other.match_filter().starts_with('$')
+        {
+            return false;
+        }
+        let mut other_levels = other.levels();
+        for level in self.levels() {
+            if level == MATCH_ALL_STR {
+                return true;
+            }
+            match other_levels.next() {
+                Some(other_level)
+                    if other_level != MATCH_ALL_STR
+                        && (level == MATCH_ONE_STR || level == other_level) => {}
+                _ => return false,
+            }
+        }
+        other_levels.next().is_none()
+    }
+
     /// return (shared group name, shared filter)
     pub fn shared_info(&self) -> Option<(&str, &str)> {
         if self.is_shared() {
@@ -476,13 +825,35 @@ impl TryFrom<String> for TopicFilter {
             Err(Error::InvalidTopicFilter(value))
         } else {
             Ok(TopicFilter {
-                inner: Arc::new(value),
+                inner: value.into(),
+                shared_filter_sep,
+            })
+        }
+    }
+}
+
+impl TryFrom<&'static str> for TopicFilter {
+    type Error = Error;
+    fn try_from(value: &'static str) -> Result<Self, Error> {
+        let (is_invalid, shared_filter_sep) = TopicFilter::is_invalid(value);
+        if is_invalid {
+            Err(Error::InvalidTopicFilter(value.to_owned()))
+        } else {
+            Ok(TopicFilter {
+                inner: value.into(),
                 shared_filter_sep,
             })
         }
     }
 }
 
+#[cfg(feature = "serde")]
+impl serde::Serialize for TopicFilter {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(&self.inner)
+    }
+}
+
 impl Deref for TopicFilter {
     type Target = str;
     fn deref(&self) -> &str {
@@ -490,6 +861,57 @@ impl Deref for TopicFilter {
     }
 }
 
+/// Check if the byte at `idx` is `n` bytes after the last level separator.
+const fn follows_sep(last_sep: Option<usize>, idx: usize, n: usize) -> bool {
+    match last_sep {
+        Some(sep) => sep + n == idx,
+        None => false,
+    }
+}
+
+/// Create a [`TopicName`] from a string literal, the topic name is
+/// validated at compile time.
+///
+/// ```
+/// let topic = mqtt_proto::topic!("sensors/temperature");
+/// assert_eq!(&*topic, "sensors/temperature");
+/// ```
+///
+/// ```compile_fail
+/// let topic = mqtt_proto::topic!("sensors/+");
+/// ```
+#[macro_export]
+macro_rules! topic {
+    ($name:expr) => {{
+        const _: () = assert!(!$crate::TopicName::is_invalid($name), "invalid topic name");
+        <$crate::TopicName as ::core::convert::TryFrom<&str>>::try_from($name)
+            .expect("topic name checked at compile time")
+    }};
+}
+
+/// Create a [`TopicFilter`] from a string literal, the topic filter is
+/// validated at compile time.
+///
+/// ```
+/// let filter = mqtt_proto::filter!("$share/group/sensors/+");
+/// assert_eq!(filter.shared_filter(), Some("sensors/+"));
+/// ```
+///
+/// ```compile_fail
+/// let filter = mqtt_proto::filter!("sensors/#/temperature");
+/// ```
+#[macro_export]
+macro_rules! filter {
+    ($filter:expr) => {{
+        const _: () = assert!(
+            !$crate::TopicFilter::is_invalid($filter).0,
+            "invalid topic filter"
+        );
+        <$crate::TopicFilter as ::core::convert::TryFrom<&str>>::try_from($filter)
+            .expect("topic filter checked at compile time")
+    }};
+}
+
 /// A bytes data structure represent a dynamic vector or fixed array.
 #[derive(Debug, Clone, PartialEq, Eq, Hash)]
 pub enum VarBytes {
@@ -512,21 +934,22 @@ impl AsRef<[u8]> for VarBytes {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::PidAllocator;
 
     #[test]
     fn pid_add_sub() {
         let t: Vec<(u16, u16, u16, u16)> = vec![
             (2, 1, 1, 3),
             (100, 1, 99, 101),
-            (1, 1, core::u16::MAX, 2),
-            (1, 2, core::u16::MAX - 1, 3),
-            (1, 3, core::u16::MAX - 2, 4),
-            (core::u16::MAX, 1, core::u16::MAX - 1, 1),
-            (core::u16::MAX, 2, core::u16::MAX - 2, 2),
-            (10, core::u16::MAX, 10, 10),
+            (1, 1, u16::MAX, 2),
+            (1, 2, u16::MAX - 1, 3),
+            (1, 3, u16::MAX - 2, 4),
+            (u16::MAX, 1, u16::MAX - 1, 1),
+            (u16::MAX, 2, u16::MAX - 2, 2),
+            (10, u16::MAX, 10, 10),
             (10, 0, 10, 10),
             (1, 0, 1, 1),
-            (core::u16::MAX, 0, core::u16::MAX, core::u16::MAX),
+            (u16::MAX, 0, u16::MAX, u16::MAX),
         ];
         for (cur, d, prev, next) in t {
             let cur = Pid::try_from(cur).unwrap();
@@ -537,6 +960,43 @@ mod tests {
         }
     }
 
+    #[test]
+    fn test_qos_forward() {
+        assert_eq!(effective_qos(QoS::Level2, QoS::Level1), QoS::Level1);
+        assert_eq!(effective_qos(QoS::Level0, QoS::Level2), QoS::Level0);
+
+        let pid1 = Pid::try_from(1).unwrap();
+        let pid9 = Pid::try_from(9).unwrap();
+        let mut allocator = PidAllocator::new();
+        assert_eq!(
+            QosPid::Level2(pid9).forward(QoS::Level1, || allocator.alloc()),
+            Ok(QosPid::Level1(pid1))
+        );
+        assert_eq!(
+            QosPid::Level1(pid9).forward(QoS::Level0, || allocator.alloc()),
+            Ok(QosPid::Level0)
+        );
+        assert_eq!(allocator.in_flight(), 1);
+        assert_eq!(
+            QosPid::Level2(pid9).forward(QoS::Level2, || Err(Error::PidExhausted)),
+            Err(Error::PidExhausted)
+        );
+    }
+
+    #[test]
+    fn test_shared_str() {
+        let literal = SharedStr::from("abc");
+        assert_eq!(literal.as_ptr(), "abc".as_ptr());
+        let owned = SharedStr::from("abc".to_owned());
+        assert_eq!(owned.clone().as_ptr(), owned.as_ptr());
+        assert_eq!(literal, owned);
+        assert!(owned.is_unique());
+        assert_eq!(
+            SharedStr::from_utf8(Bytes::from_static(b"a\xffb")),
+            Err(Error::InvalidString)
+        );
+    }
+
     #[test]
     fn test_valid_topic_name() {
         // valid topic name
@@ -548,7 +1008,7 @@ mod tests {
         // NOTE: Because v5.0 topic alias, we let up level to check empty topic name
         assert!(!TopicName::is_invalid(""));
         assert!(!TopicName::is_invalid(
-            "a".repeat(u16::max_value() as usize).as_str()
+            "a".repeat(u16::MAX as usize).as_str()
         ));
 
         // invalid topic name
@@ -561,14 +1021,17 @@ mod tests {
         assert!(TopicName::is_invalid("abc#def"));
         assert!(TopicName::is_invalid("abc+def"));
         assert!(TopicName::is_invalid(
-            "a".repeat(u16::max_value() as usize + 1).as_str()
+            "a".repeat(u16::MAX as usize + 1).as_str()
         ));
+
+        assert_eq!(&*TopicName::try_from("abc/def").unwrap(), "abc/def");
+        assert!(TopicName::try_from("abc/#").is_err());
     }
 
     #[test]
     fn test_valid_topic_filter() {
-        let string_65535 = "a".repeat(u16::max_value() as usize);
-        let string_65536 = "a".repeat(u16::max_value() as usize + 1);
+        let string_65535 = "a".repeat(u16::MAX as usize);
+        let string_65536 = "a".repeat(u16::MAX as usize + 1);
         for (is_invalid, topic) in [
             // valid topic filter
             (false, "abc/def"),
@@ -611,6 +1074,7 @@ mod tests {
             (true, string_65536.as_str()),
         ] {
             assert_eq!((is_invalid, 0), TopicFilter::is_invalid(topic));
+            assert_eq!(is_invalid, TopicFilter::try_from(topic.to_owned()).is_err());
         }
     }
 
@@ -686,6 +1150,7 @@ mod tests {
                 let filter = TopicFilter::try_from(raw_filter.to_owned()).unwrap();
                 assert_eq!(filter.shared_group_name(), shared_group);
                 assert_eq!(filter.shared_filter(), shared_filter);
+                assert_eq!(filter.match_filter(), shared_filter.unwrap_or(raw_filter));
                 if let Some(group_name) = shared_group {
                     assert_eq!(
                         filter.shared_info(),
@@ -697,4 +1162,116 @@ mod tests {
             }
         }
     }
+
+    #[test]
+    fn test_topic_name_from_levels() {
+        assert_eq!(&*TopicName::from_levels(["a", "", "b"]).unwrap(), "a//b");
+        assert_eq!(&*TopicName::from_levels([""; 0]).unwrap(), "");
+        let device = String::from("dev-1");
+        assert_eq!(
+            &*TopicName::from_levels(["sensors", &device, "temp"]).unwrap(),
+            "sensors/dev-1/temp"
+        );
+        for (levels, index) in [
+            (&["a", "+"][..], 1),
+            (&["#"], 0),
+            (&["a", "b\0c"], 1),
+            (&["a/b", "c"], 0),
+        ] {
+            assert_eq!(
+                TopicName::from_levels(levels),
+                Err(Error::InvalidTopicLevel {
+                    index,
+                    level: levels[index].to_owned()
+                })
+            );
+        }
+        let long = "a".repeat(u16::MAX as usize);
+        assert!(matches!(
+            TopicName::from_levels([long.as_str(), "b"]),
+            Err(Error::InvalidTopicName(_))
+        ));
+    }
+
+    #[test]
+    fn test_topic_levels() {
+        let topic_name = TopicName::try_from("/a//b".to_owned()).unwrap();
+        assert_eq!(topic_name.levels().collect::<Vec<_>>(), ["", "a", "", "b"]);
+        for (filter, levels) in [
+            ("a/+/#", &["a", "+", "#"][..]),
+            ("#", &["#"]),
+            ("$share/g1/a/+", &["a", "+"]),
+            ("$share/g1//", &["", ""]),
+        ] {
+            let filter = TopicFilter::try_from(filter.to_owned()).unwrap();
+            assert_eq!(filter.levels().collect::<Vec<_>>(), levels);
+        }
+    }
+
+    #[test]
+    fn test_topic_filter_matches() {
+        for (matched, filter, topic) in [
+            (true, "a/b", "a/b"),
+            (true, "a/+", "a/b"),
+            (true, "a/+", "a/"),
+            (true, "a/#", "a"),
+            (true, "a/#", "a/b/c"),
+            (true, "+/+", "/b"),
+            (true, "#", "a/b"),
+            (true, "$SYS/#", "$SYS/info"),
+            (true, "$share/g/a/+", "a/b"),
+            (true, "$share/g/#", "a/b"),
+            (false, "a/b", "a/b/c"),
+            (false, "a/+", "a"),
+            (false, "a/+", "a/b/c"),
+            (false, "+", "/b"),
+            (false, "#", "$SYS/info"),
+            (false, "+/info", "$SYS/info"),
+            (false, "$share/g/a/+", "g/a/b"),
+            (false, "$share/g/#", "$SYS/info"),
+        ] {
+            let filter = TopicFilter::try_from(filter).unwrap();
+            assert_eq!(filter.matches(topic), matched, "{filter} {topic}");
+        }
+    }
+
+    #[test]
+    fn test_topic_filter_covers() {
+        for (covered, filter, other) in [
+            (true, "a/b", "a/b"),
+            (true, "a/#", "a/+/b"),
+            (true, "a/#", "a"),
+            (true, "a/#", "a/#"),
+            (true, "#", "a/+/#"),
+            (true, "+/+", "a/b"),
+            (true, "a/+", "a/+"),
+            (true, "$share/g1/a/#", "a/b"),
+            (true, "a/#", "$share/g1/a/b"),
+            (true, "$SYS/#", "$SYS/a"),
+            (false, "a/b", "a/+"),
+            (false, "a/+", "a/#"),
+            (false, "a/+", "a"),
+            (false, "a/+", "a/b/c"),
+            (false, "a/b/#", "a/#"),
+            (false, "a/+/c", "a/b"),
+            (false, "#", "$SYS/a"),
+            (false, "+/a", "$SYS/a"),
+        ] {
+            let filter = TopicFilter::try_from(filter.to_owned()).unwrap();
+            let other = TopicFilter::try_from(other.to_owned()).unwrap();
+            assert_eq!(filter.covers(&other), covered, "{filter} {other}");
+        }
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn test_serde_validate() {
+        let filter: TopicFilter = serde_json::from_str(r#""$share/g/a/+""#).unwrap();
+        assert_eq!(filter.shared_info(), Some(("g", "a/+")));
+        assert_eq!(serde_json::to_string(&filter).unwrap(), r#""$share/g/a/+""#);
+        assert!(serde_json::from_str::<TopicFilter>(r#""a/b#""#).is_err());
+        assert!(serde_json::from_str::<TopicName>(r#""a/+""#).is_err());
+        assert!(serde_json::from_str::<Pid>("0").is_err());
+        assert_eq!(serde_json::from_str::<Pid>("3").unwrap().value(), 3);
+    }
 }
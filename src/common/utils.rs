@@ -1,19 +1,31 @@
+use std::fmt;
 use std::io;
+use std::mem;
 use std::slice;
 
 use simdutf8::basic::from_utf8;
-use tokio::io::{AsyncRead, AsyncReadExt};
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite};
 
-use crate::{Encodable, Error};
+use crate::{Encodable, Error, QosPid};
 
 /// Read first byte(packet type and flags) and decode remaining length
 #[inline]
 pub async fn decode_raw_header<T: AsyncRead + Unpin>(reader: &mut T) -> Result<(u8, u32), Error> {
-    let typ = read_u8(reader).await?;
-    let (remaining_len, _bytes) = decode_var_int(reader).await?;
+    let (typ, remaining_len, _bytes) = decode_raw_header_len(reader).await?;
     Ok((typ, remaining_len))
 }
 
+/// Same as `decode_raw_header()`, also return the number of bytes the
+/// remaining length is encoded in.
+#[inline]
+pub(crate) async fn decode_raw_header_len<T: AsyncRead + Unpin>(
+    reader: &mut T,
+) -> Result<(u8, u32, usize), Error> {
+    let typ = read_u8(reader).await?;
+    let (remaining_len, bytes) = decode_var_int(reader).await?;
+    Ok((typ, remaining_len, bytes))
+}
+
 #[inline]
 pub(crate) async fn read_string<T: AsyncRead + Unpin>(reader: &mut T) -> Result<String, Error> {
     let data_buf = read_bytes(reader).await?;
@@ -29,12 +41,62 @@ pub(crate) async fn read_bytes<T: AsyncRead + Unpin>(reader: &mut T) -> Result<V
     Ok(data_buf)
 }
 
-// Only for v5.0
-#[inline]
-pub(crate) async fn read_u32<T: AsyncRead + Unpin>(reader: &mut T) -> Result<u32, Error> {
-    let mut len4_bytes = [0u8; 4];
-    reader.read_exact(&mut len4_bytes).await?;
-    Ok(u32::from_be_bytes(len4_bytes))
+/// Classify the result of decoding a packet body from a reader limited to
+/// the remaining length (`left` is the unread part of the body):
+///   * running out of the body while reading a field, or not consuming the
+///     body exactly, means the remaining length is invalid
+///   * the EOF error is kept only when the reader ends inside the body
+pub(crate) fn check_body_end<P, E: From<Error>>(
+    left: u64,
+    result: Result<P, E>,
+    is_eof: impl FnOnce(&E) -> bool,
+) -> Result<P, E> {
+    match result {
+        Ok(_) if left > 0 => Err(Error::InvalidRemainingLength.into()),
+        Err(err) if left == 0 && is_eof(&err) => Err(Error::InvalidRemainingLength.into()),
+        result => result,
+    }
+}
+
+/// Max bytes reserved up front for a length which comes from the peer, the
+/// buffer grows as the bytes arrive.
+pub(crate) const MAX_RESERVE: usize = 64 * 1024;
+
+/// Read exactly `len` bytes. The length comes from the peer, so at most
+/// `MAX_RESERVE` bytes are reserved up front.
+pub(crate) async fn read_exact_capped<T: AsyncRead + Unpin>(
+    reader: &mut T,
+    len: usize,
+) -> Result<Vec<u8>, Error> {
+    let mut data = Vec::with_capacity(len.min(MAX_RESERVE));
+    let size = reader.take(len as u64).read_to_end(&mut data).await?;
+    if size < len {
+        return Err(Error::IoError(
+            io::ErrorKind::UnexpectedEof,
+            "eof".to_owned(),
+        ));
+    }
+    Ok(data)
+}
+
+/// Copy exactly `len` bytes from reader to writer in chunks.
+pub(crate) async fn copy_exact<T, W>(
+    reader: &mut T,
+    writer: &mut W,
+    len: usize,
+) -> Result<(), Error>
+where
+    T: AsyncRead + Unpin,
+    W: AsyncWrite + Unpin,
+{
+    let copied = tokio::io::copy(&mut reader.take(len as u64), writer).await?;
+    if copied < len as u64 {
+        return Err(Error::IoError(
+            io::ErrorKind::UnexpectedEof,
+            "eof".to_owned(),
+        ));
+    }
+    Ok(())
 }
 
 #[inline]
@@ -51,9 +113,54 @@ pub(crate) async fn read_u8<T: AsyncRead + Unpin>(reader: &mut T) -> Result<u8,
     Ok(byte)
 }
 
+/// Check the characters of an MQTT UTF-8 encoded string.
+///
+/// Besides U+0000 the spec forbids the control characters U+0001..U+001F,
+/// U+007F..U+009F and the Unicode non-characters [MQTT-1.5.3], the decoder
+/// only rejects U+0000 by default. Enable `DecodeLimits::strict_utf8` or
+/// call this function to reject all of them.
+///
+/// [MQTT-1.5.3]: https://docs.oasis-open.org/mqtt/mqtt/v5.0/os/mqtt-v5.0-os.html#_Toc3901010
+pub fn check_utf8_strict(value: &str) -> Result<(), Error> {
+    match value.chars().find(|c| is_forbidden_char(*c)) {
+        Some(c) => Err(Error::ForbiddenChar(c)),
+        None => Ok(()),
+    }
+}
+
+fn is_forbidden_char(c: char) -> bool {
+    let code = c as u32;
+    matches!(c, '\u{0}'..='\u{1F}' | '\u{7F}'..='\u{9F}' | '\u{FDD0}'..='\u{FDEF}')
+        || code & 0xFFFE == 0xFFFE
+}
+
+/// Read a length-prefixed utf8 string at the start of a packet body without
+/// copying, return the string and the rest of the body.
+pub(crate) fn peek_str(body: &[u8]) -> Result<(&str, &[u8]), Error> {
+    let (len, rest) = peek_u16(body)?;
+    if rest.len() < len as usize {
+        return Err(Error::InvalidRemainingLength);
+    }
+    let (data, rest) = rest.split_at(len as usize);
+    let value = from_utf8(data).map_err(|_| Error::InvalidString)?;
+    Ok((value, rest))
+}
+
+/// Read a big-endian u16 at the start of a packet body, return the value and
+/// the rest of the body.
+pub(crate) fn peek_u16(body: &[u8]) -> Result<(u16, &[u8]), Error> {
+    if body.len() < 2 {
+        return Err(Error::InvalidRemainingLength);
+    }
+    Ok((u16::from_be_bytes([body[0], body[1]]), &body[2..]))
+}
+
 #[inline]
 pub(crate) fn write_bytes<W: io::Write>(writer: &mut W, data: &[u8]) -> io::Result<()> {
-    write_u16(writer, data.len() as u16)?;
+    let len = u16::try_from(data.len()).map_err(|_| {
+        io::Error::new(io::ErrorKind::InvalidInput, Error::ValueTooLong(data.len()))
+    })?;
+    write_u16(writer, len)?;
     writer.write_all(data)
 }
 
@@ -74,6 +181,12 @@ pub(crate) fn write_u8<W: io::Write>(writer: &mut W, value: u8) -> io::Result<()
 
 #[inline]
 pub(crate) fn write_var_int<W: io::Write>(writer: &mut W, mut len: usize) -> io::Result<()> {
+    if len >= 268435456 {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            Error::InvalidVarByteInt,
+        ));
+    }
     loop {
         let mut byte = (len % 128) as u8;
         len /= 128;
@@ -166,20 +279,114 @@ pub fn header_len(total_len: usize) -> usize {
     }
 }
 
-/// Encode packet use control byte and body type
+/// Encode packet use control byte and body type into a writer
 #[inline]
-pub(crate) fn encode_packet<E: Encodable>(control_byte: u8, body: &E) -> Result<Vec<u8>, Error> {
+pub(crate) fn encode_packet_to<W: io::Write, E: Encodable>(
+    writer: &mut W,
+    control_byte: u8,
+    body: &E,
+) -> Result<(), Error> {
     let remaining_len = body.encode_len();
-    let total = total_len(remaining_len)?;
-    let mut buf = Vec::with_capacity(total);
+    total_len(remaining_len)?;
 
     // encode header
-    buf.push(control_byte);
-    write_var_int(&mut buf, remaining_len).expect("encode header write var int");
+    writer.write_all(&[control_byte])?;
+    write_var_int(writer, remaining_len)?;
+    body.encode(writer)?;
+    Ok(())
+}
 
-    body.encode(&mut buf)?;
-    debug_assert_eq!(buf.len(), total);
-    Ok(buf)
+/// Write the PUBLISH packet line of the packet `Display` implementations.
+pub(crate) fn fmt_publish(
+    f: &mut fmt::Formatter<'_>,
+    qos_pid: QosPid,
+    topic_name: &str,
+    payload_len: usize,
+    retain: bool,
+    dup: bool,
+) -> fmt::Result {
+    write!(f, "PUBLISH qos={}", qos_pid.qos() as u8)?;
+    if let Some(pid) = qos_pid.pid() {
+        write!(f, " pid={}", pid.value())?;
+    }
+    write!(f, " topic={topic_name} len={payload_len}")?;
+    if retain {
+        write!(f, " retain")?;
+    }
+    if dup {
+        write!(f, " dup")?;
+    }
+    Ok(())
+}
+
+/// Write `items` separated by comma.
+pub(crate) fn fmt_list<T>(
+    f: &mut fmt::Formatter<'_>,
+    items: &[T],
+    mut fmt_item: impl FnMut(&mut fmt::Formatter<'_>, &T) -> fmt::Result,
+) -> fmt::Result {
+    for (idx, item) in items.iter().enumerate() {
+        if idx > 0 {
+            write!(f, ",")?;
+        }
+        fmt_item(f, item)?;
+    }
+    Ok(())
+}
+
+/// Debug output of a secret (password, authentication data), only the length
+/// is printed: `[REDACTED len=N]`.
+pub(crate) struct Redacted(usize);
+
+impl fmt::Debug for Redacted {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "[REDACTED len={}]", self.0)
+    }
+}
+
+/// Redact an optional secret for the `Debug` implementations.
+pub(crate) fn redacted<T: AsRef<[u8]>>(value: &Option<T>) -> Option<Redacted> {
+    value.as_ref().map(|value| Redacted(value.as_ref().len()))
+}
+
+/// The capacity to reserve for the items decoded from `len` bytes, each item
+/// takes at least `min_item_len` bytes. The length comes from the peer and
+/// the bytes may not be received yet, so the capacity is capped.
+#[inline]
+pub(crate) fn capacity_hint(len: usize, min_item_len: usize) -> usize {
+    const MAX_CAPACITY_HINT: usize = 256;
+    (len / min_item_len).min(MAX_CAPACITY_HINT)
+}
+
+/// Split `items` into groups whose packet size (include header) does not
+/// exceed `max_packet_size`, `base_len` is the remaining length of a packet
+/// without any item.
+pub(crate) fn split_by_packet_size<T>(
+    items: Vec<T>,
+    base_len: usize,
+    max_packet_size: usize,
+    item_len: impl Fn(&T) -> usize,
+) -> Result<Vec<Vec<T>>, Error> {
+    let mut groups = Vec::new();
+    let mut group = Vec::new();
+    let mut remaining_len = base_len;
+    for item in items {
+        let len = item_len(&item);
+        if !group.is_empty() && total_len(remaining_len + len)? > max_packet_size {
+            groups.push(mem::take(&mut group));
+            remaining_len = base_len;
+        }
+        let total = total_len(remaining_len + len)?;
+        if total > max_packet_size {
+            return Err(Error::PacketTooLarge(total));
+        }
+        remaining_len += len;
+        group.push(item);
+    }
+    if !group.is_empty() {
+        groups.push(group);
+    }
+    Ok(groups)
 }
 
 macro_rules! packet_from {
@@ -221,4 +428,53 @@ mod tests {
             .unwrap_err()
             .is_eof());
     }
+
+    #[test]
+    fn test_read_exact_capped() {
+        let mut data = &[1, 2, 3, 4][..];
+        assert_eq!(
+            block_on(read_exact_capped(&mut data, 3)).unwrap(),
+            vec![1, 2, 3]
+        );
+        assert_eq!(data, &[4]);
+
+        // The declared length is not reserved before the bytes arrive
+        let mut data = &[1, 2, 3][..];
+        assert!(block_on(read_exact_capped(&mut data, 256 * 1024 * 1024))
+            .unwrap_err()
+            .is_eof());
+    }
+
+    #[test]
+    fn test_capacity_hint() {
+        assert_eq!(capacity_hint(0, 4), 0);
+        assert_eq!(capacity_hint(11, 4), 2);
+        assert_eq!(capacity_hint(100, 1), 100);
+        assert_eq!(capacity_hint(268435455, 1), 256);
+    }
+
+    #[test]
+    fn test_check_utf8_strict() {
+        for value in ["", "a/b", "\u{A0}", "\u{FDCF}", "\u{FFFD}", "\u{10000}"] {
+            assert_eq!(check_utf8_strict(value), Ok(()));
+        }
+        for c in [
+            '\u{0}',
+            '\u{1}',
+            '\u{1F}',
+            '\u{7F}',
+            '\u{9F}',
+            '\u{FDD0}',
+            '\u{FDEF}',
+            '\u{FFFE}',
+            '\u{FFFF}',
+            '\u{1FFFE}',
+            '\u{10FFFF}',
+        ] {
+            assert_eq!(
+                check_utf8_strict(&format!("a{c}b")),
+                Err(Error::ForbiddenChar(c))
+            );
+        }
+    }
 }
@@ -0,0 +1,189 @@
+use bytes::{Buf, Bytes, BytesMut};
+
+use super::{DecodeLimits, Error};
+
+/// Aggregate the payloads of WebSocket binary frames into MQTT packets.
+///
+/// An MQTT packet may be split across several frames and a frame may contain
+/// several packets (see [MQTT 6.0]). The complete packets are returned as
+/// bytes, decode them by `v3::Packet::decode` or `v5::Packet::decode`.
+///
+/// [MQTT 6.0]: https://docs.oasis-open.org/mqtt/mqtt/v5.0/os/mqtt-v5.0-os.html#_Toc3901285
+#[derive(Debug, Clone, Default)]
+pub struct WsPacketDecoder {
+    buf: BytesMut,
+    limits: DecodeLimits,
+}
+
+impl WsPacketDecoder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn with_limits(limits: DecodeLimits) -> Self {
+        WsPacketDecoder {
+            buf: BytesMut::new(),
+            limits,
+        }
+    }
+
+    /// Append the payload of a binary frame.
+    pub fn push(&mut self, frame: &[u8]) {
+        self.buf.extend_from_slice(frame);
+    }
+
+    /// Number of buffered bytes which are not returned as packets yet.
+    pub fn buffered(&self) -> usize {
+        self.buf.len()
+    }
+
+    /// Take the next complete packet, return `None` if more frames are needed.
+    pub fn next_packet(&mut self) -> Result<Option<Bytes>, Error> {
+        let mut remaining_len: usize = 0;
+        let mut header_len = 1;
+        loop {
+            let byte = match self.buf.get(header_len) {
+                Some(byte) => *byte,
+                None => return Ok(None),
+            };
+            remaining_len += ((byte & 0x7F) as usize) << ((header_len - 1) * 7);
+            header_len += 1;
+            if byte & 0x80 == 0 {
+                break;
+            }
+            if header_len > 4 {
+                return Err(Error::InvalidVarByteInt);
+            }
+        }
+        self.limits
+            .check_remaining_len(remaining_len as u32, header_len - 1)?;
+        let total_len = header_len + remaining_len;
+        if self.buf.len() < total_len {
+            return Ok(None);
+        }
+        Ok(Some(self.buf.split_to(total_len).freeze()))
+    }
+
+    /// Append the payload of a tungstenite message. Control messages are
+    /// ignored, text messages are not allowed for MQTT.
+    #[cfg(feature = "tungstenite")]
+    pub fn push_message(&mut self, message: &tungstenite::Message) -> Result<(), Error> {
+        use tungstenite::Message;
+        match message {
+            Message::Binary(data) => self.push(data),
+            Message::Text(_) => {
+                return Err(Error::IoError(
+                    std::io::ErrorKind::InvalidData,
+                    "text message".to_owned(),
+                ))
+            }
+            _ => {}
+        }
+        Ok(())
+    }
+}
+
+/// Split an encoded packet into the payloads of binary frames, each of them
+/// is at most `max_frame_len` bytes.
+pub fn split_frames(packet: Bytes, max_frame_len: usize) -> WsFrames {
+    WsFrames {
+        packet,
+        max_frame_len: max_frame_len.max(1),
+    }
+}
+
+/// Iterator of frame payloads, see [`split_frames`].
+#[derive(Debug, Clone)]
+pub struct WsFrames {
+    packet: Bytes,
+    max_frame_len: usize,
+}
+
+impl Iterator for WsFrames {
+    type Item = Bytes;
+
+    fn next(&mut self) -> Option<Bytes> {
+        if !self.packet.has_remaining() {
+            return None;
+        }
+        let len = self.packet.len().min(self.max_frame_len);
+        Some(self.packet.split_to(len))
+    }
+}
+
+#[cfg(feature = "tungstenite")]
+impl WsFrames {
+    /// Convert the frame payloads to tungstenite binary messages.
+    pub fn into_messages(self) -> impl Iterator<Item = tungstenite::Message> {
+        self.map(tungstenite::Message::Binary)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_aggregate_frames() {
+        // PINGREQ, PUBLISH (remaining length 130), PINGRESP
+        let mut data = vec![0xC0, 0x00, 0x30, 0x82, 0x01, 0x00, 0x01, b'a'];
+        data.extend([b'x'; 127]);
+        data.extend([0xD0, 0x00]);
+
+        let mut decoder = WsPacketDecoder::new();
+        let mut packets = Vec::new();
+        for frame in data.chunks(3) {
+            decoder.push(frame);
+            while let Some(packet) = decoder.next_packet().unwrap() {
+                packets.push(packet);
+            }
+        }
+        assert_eq!(decoder.buffered(), 0);
+        assert_eq!(packets.len(), 3);
+        assert_eq!(&packets[0][..], &[0xC0, 0x00]);
+        assert_eq!(packets[1].len(), 133);
+        assert_eq!(&packets[2][..], &[0xD0, 0x00]);
+    }
+
+    #[test]
+    fn test_invalid_header() {
+        let mut decoder = WsPacketDecoder::new();
+        decoder.push(&[0x30, 0xFF, 0xFF, 0xFF, 0xFF]);
+        assert_eq!(decoder.next_packet(), Err(Error::InvalidVarByteInt));
+
+        let limits = DecodeLimits {
+            max_remaining_len: 10,
+            ..Default::default()
+        };
+        let mut decoder = WsPacketDecoder::with_limits(limits);
+        decoder.push(&[0x30, 0x0B]);
+        assert_eq!(
+            decoder.next_packet(),
+            Err(Error::DecodeLimitExceeded("max_remaining_len"))
+        );
+    }
+
+    #[test]
+    fn test_split_frames() {
+        let packet = Bytes::from_static(&[0x30, 0x03, 0x00, 0x01, b'a']);
+        let frames: Vec<_> = split_frames(packet.clone(), 2).collect();
+        assert_eq!(frames.len(), 3);
+        assert_eq!(frames.concat(), packet.to_vec());
+        assert_eq!(split_frames(Bytes::new(), 2).count(), 0);
+    }
+
+    #[cfg(feature = "tungstenite")]
+    #[test]
+    fn test_tungstenite_messages() {
+        use tungstenite::Message;
+
+        let packet = Bytes::from_static(&[0x30, 0x03, 0x00, 0x01, b'a']);
+        let mut decoder = WsPacketDecoder::new();
+        for message in split_frames(packet.clone(), 2).into_messages() {
+            decoder.push_message(&message).unwrap();
+            decoder.push_message(&Message::Ping(Bytes::new())).unwrap();
+        }
+        assert_eq!(decoder.next_packet(), Ok(Some(packet)));
+        assert!(decoder.push_message(&Message::text("a")).is_err());
+    }
+}
@@ -0,0 +1,470 @@
+//! C API of the codec.
+//!
+//! Build the crate as a `cdylib` or `staticlib` with the `ffi` feature
+//! (e.g. `cargo rustc --release --features ffi --crate-type cdylib`). The
+//! packets are opaque `MqttPacket` handles created by `mqtt_decode()` or
+//! `mqtt_publish_new()` and released by `mqtt_packet_free()`.
+//!
+//! Every function returns one of the `MQTT_*` codes (or a value documented
+//! on the function), panics are caught and never unwind into C.
+//!
+//! ```c
+//! MqttPacket *packet = NULL;
+//! size_t consumed = 0;
+//! int rc = mqtt_decode(4, buf, len, &packet, &consumed);
+//! if (rc == MQTT_OK) {
+//!     /* use the packet, then drop `consumed` bytes from buf */
+//!     mqtt_packet_free(packet);
+//! } else if (rc == MQTT_INCOMPLETE) {
+//!     /* read more bytes */
+//! }
+//! ```
+
+use std::ffi::{c_char, c_int};
+use std::panic::{catch_unwind, AssertUnwindSafe, UnwindSafe};
+use std::slice;
+
+use bytes::Bytes;
+use futures_lite::future::block_on;
+
+use crate::{v3, v5, Pid, QoS, QosPid, TopicName};
+
+/// Success.
+pub const MQTT_OK: c_int = 0;
+/// Not enough bytes to decode a packet.
+pub const MQTT_INCOMPLETE: c_int = 1;
+/// A required pointer is null.
+pub const MQTT_ERR_NULL: c_int = -1;
+/// Invalid argument, such as an unsupported protocol level or an invalid
+/// topic name.
+pub const MQTT_ERR_INVALID_ARG: c_int = -2;
+/// The bytes are not a valid packet.
+pub const MQTT_ERR_MALFORMED: c_int = -3;
+/// The packet can not be encoded.
+pub const MQTT_ERR_ENCODE: c_int = -4;
+/// The output buffer is too small, the required size is returned.
+pub const MQTT_ERR_BUFFER_TOO_SMALL: c_int = -5;
+/// The packet is not of the requested type.
+pub const MQTT_ERR_WRONG_TYPE: c_int = -6;
+/// A panic is caught (a bug in this crate).
+pub const MQTT_ERR_PANIC: c_int = -7;
+
+/// Opaque packet handle.
+pub struct MqttPacket(Inner);
+
+// Always boxed in `MqttPacket`
+#[allow(clippy::large_enum_variant)]
+enum Inner {
+    V3(v3::Packet),
+    V5(v5::Packet),
+}
+
+impl Inner {
+    fn encode_len(&self) -> Option<usize> {
+        match self {
+            Inner::V3(packet) => packet.encode_len().ok(),
+            Inner::V5(packet) => packet.encode_len().ok(),
+        }
+    }
+
+    fn encode_into(&self, buf: &mut [u8]) -> Option<usize> {
+        match self {
+            Inner::V3(packet) => packet.encode_into(buf).ok(),
+            Inner::V5(packet) => packet.encode_into(buf).ok(),
+        }
+    }
+}
+
+fn guard<F: FnOnce() -> c_int + UnwindSafe>(f: F) -> c_int {
+    catch_unwind(f).unwrap_or(MQTT_ERR_PANIC)
+}
+
+/// Describe a `MQTT_*` code, the string is static.
+#[no_mangle]
+pub extern "C" fn mqtt_error_str(code: c_int) -> *const c_char {
+    let msg: &'static [u8] = match code {
+        MQTT_OK => b"ok\0",
+        MQTT_INCOMPLETE => b"incomplete packet\0",
+        MQTT_ERR_NULL => b"null pointer\0",
+        MQTT_ERR_INVALID_ARG => b"invalid argument\0",
+        MQTT_ERR_MALFORMED => b"malformed packet\0",
+        MQTT_ERR_ENCODE => b"encode failed\0",
+        MQTT_ERR_BUFFER_TOO_SMALL => b"buffer too small\0",
+        MQTT_ERR_WRONG_TYPE => b"wrong packet type\0",
+        MQTT_ERR_PANIC => b"internal panic\0",
+        _ => b"unknown error\0",
+    };
+    msg.as_ptr() as *const c_char
+}
+
+/// Decode one packet from `data`.
+///
+/// `level` is the protocol level of the connection: 3 or 4 for v3.x, 5 for
+/// v5.0. On `MQTT_OK` the packet is stored in `out_packet` and the packet
+/// size in `out_consumed`.
+///
+/// # Safety
+///
+/// `data` must point to `len` readable bytes, the output pointers must be
+/// valid for writes.
+#[no_mangle]
+pub unsafe extern "C" fn mqtt_decode(
+    level: u8,
+    data: *const u8,
+    len: usize,
+    out_packet: *mut *mut MqttPacket,
+    out_consumed: *mut usize,
+) -> c_int {
+    if (data.is_null() && len > 0) || out_packet.is_null() || out_consumed.is_null() {
+        return MQTT_ERR_NULL;
+    }
+    let bytes = if len == 0 {
+        &[][..]
+    } else {
+        slice::from_raw_parts(data, len)
+    };
+    guard(move || {
+        let mut reader = bytes;
+        let result = match level {
+            3 | 4 => block_on(v3::Packet::decode_async(&mut reader))
+                .map(Inner::V3)
+                .map_err(|err| err.is_eof()),
+            5 => block_on(v5::Packet::decode_async(&mut reader))
+                .map(Inner::V5)
+                .map_err(|err| err.is_eof()),
+            _ => return MQTT_ERR_INVALID_ARG,
+        };
+        match result {
+            Ok(inner) => {
+                *out_consumed = bytes.len() - reader.len();
+                *out_packet = Box::into_raw(Box::new(MqttPacket(inner)));
+                MQTT_OK
+            }
+            Err(true) => MQTT_INCOMPLETE,
+            Err(false) => MQTT_ERR_MALFORMED,
+        }
+    })
+}
+
+/// Create a PUBLISH packet, `pid` is ignored for QoS 0.
+///
+/// # Safety
+///
+/// `topic` and `payload` must point to `topic_len` and `payload_len`
+/// readable bytes, `out_packet` must be valid for writes.
+#[no_mangle]
+#[allow(clippy::too_many_arguments)]
+pub unsafe extern "C" fn mqtt_publish_new(
+    level: u8,
+    topic: *const u8,
+    topic_len: usize,
+    payload: *const u8,
+    payload_len: usize,
+    qos: u8,
+    pid: u16,
+    retain: bool,
+    out_packet: *mut *mut MqttPacket,
+) -> c_int {
+    if topic.is_null() || (payload.is_null() && payload_len > 0) || out_packet.is_null() {
+        return MQTT_ERR_NULL;
+    }
+    let topic = slice::from_raw_parts(topic, topic_len);
+    let payload = if payload_len == 0 {
+        Bytes::new()
+    } else {
+        Bytes::copy_from_slice(slice::from_raw_parts(payload, payload_len))
+    };
+    guard(move || {
+        let Ok(topic_name) = std::str::from_utf8(topic)
+            .map_err(|_| ())
+            .and_then(|topic| TopicName::try_from(topic).map_err(|_| ()))
+        else {
+            return MQTT_ERR_INVALID_ARG;
+        };
+        let qos_pid = match (QoS::from_u8(qos), Pid::try_from(pid)) {
+            (Ok(QoS::Level0), _) => QosPid::Level0,
+            (Ok(QoS::Level1), Ok(pid)) => QosPid::Level1(pid),
+            (Ok(QoS::Level2), Ok(pid)) => QosPid::Level2(pid),
+            _ => return MQTT_ERR_INVALID_ARG,
+        };
+        let inner = match level {
+            3 | 4 => {
+                let mut publish = v3::Publish::new(qos_pid, topic_name, payload);
+                publish.retain = retain;
+                Inner::V3(publish.into())
+            }
+            5 => {
+                let mut publish = v5::Publish::new(qos_pid, topic_name, payload);
+                publish.retain = retain;
+                Inner::V5(publish.into())
+            }
+            _ => return MQTT_ERR_INVALID_ARG,
+        };
+        *out_packet = Box::into_raw(Box::new(MqttPacket(inner)));
+        MQTT_OK
+    })
+}
+
+/// Release a packet, null is ignored.
+///
+/// # Safety
+///
+/// `packet` must be returned by this API and not freed yet.
+#[no_mangle]
+pub unsafe extern "C" fn mqtt_packet_free(packet: *mut MqttPacket) {
+    if !packet.is_null() {
+        drop(Box::from_raw(packet));
+    }
+}
+
+/// Return the packet type (1 for CONNECT to 15 for AUTH), 0 if `packet` is
+/// null.
+///
+/// # Safety
+///
+/// `packet` must be a valid handle or null.
+#[no_mangle]
+pub unsafe extern "C" fn mqtt_packet_type(packet: *const MqttPacket) -> u8 {
+    let Some(packet) = packet.as_ref() else {
+        return 0;
+    };
+    match &packet.0 {
+        Inner::V3(packet) => {
+            use v3::PacketType::*;
+            match packet.packet_type() {
+                Connect => 1,
+                Connack => 2,
+                Publish => 3,
+                Puback => 4,
+                Pubrec => 5,
+                Pubrel => 6,
+                Pubcomp => 7,
+                Subscribe => 8,
+                Suback => 9,
+                Unsubscribe => 10,
+                Unsuback => 11,
+                Pingreq => 12,
+                Pingresp => 13,
+                Disconnect => 14,
+            }
+        }
+        Inner::V5(packet) => {
+            use v5::PacketType::*;
+            match packet.packet_type() {
+                Connect => 1,
+                Connack => 2,
+                Publish => 3,
+                Puback => 4,
+                Pubrec => 5,
+                Pubrel => 6,
+                Pubcomp => 7,
+                Subscribe => 8,
+                Suback => 9,
+                Unsubscribe => 10,
+                Unsuback => 11,
+                Pingreq => 12,
+                Pingresp => 13,
+                Disconnect => 14,
+                Auth => 15,
+            }
+        }
+    }
+}
+
+/// Return the packet identifier, 0 if the packet has none.
+///
+/// # Safety
+///
+/// `packet` must be a valid handle or null.
+#[no_mangle]
+pub unsafe extern "C" fn mqtt_packet_pid(packet: *const MqttPacket) -> u16 {
+    let pid = match packet.as_ref().map(|packet| &packet.0) {
+        Some(Inner::V3(packet)) => packet.pid(),
+        Some(Inner::V5(packet)) => packet.pid(),
+        None => None,
+    };
+    pid.map_or(0, Pid::value)
+}
+
+/// Borrow the topic name and payload of a PUBLISH packet, the pointers are
+/// valid until the packet is freed. The topic is not NUL terminated.
+///
+/// # Safety
+///
+/// `packet` must be a valid handle, the output pointers must be valid for
+/// writes.
+#[no_mangle]
+pub unsafe extern "C" fn mqtt_publish_get(
+    packet: *const MqttPacket,
+    out_topic: *mut *const u8,
+    out_topic_len: *mut usize,
+    out_payload: *mut *const u8,
+    out_payload_len: *mut usize,
+    out_qos: *mut u8,
+) -> c_int {
+    let Some(packet) = packet.as_ref() else {
+        return MQTT_ERR_NULL;
+    };
+    if out_topic.is_null()
+        || out_topic_len.is_null()
+        || out_payload.is_null()
+        || out_payload_len.is_null()
+        || out_qos.is_null()
+    {
+        return MQTT_ERR_NULL;
+    }
+    let (topic_name, payload, qos) = match &packet.0 {
+        Inner::V3(v3::Packet::Publish(publish)) => {
+            (&publish.topic_name, &publish.payload, publish.qos_pid.qos())
+        }
+        Inner::V5(v5::Packet::Publish(publish)) => {
+            (&publish.topic_name, &publish.payload, publish.qos_pid.qos())
+        }
+        _ => return MQTT_ERR_WRONG_TYPE,
+    };
+    *out_topic = topic_name.as_ptr();
+    *out_topic_len = topic_name.len();
+    *out_payload = payload.as_ptr();
+    *out_payload_len = payload.len();
+    *out_qos = qos as u8;
+    MQTT_OK
+}
+
+/// Encode the packet into `buf`. The encoded size (or the required size on
+/// `MQTT_ERR_BUFFER_TOO_SMALL`) is stored in `out_len`.
+///
+/// # Safety
+///
+/// `packet` must be a valid handle, `buf` must point to `cap` writable
+/// bytes and `out_len` must be valid for writes.
+#[no_mangle]
+pub unsafe extern "C" fn mqtt_packet_encode(
+    packet: *const MqttPacket,
+    buf: *mut u8,
+    cap: usize,
+    out_len: *mut usize,
+) -> c_int {
+    let Some(packet) = packet.as_ref() else {
+        return MQTT_ERR_NULL;
+    };
+    if (buf.is_null() && cap > 0) || out_len.is_null() {
+        return MQTT_ERR_NULL;
+    }
+    let buf = if cap == 0 {
+        &mut [][..]
+    } else {
+        slice::from_raw_parts_mut(buf, cap)
+    };
+    let inner = &packet.0;
+    let mut len = 0;
+    let code = guard(AssertUnwindSafe(|| {
+        let Some(total) = inner.encode_len() else {
+            return MQTT_ERR_ENCODE;
+        };
+        len = total;
+        if total > buf.len() {
+            return MQTT_ERR_BUFFER_TOO_SMALL;
+        }
+        match inner.encode_into(buf) {
+            Some(_) => MQTT_OK,
+            None => MQTT_ERR_ENCODE,
+        }
+    }));
+    *out_len = if code == MQTT_OK || code == MQTT_ERR_BUFFER_TOO_SMALL {
+        len
+    } else {
+        0
+    };
+    code
+}
+
+#[cfg(test)]
+mod tests {
+    use std::ffi::CStr;
+    use std::ptr;
+
+    use super::*;
+
+    #[test]
+    fn test_ffi_publish() {
+        let topic = b"a/b";
+        let payload = b"hello";
+        let mut packet = ptr::null_mut();
+        let code = unsafe {
+            mqtt_publish_new(
+                5,
+                topic.as_ptr(),
+                topic.len(),
+                payload.as_ptr(),
+                payload.len(),
+                1,
+                10,
+                false,
+                &mut packet,
+            )
+        };
+        assert_eq!(code, MQTT_OK);
+        assert_eq!(unsafe { mqtt_packet_type(packet) }, 3);
+        assert_eq!(unsafe { mqtt_packet_pid(packet) }, 10);
+
+        let mut len = 0;
+        let code = unsafe { mqtt_packet_encode(packet, ptr::null_mut(), 0, &mut len) };
+        assert_eq!(code, MQTT_ERR_BUFFER_TOO_SMALL);
+        let mut buf = vec![0u8; len];
+        let code = unsafe { mqtt_packet_encode(packet, buf.as_mut_ptr(), buf.len(), &mut len) };
+        assert_eq!(code, MQTT_OK);
+        assert_eq!(len, buf.len());
+        unsafe { mqtt_packet_free(packet) };
+
+        let mut decoded = ptr::null_mut();
+        let mut consumed = 0;
+        let code = unsafe { mqtt_decode(5, buf.as_ptr(), len - 1, &mut decoded, &mut consumed) };
+        assert_eq!(code, MQTT_INCOMPLETE);
+        let code = unsafe { mqtt_decode(5, buf.as_ptr(), len, &mut decoded, &mut consumed) };
+        assert_eq!(code, MQTT_OK);
+        assert_eq!(consumed, len);
+
+        let (mut topic_ptr, mut topic_len) = (ptr::null(), 0);
+        let (mut payload_ptr, mut payload_len) = (ptr::null(), 0);
+        let mut qos = 0;
+        let code = unsafe {
+            mqtt_publish_get(
+                decoded,
+                &mut topic_ptr,
+                &mut topic_len,
+                &mut payload_ptr,
+                &mut payload_len,
+                &mut qos,
+            )
+        };
+        assert_eq!(code, MQTT_OK);
+        assert_eq!(
+            unsafe { slice::from_raw_parts(topic_ptr, topic_len) },
+            topic
+        );
+        assert_eq!(
+            unsafe { slice::from_raw_parts(payload_ptr, payload_len) },
+            payload
+        );
+        assert_eq!(qos, 1);
+        unsafe { mqtt_packet_free(decoded) };
+    }
+
+    #[test]
+    fn test_ffi_errors() {
+        let mut packet = ptr::null_mut();
+        let mut consumed = 0;
+        // CONNACK with an invalid return code
+        let data = [0b0010_0000, 2, 0, 9];
+        let code = unsafe { mqtt_decode(4, data.as_ptr(), 4, &mut packet, &mut consumed) };
+        assert_eq!(code, MQTT_ERR_MALFORMED);
+        let code = unsafe { mqtt_decode(9, data.as_ptr(), 4, &mut packet, &mut consumed) };
+        assert_eq!(code, MQTT_ERR_INVALID_ARG);
+        let code = unsafe { mqtt_decode(4, ptr::null(), 4, &mut packet, &mut consumed) };
+        assert_eq!(code, MQTT_ERR_NULL);
+        assert!(packet.is_null());
+
+        let msg = unsafe { CStr::from_ptr(mqtt_error_str(MQTT_ERR_MALFORMED)) };
+        assert_eq!(msg.to_str().unwrap(), "malformed packet");
+    }
+}
@@ -1,15 +1,41 @@
 mod common;
+#[cfg(feature = "ffi")]
+pub mod ffi;
 pub mod v3;
 pub mod v5;
 
 pub(crate) use common::{
-    decode_var_int, encode_packet, packet_from, read_bytes, read_string, read_u16, read_u32,
-    read_u8, write_bytes, write_u16, write_u32, write_u8, write_var_int,
+    block_decode_exact, capacity_hint, check_body_end, copy_exact, decode_exact, decode_frame,
+    decode_raw_header_len, decode_raw_header_slice, decode_var_int, encode_packet_to, fmt_list,
+    fmt_publish, packet_from, peek_str, peek_u16, read_bytes, read_exact_capped, read_string,
+    read_u16, read_u8, redacted, split_by_packet_size, write_bytes, write_u16, write_u32, write_u8,
+    write_var_int, HexdumpWriter, SliceReader, BRIDGE_BIT, MAX_RESERVE,
 };
 
+#[cfg(feature = "json")]
+pub(crate) use common::json_hex;
+#[cfg(feature = "embedded-io")]
+pub(crate) use common::{read_packet_embedded, write_all_embedded};
+
+#[cfg(feature = "conformance")]
+pub use common::conformance;
+#[cfg(feature = "proptest")]
+pub use common::strategy;
+#[cfg(feature = "proxy-protocol")]
+pub use common::ProxyHeader;
 pub use common::{
-    decode_raw_header, header_len, remaining_len, total_len, var_int_len, Encodable, Error,
-    GenericPollBodyState, GenericPollPacket, GenericPollPacketState, Pid, PollHeader,
-    PollHeaderState, Protocol, QoS, QosPid, TopicFilter, TopicName, VarBytes, LEVEL_SEP,
-    MATCH_ALL_CHAR, MATCH_ALL_STR, MATCH_ONE_CHAR, MATCH_ONE_STR, SHARED_PREFIX, SYS_PREFIX,
+    check_utf8_strict, decode_connect, decode_raw_header, effective_qos, header_len, remaining_len,
+    sniff_first_bytes, total_len, var_int_len, AnyConnect, CaptureReader, CaptureRecord,
+    CaptureWriter, CodecCounters, CodecStats, DecodeError, DecodeLimits, Direction, Encodable,
+    EncodedPacket, Error, FeedStatus, GenericOwnedPacketReader, GenericPacketDecoder,
+    GenericPacketSink, GenericPacketStream, GenericPollBodyState, GenericPollEncode,
+    GenericPollPacket, GenericPollPacketState, OwnedRead, Pid, PidAllocator, PollEncodable,
+    PollEncodeState, PollHeader, PollHeaderState, PollTimeout, Protocol, QoS, QosPid,
+    RetainedStore, RetainedTree, ScratchBufferPool, SharedStr, SniffedProtocol, TopicFilter,
+    TopicInterner, TopicName, TopicTree, VarBytes, LEVEL_SEP, MATCH_ALL_CHAR, MATCH_ALL_STR,
+    MATCH_ONE_CHAR, MATCH_ONE_STR, SHARED_PREFIX, SYS_PREFIX,
 };
+#[cfg(feature = "websocket")]
+pub use common::{split_frames, WsFrames, WsPacketDecoder};
+#[cfg(any(test, feature = "testing"))]
+pub use common::{MockBuffer, MockBufferHandle};
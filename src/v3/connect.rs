@@ -1,25 +1,45 @@
 use std::convert::TryFrom;
+use std::fmt;
 use std::io;
-use std::sync::Arc;
 
 use bytes::Bytes;
 use tokio::io::{AsyncRead, AsyncReadExt};
 
 use crate::{
-    read_bytes, read_string, read_u16, read_u8, write_bytes, write_u16, write_u8, Encodable, Error,
-    Protocol, QoS, TopicName,
+    read_bytes, read_string, read_u16, read_u8, redacted, write_bytes, write_u16, write_u8,
+    Encodable, Error, Protocol, QoS, SharedStr, SliceReader, TopicName, BRIDGE_BIT,
 };
 
 /// Connect packet body type.
-#[derive(Debug, Clone, PartialEq, Eq)]
+#[derive(Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct Connect {
     pub protocol: Protocol,
     pub clean_session: bool,
     pub keep_alive: u16,
-    pub client_id: Arc<String>,
+    pub client_id: SharedStr,
     pub last_will: Option<LastWill>,
-    pub username: Option<Arc<String>>,
+    pub username: Option<SharedStr>,
     pub password: Option<Bytes>,
+    /// The bridge bit of the protocol level (0x83 or 0x84), set by a
+    /// Mosquitto style bridge connecting to another broker.
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub is_bridge: bool,
+}
+
+impl fmt::Debug for Connect {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Connect")
+            .field("protocol", &self.protocol)
+            .field("clean_session", &self.clean_session)
+            .field("keep_alive", &self.keep_alive)
+            .field("client_id", &self.client_id)
+            .field("last_will", &self.last_will)
+            .field("username", &self.username)
+            .field("password", &redacted(&self.password))
+            .field("is_bridge", &self.is_bridge)
+            .finish()
+    }
 }
 
 #[cfg(feature = "arbitrary")]
@@ -33,26 +53,59 @@ impl<'a> arbitrary::Arbitrary<'a> for Connect {
             last_will: u.arbitrary()?,
             username: u.arbitrary()?,
             password: Option::<Vec<u8>>::arbitrary(u)?.map(Bytes::from),
+            is_bridge: u.arbitrary()?,
         })
     }
 }
 
 impl Connect {
-    pub fn new(client_id: Arc<String>, keep_alive: u16) -> Self {
+    pub fn new(client_id: impl Into<SharedStr>, keep_alive: u16) -> Self {
         Connect {
             protocol: Protocol::V311,
             clean_session: true,
             keep_alive,
-            client_id,
+            client_id: client_id.into(),
             last_will: None,
             username: None,
             password: None,
+            is_bridge: false,
+        }
+    }
+
+    /// Validate the client identifier by the [MQTT 3.1.1] recommendation:
+    /// 1 to 23 characters from `[0-9a-zA-Z]`. A zero-length identifier is
+    /// allowed (the server assigns one) only with clean session, and never
+    /// allowed in v3.1.
+    ///
+    /// The decoder accepts any client identifier, servers can opt in to this
+    /// check and reply CONNACK with `ClientIdError::return_code()`.
+    ///
+    /// [MQTT 3.1.1]: http://docs.oasis-open.org/mqtt/mqtt/v3.1.1/os/mqtt-v3.1.1-os.html#_Toc398718031
+    pub fn validate_client_id_strict(&self) -> Result<(), ClientIdError> {
+        if self.client_id.is_empty() {
+            return if self.protocol == Protocol::V310 {
+                Err(ClientIdError::Empty)
+            } else if !self.clean_session {
+                Err(ClientIdError::EmptyWithoutCleanSession)
+            } else {
+                Ok(())
+            };
         }
+        if let Some(c) = self.client_id.chars().find(|c| !c.is_ascii_alphanumeric()) {
+            return Err(ClientIdError::InvalidChar(c));
+        }
+        if self.client_id.len() > 23 {
+            return Err(ClientIdError::TooLong(self.client_id.len()));
+        }
+        Ok(())
     }
 
     pub async fn decode_async<T: AsyncRead + Unpin>(reader: &mut T) -> Result<Self, Error> {
-        let protocol = Protocol::decode_async(reader).await?;
-        Self::decode_with_protocol(reader, protocol).await
+        let name = read_bytes(reader).await?;
+        let (protocol, is_bridge) = Protocol::new_bridge(&name, read_u8(reader).await?)?;
+        let mut connect = Self::decode_with_protocol(reader, protocol).await?;
+        connect.is_bridge = is_bridge;
+        Ok(connect)
     }
 
     #[inline]
@@ -68,7 +121,7 @@ impl Connect {
             return Err(Error::InvalidConnectFlags(connect_flags));
         }
         let keep_alive = read_u16(reader).await?;
-        let client_id = Arc::new(read_string(reader).await?);
+        let client_id = SharedStr::from(read_string(reader).await?);
         let last_will = if connect_flags & 0b100 != 0 {
             let topic_name = read_string(reader).await?;
             let message = read_bytes(reader).await?;
@@ -86,7 +139,7 @@ impl Connect {
             None
         };
         let username = if connect_flags & 0b10000000 != 0 {
-            Some(Arc::new(read_string(reader).await?))
+            Some(SharedStr::from(read_string(reader).await?))
         } else {
             None
         };
@@ -104,6 +157,7 @@ impl Connect {
             password,
             last_will,
             clean_session,
+            is_bridge: false,
         })
     }
 }
@@ -128,7 +182,13 @@ impl Encodable for Connect {
             }
         }
 
-        self.protocol.encode(writer)?;
+        if self.is_bridge {
+            let (name, level) = self.protocol.to_pair();
+            write_bytes(writer, name)?;
+            write_u8(writer, level | BRIDGE_BIT)?;
+        } else {
+            self.protocol.encode(writer)?;
+        }
         write_u8(writer, connect_flags)?;
         write_u16(writer, self.keep_alive)?;
         write_bytes(writer, self.client_id.as_bytes())?;
@@ -166,6 +226,7 @@ impl Encodable for Connect {
 /// Connack packet body type.
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 #[cfg_attr(feature = "arbitrary", derive(arbitrary::Arbitrary))]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct Connack {
     pub session_present: bool,
     pub code: ConnectReturnCode,
@@ -182,6 +243,11 @@ impl Connack {
     pub async fn decode_async<T: AsyncRead + Unpin>(reader: &mut T) -> Result<Self, Error> {
         let mut payload = [0u8; 2];
         reader.read_exact(&mut payload).await?;
+        Self::decode_slice(&mut SliceReader(&payload))
+    }
+
+    pub(crate) fn decode_slice(reader: &mut SliceReader<'_>) -> Result<Self, Error> {
+        let payload = reader.take(2)?;
         let session_present = match payload[0] {
             0 => false,
             1 => true,
@@ -202,6 +268,7 @@ impl Connack {
 /// [Connect]: struct.Connect.html
 /// [MQTT 3.1.3.3]: http://docs.oasis-open.org/mqtt/mqtt/v3.1.1/os/mqtt-v3.1.1-os.html#_Toc398718031
 #[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct LastWill {
     pub qos: QoS,
     pub retain: bool,
@@ -222,12 +289,12 @@ impl<'a> arbitrary::Arbitrary<'a> for LastWill {
 }
 
 impl LastWill {
-    pub fn new(qos: QoS, topic_name: TopicName, message: Bytes) -> Self {
+    pub fn new(qos: QoS, topic_name: TopicName, message: impl Into<Bytes>) -> Self {
         LastWill {
             qos,
             retain: false,
             topic_name,
-            message,
+            message: message.into(),
         }
     }
 }
@@ -244,6 +311,33 @@ impl Encodable for LastWill {
     }
 }
 
+/// Client identifier violations reported by `Connect::validate_client_id_strict()`.
+#[derive(thiserror::Error, Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum ClientIdError {
+    /// Zero-length client identifier is not allowed in v3.1.
+    #[error("zero-length client identifier")]
+    Empty,
+
+    /// Zero-length client identifier requires clean session.
+    #[error("zero-length client identifier without clean session")]
+    EmptyWithoutCleanSession,
+
+    /// More than 23 characters, the value is the length.
+    #[error("client identifier too long: {0}")]
+    TooLong(usize),
+
+    /// A character not in `[0-9a-zA-Z]`.
+    #[error("invalid character in client identifier: {0:?}")]
+    InvalidChar(char),
+}
+
+impl ClientIdError {
+    /// The return code of the CONNACK packet for the violation.
+    pub fn return_code(&self) -> ConnectReturnCode {
+        ConnectReturnCode::IdentifierRejected
+    }
+}
+
 /// Return code of a [Connack] packet.
 ///
 /// See [MQTT 3.2.2.3] for interpretations.
@@ -253,6 +347,7 @@ impl Encodable for LastWill {
 #[repr(u8)]
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
 #[cfg_attr(feature = "arbitrary", derive(arbitrary::Arbitrary))]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum ConnectReturnCode {
     Accepted = 0,
     UnacceptableProtocolVersion = 1,
@@ -274,4 +369,29 @@ impl ConnectReturnCode {
             n => Err(Error::InvalidConnectReturnCode(n)),
         }
     }
+
+    /// The description of the return code in the specification.
+    pub fn description(&self) -> &'static str {
+        match self {
+            ConnectReturnCode::Accepted => "Connection accepted.",
+            ConnectReturnCode::UnacceptableProtocolVersion => {
+                "The Server does not support the level of the MQTT protocol requested by the Client."
+            }
+            ConnectReturnCode::IdentifierRejected => {
+                "The Client identifier is correct UTF-8 but not allowed by the Server."
+            }
+            ConnectReturnCode::ServerUnavailable => {
+                "The Network Connection has been made but the MQTT service is unavailable."
+            }
+            ConnectReturnCode::BadUserNameOrPassword => {
+                "The data in the user name or password is malformed."
+            }
+            ConnectReturnCode::NotAuthorized => "The Client is not authorized to connect.",
+        }
+    }
+
+    /// All the return codes other than `Accepted` indicate an error.
+    pub fn is_error(&self) -> bool {
+        *self != ConnectReturnCode::Accepted
+    }
 }
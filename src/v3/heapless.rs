@@ -0,0 +1,306 @@
+//! Fixed capacity decoding into `heapless` containers.
+//!
+//! The packets are decoded by [`PacketRef`] then copied into `heapless`
+//! containers with const-generic capacities:
+//!
+//!   * `S`: capacity of client identifier, username, topic names and topic filters.
+//!   * `P`: capacity of payload, password and will message.
+//!   * `N`: max number of topics in SUBSCRIBE, SUBACK and UNSUBSCRIBE.
+//!
+//! A value exceeding the capacity is reported as `Error::DecodeLimitExceeded`.
+//! Decoding a valid packet does not allocate, only some errors do.
+//!
+//! The crate still requires `std` (it is not `no_std` yet, see README), so
+//! this is an option for `std` users who want a bounded memory use per
+//! packet, not a way to build for targets without a heap.
+
+use std::fmt;
+
+use heapless::{String, Vec};
+
+use super::{
+    Connack, ConnectRef, LastWillRef, PacketRef, PublishRef, SubackRef, SubscribeRef,
+    SubscribeReturnCode, UnsubscribeRef,
+};
+use crate::{redacted, Error, Pid, Protocol, QoS, QosPid};
+
+/// MQTT v3.x packet in heapless containers, see the [module docs](self).
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum HeaplessPacket<const S: usize, const P: usize, const N: usize> {
+    Connect(HeaplessConnect<S, P>),
+    Connack(Connack),
+    Publish(HeaplessPublish<S, P>),
+    Puback(Pid),
+    Pubrec(Pid),
+    Pubrel(Pid),
+    Pubcomp(Pid),
+    Subscribe(HeaplessSubscribe<S, N>),
+    Suback(HeaplessSuback<N>),
+    Unsubscribe(HeaplessUnsubscribe<S, N>),
+    Unsuback(Pid),
+    Pingreq,
+    Pingresp,
+    Disconnect,
+}
+
+/// Heapless version of [`Connect`](super::Connect).
+#[derive(Clone, PartialEq, Eq)]
+pub struct HeaplessConnect<const S: usize, const P: usize> {
+    pub protocol: Protocol,
+    pub clean_session: bool,
+    pub keep_alive: u16,
+    pub client_id: String<S>,
+    pub last_will: Option<HeaplessLastWill<S, P>>,
+    pub username: Option<String<S>>,
+    pub password: Option<Vec<u8, P>>,
+    pub is_bridge: bool,
+}
+
+impl<const S: usize, const P: usize> fmt::Debug for HeaplessConnect<S, P> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("HeaplessConnect")
+            .field("protocol", &self.protocol)
+            .field("clean_session", &self.clean_session)
+            .field("keep_alive", &self.keep_alive)
+            .field("client_id", &self.client_id)
+            .field("last_will", &self.last_will)
+            .field("username", &self.username)
+            .field("password", &redacted(&self.password))
+            .field("is_bridge", &self.is_bridge)
+            .finish()
+    }
+}
+
+/// Heapless version of [`LastWill`](super::LastWill).
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct HeaplessLastWill<const S: usize, const P: usize> {
+    pub qos: QoS,
+    pub retain: bool,
+    pub topic_name: String<S>,
+    pub message: Vec<u8, P>,
+}
+
+/// Heapless version of [`Publish`](super::Publish).
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct HeaplessPublish<const S: usize, const P: usize> {
+    pub dup: bool,
+    pub retain: bool,
+    pub qos_pid: QosPid,
+    pub topic_name: String<S>,
+    pub payload: Vec<u8, P>,
+}
+
+/// Heapless version of [`Subscribe`](super::Subscribe).
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct HeaplessSubscribe<const S: usize, const N: usize> {
+    pub pid: Pid,
+    pub topics: Vec<(String<S>, QoS), N>,
+}
+
+/// Heapless version of [`Suback`](super::Suback).
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct HeaplessSuback<const N: usize> {
+    pub pid: Pid,
+    pub topics: Vec<SubscribeReturnCode, N>,
+}
+
+/// Heapless version of [`Unsubscribe`](super::Unsubscribe).
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct HeaplessUnsubscribe<const S: usize, const N: usize> {
+    pub pid: Pid,
+    pub topics: Vec<String<S>, N>,
+}
+
+impl<const S: usize, const P: usize, const N: usize> HeaplessPacket<S, P, N> {
+    /// Decode a packet from a slice. If not enough bytes to decode a packet,
+    /// it will return `Ok(None)`. The slice is advanced past the decoded
+    /// packet only on success.
+    pub fn decode(buf: &mut &[u8]) -> Result<Option<Self>, Error> {
+        let mut reader = *buf;
+        let packet = match PacketRef::decode(&mut reader)? {
+            Some(packet) => Self::from_ref(&packet)?,
+            None => return Ok(None),
+        };
+        *buf = reader;
+        Ok(Some(packet))
+    }
+
+    /// Copy a borrowed packet into heapless containers.
+    pub fn from_ref(packet: &PacketRef<'_>) -> Result<Self, Error> {
+        Ok(match packet {
+            PacketRef::Connect(inner) => HeaplessPacket::Connect(HeaplessConnect::from_ref(inner)?),
+            PacketRef::Connack(inner) => HeaplessPacket::Connack(*inner),
+            PacketRef::Publish(inner) => HeaplessPacket::Publish(HeaplessPublish::from_ref(inner)?),
+            PacketRef::Puback(pid) => HeaplessPacket::Puback(*pid),
+            PacketRef::Pubrec(pid) => HeaplessPacket::Pubrec(*pid),
+            PacketRef::Pubrel(pid) => HeaplessPacket::Pubrel(*pid),
+            PacketRef::Pubcomp(pid) => HeaplessPacket::Pubcomp(*pid),
+            PacketRef::Subscribe(inner) => {
+                HeaplessPacket::Subscribe(HeaplessSubscribe::from_ref(inner)?)
+            }
+            PacketRef::Suback(inner) => HeaplessPacket::Suback(HeaplessSuback::from_ref(inner)?),
+            PacketRef::Unsubscribe(inner) => {
+                HeaplessPacket::Unsubscribe(HeaplessUnsubscribe::from_ref(inner)?)
+            }
+            PacketRef::Unsuback(pid) => HeaplessPacket::Unsuback(*pid),
+            PacketRef::Pingreq => HeaplessPacket::Pingreq,
+            PacketRef::Pingresp => HeaplessPacket::Pingresp,
+            PacketRef::Disconnect => HeaplessPacket::Disconnect,
+        })
+    }
+}
+
+impl<const S: usize, const P: usize> HeaplessConnect<S, P> {
+    pub fn from_ref(connect: &ConnectRef<'_>) -> Result<Self, Error> {
+        Ok(HeaplessConnect {
+            protocol: connect.protocol,
+            clean_session: connect.clean_session,
+            is_bridge: connect.is_bridge,
+            keep_alive: connect.keep_alive,
+            client_id: to_string(connect.client_id)?,
+            last_will: connect
+                .last_will
+                .as_ref()
+                .map(HeaplessLastWill::from_ref)
+                .transpose()?,
+            username: connect.username.map(to_string).transpose()?,
+            password: connect.password.map(to_vec).transpose()?,
+        })
+    }
+}
+
+impl<const S: usize, const P: usize> HeaplessLastWill<S, P> {
+    pub fn from_ref(last_will: &LastWillRef<'_>) -> Result<Self, Error> {
+        Ok(HeaplessLastWill {
+            qos: last_will.qos,
+            retain: last_will.retain,
+            topic_name: to_string(last_will.topic_name)?,
+            message: to_vec(last_will.message)?,
+        })
+    }
+}
+
+impl<const S: usize, const P: usize> HeaplessPublish<S, P> {
+    pub fn from_ref(publish: &PublishRef<'_>) -> Result<Self, Error> {
+        Ok(HeaplessPublish {
+            dup: publish.dup,
+            retain: publish.retain,
+            qos_pid: publish.qos_pid,
+            topic_name: to_string(publish.topic_name)?,
+            payload: to_vec(publish.payload)?,
+        })
+    }
+}
+
+impl<const S: usize, const N: usize> HeaplessSubscribe<S, N> {
+    pub fn from_ref(subscribe: &SubscribeRef<'_>) -> Result<Self, Error> {
+        let mut topics = Vec::new();
+        for (filter, qos) in subscribe.topics() {
+            topics
+                .push((to_string(filter)?, qos))
+                .map_err(|_| topics_exceeded())?;
+        }
+        Ok(HeaplessSubscribe {
+            pid: subscribe.pid,
+            topics,
+        })
+    }
+}
+
+impl<const N: usize> HeaplessSuback<N> {
+    pub fn from_ref(suback: &SubackRef<'_>) -> Result<Self, Error> {
+        let mut topics = Vec::new();
+        for code in suback.topics() {
+            topics.push(code).map_err(|_| topics_exceeded())?;
+        }
+        Ok(HeaplessSuback {
+            pid: suback.pid,
+            topics,
+        })
+    }
+}
+
+impl<const S: usize, const N: usize> HeaplessUnsubscribe<S, N> {
+    pub fn from_ref(unsubscribe: &UnsubscribeRef<'_>) -> Result<Self, Error> {
+        let mut topics = Vec::new();
+        for filter in unsubscribe.topics() {
+            topics
+                .push(to_string(filter)?)
+                .map_err(|_| topics_exceeded())?;
+        }
+        Ok(HeaplessUnsubscribe {
+            pid: unsubscribe.pid,
+            topics,
+        })
+    }
+}
+
+fn to_string<const S: usize>(value: &str) -> Result<String<S>, Error> {
+    String::try_from(value).map_err(|_| Error::DecodeLimitExceeded("string capacity"))
+}
+
+fn to_vec<const P: usize>(value: &[u8]) -> Result<Vec<u8, P>, Error> {
+    Vec::from_slice(value).map_err(|_| Error::DecodeLimitExceeded("bytes capacity"))
+}
+
+fn topics_exceeded() -> Error {
+    Error::DecodeLimitExceeded("topics capacity")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    type Packet = HeaplessPacket<8, 8, 2>;
+
+    #[test]
+    fn test_decode() {
+        // CONNECT with client identifier "abc", then SUBSCRIBE "a/b"
+        let data = [
+            0x10, 15, 0, 4, b'M', b'Q', b'T', b'T', 4, 0b10, 0, 60, 0, 3, b'a', b'b', b'c',
+        ];
+        let mut buf = &data[..];
+        match Packet::decode(&mut buf).unwrap().unwrap() {
+            HeaplessPacket::Connect(connect) => {
+                assert_eq!(connect.client_id.as_str(), "abc");
+                assert_eq!(connect.keep_alive, 60);
+                assert!(connect.clean_session);
+            }
+            packet => panic!("unexpected packet: {packet:?}"),
+        }
+        assert!(buf.is_empty());
+
+        let data = [0x82, 8, 0, 1, 0, 3, b'a', b'/', b'b', 1];
+        let mut buf = &data[..];
+        match Packet::decode(&mut buf).unwrap().unwrap() {
+            HeaplessPacket::Subscribe(subscribe) => {
+                assert_eq!(subscribe.topics.len(), 1);
+                assert_eq!(subscribe.topics[0].0.as_str(), "a/b");
+                assert_eq!(subscribe.topics[0].1, QoS::Level1);
+            }
+            packet => panic!("unexpected packet: {packet:?}"),
+        }
+        assert_eq!(Packet::decode(&mut &data[..5]), Ok(None));
+    }
+
+    #[test]
+    fn test_capacity_exceeded() {
+        // PUBLISH with a 9 bytes payload
+        let mut data = vec![0x30, 12, 0, 1, b'a'];
+        data.extend([b'x'; 9]);
+        let mut buf = &data[..];
+        assert_eq!(
+            Packet::decode(&mut buf),
+            Err(Error::DecodeLimitExceeded("bytes capacity"))
+        );
+        // The slice is untouched on error
+        assert_eq!(buf.len(), data.len());
+        assert!(HeaplessPacket::<8, 16, 2>::decode(&mut buf).is_ok());
+
+        let data = [0xA2, 8, 0, 1, 0, 1, b'a', 0, 1, b'b'];
+        assert_eq!(
+            HeaplessPacket::<8, 8, 1>::decode(&mut &data[..]),
+            Err(Error::DecodeLimitExceeded("topics capacity"))
+        );
+    }
+}
@@ -0,0 +1,69 @@
+use crate::HexdumpWriter;
+
+/// Format MQTT v3.x packet bytes as a hexdump annotated with the fields
+/// (fixed header, remaining length, variable header and payload), for
+/// debugging interoperability issues.
+///
+/// All the packets in `data` are annotated, malformed or incomplete packets
+/// are annotated as far as possible.
+pub fn annotated_hexdump(data: &[u8]) -> String {
+    HexdumpWriter::annotate(data, annotate_body)
+}
+
+fn annotate_body(writer: &mut HexdumpWriter<'_>, control_byte: u8) -> Option<()> {
+    match control_byte >> 4 {
+        1 => {
+            writer.string("protocol name")?;
+            writer.u8("protocol level")?;
+            let flags = writer.connect_flags("clean_session")?;
+            writer.u16("keep alive")?;
+            writer.string("client identifier")?;
+            if flags & 0b100 != 0 {
+                writer.string("will topic")?;
+                writer.binary("will message")?;
+            }
+            if flags & 0b10000000 != 0 {
+                writer.string("username")?;
+            }
+            if flags & 0b01000000 != 0 {
+                writer.binary("password")?;
+            }
+        }
+        2 => {
+            writer.u8("connack flags")?;
+            writer.u8("return code")?;
+        }
+        3 => {
+            writer.string("topic name")?;
+            if control_byte & 0b110 != 0 {
+                writer.u16("packet identifier")?;
+            }
+            writer.rest("payload");
+        }
+        4..=7 | 11 => {
+            writer.u16("packet identifier")?;
+        }
+        8 => {
+            writer.u16("packet identifier")?;
+            while writer.remaining() > 0 {
+                writer.string("topic filter")?;
+                writer.u8("requested qos")?;
+            }
+        }
+        9 => {
+            writer.u16("packet identifier")?;
+            while writer.remaining() > 0 {
+                writer.u8("return code")?;
+            }
+        }
+        10 => {
+            writer.u16("packet identifier")?;
+            while writer.remaining() > 0 {
+                writer.string("topic filter")?;
+            }
+        }
+        12..=14 => {}
+        _ => return None,
+    }
+    Some(())
+}
@@ -0,0 +1,88 @@
+use serde_json::{json, Value};
+
+use super::{LastWill, Packet, Publish};
+use crate::json_hex;
+
+impl Packet {
+    /// A structured JSON representation for protocol analysis tools and
+    /// snapshot tests, the binary fields are hex encoded.
+    ///
+    /// The layout is not the serde representation of the packet, the `type`
+    /// field is the packet type name such as `"PUBLISH"`.
+    pub fn to_json(&self) -> Value {
+        match self {
+            Packet::Connect(connect) => json!({
+                "type": "CONNECT",
+                "protocol": connect.protocol.to_string(),
+                "clean_session": connect.clean_session,
+                "keep_alive": connect.keep_alive,
+                "client_id": &*connect.client_id,
+                "last_will": connect.last_will.as_ref().map(last_will_json),
+                "username": connect.username.as_deref(),
+                "password": connect.password.as_deref().map(json_hex),
+            }),
+            Packet::Connack(connack) => json!({
+                "type": "CONNACK",
+                "session_present": connack.session_present,
+                "code": format!("{:?}", connack.code),
+            }),
+            Packet::Publish(publish) => publish_json(publish),
+            Packet::Puback(pid) => json!({ "type": "PUBACK", "pid": pid.value() }),
+            Packet::Pubrec(pid) => json!({ "type": "PUBREC", "pid": pid.value() }),
+            Packet::Pubrel(pid) => json!({ "type": "PUBREL", "pid": pid.value() }),
+            Packet::Pubcomp(pid) => json!({ "type": "PUBCOMP", "pid": pid.value() }),
+            Packet::Subscribe(subscribe) => json!({
+                "type": "SUBSCRIBE",
+                "pid": subscribe.pid.value(),
+                "topics": subscribe
+                    .topics
+                    .iter()
+                    .map(|(filter, qos)| json!({ "topic_filter": &**filter, "qos": *qos as u8 }))
+                    .collect::<Vec<_>>(),
+            }),
+            Packet::Suback(suback) => json!({
+                "type": "SUBACK",
+                "pid": suback.pid.value(),
+                "codes": suback
+                    .topics
+                    .iter()
+                    .map(|code| format!("{code:?}"))
+                    .collect::<Vec<_>>(),
+            }),
+            Packet::Unsubscribe(unsubscribe) => json!({
+                "type": "UNSUBSCRIBE",
+                "pid": unsubscribe.pid.value(),
+                "topics": unsubscribe
+                    .topics
+                    .iter()
+                    .map(|filter| &**filter)
+                    .collect::<Vec<_>>(),
+            }),
+            Packet::Unsuback(pid) => json!({ "type": "UNSUBACK", "pid": pid.value() }),
+            Packet::Pingreq => json!({ "type": "PINGREQ" }),
+            Packet::Pingresp => json!({ "type": "PINGRESP" }),
+            Packet::Disconnect => json!({ "type": "DISCONNECT" }),
+        }
+    }
+}
+
+fn last_will_json(last_will: &LastWill) -> Value {
+    json!({
+        "qos": last_will.qos as u8,
+        "retain": last_will.retain,
+        "topic_name": &*last_will.topic_name,
+        "message": json_hex(&last_will.message),
+    })
+}
+
+fn publish_json(publish: &Publish) -> Value {
+    json!({
+        "type": "PUBLISH",
+        "dup": publish.dup,
+        "retain": publish.retain,
+        "qos": publish.qos_pid.qos() as u8,
+        "pid": publish.qos_pid.pid().map(|pid| pid.value()),
+        "topic_name": &*publish.topic_name,
+        "payload": json_hex(&publish.payload),
+    })
+}
@@ -0,0 +1,76 @@
+use bytes::Bytes;
+
+use super::{Header, Packet, PacketType};
+use crate::{peek_str, peek_u16, Error, Pid, QoS};
+
+/// A packet with only the fixed header decoded.
+///
+/// The packet type, flags, packet identifier and topic name (of PUBLISH) are
+/// read from the raw body on demand, the body is fully decoded (and
+/// validated) only when `into_packet()` is called. Useful for routers that
+/// only need the topic name.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LazyPacket {
+    header: Header,
+    body: Bytes,
+}
+
+impl LazyPacket {
+    /// Create from the fixed header and the raw body, see
+    /// `Packet::decode_header_only()`.
+    pub fn new(header: Header, body: Bytes) -> Self {
+        LazyPacket { header, body }
+    }
+
+    /// Decode the fixed header from a shared bytes buffer. If not enough bytes
+    /// to decode a packet, it will return `Ok(None)` and the buffer is
+    /// untouched, otherwise the buffer is advanced past the packet.
+    pub fn decode(buf: &mut Bytes) -> Result<Option<Self>, Error> {
+        Ok(Packet::decode_header_only(buf)?.map(|(header, body)| Self::new(header, body)))
+    }
+
+    pub fn header(&self) -> &Header {
+        &self.header
+    }
+
+    pub fn packet_type(&self) -> PacketType {
+        self.header.typ
+    }
+
+    /// The raw body (without the fixed header).
+    pub fn body(&self) -> &Bytes {
+        &self.body
+    }
+
+    /// The packet identifier, `None` if the packet does not have one.
+    pub fn pid(&self) -> Result<Option<Pid>, Error> {
+        let body: &[u8] = match self.header.typ {
+            PacketType::Publish if self.header.qos == QoS::Level0 => return Ok(None),
+            PacketType::Publish => peek_str(&self.body)?.1,
+            PacketType::Puback
+            | PacketType::Pubrec
+            | PacketType::Pubrel
+            | PacketType::Pubcomp
+            | PacketType::Subscribe
+            | PacketType::Suback
+            | PacketType::Unsubscribe
+            | PacketType::Unsuback => &self.body,
+            _ => return Ok(None),
+        };
+        Ok(Some(Pid::try_from(peek_u16(body)?.0)?))
+    }
+
+    /// The topic name of a PUBLISH packet. The value is valid utf8 but not
+    /// validated as a topic name until `into_packet()`.
+    pub fn topic_name(&self) -> Result<Option<&str>, Error> {
+        if self.header.typ != PacketType::Publish {
+            return Ok(None);
+        }
+        Ok(Some(peek_str(&self.body)?.0))
+    }
+
+    /// Decode the body into a full packet.
+    pub fn into_packet(self) -> Result<Packet, Error> {
+        Packet::decode_body(self.header, self.body)
+    }
+}
@@ -4,16 +4,37 @@
 //! [v3.1]: https://public.dhe.ibm.com/software/dw/webservices/ws-mqtt/mqtt-v3r1.html
 
 mod connect;
+#[cfg(feature = "heapless")]
+pub mod heapless;
+mod hexdump;
+mod iter;
+#[cfg(feature = "json")]
+mod json;
+mod lazy;
 mod packet;
+mod packet_ref;
 mod poll;
 mod publish;
+#[cfg(feature = "proptest")]
+pub mod strategy;
 mod subscribe;
+#[cfg(any(test, feature = "testing"))]
+pub mod testing;
 
 #[cfg(test)]
 mod tests;
 
-pub use connect::{Connack, Connect, ConnectReturnCode, LastWill};
+pub use connect::{ClientIdError, Connack, Connect, ConnectReturnCode, LastWill};
+pub use hexdump::annotated_hexdump;
+pub use iter::{BytesMutPacketIter, PacketIter};
+pub use lazy::LazyPacket;
 pub use packet::{Header, Packet, PacketType};
-pub use poll::{PollBodyState, PollPacket, PollPacketState};
+pub use packet_ref::{
+    ConnectRef, LastWillRef, PacketRef, PublishRef, SubackRef, SubscribeRef, UnsubscribeRef,
+};
+pub use poll::{
+    OwnedPacketReader, PacketDecoder, PacketSink, PacketStream, PollBodyState, PollEncode,
+    PollPacket, PollPacketState,
+};
 pub use publish::Publish;
 pub use subscribe::{Suback, Subscribe, SubscribeReturnCode, Unsubscribe};
@@ -1,16 +1,23 @@
+use bytes::{Buf, BufMut, Bytes, BytesMut};
 use futures_lite::future::block_on;
 use std::convert::AsRef;
+use std::fmt;
+use std::io;
 use tokio::io::{AsyncRead, AsyncWrite, AsyncWriteExt};
 
-use super::{Connack, Connect, Publish, Suback, Subscribe, Unsubscribe};
+use super::{Connack, Connect, PacketRef, Publish, Suback, Subscribe, Unsubscribe};
 use crate::{
-    decode_raw_header, encode_packet, packet_from, read_u16, total_len, Encodable, Error, Pid, QoS,
-    QosPid, VarBytes,
+    block_decode_exact, check_body_end, copy_exact, decode_frame, decode_raw_header,
+    decode_raw_header_len, decode_raw_header_slice, encode_packet_to, fmt_list, fmt_publish,
+    packet_from, read_u16, total_len, var_int_len, write_u8, write_var_int, DecodeError,
+    DecodeLimits, Encodable, EncodedPacket, Error, Pid, PollEncodeState, PollHeader, Protocol, QoS,
+    QosPid, TopicName, VarBytes,
 };
 
 /// MQTT v3.x packet types.
 #[derive(Debug, Clone, PartialEq, Eq)]
 #[cfg_attr(feature = "arbitrary", derive(arbitrary::Arbitrary))]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum Packet {
     /// [MQTT 3.1](http://docs.oasis-open.org/mqtt/mqtt/v3.1.1/os/mqtt-v3.1.1-os.html#_Toc398718028)
     Connect(Connect),
@@ -48,6 +55,11 @@ impl Packet {
     /// This can be used for matching, categorising, debuging, etc. Most users
     /// will match directly on `Packet` instead.
     pub fn get_type(&self) -> PacketType {
+        self.packet_type()
+    }
+
+    /// Return the packet type variant, same as `get_type()`.
+    pub fn packet_type(&self) -> PacketType {
         match self {
             Packet::Pingreq => PacketType::Pingreq,
             Packet::Pingresp => PacketType::Pingresp,
@@ -66,9 +78,91 @@ impl Packet {
         }
     }
 
+    /// Return the packet identifier, if the packet has one (QoS 0 PUBLISH
+    /// has no packet identifier).
+    pub fn pid(&self) -> Option<Pid> {
+        match self {
+            Packet::Publish(publish) => publish.qos_pid.pid(),
+            Packet::Puback(pid)
+            | Packet::Pubrec(pid)
+            | Packet::Pubrel(pid)
+            | Packet::Pubcomp(pid)
+            | Packet::Unsuback(pid) => Some(*pid),
+            Packet::Subscribe(inner) => Some(inner.pid),
+            Packet::Suback(inner) => Some(inner.pid),
+            Packet::Unsubscribe(inner) => Some(inner.pid),
+            _ => None,
+        }
+    }
+
+    /// Return the topic name of a PUBLISH packet.
+    pub fn topic_name(&self) -> Option<&TopicName> {
+        match self {
+            Packet::Publish(publish) => Some(&publish.topic_name),
+            _ => None,
+        }
+    }
+
+    /// Return the QoS level of a PUBLISH packet.
+    pub fn qos(&self) -> Option<QoS> {
+        match self {
+            Packet::Publish(publish) => Some(publish.qos_pid.qos()),
+            _ => None,
+        }
+    }
+
     /// Asynchronously decode a packet from an async reader.
     pub async fn decode_async<T: AsyncRead + Unpin>(reader: &mut T) -> Result<Self, Error> {
         let header = Header::decode_async(reader).await?;
+        Self::decode_body_async(reader, header).await
+    }
+
+    /// Asynchronously decode a packet from an async reader, the packet must
+    /// not exceed the limits.
+    pub async fn decode_async_with_limits<T: AsyncRead + Unpin>(
+        reader: &mut T,
+        limits: &DecodeLimits,
+    ) -> Result<Self, Error> {
+        let (typ, remaining_len, var_int_bytes) = decode_raw_header_len(reader).await?;
+        let header = Header::new_with(typ, remaining_len)?;
+        limits.check_remaining_len(remaining_len, var_int_bytes)?;
+        let packet = Self::decode_body_async(reader, header).await?;
+        packet.check_limits(limits)?;
+        Ok(packet)
+    }
+
+    /// Asynchronously decode a packet from an async reader, the payload of
+    /// PUBLISH packet is written to `writer` in chunks instead of buffered.
+    pub async fn decode_async_streaming<T, W>(reader: &mut T, writer: &mut W) -> Result<Self, Error>
+    where
+        T: AsyncRead + Unpin,
+        W: AsyncWrite + Unpin,
+    {
+        let header = Header::decode_async(reader).await?;
+        if header.typ == PacketType::Publish {
+            let mut body = tokio::io::AsyncReadExt::take(reader, u64::from(header.remaining_len));
+            let result = Publish::decode_async_streaming(&mut body, header, writer).await;
+            check_body_end(body.limit(), result, Error::is_eof).map(Into::into)
+        } else {
+            Self::decode_body_async(reader, header).await
+        }
+    }
+
+    /// Decode the body, the fields must not exceed the remaining length, and
+    /// the EOF error is only returned when the reader ends inside the body.
+    async fn decode_body_async<T: AsyncRead + Unpin>(
+        reader: &mut T,
+        header: Header,
+    ) -> Result<Self, Error> {
+        let mut body = tokio::io::AsyncReadExt::take(reader, u64::from(header.remaining_len));
+        let result = Self::decode_fields_async(&mut body, header).await;
+        check_body_end(body.limit(), result, Error::is_eof)
+    }
+
+    async fn decode_fields_async<T: AsyncRead + Unpin>(
+        reader: &mut T,
+        header: Header,
+    ) -> Result<Self, Error> {
         Ok(match header.typ {
             PacketType::Pingreq => Packet::Pingreq,
             PacketType::Pingresp => Packet::Pingresp,
@@ -96,6 +190,124 @@ impl Packet {
         })
     }
 
+    /// Check the decoded packet against the limits, the remaining length is
+    /// not checked here.
+    pub fn check_limits(&self, limits: &DecodeLimits) -> Result<(), Error> {
+        match self {
+            Packet::Connect(connect) => {
+                limits.check_str(&connect.client_id)?;
+                if let Some(username) = connect.username.as_ref() {
+                    limits.check_str(username)?;
+                }
+                if let Some(last_will) = connect.last_will.as_ref() {
+                    limits.check_str(&last_will.topic_name)?;
+                }
+            }
+            Packet::Publish(publish) => limits.check_str(&publish.topic_name)?,
+            Packet::Subscribe(subscribe) => {
+                limits.check_topics(subscribe.topics.len())?;
+                for (filter, _) in &subscribe.topics {
+                    limits.check_str(filter)?;
+                }
+            }
+            Packet::Unsubscribe(unsubscribe) => {
+                limits.check_topics(unsubscribe.topics.len())?;
+                for filter in &unsubscribe.topics {
+                    limits.check_str(filter)?;
+                }
+            }
+            _ => {}
+        }
+        Ok(())
+    }
+
+    /// Check the rules which are not enforced by the types, useful to catch
+    /// a hand-built packet which would be rejected by the peer before it is
+    /// encoded. Decoded packets already pass most of the checks.
+    ///
+    /// The checks depending on the peer (e.g. the client identifier rules of
+    /// a server) are left to the caller.
+    pub fn validate(&self) -> Result<(), Error> {
+        match self {
+            Packet::Connect(connect) => {
+                // [MQTT-3.1.2-22]
+                if connect.protocol == Protocol::V311
+                    && connect.password.is_some()
+                    && connect.username.is_none()
+                {
+                    return Err(Error::InvalidPacket("password without username"));
+                }
+                // The client identifier of v3.1 is 1 to 23 characters
+                if connect.protocol == Protocol::V310
+                    && !(1..=23).contains(&connect.client_id.chars().count())
+                {
+                    return Err(Error::InvalidPacket(
+                        "v3.1 client identifier must be 1 to 23 characters",
+                    ));
+                }
+                // [MQTT-3.1.3-7]
+                if connect.client_id.is_empty() && !connect.clean_session {
+                    return Err(Error::InvalidPacket(
+                        "empty client identifier without clean session",
+                    ));
+                }
+                if let Some(last_will) = connect.last_will.as_ref() {
+                    // [MQTT-4.7.3-1]
+                    if last_will.topic_name.is_empty() {
+                        return Err(Error::InvalidPacket("empty will topic"));
+                    }
+                }
+            }
+            Packet::Publish(publish) => {
+                // [MQTT-3.3.1-2]
+                if publish.dup && publish.qos_pid == QosPid::Level0 {
+                    return Err(Error::InvalidPacket("dup flag with QoS 0"));
+                }
+                // [MQTT-4.7.3-1]
+                if publish.topic_name.is_empty() {
+                    return Err(Error::InvalidPacket("empty topic name"));
+                }
+            }
+            // [MQTT-3.8.3-3]
+            Packet::Subscribe(subscribe) if subscribe.topics.is_empty() => {
+                return Err(Error::EmptySubscription);
+            }
+            Packet::Suback(suback) if suback.topics.is_empty() => {
+                return Err(Error::InvalidPacket("empty suback"));
+            }
+            // [MQTT-3.10.3-2]
+            Packet::Unsubscribe(unsubscribe) if unsubscribe.topics.is_empty() => {
+                return Err(Error::EmptySubscription);
+            }
+            _ => {}
+        }
+        Ok(())
+    }
+
+    /// Check the packet does not use the behaviors added in v3.1.1, which an
+    /// MQTT 3.1 (MQIsdp) peer may reject:
+    ///
+    ///   * CONNECT of other protocol levels.
+    ///   * Session present flag in CONNACK, the byte is reserved in v3.1.
+    ///   * Failure return code (0x80) in SUBACK.
+    ///
+    /// Call it in addition to `validate()` before sending a packet to a v3.1
+    /// peer (the protocol of its CONNECT is `Protocol::V310`).
+    pub fn validate_v310(&self) -> Result<(), Error> {
+        match self {
+            Packet::Connect(connect) if connect.protocol != Protocol::V310 => {
+                Err(Error::UnexpectedProtocol(connect.protocol))
+            }
+            Packet::Connack(connack) if connack.session_present => {
+                Err(Error::InvalidPacket("session present in v3.1 connack"))
+            }
+            Packet::Suback(suback) if suback.topics.iter().any(|code| code.is_failure()) => {
+                Err(Error::InvalidPacket("failure return code in v3.1 suback"))
+            }
+            _ => Ok(()),
+        }
+    }
+
     /// Asynchronously encode the packet to an async writer.
     pub async fn encode_async<T: AsyncWrite + Unpin>(&self, writer: &mut T) -> Result<(), Error> {
         let data = self.encode()?;
@@ -103,18 +315,143 @@ impl Packet {
         Ok(())
     }
 
+    /// Decode a packet from an `embedded-io-async` reader, the tokio based
+    /// methods are still available for other connections.
+    #[cfg(feature = "embedded-io")]
+    pub async fn decode_embedded<T: embedded_io_async::Read>(
+        reader: &mut T,
+    ) -> Result<Self, Error> {
+        crate::read_packet_embedded::<_, Header>(reader).await
+    }
+
+    /// Encode the packet to an `embedded-io-async` writer.
+    #[cfg(feature = "embedded-io")]
+    pub async fn encode_embedded<T: embedded_io_async::Write>(
+        &self,
+        writer: &mut T,
+    ) -> Result<(), Error> {
+        let data = self.encode()?;
+        crate::write_all_embedded(writer, data.as_ref()).await
+    }
+
     /// Decode a packet from some bytes. If not enough bytes to decode a packet,
     /// it will return `Ok(None)`.
-    pub fn decode(mut bytes: &[u8]) -> Result<Option<Self>, Error> {
-        match block_on(Self::decode_async(&mut bytes)) {
+    ///
+    /// The fields are read from the slice directly (see `PacketRef`), a
+    /// complete packet gives the same result as `decode_async()`.
+    pub fn decode(bytes: &[u8]) -> Result<Option<Self>, Error> {
+        PacketRef::decode(&mut &bytes[..]).map(|packet| packet.map(|packet| packet.to_packet()))
+    }
+
+    /// Same as `decode()`, but the error contains the byte offset (from the
+    /// start of `bytes`) where the decoding failed.
+    pub fn decode_with_offset(bytes: &[u8]) -> Result<Option<Self>, DecodeError<Error>> {
+        let mut reader = bytes;
+        match block_on(Self::decode_async(&mut reader)) {
             Ok(pkt) => Ok(Some(pkt)),
-            Err(err) => {
-                if err.is_eof() {
-                    Ok(None)
-                } else {
-                    Err(err)
-                }
-            }
+            Err(err) if err.is_eof() => Ok(None),
+            Err(error) => Err(DecodeError {
+                offset: bytes.len() - reader.len(),
+                error,
+            }),
+        }
+    }
+
+    /// Decode a packet from some bytes, the packet must not exceed the limits.
+    /// If not enough bytes to decode a packet, it will return `Ok(None)`.
+    pub fn decode_with_limits(
+        mut bytes: &[u8],
+        limits: &DecodeLimits,
+    ) -> Result<Option<Self>, Error> {
+        match block_on(Self::decode_async_with_limits(&mut bytes, limits)) {
+            Ok(pkt) => Ok(Some(pkt)),
+            Err(err) if err.is_eof() => Ok(None),
+            Err(err) => Err(err),
+        }
+    }
+
+    /// Decode a packet from a shared bytes buffer. The payload of PUBLISH
+    /// packet is a slice of the buffer instead of a fresh allocation, the
+    /// strings (topic name, client identifier, etc.) are still copied.
+    ///
+    /// If not enough bytes to decode a packet, it will return `Ok(None)` and
+    /// the buffer is untouched, otherwise the buffer is advanced past the
+    /// decoded packet.
+    pub fn decode_shared(buf: &mut Bytes) -> Result<Option<Self>, Error> {
+        match Self::decode_header_only(buf)? {
+            Some((header, body)) => Self::decode_body(header, body).map(Some),
+            None => Ok(None),
+        }
+    }
+
+    /// Decode a packet from a read buffer. The payload of PUBLISH packet
+    /// shares the memory of the buffer.
+    ///
+    /// If not enough bytes to decode a packet, it will return `Ok(None)` and
+    /// the buffer is untouched, so more data can be appended to it. Otherwise
+    /// the buffer is advanced past the packet (also when the body is
+    /// invalid).
+    pub fn decode_buf(buf: &mut BytesMut) -> Result<Option<Self>, Error> {
+        let Some((header, header_len)) = decode_frame::<Header>(buf)? else {
+            return Ok(None);
+        };
+        let total = header_len + header.remaining_len as usize;
+        let mut body = buf.split_to(total).freeze();
+        body.advance(header_len);
+        Self::decode_body(header, body).map(Some)
+    }
+
+    /// Decode the fixed header from a shared bytes buffer and return it with
+    /// the unparsed body (a slice of the buffer), so the packet can be routed
+    /// or dropped by its type or size before decoding the body by
+    /// `decode_body()`.
+    ///
+    /// If not enough bytes to decode a packet, it will return `Ok(None)` and
+    /// the buffer is untouched, otherwise the buffer is advanced past the
+    /// packet.
+    pub fn decode_header_only(buf: &mut Bytes) -> Result<Option<(Header, Bytes)>, Error> {
+        let Some((header, header_len)) = decode_frame::<Header>(buf)? else {
+            return Ok(None);
+        };
+        let total = header_len + header.remaining_len as usize;
+        let body = buf.slice(header_len..total);
+        buf.advance(total);
+        Ok(Some((header, body)))
+    }
+
+    /// Asynchronously decode the fixed header and read the unparsed body,
+    /// see `decode_header_only()`.
+    pub async fn decode_header_only_async<T: AsyncRead + Unpin>(
+        reader: &mut T,
+    ) -> Result<(Header, Bytes), Error> {
+        let header = Header::decode_async(reader).await?;
+        let mut body = Vec::new();
+        copy_exact(reader, &mut body, header.remaining_len as usize).await?;
+        Ok((header, body.into()))
+    }
+
+    /// Decode the packet body returned by `decode_header_only()`, the body
+    /// must be consumed exactly.
+    pub fn decode_body(header: Header, body: Bytes) -> Result<Self, Error> {
+        if body.len() != header.remaining_len as usize {
+            return Err(Error::InvalidRemainingLength);
+        }
+        if header.typ == PacketType::Publish {
+            Ok(Publish::decode_shared(body, header)?.into())
+        } else {
+            Self::decode_body_exact(header, &body)
+        }
+    }
+
+    /// Decode a fully received packet body, the payload of PUBLISH packet is
+    /// copied.
+    pub(crate) fn decode_body_exact(header: Header, body: &[u8]) -> Result<Self, Error> {
+        if let Some(packet) = header.build_empty_packet() {
+            Ok(packet)
+        } else if header.remaining_len == 0 {
+            Err(Error::InvalidRemainingLength)
+        } else {
+            block_decode_exact(header, body)
         }
     }
 
@@ -130,10 +467,6 @@ impl Packet {
                 const CONTROL_BYTE: u8 = 0b11010000;
                 VarBytes::Fixed2([CONTROL_BYTE, VOID_PACKET_REMAINING_LEN])
             }
-            Packet::Connect(connect) => {
-                const CONTROL_BYTE: u8 = 0b00010000;
-                VarBytes::Dynamic(encode_packet(CONTROL_BYTE, connect)?)
-            }
             Packet::Connack(connack) => {
                 const CONTROL_BYTE: u8 = 0b00100000;
                 const REMAINING_LEN: u8 = 2;
@@ -141,20 +474,6 @@ impl Packet {
                 let rc: u8 = connack.code as u8;
                 VarBytes::Fixed4([CONTROL_BYTE, REMAINING_LEN, flags, rc])
             }
-            Packet::Publish(publish) => {
-                let mut control_byte: u8 = match publish.qos_pid {
-                    QosPid::Level0 => 0b00110000,
-                    QosPid::Level1(_) => 0b00110010,
-                    QosPid::Level2(_) => 0b00110100,
-                };
-                if publish.dup {
-                    control_byte |= 0b00001000;
-                }
-                if publish.retain {
-                    control_byte |= 0b00000001;
-                }
-                VarBytes::Dynamic(encode_packet(control_byte, publish)?)
-            }
             Packet::Puback(pid) => {
                 const CONTROL_BYTE: u8 = 0b01000000;
                 VarBytes::Fixed4(encode_with_pid(CONTROL_BYTE, *pid))
@@ -171,28 +490,137 @@ impl Packet {
                 const CONTROL_BYTE: u8 = 0b01110000;
                 VarBytes::Fixed4(encode_with_pid(CONTROL_BYTE, *pid))
             }
+            Packet::Unsuback(pid) => {
+                const CONTROL_BYTE: u8 = 0b10110000;
+                VarBytes::Fixed4(encode_with_pid(CONTROL_BYTE, *pid))
+            }
+            Packet::Disconnect => {
+                const CONTROL_BYTE: u8 = 0b11100000;
+                VarBytes::Fixed2([CONTROL_BYTE, VOID_PACKET_REMAINING_LEN])
+            }
+            Packet::Connect(_)
+            | Packet::Publish(_)
+            | Packet::Subscribe(_)
+            | Packet::Suback(_)
+            | Packet::Unsubscribe(_) => {
+                let mut data = Vec::with_capacity(self.encode_len()?);
+                self.encode_to(&mut data)?;
+                VarBytes::Dynamic(data)
+            }
+        };
+        Ok(data)
+    }
+
+    /// Encode the packet into a writer.
+    pub fn encode_to<W: io::Write>(&self, writer: &mut W) -> Result<(), Error> {
+        match self {
+            Packet::Connect(connect) => {
+                const CONTROL_BYTE: u8 = 0b00010000;
+                encode_packet_to(writer, CONTROL_BYTE, connect)
+            }
+            Packet::Publish(publish) => {
+                encode_packet_to(writer, publish_control_byte(publish), publish)
+            }
             Packet::Subscribe(subscribe) => {
                 const CONTROL_BYTE: u8 = 0b10000010;
-                VarBytes::Dynamic(encode_packet(CONTROL_BYTE, subscribe)?)
+                encode_packet_to(writer, CONTROL_BYTE, subscribe)
             }
             Packet::Suback(suback) => {
                 const CONTROL_BYTE: u8 = 0b10010000;
-                VarBytes::Dynamic(encode_packet(CONTROL_BYTE, suback)?)
+                encode_packet_to(writer, CONTROL_BYTE, suback)
             }
             Packet::Unsubscribe(unsubscribe) => {
                 const CONTROL_BYTE: u8 = 0b10100010;
-                VarBytes::Dynamic(encode_packet(CONTROL_BYTE, unsubscribe)?)
+                encode_packet_to(writer, CONTROL_BYTE, unsubscribe)
             }
-            Packet::Unsuback(pid) => {
-                const CONTROL_BYTE: u8 = 0b10110000;
-                VarBytes::Fixed4(encode_with_pid(CONTROL_BYTE, *pid))
+            Packet::Pingreq
+            | Packet::Pingresp
+            | Packet::Connack(_)
+            | Packet::Puback(_)
+            | Packet::Pubrec(_)
+            | Packet::Pubrel(_)
+            | Packet::Pubcomp(_)
+            | Packet::Unsuback(_)
+            | Packet::Disconnect => {
+                writer.write_all(self.encode()?.as_ref())?;
+                Ok(())
             }
-            Packet::Disconnect => {
-                const CONTROL_BYTE: u8 = 0b11100000;
-                VarBytes::Fixed2([CONTROL_BYTE, VOID_PACKET_REMAINING_LEN])
+        }
+    }
+
+    /// Encode the packet into a caller-provided buffer, return the number of
+    /// bytes written. If the buffer is too small nothing is written and
+    /// `Error::IoError(WriteZero, _)` is returned, use `encode_len()` to get
+    /// the required size.
+    pub fn encode_into(&self, buf: &mut [u8]) -> Result<usize, Error> {
+        let total = self.encode_len()?;
+        if buf.len() < total {
+            return Err(Error::IoError(
+                io::ErrorKind::WriteZero,
+                format!("buffer too small: {} < {}", buf.len(), total),
+            ));
+        }
+        self.encode_to(&mut &mut buf[..total])?;
+        Ok(total)
+    }
+
+    /// Encode the packet into a `BufMut`, return the number of bytes written.
+    /// Same as `encode_into()`, nothing is written if the remaining capacity
+    /// is not enough.
+    pub fn encode_to_buf<B: BufMut>(&self, buf: &mut B) -> Result<usize, Error> {
+        let total = self.encode_len()?;
+        if buf.remaining_mut() < total {
+            return Err(Error::IoError(
+                io::ErrorKind::WriteZero,
+                format!("buffer too small: {} < {}", buf.remaining_mut(), total),
+            ));
+        }
+        self.encode_to(&mut buf.writer())?;
+        Ok(total)
+    }
+
+    /// Encode the packet except the PUBLISH payload, return the encoded bytes
+    /// and the payload. The payload is not copied, so the two parts can be
+    /// written with `write_vectored`. The payload is empty for other packets.
+    pub fn encode_vectored(&self) -> Result<(VarBytes, Bytes), Error> {
+        match self {
+            Packet::Publish(publish) => {
+                let remaining_len = publish.encode_len();
+                let total = total_len(remaining_len)?;
+                let mut data = Vec::with_capacity(total - publish.payload.len());
+                data.push(publish_control_byte(publish));
+                write_var_int(&mut data, remaining_len)?;
+                publish.encode_without_payload(&mut data)?;
+                Ok((VarBytes::Dynamic(data), publish.payload.clone()))
             }
-        };
-        Ok(data)
+            _ => Ok((self.encode()?, Bytes::new())),
+        }
+    }
+
+    /// Encode the packet into a state for writing it incrementally, see
+    /// `PollEncode`.
+    pub fn encode_poll_state(&self) -> Result<PollEncodeState, Error> {
+        let (data, payload) = self.encode_vectored()?;
+        Ok(PollEncodeState::new(data, payload))
+    }
+
+    /// Encode the packet once for writing it to many connections, see
+    /// `EncodedPacket`.
+    pub fn encode_shared(&self) -> Result<EncodedPacket, Error> {
+        let (data, payload) = self.encode_vectored()?;
+        Ok(EncodedPacket::new(data, payload))
+    }
+
+    /// Same as `encode_async()`, but the PUBLISH payload is not copied into
+    /// the encoded buffer (see `encode_vectored()`).
+    pub async fn encode_vectored_async<T: AsyncWrite + Unpin>(
+        &self,
+        writer: &mut T,
+    ) -> Result<(), Error> {
+        let (data, payload) = self.encode_vectored()?;
+        let mut buf = data.as_ref().chain(payload);
+        writer.write_all_buf(&mut buf).await?;
+        Ok(())
     }
 
     /// Return the total length of bytes the packet encoded into.
@@ -217,8 +645,81 @@ impl Packet {
     }
 }
 
+/// Single line summary for logging, the password is elided.
+impl fmt::Display for Packet {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Packet::Connect(connect) => {
+                write!(
+                    f,
+                    "CONNECT protocol={} client_id={} keep_alive={}",
+                    connect.protocol, connect.client_id, connect.keep_alive
+                )?;
+                if connect.clean_session {
+                    write!(f, " clean_session")?;
+                }
+                if connect.is_bridge {
+                    write!(f, " bridge")?;
+                }
+                if let Some(last_will) = &connect.last_will {
+                    write!(
+                        f,
+                        " will={} will_qos={}",
+                        last_will.topic_name, last_will.qos as u8
+                    )?;
+                }
+                if let Some(username) = &connect.username {
+                    write!(f, " username={username}")?;
+                }
+                if connect.password.is_some() {
+                    write!(f, " password=***")?;
+                }
+                Ok(())
+            }
+            Packet::Connack(connack) => {
+                write!(f, "CONNACK code={:?}", connack.code)?;
+                if connack.session_present {
+                    write!(f, " session_present")?;
+                }
+                Ok(())
+            }
+            Packet::Publish(publish) => fmt_publish(
+                f,
+                publish.qos_pid,
+                &publish.topic_name,
+                publish.payload.len(),
+                publish.retain,
+                publish.dup,
+            ),
+            Packet::Puback(pid) => write!(f, "PUBACK pid={}", pid.value()),
+            Packet::Pubrec(pid) => write!(f, "PUBREC pid={}", pid.value()),
+            Packet::Pubrel(pid) => write!(f, "PUBREL pid={}", pid.value()),
+            Packet::Pubcomp(pid) => write!(f, "PUBCOMP pid={}", pid.value()),
+            Packet::Subscribe(subscribe) => {
+                write!(f, "SUBSCRIBE pid={} topics=", subscribe.pid.value())?;
+                fmt_list(f, &subscribe.topics, |f, (filter, qos)| {
+                    write!(f, "{filter}:{}", *qos as u8)
+                })
+            }
+            Packet::Suback(suback) => {
+                write!(f, "SUBACK pid={} codes=", suback.pid.value())?;
+                fmt_list(f, &suback.topics, |f, code| write!(f, "{code:?}"))
+            }
+            Packet::Unsubscribe(unsubscribe) => {
+                write!(f, "UNSUBSCRIBE pid={} topics=", unsubscribe.pid.value())?;
+                fmt_list(f, &unsubscribe.topics, |f, filter| write!(f, "{filter}"))
+            }
+            Packet::Unsuback(pid) => write!(f, "UNSUBACK pid={}", pid.value()),
+            Packet::Pingreq => write!(f, "PINGREQ"),
+            Packet::Pingresp => write!(f, "PINGRESP"),
+            Packet::Disconnect => write!(f, "DISCONNECT"),
+        }
+    }
+}
+
 /// MQTT v3.x packet type variant, without the associated data.
 #[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum PacketType {
     Connect,
     Connack,
@@ -236,8 +737,58 @@ pub enum PacketType {
     Disconnect,
 }
 
+impl PacketType {
+    /// The packet can be sent by a client.
+    pub fn is_client_to_server(&self) -> bool {
+        !matches!(
+            self,
+            PacketType::Connack | PacketType::Suback | PacketType::Unsuback | PacketType::Pingresp
+        )
+    }
+
+    /// The packet can be sent by a server.
+    pub fn is_server_to_client(&self) -> bool {
+        !matches!(
+            self,
+            PacketType::Connect
+                | PacketType::Subscribe
+                | PacketType::Unsubscribe
+                | PacketType::Pingreq
+                | PacketType::Disconnect
+        )
+    }
+
+    /// The packet identifier is required in the variable header, `qos` is
+    /// the QoS of a PUBLISH packet (ignored for the other packet types).
+    pub fn requires_pid(&self, qos: QoS) -> bool {
+        match self {
+            PacketType::Publish => qos != QoS::Level0,
+            PacketType::Puback
+            | PacketType::Pubrec
+            | PacketType::Pubrel
+            | PacketType::Pubcomp
+            | PacketType::Subscribe
+            | PacketType::Suback
+            | PacketType::Unsubscribe
+            | PacketType::Unsuback => true,
+            _ => false,
+        }
+    }
+
+    /// The flags (lower 4 bits) of the first byte of the fixed header, or
+    /// `None` for PUBLISH whose flags are DUP, QoS and RETAIN.
+    pub fn expected_flags(&self) -> Option<u8> {
+        match self {
+            PacketType::Publish => None,
+            PacketType::Pubrel | PacketType::Subscribe | PacketType::Unsubscribe => Some(0b0010),
+            _ => Some(0),
+        }
+    }
+}
+
 /// Fixed header type.
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct Header {
     pub typ: PacketType,
     pub dup: bool,
@@ -296,14 +847,66 @@ impl Header {
         })
     }
 
-    pub fn decode(mut reader: &[u8]) -> Result<Self, Error> {
-        block_on(Self::decode_async(&mut reader))
+    pub fn decode(reader: &[u8]) -> Result<Self, Error> {
+        match decode_raw_header_slice(reader)? {
+            Some((typ, remaining_len, _)) => Header::new_with(typ, remaining_len),
+            None => Err(io::Error::from(io::ErrorKind::UnexpectedEof).into()),
+        }
     }
 
     pub async fn decode_async<T: AsyncRead + Unpin>(reader: &mut T) -> Result<Self, Error> {
         let (typ, remaining_len) = decode_raw_header(reader).await?;
         Header::new_with(typ, remaining_len)
     }
+
+    /// The first byte of the fixed header (packet type and flags).
+    pub(crate) fn control_byte(&self) -> u8 {
+        let typ: u8 = match self.typ {
+            PacketType::Connect => 1,
+            PacketType::Connack => 2,
+            PacketType::Publish => 3,
+            PacketType::Puback => 4,
+            PacketType::Pubrec => 5,
+            PacketType::Pubrel => 6,
+            PacketType::Pubcomp => 7,
+            PacketType::Subscribe => 8,
+            PacketType::Suback => 9,
+            PacketType::Unsubscribe => 10,
+            PacketType::Unsuback => 11,
+            PacketType::Pingreq => 12,
+            PacketType::Pingresp => 13,
+            PacketType::Disconnect => 14,
+        };
+        let flags = match self.typ {
+            PacketType::Publish => {
+                let mut flags = (self.qos as u8) << 1;
+                if self.dup {
+                    flags |= 0b1000;
+                }
+                if self.retain {
+                    flags |= 0b0001;
+                }
+                flags
+            }
+            PacketType::Pubrel | PacketType::Subscribe | PacketType::Unsubscribe => 0b0010,
+            _ => 0,
+        };
+        (typ << 4) | flags
+    }
+}
+
+impl Encodable for Header {
+    fn encode<W: io::Write>(&self, writer: &mut W) -> io::Result<()> {
+        var_int_len(self.remaining_len as usize)?;
+        write_u8(writer, self.control_byte())?;
+        write_var_int(writer, self.remaining_len as usize)?;
+        Ok(())
+    }
+
+    fn encode_len(&self) -> usize {
+        // Invalid remaining length is rejected by encode()
+        1 + var_int_len(self.remaining_len as usize).unwrap_or(4)
+    }
 }
 
 #[inline]
@@ -319,3 +922,19 @@ fn encode_with_pid(control_byte: u8, pid: Pid) -> [u8; 4] {
 }
 
 packet_from!(Connect, Publish, Suback, Connack, Subscribe, Unsubscribe);
+
+#[inline]
+fn publish_control_byte(publish: &Publish) -> u8 {
+    let mut control_byte: u8 = match publish.qos_pid {
+        QosPid::Level0 => 0b00110000,
+        QosPid::Level1(_) => 0b00110010,
+        QosPid::Level2(_) => 0b00110100,
+    };
+    if publish.dup {
+        control_byte |= 0b00001000;
+    }
+    if publish.retain {
+        control_byte |= 0b00000001;
+    }
+    control_byte
+}
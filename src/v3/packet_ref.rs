@@ -0,0 +1,370 @@
+use std::fmt;
+
+use bytes::Bytes;
+
+use super::{
+    Connack, Connect, Header, LastWill, Packet, PacketType, Publish, Suback, Subscribe,
+    SubscribeReturnCode, Unsubscribe,
+};
+use crate::{
+    decode_raw_header_slice, redacted, Error, Pid, Protocol, QoS, QosPid, SharedStr, SliceReader,
+    TopicFilter, TopicName,
+};
+
+/// MQTT v3.x packet borrowed from the input slice.
+///
+/// The strings, topics and payloads are references into the input, so decode
+/// it does not allocate. Topic names and topic filters are validated the same
+/// way as `Packet::decode()`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PacketRef<'a> {
+    Connect(ConnectRef<'a>),
+    Connack(Connack),
+    Publish(PublishRef<'a>),
+    Puback(Pid),
+    Pubrec(Pid),
+    Pubrel(Pid),
+    Pubcomp(Pid),
+    Subscribe(SubscribeRef<'a>),
+    Suback(SubackRef<'a>),
+    Unsubscribe(UnsubscribeRef<'a>),
+    Unsuback(Pid),
+    Pingreq,
+    Pingresp,
+    Disconnect,
+}
+
+/// Borrowed version of [`Connect`].
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub struct ConnectRef<'a> {
+    pub protocol: Protocol,
+    pub clean_session: bool,
+    pub keep_alive: u16,
+    pub client_id: &'a str,
+    pub last_will: Option<LastWillRef<'a>>,
+    pub username: Option<&'a str>,
+    pub password: Option<&'a [u8]>,
+    pub is_bridge: bool,
+}
+
+impl fmt::Debug for ConnectRef<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("ConnectRef")
+            .field("protocol", &self.protocol)
+            .field("clean_session", &self.clean_session)
+            .field("keep_alive", &self.keep_alive)
+            .field("client_id", &self.client_id)
+            .field("last_will", &self.last_will)
+            .field("username", &self.username)
+            .field("password", &redacted(&self.password))
+            .field("is_bridge", &self.is_bridge)
+            .finish()
+    }
+}
+
+/// Borrowed version of [`LastWill`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct LastWillRef<'a> {
+    pub qos: QoS,
+    pub retain: bool,
+    pub topic_name: &'a str,
+    pub message: &'a [u8],
+}
+
+/// Borrowed version of [`Publish`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PublishRef<'a> {
+    pub dup: bool,
+    pub retain: bool,
+    pub qos_pid: QosPid,
+    pub topic_name: &'a str,
+    pub payload: &'a [u8],
+}
+
+/// Borrowed version of [`Subscribe`], the topics are validated when decoding.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SubscribeRef<'a> {
+    pub pid: Pid,
+    topics: &'a [u8],
+}
+
+/// Borrowed version of [`Suback`], the return codes are validated when decoding.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SubackRef<'a> {
+    pub pid: Pid,
+    topics: &'a [u8],
+}
+
+/// Borrowed version of [`Unsubscribe`], the topics are validated when decoding.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct UnsubscribeRef<'a> {
+    pub pid: Pid,
+    topics: &'a [u8],
+}
+
+impl<'a> PacketRef<'a> {
+    /// Decode a packet from a slice. If not enough bytes to decode a packet,
+    /// it will return `Ok(None)` and the slice is untouched, otherwise the
+    /// slice is advanced past the decoded packet.
+    pub fn decode(buf: &mut &'a [u8]) -> Result<Option<Self>, Error> {
+        let data: &'a [u8] = buf;
+        let Some((control_byte, remaining_len, header_len)) = decode_raw_header_slice(data)? else {
+            return Ok(None);
+        };
+        let header = Header::new_with(control_byte, remaining_len)?;
+        let total = header_len + header.remaining_len as usize;
+        if data.len() < total {
+            return Ok(None);
+        }
+        let mut body = SliceReader(&data[header_len..total]);
+        let packet = Self::decode_body(&mut body, header)?;
+        if !body.0.is_empty() {
+            return Err(Error::InvalidRemainingLength);
+        }
+        *buf = &data[total..];
+        Ok(Some(packet))
+    }
+
+    fn decode_body(body: &mut SliceReader<'a>, header: Header) -> Result<Self, Error> {
+        let packet = match header.typ {
+            PacketType::Pingreq => PacketRef::Pingreq,
+            PacketType::Pingresp => PacketRef::Pingresp,
+            PacketType::Disconnect => PacketRef::Disconnect,
+            PacketType::Connect => PacketRef::Connect(ConnectRef::decode(body)?),
+            PacketType::Connack => PacketRef::Connack(Connack::decode_slice(body)?),
+            PacketType::Publish => PacketRef::Publish(PublishRef::decode(body, header)?),
+            PacketType::Puback => PacketRef::Puback(body.read_pid()?),
+            PacketType::Pubrec => PacketRef::Pubrec(body.read_pid()?),
+            PacketType::Pubrel => PacketRef::Pubrel(body.read_pid()?),
+            PacketType::Pubcomp => PacketRef::Pubcomp(body.read_pid()?),
+            PacketType::Subscribe => {
+                let pid = body.read_pid()?;
+                let topics = body.take(body.0.len())?;
+                if topics.is_empty() {
+                    return Err(Error::EmptySubscription);
+                }
+                let mut reader = SliceReader(topics);
+                while !reader.0.is_empty() {
+                    check_topic_filter(reader.read_str()?)?;
+                    QoS::from_u8(reader.read_u8()?)?;
+                }
+                PacketRef::Subscribe(SubscribeRef { pid, topics })
+            }
+            PacketType::Suback => {
+                let pid = body.read_pid()?;
+                let topics = body.take(body.0.len())?;
+                for value in topics {
+                    SubscribeReturnCode::from_u8(*value)?;
+                }
+                PacketRef::Suback(SubackRef { pid, topics })
+            }
+            PacketType::Unsubscribe => {
+                let pid = body.read_pid()?;
+                let topics = body.take(body.0.len())?;
+                if topics.is_empty() {
+                    return Err(Error::EmptySubscription);
+                }
+                let mut reader = SliceReader(topics);
+                while !reader.0.is_empty() {
+                    check_topic_filter(reader.read_str()?)?;
+                }
+                PacketRef::Unsubscribe(UnsubscribeRef { pid, topics })
+            }
+            PacketType::Unsuback => PacketRef::Unsuback(body.read_pid()?),
+        };
+        Ok(packet)
+    }
+
+    /// Convert to an owned packet.
+    pub fn to_packet(&self) -> Packet {
+        match self {
+            PacketRef::Connect(inner) => inner.to_connect().into(),
+            PacketRef::Connack(inner) => Packet::Connack(*inner),
+            PacketRef::Publish(inner) => inner.to_publish().into(),
+            PacketRef::Puback(pid) => Packet::Puback(*pid),
+            PacketRef::Pubrec(pid) => Packet::Pubrec(*pid),
+            PacketRef::Pubrel(pid) => Packet::Pubrel(*pid),
+            PacketRef::Pubcomp(pid) => Packet::Pubcomp(*pid),
+            PacketRef::Subscribe(inner) => inner.to_subscribe().into(),
+            PacketRef::Suback(inner) => inner.to_suback().into(),
+            PacketRef::Unsubscribe(inner) => inner.to_unsubscribe().into(),
+            PacketRef::Unsuback(pid) => Packet::Unsuback(*pid),
+            PacketRef::Pingreq => Packet::Pingreq,
+            PacketRef::Pingresp => Packet::Pingresp,
+            PacketRef::Disconnect => Packet::Disconnect,
+        }
+    }
+}
+
+impl<'a> ConnectRef<'a> {
+    fn decode(body: &mut SliceReader<'a>) -> Result<Self, Error> {
+        let name = body.read_bytes()?;
+        let (protocol, is_bridge) = Protocol::new_bridge(name, body.read_u8()?)?;
+        if protocol as u8 > 4 {
+            return Err(Error::UnexpectedProtocol(protocol));
+        }
+        let connect_flags = body.read_u8()?;
+        if connect_flags & 1 != 0 {
+            return Err(Error::InvalidConnectFlags(connect_flags));
+        }
+        let keep_alive = body.read_u16()?;
+        let client_id = body.read_str()?;
+        let last_will = if connect_flags & 0b100 != 0 {
+            // Same order of the checks as `Connect::decode_async()`
+            let topic_name = body.read_str()?;
+            let message = body.read_bytes()?;
+            let qos = QoS::from_u8((connect_flags & 0b11000) >> 3)?;
+            let retain = (connect_flags & 0b00100000) != 0;
+            Some(LastWillRef {
+                qos,
+                retain,
+                topic_name: check_topic_name(topic_name)?,
+                message,
+            })
+        } else if connect_flags & 0b11000 != 0 {
+            return Err(Error::InvalidConnectFlags(connect_flags));
+        } else {
+            None
+        };
+        let username = if connect_flags & 0b10000000 != 0 {
+            Some(body.read_str()?)
+        } else {
+            None
+        };
+        let password = if connect_flags & 0b01000000 != 0 {
+            Some(body.read_bytes()?)
+        } else {
+            None
+        };
+        Ok(ConnectRef {
+            protocol,
+            clean_session: (connect_flags & 0b10) != 0,
+            keep_alive,
+            client_id,
+            last_will,
+            username,
+            password,
+            is_bridge,
+        })
+    }
+
+    pub fn to_connect(&self) -> Connect {
+        Connect {
+            protocol: self.protocol,
+            clean_session: self.clean_session,
+            is_bridge: self.is_bridge,
+            keep_alive: self.keep_alive,
+            client_id: SharedStr::from(self.client_id.to_owned()),
+            last_will: self.last_will.as_ref().map(|last_will| LastWill {
+                qos: last_will.qos,
+                retain: last_will.retain,
+                topic_name: to_topic_name(last_will.topic_name),
+                message: Bytes::copy_from_slice(last_will.message),
+            }),
+            username: self.username.map(|value| SharedStr::from(value.to_owned())),
+            password: self.password.map(Bytes::copy_from_slice),
+        }
+    }
+}
+
+impl<'a> PublishRef<'a> {
+    fn decode(body: &mut SliceReader<'a>, header: Header) -> Result<Self, Error> {
+        // Same order of the checks as `Publish::decode_async()`
+        let topic_name = body.read_str()?;
+        let qos_pid = match header.qos {
+            QoS::Level0 => QosPid::Level0,
+            QoS::Level1 => QosPid::Level1(body.read_pid()?),
+            QoS::Level2 => QosPid::Level2(body.read_pid()?),
+        };
+        let payload = body.take(body.0.len())?;
+        Ok(PublishRef {
+            dup: header.dup,
+            retain: header.retain,
+            qos_pid,
+            topic_name: check_topic_name(topic_name)?,
+            payload,
+        })
+    }
+
+    pub fn to_publish(&self) -> Publish {
+        Publish {
+            dup: self.dup,
+            retain: self.retain,
+            qos_pid: self.qos_pid,
+            topic_name: to_topic_name(self.topic_name),
+            payload: Bytes::copy_from_slice(self.payload),
+        }
+    }
+}
+
+impl<'a> SubscribeRef<'a> {
+    pub fn topics(&self) -> impl Iterator<Item = (&'a str, QoS)> {
+        let mut reader = SliceReader(self.topics);
+        std::iter::from_fn(move || {
+            if reader.0.is_empty() {
+                return None;
+            }
+            let filter = reader.read_str().expect("validated");
+            let qos = QoS::from_u8(reader.read_u8().expect("validated")).expect("validated");
+            Some((filter, qos))
+        })
+    }
+
+    pub fn to_subscribe(&self) -> Subscribe {
+        let topics = self
+            .topics()
+            .map(|(filter, qos)| (to_topic_filter(filter), qos))
+            .collect();
+        Subscribe::new(self.pid, topics)
+    }
+}
+
+impl<'a> SubackRef<'a> {
+    pub fn topics(&self) -> impl Iterator<Item = SubscribeReturnCode> + 'a {
+        self.topics
+            .iter()
+            .map(|value| SubscribeReturnCode::from_u8(*value).expect("validated"))
+    }
+
+    pub fn to_suback(&self) -> Suback {
+        Suback::new(self.pid, self.topics().collect())
+    }
+}
+
+impl<'a> UnsubscribeRef<'a> {
+    pub fn topics(&self) -> impl Iterator<Item = &'a str> {
+        let mut reader = SliceReader(self.topics);
+        std::iter::from_fn(move || {
+            if reader.0.is_empty() {
+                return None;
+            }
+            Some(reader.read_str().expect("validated"))
+        })
+    }
+
+    pub fn to_unsubscribe(&self) -> Unsubscribe {
+        Unsubscribe::new(self.pid, self.topics().map(to_topic_filter).collect())
+    }
+}
+
+fn check_topic_name(value: &str) -> Result<&str, Error> {
+    if TopicName::is_invalid(value) {
+        return Err(Error::InvalidTopicName(value.to_owned()));
+    }
+    Ok(value)
+}
+
+fn check_topic_filter(value: &str) -> Result<&str, Error> {
+    if TopicFilter::is_invalid(value).0 {
+        return Err(Error::InvalidTopicFilter(value.to_owned()));
+    }
+    Ok(value)
+}
+
+fn to_topic_name(value: &str) -> TopicName {
+    TopicName::try_from(value.to_owned()).expect("validated")
+}
+
+fn to_topic_filter(value: &str) -> TopicFilter {
+    TopicFilter::try_from(value.to_owned()).expect("validated")
+}
@@ -1,11 +1,13 @@
+use bytes::Bytes;
 use futures_lite::future::block_on;
 
 use super::{
     Connack, Connect, Header, Packet, PacketType, Publish, Suback, Subscribe, Unsubscribe,
 };
 use crate::{
-    read_u16, Error, GenericPollBodyState, GenericPollPacket, GenericPollPacketState, Pid,
-    PollHeader,
+    read_u16, DecodeLimits, Error, GenericOwnedPacketReader, GenericPacketDecoder,
+    GenericPacketSink, GenericPacketStream, GenericPollBodyState, GenericPollEncode,
+    GenericPollPacket, GenericPollPacketState, Pid, PollEncodable, PollEncodeState, PollHeader,
 };
 
 impl PollHeader for Header {
@@ -20,6 +22,9 @@ impl PollHeader for Header {
     }
 
     fn build_empty_packet(&self) -> Option<Self::Packet> {
+        if self.remaining_len != 0 {
+            return None;
+        }
         let packet = match self.typ {
             PacketType::Pingreq => Packet::Pingreq,
             PacketType::Pingresp => Packet::Pingresp,
@@ -50,19 +55,45 @@ impl PollHeader for Header {
             PacketType::Unsuback => Ok(Packet::Unsuback(Pid::try_from(block_on(read_u16(
                 reader,
             ))?)?)),
-            PacketType::Pingreq | PacketType::Pingresp | PacketType::Disconnect => unreachable!(),
+            // Only reached when the body is not empty
+            PacketType::Pingreq | PacketType::Pingresp | PacketType::Disconnect => {
+                Err(Error::InvalidRemainingLength)
+            }
         }
     }
 
+    fn decode_shared(self, body: Bytes) -> Result<Self::Packet, Self::Error> {
+        Packet::decode_body(self, body)
+    }
+
     fn remaining_len(&self) -> usize {
         self.remaining_len as usize
     }
 
+    fn control_byte(&self) -> u8 {
+        Header::control_byte(self)
+    }
+
     fn is_eof_error(err: &Self::Error) -> bool {
         err.is_eof()
     }
+
+    fn check_limits(packet: &Self::Packet, limits: &DecodeLimits) -> Result<(), Self::Error> {
+        packet.check_limits(limits)
+    }
+}
+
+impl PollEncodable for Packet {
+    fn encode_poll_state(&self) -> Result<PollEncodeState, Error> {
+        Packet::encode_poll_state(self)
+    }
 }
 
 pub type PollPacket<'a, T> = GenericPollPacket<'a, T, Header>;
 pub type PollPacketState = GenericPollPacketState<Header>;
 pub type PollBodyState = GenericPollBodyState<Header>;
+pub type PollEncode<'a, T> = GenericPollEncode<'a, T>;
+pub type PacketStream<R> = GenericPacketStream<R, Header>;
+pub type PacketSink<W> = GenericPacketSink<W, Packet>;
+pub type OwnedPacketReader<R> = GenericOwnedPacketReader<R, Header>;
+pub type PacketDecoder = GenericPacketDecoder<Header>;
@@ -1,15 +1,18 @@
 use std::io;
 
 use bytes::Bytes;
-use tokio::io::{AsyncRead, AsyncReadExt};
+use futures_lite::future::block_on;
+use tokio::io::{AsyncRead, AsyncWrite};
 
 use super::Header;
 use crate::{
-    read_string, read_u16, write_bytes, write_u16, Encodable, Error, Pid, QoS, QosPid, TopicName,
+    copy_exact, read_exact_capped, read_string, read_u16, write_bytes, write_u16, Encodable, Error,
+    Pid, QoS, QosPid, TopicName,
 };
 
 /// Publish packet body type.
 #[derive(Debug, Clone, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct Publish {
     pub dup: bool,
     pub retain: bool,
@@ -32,13 +35,15 @@ impl<'a> arbitrary::Arbitrary<'a> for Publish {
 }
 
 impl Publish {
-    pub fn new(qos_pid: QosPid, topic_name: TopicName, payload: Bytes) -> Self {
+    /// Create a publish packet, the payload can be `Bytes`, `Vec<u8>`,
+    /// `&'static [u8]`, `String` or `&'static str`.
+    pub fn new(qos_pid: QosPid, topic_name: TopicName, payload: impl Into<Bytes>) -> Self {
         Publish {
             dup: false,
             retain: false,
             qos_pid,
             topic_name,
-            payload,
+            payload: payload.into(),
         }
     }
 
@@ -46,6 +51,69 @@ impl Publish {
         reader: &mut T,
         header: Header,
     ) -> Result<Self, Error> {
+        let (topic_name, qos_pid, payload_len) =
+            Self::decode_variable_header(reader, header).await?;
+        let payload = read_exact_capped(reader, payload_len).await?;
+        Ok(Publish {
+            dup: header.dup,
+            qos_pid,
+            retain: header.retain,
+            topic_name,
+            payload: Bytes::from(payload),
+        })
+    }
+
+    /// Decode the packet and write the payload to `writer` in chunks instead
+    /// of buffering it, the payload of returned packet is empty.
+    pub async fn decode_async_streaming<T, W>(
+        reader: &mut T,
+        header: Header,
+        writer: &mut W,
+    ) -> Result<Self, Error>
+    where
+        T: AsyncRead + Unpin,
+        W: AsyncWrite + Unpin,
+    {
+        let (topic_name, qos_pid, payload_len) =
+            Self::decode_variable_header(reader, header).await?;
+        copy_exact(reader, writer, payload_len).await?;
+        Ok(Publish {
+            dup: header.dup,
+            qos_pid,
+            retain: header.retain,
+            topic_name,
+            payload: Bytes::new(),
+        })
+    }
+
+    /// Decode from a shared buffer which contains exactly the packet body, the
+    /// payload is a slice of the buffer.
+    pub(crate) fn decode_shared(data: Bytes, header: Header) -> Result<Self, Error> {
+        let mut reader: &[u8] = &data;
+        let (topic_name, qos_pid, payload_len) =
+            block_on(Self::decode_variable_header(&mut reader, header)).map_err(|err| {
+                if err.is_eof() {
+                    Error::InvalidRemainingLength
+                } else {
+                    err
+                }
+            })?;
+        debug_assert_eq!(payload_len, reader.len());
+        Ok(Publish {
+            dup: header.dup,
+            qos_pid,
+            retain: header.retain,
+            topic_name,
+            payload: data.slice(data.len() - payload_len..),
+        })
+    }
+
+    /// Decode topic name and packet identifier, return them with the payload
+    /// length.
+    async fn decode_variable_header<T: AsyncRead + Unpin>(
+        reader: &mut T,
+        header: Header,
+    ) -> Result<(TopicName, QosPid, usize), Error> {
         let mut remaining_len = header.remaining_len as usize;
         let topic_name = read_string(reader).await?;
         remaining_len = remaining_len
@@ -66,25 +134,11 @@ impl Publish {
                 QosPid::Level2(Pid::try_from(read_u16(reader).await?)?)
             }
         };
-        let payload = if remaining_len > 0 {
-            let mut data = vec![0u8; remaining_len];
-            reader.read_exact(&mut data).await?;
-            data
-        } else {
-            Vec::new()
-        };
-        Ok(Publish {
-            dup: header.dup,
-            qos_pid,
-            retain: header.retain,
-            topic_name: TopicName::try_from(topic_name)?,
-            payload: Bytes::from(payload),
-        })
+        Ok((TopicName::try_from(topic_name)?, qos_pid, remaining_len))
     }
-}
 
-impl Encodable for Publish {
-    fn encode<W: io::Write>(&self, writer: &mut W) -> io::Result<()> {
+    /// Encode the variable header (everything except the payload).
+    pub(crate) fn encode_without_payload<W: io::Write>(&self, writer: &mut W) -> io::Result<()> {
         write_bytes(writer, self.topic_name.as_bytes())?;
         match self.qos_pid {
             QosPid::Level0 => {}
@@ -92,6 +146,13 @@ impl Encodable for Publish {
                 write_u16(writer, pid.value())?;
             }
         }
+        Ok(())
+    }
+}
+
+impl Encodable for Publish {
+    fn encode<W: io::Write>(&self, writer: &mut W) -> io::Result<()> {
+        self.encode_without_payload(writer)?;
         writer.write_all(self.payload.as_ref())?;
         Ok(())
     }
@@ -0,0 +1,123 @@
+//! `proptest` strategies of MQTT v3.x packets.
+//!
+//! The generated packets pass `Packet::validate()`, see
+//! [`crate::strategy`] for the common types.
+
+use proptest::prelude::*;
+
+use super::{
+    Connack, Connect, ConnectReturnCode, LastWill, Packet, Publish, Suback, Subscribe,
+    SubscribeReturnCode, Unsubscribe,
+};
+use crate::strategy::{client_id, payload, pid, qos, qos_pid, topic_filter, topic_name};
+use crate::{Protocol, SharedStr};
+
+pub fn last_will() -> impl Strategy<Value = LastWill> {
+    (qos(), any::<bool>(), topic_name(), payload()).prop_map(
+        |(qos, retain, topic_name, message)| LastWill {
+            qos,
+            retain,
+            topic_name,
+            message,
+        },
+    )
+}
+
+pub fn connect() -> impl Strategy<Value = Connect> {
+    (
+        client_id(),
+        any::<bool>(),
+        any::<u16>(),
+        prop::option::of(last_will()),
+        prop::option::of(("[a-zA-Z0-9]{0,8}", prop::option::of(payload()))),
+    )
+        .prop_map(
+            |(client_id, clean_session, keep_alive, last_will, credentials)| {
+                let (username, password) = match credentials {
+                    Some((username, password)) => (Some(SharedStr::from(username)), password),
+                    None => (None, None),
+                };
+                Connect {
+                    protocol: Protocol::V311,
+                    // [MQTT-3.1.3-7]
+                    clean_session: clean_session || client_id.is_empty(),
+                    keep_alive,
+                    client_id,
+                    last_will,
+                    username,
+                    password,
+                    is_bridge: false,
+                }
+            },
+        )
+}
+
+pub fn connack() -> impl Strategy<Value = Connack> {
+    let code = prop::sample::select(
+        (0..=u8::MAX)
+            .filter_map(|value| ConnectReturnCode::from_u8(value).ok())
+            .collect::<Vec<_>>(),
+    );
+    (any::<bool>(), code).prop_map(|(session_present, code)| Connack {
+        session_present,
+        code,
+    })
+}
+
+pub fn publish() -> impl Strategy<Value = Publish> {
+    (
+        any::<bool>(),
+        any::<bool>(),
+        qos_pid(),
+        topic_name(),
+        payload(),
+    )
+        .prop_map(|(dup, retain, qos_pid, topic_name, payload)| Publish {
+            // [MQTT-3.3.1-2]
+            dup: dup && qos_pid.qos() != crate::QoS::Level0,
+            retain,
+            qos_pid,
+            topic_name,
+            payload,
+        })
+}
+
+pub fn subscribe() -> impl Strategy<Value = Subscribe> {
+    (pid(), prop::collection::vec((topic_filter(), qos()), 1..4))
+        .prop_map(|(pid, topics)| Subscribe::new(pid, topics))
+}
+
+pub fn suback() -> impl Strategy<Value = Suback> {
+    let code = prop_oneof![
+        Just(SubscribeReturnCode::MaxLevel0),
+        Just(SubscribeReturnCode::MaxLevel1),
+        Just(SubscribeReturnCode::MaxLevel2),
+        Just(SubscribeReturnCode::Failure),
+    ];
+    (pid(), prop::collection::vec(code, 1..4)).prop_map(|(pid, topics)| Suback::new(pid, topics))
+}
+
+pub fn unsubscribe() -> impl Strategy<Value = Unsubscribe> {
+    (pid(), prop::collection::vec(topic_filter(), 1..4))
+        .prop_map(|(pid, topics)| Unsubscribe::new(pid, topics))
+}
+
+/// Any valid packet.
+pub fn packet() -> impl Strategy<Value = Packet> {
+    prop_oneof![
+        connect().prop_map(Packet::Connect),
+        connack().prop_map(Packet::Connack),
+        publish().prop_map(Packet::Publish),
+        pid().prop_map(Packet::Puback),
+        pid().prop_map(Packet::Pubrec),
+        pid().prop_map(Packet::Pubrel),
+        pid().prop_map(Packet::Pubcomp),
+        subscribe().prop_map(Packet::Subscribe),
+        suback().prop_map(Packet::Suback),
+        unsubscribe().prop_map(Packet::Unsubscribe),
+        pid().prop_map(Packet::Unsuback),
+        Just(Packet::Pingreq),
+        Just(Packet::Pingresp),
+        Just(Packet::Disconnect),
+    ]
+}
@@ -3,13 +3,14 @@ use std::io;
 use tokio::io::AsyncRead;
 
 use crate::{
-    read_string, read_u16, read_u8, write_bytes, write_u16, write_u8, Encodable, Error, Pid, QoS,
-    TopicFilter,
+    capacity_hint, read_string, read_u16, read_u8, split_by_packet_size, write_bytes, write_u16,
+    write_u8, Encodable, Error, Pid, QoS, TopicFilter,
 };
 
 /// Subscribe packet body type.
 #[derive(Debug, Clone, PartialEq, Eq, Hash)]
 #[cfg_attr(feature = "arbitrary", derive(arbitrary::Arbitrary))]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct Subscribe {
     pub pid: Pid,
     pub topics: Vec<(TopicFilter, QoS)>,
@@ -18,6 +19,7 @@ pub struct Subscribe {
 /// Suback packet body type.
 #[derive(Debug, Clone, PartialEq, Eq)]
 #[cfg_attr(feature = "arbitrary", derive(arbitrary::Arbitrary))]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct Suback {
     pub pid: Pid,
     pub topics: Vec<SubscribeReturnCode>,
@@ -26,6 +28,7 @@ pub struct Suback {
 /// Unsubscribe packet body type.
 #[derive(Debug, Clone, PartialEq, Eq, Hash)]
 #[cfg_attr(feature = "arbitrary", derive(arbitrary::Arbitrary))]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct Unsubscribe {
     pub pid: Pid,
     pub topics: Vec<TopicFilter>,
@@ -36,6 +39,24 @@ impl Subscribe {
         Self { pid, topics }
     }
 
+    /// Split `topics` into packets not larger than `max_packet_size`
+    /// (include header), each packet takes a packet identifier from
+    /// `next_pid` (such as `PidAllocator::alloc()`). A topic filter which
+    /// can not fit in a packet is reported as `Error::PacketTooLarge`.
+    pub fn split<F>(
+        topics: Vec<(TopicFilter, QoS)>,
+        max_packet_size: usize,
+        mut next_pid: F,
+    ) -> Result<Vec<Self>, Error>
+    where
+        F: FnMut() -> Result<Pid, Error>,
+    {
+        split_by_packet_size(topics, 2, max_packet_size, |(filter, _)| 3 + filter.len())?
+            .into_iter()
+            .map(|topics| Ok(Self::new(next_pid()?, topics)))
+            .collect()
+    }
+
     pub async fn decode_async<T: AsyncRead + Unpin>(
         reader: &mut T,
         mut remaining_len: usize,
@@ -47,7 +68,7 @@ impl Subscribe {
         if remaining_len == 0 {
             return Err(Error::EmptySubscription);
         }
-        let mut topics = Vec::new();
+        let mut topics = Vec::with_capacity(capacity_hint(remaining_len, 4));
         while remaining_len > 0 {
             let topic_filter = TopicFilter::try_from(read_string(reader).await?)?;
             let max_qos = QoS::from_u8(read_u8(reader).await?)?;
@@ -84,6 +105,19 @@ impl Suback {
         Self { pid, topics }
     }
 
+    /// Check the SUBACK is the response of the SUBSCRIBE: the packet
+    /// identifier is the same and there is one return code for each topic
+    /// filter [MQTT-3.8.4-5].
+    pub fn check_subscribe(&self, subscribe: &Subscribe) -> Result<(), Error> {
+        if self.pid != subscribe.pid {
+            return Err(Error::InvalidPacket("SUBACK packet identifier mismatch"));
+        }
+        if self.topics.len() != subscribe.topics.len() {
+            return Err(Error::InvalidPacket("SUBACK return code count mismatch"));
+        }
+        Ok(())
+    }
+
     pub async fn decode_async<T: AsyncRead + Unpin>(
         reader: &mut T,
         mut remaining_len: usize,
@@ -92,7 +126,7 @@ impl Suback {
         remaining_len = remaining_len
             .checked_sub(2)
             .ok_or(Error::InvalidRemainingLength)?;
-        let mut topics = Vec::new();
+        let mut topics = Vec::with_capacity(capacity_hint(remaining_len, 1));
         while remaining_len > 0 {
             let value = read_u8(reader).await?;
             let code = SubscribeReturnCode::from_u8(value)?;
@@ -121,6 +155,21 @@ impl Unsubscribe {
         Self { pid, topics }
     }
 
+    /// Same as `Subscribe::split()`, but for UNSUBSCRIBE packets.
+    pub fn split<F>(
+        topics: Vec<TopicFilter>,
+        max_packet_size: usize,
+        mut next_pid: F,
+    ) -> Result<Vec<Self>, Error>
+    where
+        F: FnMut() -> Result<Pid, Error>,
+    {
+        split_by_packet_size(topics, 2, max_packet_size, |filter| 2 + filter.len())?
+            .into_iter()
+            .map(|topics| Ok(Self::new(next_pid()?, topics)))
+            .collect()
+    }
+
     pub async fn decode_async<T: AsyncRead + Unpin>(
         reader: &mut T,
         mut remaining_len: usize,
@@ -132,7 +181,7 @@ impl Unsubscribe {
         if remaining_len == 0 {
             return Err(Error::EmptySubscription);
         }
-        let mut topics = Vec::new();
+        let mut topics = Vec::with_capacity(capacity_hint(remaining_len, 3));
         while remaining_len > 0 {
             let topic_filter = TopicFilter::try_from(read_string(reader).await?)?;
             remaining_len = remaining_len
@@ -165,11 +214,12 @@ impl Encodable for Unsubscribe {
 /// Subscribe return code type.
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
 #[cfg_attr(feature = "arbitrary", derive(arbitrary::Arbitrary))]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum SubscribeReturnCode {
     MaxLevel0,
     MaxLevel1,
     MaxLevel2,
-    Failure,
+    Failure = 0x80,
 }
 
 impl SubscribeReturnCode {
@@ -182,6 +232,40 @@ impl SubscribeReturnCode {
             _ => Err(Error::InvalidQos(value)),
         }
     }
+
+    /// The description of the return code in the specification.
+    pub fn description(&self) -> &'static str {
+        match self {
+            SubscribeReturnCode::MaxLevel0 => "Success - Maximum QoS 0",
+            SubscribeReturnCode::MaxLevel1 => "Success - Maximum QoS 1",
+            SubscribeReturnCode::MaxLevel2 => "Success - Maximum QoS 2",
+            SubscribeReturnCode::Failure => "Failure",
+        }
+    }
+
+    pub fn is_error(&self) -> bool {
+        *self == SubscribeReturnCode::Failure
+    }
+
+    /// Same as `is_error()`, the only error is the failure return code.
+    pub fn is_failure(&self) -> bool {
+        self.is_error()
+    }
+
+    /// The success return code of the granted QoS, same as `From<QoS>`.
+    pub fn granted(qos: QoS) -> Self {
+        qos.into()
+    }
+
+    /// The granted QoS, or `None` for the failure return code.
+    pub fn granted_qos(&self) -> Option<QoS> {
+        match self {
+            SubscribeReturnCode::MaxLevel0 => Some(QoS::Level0),
+            SubscribeReturnCode::MaxLevel1 => Some(QoS::Level1),
+            SubscribeReturnCode::MaxLevel2 => Some(QoS::Level2),
+            SubscribeReturnCode::Failure => None,
+        }
+    }
 }
 
 impl From<QoS> for SubscribeReturnCode {
@@ -0,0 +1,41 @@
+//! Round-trip checks for code built on the MQTT v3.x codec, enabled by the
+//! `testing` feature.
+
+use futures_lite::future::block_on;
+
+use super::{Packet, PollPacket, PollPacketState};
+
+/// Encode the packet, then decode it by `Packet::decode()`,
+/// `Packet::decode_async()` and `PollPacket`, panic if any of them fails
+/// or does not return the same packet. Return the encoded bytes.
+pub fn assert_round_trip(packet: &Packet) -> Vec<u8> {
+    let data = packet.encode().expect("encode packet").as_ref().to_vec();
+    assert_eq!(
+        packet.encode_len().expect("encode length"),
+        data.len(),
+        "encode_len() mismatch"
+    );
+
+    let decoded = Packet::decode(&data)
+        .expect("decode packet")
+        .expect("complete packet");
+    assert_eq!(&decoded, packet, "decode() mismatch");
+
+    let mut reader = &data[..];
+    let decoded = block_on(Packet::decode_async(&mut reader)).expect("decode_async packet");
+    assert!(
+        reader.is_empty(),
+        "decode_async() left {} bytes",
+        reader.len()
+    );
+    assert_eq!(&decoded, packet, "decode_async() mismatch");
+
+    let mut state = PollPacketState::default();
+    let mut reader = &data[..];
+    let (total, _, decoded) =
+        block_on(PollPacket::new(&mut state, &mut reader)).expect("poll packet");
+    assert_eq!(total, data.len(), "PollPacket total length mismatch");
+    assert_eq!(&decoded, packet, "PollPacket mismatch");
+
+    data
+}
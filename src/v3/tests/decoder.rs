@@ -1,7 +1,7 @@
+use std::io;
 use std::ops::Deref;
-use std::sync::Arc;
 
-use bytes::Bytes;
+use bytes::{BufMut, Bytes, BytesMut};
 use futures_lite::future::block_on;
 
 use crate::v3::*;
@@ -58,7 +58,7 @@ fn test_header_firstbyte() {
 #[test]
 fn test_header_len() {
     use PacketType::*;
-    for (bytes, res) in vec![
+    for (bytes, res) in [
         (
             vec![1 << 4, 0],
             Ok(Header::new(Connect, false, Level0, false, 0)),
@@ -115,7 +115,18 @@ fn test_inner_length_too_long() {
         0x00, 0x04, b't', b'e', b's', b't', // client_id
         0x00, 0x03, b'm', b'q', // password with invalid length
     ];
-    assert_eq!(Ok(None), Packet::decode(data));
+    // The packet is complete, so it is malformed instead of incomplete
+    assert_eq!(Err(Error::InvalidRemainingLength), Packet::decode(data));
+    assert_eq!(
+        block_on(Packet::decode_async(&mut &data[..])),
+        Err(Error::InvalidRemainingLength)
+    );
+    // Only the missing bytes of the packet are incomplete
+    let partial = &data[..data.len() - 1];
+    assert_eq!(Ok(None), Packet::decode(partial));
+    assert!(block_on(Packet::decode_async(&mut &partial[..]))
+        .unwrap_err()
+        .is_eof());
     assert_eq!(
         block_on(PollPacket::new(&mut Default::default(), &mut data)).unwrap_err(),
         Error::InvalidRemainingLength
@@ -205,7 +216,7 @@ fn test_decode_packet_n() {
     let pkt1 = v3::Connect {
         protocol: Protocol::V311,
         keep_alive: 10,
-        client_id: Arc::new("test".to_owned()),
+        client_id: SharedStr::from("test"),
         clean_session: true,
         last_will: Some(LastWill {
             topic_name: TopicName::try_from("/a".to_owned()).unwrap(),
@@ -213,8 +224,9 @@ fn test_decode_packet_n() {
             qos: QoS::Level1,
             retain: false,
         }),
-        username: Some(Arc::new("rust".to_owned())),
+        username: Some(SharedStr::from("rust")),
         password: Some(Bytes::from(b"mq".to_vec())),
+        is_bridge: false,
     };
 
     let pkt2 = Packet::Pingreq;
@@ -298,6 +310,39 @@ fn test_decode_ping_resp() {
     );
 }
 
+#[test]
+fn test_decode_empty_packet_with_body() {
+    // The packets without body, followed by a junk body
+    for control_byte in [0b1100_0000, 0b1101_0000, 0b1110_0000] {
+        let data = [control_byte, 2, 0xaa, 0xbb];
+        let err = Error::InvalidRemainingLength;
+        assert_eq!(Packet::decode(&data), Err(err.clone()));
+        assert_eq!(
+            Packet::decode_shared(&mut Bytes::copy_from_slice(&data)),
+            Err(err.clone())
+        );
+        assert_eq!(
+            Packet::decode_buf(&mut BytesMut::from(&data[..])),
+            Err(err.clone())
+        );
+        assert_eq!(Packet::iter_from(&data).next(), Some(Err(err.clone())));
+        assert_eq!(
+            Packet::iter_from_bytes_mut(&mut BytesMut::from(&data[..])).next(),
+            Some(Err(err.clone()))
+        );
+        let (header, body) = Packet::decode_header_only(&mut Bytes::copy_from_slice(&data))
+            .unwrap()
+            .unwrap();
+        assert_eq!(Packet::decode_body(header, body), Err(err.clone()));
+        assert_eq!(PacketDecoder::new().feed(&data), Err(err.clone()));
+        let mut reader = &data[..];
+        assert_eq!(
+            block_on(PollPacket::new(&mut Default::default(), &mut reader)).unwrap_err(),
+            err
+        );
+    }
+}
+
 #[test]
 fn test_decode_disconnect() {
     let mut data: &[u8] = &[0b11100000, 0b00000000];
@@ -379,6 +424,211 @@ fn test_decode_publish() {
     );
 }
 
+#[test]
+fn test_decode_buf() {
+    let data: &[u8] = &[
+        0b00110010, 12, 0x00, 0x03, b'a', b'/', b'b', 0, 10, b'h', b'e', b'l', b'l', b'o', //
+        0b11000000, 0b00000000,
+    ];
+
+    let mut buf = BytesMut::new();
+    for byte in &data[..13] {
+        buf.put_u8(*byte);
+        assert_eq!(Packet::decode_buf(&mut buf), Ok(None));
+        assert_eq!(buf.as_ref(), &data[..buf.len()]);
+    }
+    buf.extend_from_slice(&data[13..]);
+    match Packet::decode_buf(&mut buf).unwrap().unwrap() {
+        Packet::Publish(p) => {
+            assert_eq!(p.topic_name.deref(), "a/b");
+            assert_eq!(p.payload.as_ref(), b"hello");
+        }
+        other => panic!("Failed decode: {:?}", other),
+    }
+    assert_eq!(buf.as_ref(), &data[14..]);
+    assert_eq!(Packet::decode_buf(&mut buf), Ok(Some(Packet::Pingreq)));
+    assert!(buf.is_empty());
+    assert_eq!(Packet::decode_buf(&mut buf), Ok(None));
+}
+
+#[test]
+fn test_decode_shared_publish() {
+    let data: &[u8] = &[
+        0b00110010, 12, 0x00, 0x03, b'a', b'/', b'b', 0, 10, b'h', b'e', b'l', b'l', b'o', //
+        0b11000000, 0b00000000,
+    ];
+
+    let mut buf = Bytes::copy_from_slice(&data[..10]);
+    assert_eq!(Packet::decode_shared(&mut buf), Ok(None));
+    assert_eq!(buf.as_ref(), &data[..10]);
+
+    let mut buf = Bytes::copy_from_slice(data);
+    let base = buf.as_ptr() as usize;
+    match Packet::decode_shared(&mut buf).unwrap().unwrap() {
+        Packet::Publish(p) => {
+            assert_eq!(p.qos_pid, QosPid::Level1(Pid::try_from(10).unwrap()));
+            assert_eq!(p.topic_name.deref(), "a/b");
+            assert_eq!(p.payload.as_ref(), b"hello");
+            assert_eq!(p.payload.as_ptr() as usize, base + 9);
+        }
+        other => panic!("Failed decode: {:?}", other),
+    }
+    assert_eq!(Packet::decode_shared(&mut buf), Ok(Some(Packet::Pingreq)));
+    assert!(buf.is_empty());
+    assert_eq!(Packet::decode_shared(&mut buf), Ok(None));
+}
+
+#[test]
+fn test_decode_publish_streaming() {
+    let payload = vec![7u8; 20000];
+    let packet = Packet::Publish(Publish::new(
+        QosPid::Level1(Pid::try_from(10).unwrap()),
+        TopicName::try_from("a/b".to_owned()).unwrap(),
+        Bytes::from(payload.clone()),
+    ));
+    let data = packet.encode().unwrap();
+
+    let mut reader = data.as_ref();
+    let mut sink = Vec::new();
+    match block_on(Packet::decode_async_streaming(&mut reader, &mut sink)).unwrap() {
+        Packet::Publish(p) => {
+            assert_eq!(p.qos_pid, QosPid::Level1(Pid::try_from(10).unwrap()));
+            assert_eq!(p.topic_name.deref(), "a/b");
+            assert!(p.payload.is_empty());
+        }
+        other => panic!("Failed decode: {:?}", other),
+    }
+    assert!(reader.is_empty());
+    assert_eq!(sink, payload);
+
+    let mut reader = &data.as_ref()[..data.as_ref().len() - 1];
+    let mut sink = Vec::new();
+    assert!(
+        block_on(Packet::decode_async_streaming(&mut reader, &mut sink))
+            .unwrap_err()
+            .is_eof()
+    );
+}
+
+#[test]
+fn test_decode_packet_ref() {
+    let data: &[u8] = &[
+        0b00110010, 12, 0x00, 0x03, b'a', b'/', b'b', 0, 10, b'h', b'e', b'l', b'l', b'o', //
+        0b10000010, 8, 0, 10, 0, 3, b'a', b'/', b'+', 1,
+    ];
+
+    let mut slice = &data[..10];
+    assert_eq!(PacketRef::decode(&mut slice), Ok(None));
+    assert_eq!(slice.len(), 10);
+
+    let mut slice = data;
+    match PacketRef::decode(&mut slice).unwrap().unwrap() {
+        PacketRef::Publish(p) => {
+            assert_eq!(p.qos_pid, QosPid::Level1(Pid::try_from(10).unwrap()));
+            assert_eq!(p.topic_name, "a/b");
+            assert_eq!(p.payload, b"hello");
+            assert_eq!(p.payload.as_ptr(), data[9..].as_ptr());
+        }
+        other => panic!("Failed decode: {:?}", other),
+    }
+    match PacketRef::decode(&mut slice).unwrap().unwrap() {
+        PacketRef::Subscribe(s) => {
+            assert_eq!(s.pid, Pid::try_from(10).unwrap());
+            assert_eq!(s.topics().collect::<Vec<_>>(), vec![("a/+", Level1)]);
+        }
+        other => panic!("Failed decode: {:?}", other),
+    }
+    assert!(slice.is_empty());
+
+    // invalid topic filter
+    let mut slice: &[u8] = &[0b10000010, 8, 0, 10, 0, 3, b'a', b'+', b'b', 1];
+    assert_eq!(
+        PacketRef::decode(&mut slice),
+        Err(Error::InvalidTopicFilter("a+b".to_owned()))
+    );
+    // body longer than the remaining length consumed
+    let mut slice: &[u8] = &[0b01000000, 3, 0, 10, 0];
+    assert_eq!(
+        PacketRef::decode(&mut slice),
+        Err(Error::InvalidRemainingLength)
+    );
+}
+
+#[test]
+fn test_decode_slice_same_as_async() {
+    let cases: &[&[u8]] = &[
+        &[
+            0b00010000, 20, 0x00, 0x04, b'M', b'Q', b'T', b'T', 0x04, 0b11000010, 0x00, 0x0a, 0x00,
+            0x02, b'i', b'd', 0x00, 0x01, b'u', 0x00, 0x01, b'p',
+        ],
+        &[0b00100000, 2, 0x01, 0x00],
+        &[
+            0b00110010, 12, 0x00, 0x03, b'a', b'/', b'b', 0, 10, b'h', b'e', b'l', b'l', b'o',
+        ],
+        &[0b10000010, 8, 0, 10, 0, 3, b'a', b'/', b'+', 1],
+        &[0b10010000, 4, 0, 10, 0x01, 0x80],
+        &[0b10100010, 7, 0, 10, 0, 3, b'a', b'/', b'+'],
+        &[0b01000000, 2, 0, 10],
+        &[0b11000000, 0],
+        &[
+            0b00010000, 21, 0x00, 0x04, b'M', b'Q', b'T', b'T', 0x04, 0b00101110, 0x00, 0x0a, 0x00,
+            0x02, b'i', b'd', 0x00, 0x01, b't', 0x00, 0x02, b'h', b'i',
+        ],
+        &[0b00110101, 7, 0x00, 0x01, b'a', 0, 1, b'x', b'y'],
+        // incomplete
+        &[0b00110000],
+        &[0b00110000, 0x80],
+        &[0b00110000, 10, 0x00, 0x03, b'a'],
+        // remaining length does not match the body
+        &[0b01000000, 3, 0, 10, 0],
+        &[0b11000000, 2, 0, 0],
+        &[0b00100000, 3, 0x01, 0x00, 0x00],
+        // invalid
+        &[0b00100000, 2, 0x02, 0x00],
+        &[0b01000000, 2, 0, 0],
+        &[0b00110000, 0xff, 0xff, 0xff, 0xff, 0x01],
+        &[0b10000010, 8, 0, 10, 0, 3, b'a', b'+', b'b', 1],
+        &[0b10000010, 2, 0, 10],
+    ];
+    let decode_async = |data: &[u8]| match block_on(Packet::decode_async(&mut &data[..])) {
+        Ok(packet) => Ok(Some(packet)),
+        Err(err) if err.is_eof() => Ok(None),
+        Err(err) => Err(err),
+    };
+    for data in cases {
+        assert_eq!(Packet::decode(data), decode_async(data), "data: {:?}", data);
+    }
+
+    // Every single byte mutation of the valid packets. The async decoder may
+    // fail before an incomplete body is received, so only the complete
+    // packets are compared.
+    for data in &cases[..10] {
+        for idx in 0..data.len() {
+            for value in [0, 1, 2, 3, 0x7f, 0x80, 0xff, data[idx] ^ 1] {
+                let mut data = data.to_vec();
+                data[idx] = value;
+                for len in [data.len() - 1, data.len(), data.len() + 1] {
+                    data.resize(len, 0);
+                    let complete = match decode_raw_header_slice(&data) {
+                        Ok(Some((_, remaining_len, header_len))) => {
+                            data.len() >= header_len + remaining_len as usize
+                        }
+                        _ => true,
+                    };
+                    if complete {
+                        assert_eq!(
+                            Packet::decode(&data),
+                            decode_async(&data),
+                            "data: {:?}",
+                            data
+                        );
+                    }
+                }
+            }
+        }
+    }
+}
+
 #[test]
 fn test_decode_pub_ack() {
     let mut data: &[u8] = &[0b01000000, 0b00000010, 0, 10];
@@ -439,6 +689,85 @@ fn test_decode_pub_comp() {
     );
 }
 
+#[test]
+fn test_decode_with_limits() {
+    // subscribe: pid = 10, topics = ["a/b" (qos1), "c" (qos0)]
+    let data: &[u8] = &[
+        0b10000010, 12, 0, 10, 0, 3, b'a', b'/', b'b', 1, 0, 1, b'c', 0,
+    ];
+    let limits = DecodeLimits::default();
+    assert_eq!(
+        Packet::decode_with_limits(data, &limits),
+        Packet::decode(data)
+    );
+
+    let limits = DecodeLimits {
+        max_remaining_len: 11,
+        ..Default::default()
+    };
+    // the remaining length is checked before reading the body
+    assert_eq!(
+        Packet::decode_with_limits(&data[..2], &limits),
+        Err(Error::DecodeLimitExceeded("max_remaining_len"))
+    );
+    let mut reader = data;
+    assert_eq!(
+        block_on(PollPacket::new(&mut Default::default(), &mut reader).with_limits(limits))
+            .unwrap_err(),
+        Error::DecodeLimitExceeded("max_remaining_len")
+    );
+    assert_eq!(reader, &data[2..]);
+
+    let limits = DecodeLimits {
+        max_topics: 1,
+        ..Default::default()
+    };
+    assert_eq!(
+        Packet::decode_with_limits(data, &limits),
+        Err(Error::DecodeLimitExceeded("max_topics"))
+    );
+
+    let limits = DecodeLimits {
+        max_string_len: 2,
+        ..Default::default()
+    };
+    assert_eq!(
+        Packet::decode_with_limits(data, &limits),
+        Err(Error::DecodeLimitExceeded("max_string_len"))
+    );
+    let mut reader = data;
+    assert_eq!(
+        block_on(PollPacket::new(&mut Default::default(), &mut reader).with_limits(limits))
+            .unwrap_err(),
+        Error::DecodeLimitExceeded("max_string_len")
+    );
+}
+
+#[test]
+fn test_decode_strict_utf8() {
+    // publish: topic name = "a\x01b", payload = "x"
+    let data: &[u8] = &[0b00110000, 6, 0, 3, b'a', 0x01, b'b', b'x'];
+    assert!(Packet::decode(data).is_ok());
+
+    let limits = DecodeLimits {
+        strict_utf8: true,
+        ..Default::default()
+    };
+    assert_eq!(
+        Packet::decode_with_limits(data, &limits),
+        Err(Error::ForbiddenChar('\u{1}'))
+    );
+    assert_eq!(
+        TopicName::try_from_strict("a\u{1}b".to_owned()),
+        Err(Error::ForbiddenChar('\u{1}'))
+    );
+    assert_eq!(
+        TopicFilter::try_from_strict("a/\u{FFFF}".to_owned()),
+        Err(Error::ForbiddenChar('\u{FFFF}'))
+    );
+    assert!(TopicFilter::try_from_strict("a/+".to_owned()).is_ok());
+}
+
 #[test]
 fn test_decode_subscribe() {
     let mut data: &[u8] = &[0b10000010, 8, 0, 10, 0, 3, b'a', b'/', b'b', 0];
@@ -510,3 +839,583 @@ fn test_decode_unsub_ack() {
             .2
     );
 }
+
+#[test]
+fn test_decode_with_offset() {
+    // QoS 1 PUBLISH with packet identifier 0
+    let data = [0b0011_0010, 5, 0, 1, b'a', 0, 0];
+    let err = Packet::decode_with_offset(&data).unwrap_err();
+    assert_eq!(err.error, Error::ZeroPid);
+    assert_eq!(err.offset, 7);
+
+    let data = [0b0011_0010, 5, 0, 1, b'a', 0];
+    assert_eq!(Packet::decode_with_offset(&data).unwrap(), None);
+
+    let data = [0b1111_0000, 0];
+    let err = Packet::decode_with_offset(&data).unwrap_err();
+    assert_eq!(err.error, Error::InvalidHeader);
+    assert_eq!(err.offset, 2);
+}
+
+#[test]
+fn test_decode_header_only() {
+    // PUBLISH then PINGREQ, the last packet is incomplete
+    let data = [
+        0b0011_0000,
+        4,
+        0,
+        1,
+        b'a',
+        b'x',
+        0b1100_0000,
+        0,
+        0b0100_0000,
+        2,
+        0,
+    ];
+    let mut buf = Bytes::copy_from_slice(&data);
+    let (header, body) = Packet::decode_header_only(&mut buf).unwrap().unwrap();
+    assert_eq!(header.typ, PacketType::Publish);
+    assert_eq!(&body[..], &[0, 1, b'a', b'x']);
+    let packet = Packet::decode_body(header, body).unwrap();
+    assert_eq!(packet.topic_name().map(|name| &**name), Some("a"));
+
+    let (header, body) = Packet::decode_header_only(&mut buf).unwrap().unwrap();
+    assert_eq!(Packet::decode_body(header, body).unwrap(), Packet::Pingreq);
+    assert_eq!(Packet::decode_header_only(&mut buf).unwrap(), None);
+    assert_eq!(buf.len(), 3);
+
+    let mut reader = &data[..];
+    let (header, body) = block_on(Packet::decode_header_only_async(&mut reader)).unwrap();
+    assert_eq!(header.remaining_len, 4);
+    assert_eq!(body.len(), 4);
+
+    // The body does not match the remaining length
+    let header = Header::new(PacketType::Puback, false, QoS::Level0, false, 2);
+    assert_eq!(
+        Packet::decode_body(header, Bytes::from_static(&[0, 1, 0])),
+        Err(Error::InvalidRemainingLength)
+    );
+}
+
+#[test]
+fn test_lazy_packet() {
+    // QoS 1 PUBLISH with packet identifier 10
+    let data = [0b0011_0010, 8, 0, 3, b'a', b'/', b'b', 0, 10, b'x'];
+    let mut buf = Bytes::copy_from_slice(&data[..9]);
+    assert_eq!(LazyPacket::decode(&mut buf).unwrap(), None);
+
+    let mut buf = Bytes::copy_from_slice(&data);
+    let packet = LazyPacket::decode(&mut buf).unwrap().unwrap();
+    assert_eq!(packet.packet_type(), PacketType::Publish);
+    assert_eq!(packet.header().qos, QoS::Level1);
+    assert_eq!(packet.topic_name().unwrap(), Some("a/b"));
+    assert_eq!(packet.pid().unwrap(), Some(Pid::try_from(10).unwrap()));
+    let publish = match packet.into_packet().unwrap() {
+        Packet::Publish(publish) => publish,
+        packet => panic!("unexpected packet: {packet:?}"),
+    };
+    assert_eq!(publish.payload.as_ref(), b"x");
+
+    let mut buf = Bytes::from_static(&[0b0100_0000, 2, 0, 5, 0b1100_0000, 0]);
+    let packet = LazyPacket::decode(&mut buf).unwrap().unwrap();
+    assert_eq!(packet.pid().unwrap(), Some(Pid::try_from(5).unwrap()));
+    assert_eq!(packet.topic_name().unwrap(), None);
+    let packet = LazyPacket::decode(&mut buf).unwrap().unwrap();
+    assert_eq!(packet.pid().unwrap(), None);
+
+    // Invalid topic name is only detected by into_packet()
+    let mut buf = Bytes::from_static(&[0b0011_0000, 4, 0, 2, b'a', b'+']);
+    let packet = LazyPacket::decode(&mut buf).unwrap().unwrap();
+    assert_eq!(packet.topic_name().unwrap(), Some("a+"));
+    assert!(matches!(
+        packet.into_packet(),
+        Err(Error::InvalidTopicName(_))
+    ));
+}
+
+#[test]
+fn test_packet_iter() {
+    // PINGREQ, PUBACK, incomplete PUBLISH
+    let data = [0b1100_0000, 0, 0b0100_0000, 2, 0, 1, 0b0011_0000, 4, 0];
+    let mut iter = Packet::iter_from(&data);
+    assert_eq!(iter.next(), Some(Ok((Packet::Pingreq, 2))));
+    assert_eq!(
+        iter.next(),
+        Some(Ok((Packet::Puback(Pid::try_from(1).unwrap()), 4)))
+    );
+    assert_eq!(iter.next(), None);
+    assert_eq!(iter.remaining(), &data[6..]);
+
+    let mut buf = bytes::BytesMut::from(&data[..]);
+    let packets: Vec<_> = Packet::iter_from_bytes_mut(&mut buf)
+        .map(Result::unwrap)
+        .collect();
+    assert_eq!(packets.len(), 2);
+    assert_eq!(&buf[..], &data[6..]);
+
+    // Stop after an error
+    let data = [0b1100_0000, 0, 0b0100_0000, 2, 0, 0, 0b1100_0000, 0];
+    let mut iter = Packet::iter_from(&data);
+    assert!(iter.next().unwrap().is_ok());
+    assert_eq!(iter.next(), Some(Err(Error::ZeroPid)));
+    assert_eq!(iter.next(), None);
+    assert_eq!(iter.remaining(), &data[2..]);
+}
+
+#[test]
+fn test_validate_client_id_strict() {
+    let mut connect = Connect::new("client01", 0);
+    assert_eq!(connect.validate_client_id_strict(), Ok(()));
+
+    connect.client_id = SharedStr::from("a".repeat(24));
+    assert_eq!(
+        connect.validate_client_id_strict(),
+        Err(ClientIdError::TooLong(24))
+    );
+    connect.client_id = SharedStr::from("client-01");
+    assert_eq!(
+        connect.validate_client_id_strict(),
+        Err(ClientIdError::InvalidChar('-'))
+    );
+
+    connect.client_id = SharedStr::from("");
+    assert_eq!(connect.validate_client_id_strict(), Ok(()));
+    connect.clean_session = false;
+    let err = connect.validate_client_id_strict().unwrap_err();
+    assert_eq!(err, ClientIdError::EmptyWithoutCleanSession);
+    assert_eq!(err.return_code(), ConnectReturnCode::IdentifierRejected);
+    connect.protocol = Protocol::V310;
+    connect.clean_session = true;
+    assert_eq!(
+        connect.validate_client_id_strict(),
+        Err(ClientIdError::Empty)
+    );
+}
+
+#[test]
+fn test_poll_packet_stats() {
+    let stats = CodecCounters::new();
+    // pingreq, publish (topic name = "a", payload = "x"), invalid header
+    let data: &[u8] = &[
+        0b11000000, 0, 0b00110000, 4, 0, 1, b'a', b'x', 0b11110000, 0,
+    ];
+    let mut reader = data;
+    let mut decode = || {
+        let mut state = PollPacketState::default();
+        block_on(PollPacket::new(&mut state, &mut reader).with_stats(&stats)).map(|(.., p)| p)
+    };
+    assert_eq!(decode(), Ok(Packet::Pingreq));
+    assert!(decode().is_ok());
+    assert_eq!(decode(), Err(Error::InvalidHeader));
+    // EOF is not a malformed packet
+    assert!(decode().unwrap_err().is_eof());
+
+    assert_eq!(stats.decoded(12), 1);
+    assert_eq!(stats.decoded(3), 1);
+    assert_eq!(stats.bytes_in(), 8);
+    assert_eq!(stats.malformed(), 1);
+
+    let mut state = Packet::Pingresp.encode_poll_state().unwrap();
+    let mut writer = Vec::new();
+    let poll = PollEncode::new(&mut state, &mut writer).with_stats(&stats);
+    assert_eq!(block_on(poll), Ok(2));
+    // Already done, not counted again
+    let poll = PollEncode::new(&mut state, &mut writer).with_stats(&stats);
+    assert_eq!(block_on(poll), Ok(2));
+    assert_eq!(stats.encoded(13), 1);
+    assert_eq!(stats.bytes_out(), 2);
+}
+
+#[test]
+fn test_poll_packet_pool() {
+    let pool = ScratchBufferPool::new(4, 1024);
+    // two publish packets (topic name = "a", payload = "x")
+    let data: &[u8] = &[
+        0b00110000, 4, 0, 1, b'a', b'x', 0b00110000, 4, 0, 1, b'a', b'x',
+    ];
+    let mut reader = data;
+    for _ in 0..2 {
+        let mut state = PollPacketState::default();
+        let (total, buf, packet) =
+            block_on(PollPacket::new(&mut state, &mut reader).with_scratch_pool(&pool)).unwrap();
+        assert_eq!(total, 6);
+        assert!(buf.is_empty());
+        assert!(matches!(packet, Packet::Publish(_)));
+        // The payload shares the buffer
+        assert_eq!(pool.idle(), 0);
+        drop(packet);
+        assert_eq!(pool.idle(), 1);
+    }
+}
+
+#[test]
+fn test_poll_packet_capped_reserve() {
+    // PUBLISH with remaining length = 268,435,455, only 3 bytes of the body
+    // are received
+    let pool = ScratchBufferPool::new(4, 1024);
+    for with_pool in [false, true] {
+        let mut reader = &[0x30, 0xff, 0xff, 0xff, 0x7f, 0x00, 0x01, b'a'][..];
+        let mut state = PollPacketState::default();
+        let mut poll = PollPacket::new(&mut state, &mut reader);
+        if with_pool {
+            poll = poll.with_scratch_pool(&pool);
+        }
+        assert!(block_on(poll).unwrap_err().is_eof());
+        let PollPacketState::Body(body) = state else {
+            panic!("body state expected");
+        };
+        assert_eq!(body.idx, 3);
+        assert!(body.buf.capacity() <= MAX_RESERVE);
+    }
+
+    // The body is larger than one chunk
+    let packet: Packet = Publish::new(
+        QosPid::Level0,
+        TopicName::try_from("a/b").unwrap(),
+        vec![7u8; MAX_RESERVE * 2 + 100],
+    )
+    .into();
+    let data = packet.encode().unwrap().as_ref().to_vec();
+    let mut reader = MockBuffer::new(data.clone()).chunk_size(10000);
+    let mut state = PollPacketState::default();
+    let (total, _, polled) = block_on(PollPacket::new(&mut state, &mut reader)).unwrap();
+    assert_eq!(total, data.len());
+    assert_eq!(polled, packet);
+}
+
+#[test]
+fn test_poll_packet_partial_read() {
+    let packet: Packet = Publish::new(
+        QosPid::Level1(Pid::try_from(10).unwrap()),
+        TopicName::try_from("a/b").unwrap(),
+        Bytes::from_static(b"hello world"),
+    )
+    .into();
+    let data = packet.encode().unwrap().as_ref().to_vec();
+
+    // One byte per read, with `Pending` before every read
+    let mut reader = MockBuffer::new(data.clone())
+        .chunk_size(1)
+        .interleave_pending(true);
+    let handle = reader.handle();
+    let mut state = PollPacketState::default();
+    let (total, _, polled) = block_on(PollPacket::new(&mut state, &mut reader)).unwrap();
+    assert_eq!(total, data.len());
+    assert_eq!(polled, packet);
+    assert!(handle.reads().iter().all(|size| *size == 1));
+    assert_eq!(handle.pending(), handle.reads().len());
+
+    // The future is dropped on `Pending`, then polled again with the same state
+    let mut reader = MockBuffer::new(data.clone())
+        .chunk_size(3)
+        .interleave_pending(true);
+    let handle = reader.handle();
+    let mut state = PollPacketState::default();
+    let mut result = None;
+    while result.is_none() {
+        result = block_on(futures_lite::future::poll_once(PollPacket::new(
+            &mut state,
+            &mut reader,
+        )));
+    }
+    let (total, _, polled) = result.unwrap().unwrap();
+    assert_eq!(total, data.len());
+    assert_eq!(polled, packet);
+    assert_eq!(handle.position(), data.len());
+
+    // I/O error in the middle of the body
+    let mut reader = MockBuffer::new(data).error_at(5, io::ErrorKind::ConnectionReset);
+    let mut state = PollPacketState::default();
+    let err = block_on(PollPacket::new(&mut state, &mut reader)).unwrap_err();
+    assert!(matches!(
+        err,
+        Error::IoError(io::ErrorKind::ConnectionReset, _)
+    ));
+}
+
+#[test]
+fn test_poll_packet_timeout() {
+    use futures_lite::future::poll_once;
+    use std::time::Duration;
+
+    let packet: Packet = Publish::new(
+        QosPid::Level1(Pid::try_from(10).unwrap()),
+        TopicName::try_from("a/b").unwrap(),
+        Bytes::from_static(b"hello world"),
+    )
+    .into();
+    let data = packet.encode().unwrap().as_ref().to_vec();
+
+    // Enough polls to receive the packet
+    let timeout = PollTimeout {
+        max_polls: Some(data.len() as u32 * 2),
+        ..Default::default()
+    };
+    let mut reader = MockBuffer::new(data.clone())
+        .chunk_size(1)
+        .interleave_pending(true);
+    let mut state = PollPacketState::default();
+    let (_, _, polled) =
+        block_on(PollPacket::new(&mut state, &mut reader).with_timeout(timeout)).unwrap();
+    assert_eq!(polled, packet);
+
+    // Too many polls, also when the future is created again for each poll
+    let timeout = PollTimeout {
+        max_polls: Some(4),
+        ..Default::default()
+    };
+    let mut reader = MockBuffer::new(data.clone())
+        .chunk_size(1)
+        .interleave_pending(true);
+    let mut state = PollPacketState::default();
+    let mut polls = 0;
+    let err = loop {
+        polls += 1;
+        let fut = PollPacket::new(&mut state, &mut reader).with_timeout(timeout);
+        if let Some(result) = block_on(poll_once(fut)) {
+            break result.unwrap_err();
+        }
+    };
+    assert_eq!(err, Error::DecodeTimeout);
+    // The first poll is pending, the second receives the first byte
+    assert_eq!(polls, 2 + 5);
+
+    // Too long since the first byte
+    let timeout = PollTimeout {
+        duration: Some(Duration::from_millis(1)),
+        ..Default::default()
+    };
+    let mut reader = MockBuffer::new(data).chunk_size(1).interleave_pending(true);
+    let mut state = PollPacketState::default();
+    let err = loop {
+        let fut = PollPacket::new(&mut state, &mut reader).with_timeout(timeout);
+        if let Some(result) = block_on(poll_once(fut)) {
+            break result.unwrap_err();
+        }
+        std::thread::sleep(Duration::from_millis(2));
+    };
+    assert_eq!(err, Error::DecodeTimeout);
+}
+
+#[test]
+fn test_packet_stream() {
+    use futures_lite::StreamExt;
+
+    let packets: Vec<Packet> = vec![
+        Connect::new("client", 30).into(),
+        Publish::new(
+            QosPid::Level1(Pid::try_from(10).unwrap()),
+            TopicName::try_from("a/b").unwrap(),
+            Bytes::from_static(b"hello world"),
+        )
+        .into(),
+        Packet::Pingreq,
+    ];
+    let mut data = Vec::new();
+    for packet in &packets {
+        data.extend_from_slice(packet.encode().unwrap().as_ref());
+    }
+
+    let reader = MockBuffer::new(data.clone())
+        .chunk_size(3)
+        .interleave_pending(true);
+    let stream = PacketStream::new(reader);
+    let polled: Vec<_> = block_on(stream.try_collect::<_, _, Vec<_>>()).unwrap();
+    assert_eq!(polled, packets);
+
+    // EOF in the middle of a packet is an error
+    let mut stream = PacketStream::new(MockBuffer::new(data[..data.len() - 1].to_vec()));
+    for packet in &packets[..2] {
+        assert_eq!(&block_on(stream.next()).unwrap().unwrap(), packet);
+    }
+    assert!(block_on(stream.next()).unwrap().unwrap_err().is_eof());
+    assert!(block_on(stream.next()).is_none());
+}
+
+#[cfg(feature = "embedded-io")]
+#[test]
+fn test_embedded_io() {
+    let packet: Packet = Publish::new(
+        QosPid::Level1(Pid::try_from(10).unwrap()),
+        TopicName::try_from("a/b").unwrap(),
+        Bytes::from_static(b"hello world"),
+    )
+    .into();
+    let mut data = Vec::new();
+    block_on(packet.encode_embedded(&mut data)).unwrap();
+    block_on(Packet::Pingresp.encode_async(&mut data)).unwrap();
+
+    // Both backends in the same build
+    let mut reader = &data[..];
+    assert_eq!(
+        block_on(Packet::decode_embedded(&mut reader)).unwrap(),
+        packet
+    );
+    assert_eq!(
+        block_on(Packet::decode_async(&mut reader)).unwrap(),
+        Packet::Pingresp
+    );
+
+    let mut reader = &data[..data.len() - 3];
+    assert!(block_on(Packet::decode_embedded(&mut reader))
+        .unwrap_err()
+        .is_eof());
+
+    // The declared length is not allocated before the bytes arrive
+    let mut reader = &[0x30, 0xff, 0xff, 0xff, 0x7f, 0x00, 0x01, b'a'][..];
+    assert!(block_on(Packet::decode_embedded(&mut reader))
+        .unwrap_err()
+        .is_eof());
+}
+
+#[test]
+fn test_owned_packet_reader() {
+    // Completion based reader, which owns the buffer while reading
+    struct ChunkReader(Vec<u8>);
+    impl OwnedRead for ChunkReader {
+        async fn read_owned(&mut self, mut buf: Vec<u8>) -> (std::io::Result<usize>, Vec<u8>) {
+            let size = self.0.len().min(3);
+            buf.extend(self.0.drain(..size));
+            (Ok(size), buf)
+        }
+    }
+
+    let packet: Packet = Publish::new(
+        QosPid::Level1(Pid::try_from(10).unwrap()),
+        TopicName::try_from("a/b").unwrap(),
+        Bytes::from_static(b"hello world"),
+    )
+    .into();
+    let mut data = packet.encode().unwrap().as_ref().to_vec();
+    data.extend_from_slice(&[0b1101_0000, 0]);
+
+    let mut reader = OwnedPacketReader::new(ChunkReader(data.clone()));
+    assert_eq!(block_on(reader.read_packet()).unwrap(), Some(packet));
+    assert_eq!(
+        block_on(reader.read_packet()).unwrap(),
+        Some(Packet::Pingresp)
+    );
+    assert_eq!(block_on(reader.read_packet()).unwrap(), None);
+
+    // EOF in the middle of a packet
+    let mut reader = OwnedPacketReader::new(ChunkReader(data[..5].to_vec()));
+    assert!(block_on(reader.read_packet()).unwrap_err().is_eof());
+    assert_eq!(reader.buffered(), &data[..5]);
+}
+
+#[test]
+fn test_decode_strict_var_int() {
+    let strict = DecodeLimits {
+        strict_var_int: true,
+        ..Default::default()
+    };
+    // Remaining length 5 encoded in 2 bytes
+    let data = [0b0011_0000, 0x85, 0x00, 0, 1, b'a', b'x', b'y'];
+    let packet = Packet::decode_with_limits(&data, &DecodeLimits::default())
+        .unwrap()
+        .unwrap();
+    assert_eq!(packet.topic_name().map(|name| &**name), Some("a"));
+    assert_eq!(
+        Packet::decode_with_limits(&data, &strict),
+        Err(Error::InvalidVarByteInt)
+    );
+
+    let mut reader = &data[..];
+    let mut state = PollPacketState::default();
+    let err = block_on(PollPacket::new(&mut state, &mut reader).with_limits(strict)).unwrap_err();
+    assert_eq!(err, Error::InvalidVarByteInt);
+
+    // Minimal encoding is accepted
+    let data = [0b0011_0000, 5, 0, 1, b'a', b'x', b'y'];
+    assert!(Packet::decode_with_limits(&data, &strict)
+        .unwrap()
+        .is_some());
+}
+
+#[test]
+fn test_annotated_hexdump() {
+    let data: &[u8] = &[
+        0b00110010, 12, 0x00, 0x03, b'a', b'/', b'b', 0, 10, b'h', b'e', b'l', b'l', b'o', //
+        0b11000000, 0, //
+        0b10000010, 8, 0, 10, 0, 3, b'a', b'/', b'+',
+    ];
+    let expected = [
+        "000000  32                                               PUBLISH flags=0b0010",
+        "000001  0c                                               remaining length: 12",
+        "000002  00 03 61 2f 62                                   topic name: \"a/b\"",
+        "000007  00 0a                                            packet identifier: 10",
+        "000009  68 65 6c 6c 6f                                   payload: 5 bytes",
+        "00000e  c0                                               PINGREQ flags=0b0000",
+        "00000f  00                                               remaining length: 0",
+        "000010  82                                               SUBSCRIBE flags=0b0010",
+        "000011  08                                               remaining length: 8",
+        "000012  00 0a                                            packet identifier: 10",
+        "000014  00 03 61 2f 2b                                   topic filter: \"a/+\"",
+        "        incomplete packet: 1 more bytes expected",
+    ];
+    assert_eq!(
+        annotated_hexdump(data).lines().collect::<Vec<_>>(),
+        expected
+    );
+
+    // Invalid UTF-8 topic name and unexpected bytes after PINGREQ
+    let data: &[u8] = &[0b00110000, 3, 0x00, 0x01, 0xc0, 0b11000000, 1, 0xff];
+    let output = annotated_hexdump(data);
+    assert!(output.contains("topic name: invalid UTF-8"));
+    assert!(output.contains("unexpected bytes: 1 bytes"));
+}
+
+#[test]
+fn test_would_block_error() {
+    let err = Error::from(io::Error::from(io::ErrorKind::WouldBlock));
+    assert!(err.is_would_block());
+    assert!(!err.is_eof());
+    assert_eq!(io::Error::from(err).kind(), io::ErrorKind::WouldBlock);
+    assert!(!Error::InvalidRemainingLength.is_would_block());
+
+    let err = v5::ErrorV5::from(io::Error::from(io::ErrorKind::WouldBlock));
+    assert!(err.is_would_block());
+}
+
+#[test]
+fn test_packet_decoder_fragments() {
+    let packets = vec![
+        Packet::Pingreq,
+        Publish::new(
+            QosPid::Level1(Pid::try_from(3).unwrap()),
+            TopicName::try_from("a/b".to_owned()).unwrap(),
+            Bytes::from(vec![7u8; 200]),
+        )
+        .into(),
+        Packet::Puback(Pid::try_from(3).unwrap()),
+    ];
+    let mut data = Vec::new();
+    for packet in &packets {
+        data.extend_from_slice(packet.encode().unwrap().as_ref());
+    }
+
+    for chunk_size in [1, 2, 7, 150, data.len()] {
+        let mut decoder = PacketDecoder::new();
+        let mut decoded = Vec::new();
+        for mut chunk in data.chunks(chunk_size) {
+            while let FeedStatus::Packet(packet, size) = decoder.feed(chunk).unwrap() {
+                decoded.push(packet);
+                chunk = &chunk[size..];
+            }
+            assert!(decoder.buffered() <= data.len());
+        }
+        assert_eq!(decoded, packets, "chunk size: {chunk_size}");
+        assert_eq!(decoder.buffered(), 0);
+    }
+
+    // Malformed body is reported once all of it is received
+    let mut decoder = PacketDecoder::new();
+    assert_eq!(decoder.feed(&[0x40, 0x02, 0x00]), Ok(FeedStatus::NeedMore));
+    assert_eq!(decoder.feed(&[0x00]), Err(Error::ZeroPid));
+    assert_eq!(decoder.buffered(), 0);
+
+    let mut decoder = PacketDecoder::with_max_packet_size(100);
+    assert_eq!(decoder.feed(&[0x30]), Ok(FeedStatus::NeedMore));
+    assert_eq!(decoder.feed(&[0xc8, 0x01]), Err(Error::PacketTooLarge(203)));
+}
@@ -1,7 +1,10 @@
+use std::io;
 use std::mem;
+use std::pin::Pin;
 use std::sync::Arc;
+use std::task::{Context, Poll};
 
-use bytes::Bytes;
+use bytes::{Bytes, BytesMut};
 use futures_lite::future::block_on;
 
 use crate::v3::*;
@@ -15,8 +18,31 @@ fn assert_encode(pkt: Packet, len: usize) {
     assert_eq!(pkt.encode_len().unwrap(), len);
     assert_eq!(data_async.len(), len);
 
-    let decoded_pkt = Packet::decode(&data_async).unwrap().unwrap();
-    assert_eq!(pkt, decoded_pkt);
+    let mut buf = vec![0u8; len + 1];
+    assert_eq!(pkt.encode_into(&mut buf).unwrap(), len);
+    assert_eq!(&buf[..len], &data_async);
+    let mut bytes_mut = BytesMut::new();
+    assert_eq!(pkt.encode_to_buf(&mut bytes_mut).unwrap(), len);
+    assert_eq!(&bytes_mut[..], &data_async);
+
+    let (head, payload) = pkt.encode_vectored().unwrap();
+    assert_eq!([head.as_ref(), &payload[..]].concat(), data_async);
+    let mut data_vectored = Vec::new();
+    block_on(pkt.encode_vectored_async(&mut data_vectored)).unwrap();
+    assert_eq!(data_vectored, data_async);
+    let mut state = pkt.encode_poll_state().unwrap();
+    let mut data_polled = Vec::new();
+    let total = block_on(PollEncode::new(&mut state, &mut data_polled)).unwrap();
+    assert_eq!(total, len);
+    assert_eq!(data_polled, data_async);
+
+    let header = Header::decode(&data_async).unwrap();
+    let mut header_data = Vec::new();
+    header.encode(&mut header_data).unwrap();
+    assert_eq!(header_data.len(), header.encode_len());
+    assert_eq!(header_data, &data_async[..header_len(len)]);
+
+    assert_eq!(testing::assert_round_trip(&pkt), data_async);
 
     let mut data = &data_async[..];
     let (total, buf, polled_pkt) =
@@ -25,6 +51,22 @@ fn assert_encode(pkt: Packet, len: usize) {
     assert_eq!(total, len);
     assert_eq!(buf_ref, &data_async[header_len(total)..]);
     assert_eq!(pkt, polled_pkt);
+
+    let mut slice = &data_async[..];
+    let packet_ref = PacketRef::decode(&mut slice).unwrap().unwrap();
+    assert!(slice.is_empty());
+    assert_eq!(pkt, packet_ref.to_packet());
+
+    let mut shared = Bytes::from(data_async);
+    let shared_pkt = Packet::decode_shared(&mut shared).unwrap().unwrap();
+    assert!(shared.is_empty());
+    assert_eq!(pkt, shared_pkt);
+
+    #[cfg(feature = "serde")]
+    {
+        let json = serde_json::to_string(&pkt).unwrap();
+        assert_eq!(pkt, serde_json::from_str::<Packet>(&json).unwrap());
+    }
 }
 
 #[test]
@@ -32,18 +74,19 @@ fn test_encode_connect() {
     let packet = Connect {
         protocol: Protocol::V311,
         keep_alive: 120,
-        client_id: Arc::new("sample".to_owned()),
+        client_id: SharedStr::from("sample"),
         clean_session: true,
         last_will: None,
         username: None,
         password: None,
+        is_bridge: false,
     };
     assert_encode(packet.into(), 20);
 
     let packet = Connect {
         protocol: Protocol::V311,
         keep_alive: 120,
-        client_id: Arc::new("sample".to_owned()),
+        client_id: SharedStr::from("sample"),
         clean_session: true,
         last_will: Some(LastWill {
             qos: QoS::Level1,
@@ -51,23 +94,62 @@ fn test_encode_connect() {
             topic_name: TopicName::try_from("abc".to_owned()).unwrap(),
             message: Bytes::from("msg-content"),
         }),
-        username: Some(Arc::new("username".to_owned())),
+        username: Some(SharedStr::from("username")),
         password: Some(Bytes::from("password")),
+        is_bridge: false,
     };
     assert_encode(packet.into(), 58);
 
     let packet = Connect {
         protocol: Protocol::V310,
         keep_alive: 120,
-        client_id: Arc::new("sample".to_owned()),
+        client_id: SharedStr::from("sample"),
         clean_session: true,
         last_will: None,
         username: None,
         password: None,
+        is_bridge: false,
     };
     assert_encode(packet.into(), 22);
 }
 
+#[test]
+fn test_encode_bridge_connect() {
+    for (protocol, level) in [(Protocol::V310, 0x83), (Protocol::V311, 0x84)] {
+        let mut connect = Connect::new("bridge", 60);
+        connect.protocol = protocol;
+        connect.is_bridge = true;
+        let data = Packet::from(connect.clone()).encode().unwrap();
+        assert_eq!(data.as_ref()[protocol.encode_len() + 1], level);
+        assert_encode(connect.into(), protocol.encode_len() + 13);
+    }
+    assert_eq!(
+        Protocol::new_bridge(b"MQTT", 0x84),
+        Ok((Protocol::V311, true))
+    );
+    assert_eq!(
+        Protocol::new_bridge(b"MQTT", 4),
+        Ok((Protocol::V311, false))
+    );
+    assert_eq!(
+        Protocol::new_bridge(b"MQTT", 0x85),
+        Err(Error::InvalidProtocol("MQTT".to_owned(), 0x85))
+    );
+    assert_eq!(
+        Protocol::new(b"MQTT", 0x84),
+        Err(Error::InvalidProtocol("MQTT".to_owned(), 0x84))
+    );
+}
+
+#[test]
+fn test_connect_debug_redacted() {
+    let mut connect = Connect::new(SharedStr::from("client"), 30);
+    connect.password = Some(Bytes::from("password"));
+    let output = format!("{connect:?}");
+    assert!(output.contains("password: Some([REDACTED len=8])"));
+    assert!(!output.contains(r#"b"password""#));
+}
+
 #[test]
 fn test_encode_connack() {
     let packet = Connack {
@@ -89,6 +171,21 @@ fn test_encode_publish() {
     assert_encode(packet.into(), 15);
 }
 
+#[test]
+fn test_publish_new_payload() {
+    let qos_pid = QosPid::Level1(Pid::try_from(10).unwrap());
+    let topic_name = TopicName::try_from("a/b").unwrap();
+    let expected = Publish::new(qos_pid, topic_name.clone(), Bytes::from_static(b"hello"));
+    for packet in [
+        Publish::new(qos_pid, topic_name.clone(), b"hello".to_vec()),
+        Publish::new(qos_pid, topic_name.clone(), &b"hello"[..]),
+        Publish::new(qos_pid, topic_name.clone(), "hello"),
+        Publish::new(qos_pid, topic_name.clone(), String::from("hello")),
+    ] {
+        assert_eq!(packet, expected);
+    }
+}
+
 #[test]
 fn test_encode_puback() {
     let packet = Packet::Puback(Pid::try_from(19).unwrap());
@@ -132,6 +229,46 @@ fn test_encode_suback() {
         vec![SubscribeReturnCode::MaxLevel2],
     );
     assert_encode(packet.into(), 5);
+
+    let packet = Suback::new(
+        Pid::try_from(1).unwrap(),
+        vec![SubscribeReturnCode::Failure, SubscribeReturnCode::MaxLevel0],
+    );
+    assert_eq!(
+        Packet::from(packet.clone()).encode().unwrap().as_ref(),
+        &[0x90, 4, 0, 1, 0x80, 0x00]
+    );
+    assert_encode(packet.into(), 6);
+}
+
+#[test]
+fn test_suback_check_subscribe() {
+    let pid = Pid::try_from(5).unwrap();
+    let subscribe = Subscribe::new(
+        pid,
+        vec![
+            (
+                TopicFilter::try_from("a/#".to_owned()).unwrap(),
+                QoS::Level2,
+            ),
+            (TopicFilter::try_from("b".to_owned()).unwrap(), QoS::Level1),
+        ],
+    );
+    let suback = Suback::new(pid, vec![QoS::Level1.into(), SubscribeReturnCode::Failure]);
+    assert!(suback.check_subscribe(&subscribe).is_ok());
+    assert!(suback.topics[1].is_failure());
+    assert!(!suback.topics[0].is_failure());
+
+    let suback = Suback::new(pid, vec![SubscribeReturnCode::MaxLevel0]);
+    assert_eq!(
+        suback.check_subscribe(&subscribe),
+        Err(Error::InvalidPacket("SUBACK return code count mismatch"))
+    );
+    let suback = Suback::new(Pid::try_from(6).unwrap(), vec![]);
+    assert_eq!(
+        suback.check_subscribe(&subscribe),
+        Err(Error::InvalidPacket("SUBACK packet identifier mismatch"))
+    );
 }
 
 #[test]
@@ -163,3 +300,521 @@ fn test_encode_ping_resp() {
 fn test_encode_disconnect() {
     assert_encode(Packet::Disconnect, 2);
 }
+
+#[test]
+fn test_encode_into_small_buffer() {
+    let packet = Packet::Subscribe(Subscribe::new(
+        Pid::try_from(10).unwrap(),
+        vec![(
+            TopicFilter::try_from("a/b".to_owned()).unwrap(),
+            QoS::Level1,
+        )],
+    ));
+    let len = packet.encode_len().unwrap();
+    let mut buf = vec![0u8; len - 1];
+    let err = packet.encode_into(&mut buf).unwrap_err();
+    assert!(matches!(err, Error::IoError(io::ErrorKind::WriteZero, _)));
+    assert!(buf.iter().all(|b| *b == 0));
+
+    let mut buf = [0u8; 3];
+    let err = packet.encode_to_buf(&mut &mut buf[..]).unwrap_err();
+    assert!(matches!(err, Error::IoError(io::ErrorKind::WriteZero, _)));
+}
+
+#[test]
+fn test_encode_vectored_publish() {
+    let payload = Bytes::from(vec![7u8; 1024]);
+    let packet: Packet = Publish {
+        dup: false,
+        qos_pid: QosPid::Level1(Pid::try_from(10).unwrap()),
+        retain: false,
+        topic_name: TopicName::try_from("asdf".to_owned()).unwrap(),
+        payload: payload.clone(),
+    }
+    .into();
+    let (head, vectored_payload) = packet.encode_vectored().unwrap();
+    assert_eq!(
+        head.as_ref(),
+        &[0b00110010, 0x88, 0x08, 0, 4, b'a', b's', b'd', b'f', 0, 10]
+    );
+    // The payload is shared, not copied
+    assert_eq!(vectored_payload.as_ptr(), payload.as_ptr());
+    assert_eq!(vectored_payload.len(), payload.len());
+}
+
+/// A writer accepts at most 3 bytes per call and blocks every other call.
+#[derive(Default)]
+struct SlowWriter {
+    data: Vec<u8>,
+    blocked: bool,
+}
+
+impl io::Write for SlowWriter {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.blocked = !self.blocked;
+        if self.blocked {
+            return Err(io::ErrorKind::WouldBlock.into());
+        }
+        let size = buf.len().min(3);
+        self.data.extend_from_slice(&buf[..size]);
+        Ok(size)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+impl tokio::io::AsyncWrite for SlowWriter {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        match io::Write::write(self.get_mut(), buf) {
+            Err(err) if err.kind() == io::ErrorKind::WouldBlock => {
+                cx.waker().wake_by_ref();
+                Poll::Pending
+            }
+            result => Poll::Ready(result),
+        }
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Poll::Ready(Ok(()))
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Poll::Ready(Ok(()))
+    }
+}
+
+#[test]
+fn test_poll_encode_partial_write() {
+    let packet: Packet = Publish {
+        dup: false,
+        qos_pid: QosPid::Level1(Pid::try_from(10).unwrap()),
+        retain: false,
+        topic_name: TopicName::try_from("asdf".to_owned()).unwrap(),
+        payload: Bytes::from(b"hello world".to_vec()),
+    }
+    .into();
+    let expected = packet.encode().unwrap();
+
+    let mut state = packet.encode_poll_state().unwrap();
+    let mut writer = SlowWriter::default();
+    let mut blocked = 0;
+    while let Err(err) = state.write_to(&mut writer) {
+        assert_eq!(err.kind(), io::ErrorKind::WouldBlock);
+        blocked += 1;
+    }
+    assert!(blocked > 1);
+    assert!(state.is_done());
+    assert_eq!(state.written(), expected.as_ref().len());
+    assert_eq!(writer.data, expected.as_ref());
+
+    let mut state = packet.encode_poll_state().unwrap();
+    let mut writer = SlowWriter::default();
+    let total = block_on(PollEncode::new(&mut state, &mut writer)).unwrap();
+    assert_eq!(total, expected.as_ref().len());
+    assert_eq!(writer.data, expected.as_ref());
+}
+
+#[test]
+fn test_packet_sink() {
+    use futures_lite::future::poll_fn;
+    use futures_sink::Sink;
+
+    let packets: Vec<Packet> = vec![
+        Publish::new(
+            QosPid::Level1(Pid::try_from(10).unwrap()),
+            TopicName::try_from("a/b").unwrap(),
+            Bytes::from_static(b"hello world"),
+        )
+        .into(),
+        Packet::Pingreq,
+    ];
+    let mut expected = Vec::new();
+    for packet in &packets {
+        expected.extend_from_slice(packet.encode().unwrap().as_ref());
+    }
+
+    let mut sink = PacketSink::new(SlowWriter::default());
+    for packet in packets {
+        block_on(poll_fn(|cx| Pin::new(&mut sink).poll_ready(cx))).unwrap();
+        Pin::new(&mut sink).start_send(packet).unwrap();
+    }
+    // The last packet is only written by flush
+    assert!(sink.get_ref().data.len() < expected.len());
+    block_on(poll_fn(|cx| Pin::new(&mut sink).poll_close(cx))).unwrap();
+    assert_eq!(sink.into_inner().data, expected);
+}
+
+#[test]
+fn test_packet_sink_shared_packet() {
+    use futures_lite::future::poll_fn;
+    use futures_sink::Sink;
+
+    let publish = Publish::new(
+        QosPid::Level0,
+        TopicName::try_from("a/b").unwrap(),
+        Bytes::from_static(b"hello world"),
+    );
+    let packet = Arc::new(Packet::from(publish.clone()));
+    let expected = packet.encode().unwrap().as_ref().to_vec();
+
+    // The same packet is written to every sink without cloning it
+    let mut sinks: Vec<GenericPacketSink<Vec<u8>, Arc<Packet>>> =
+        (0..3).map(|_| GenericPacketSink::new(Vec::new())).collect();
+    for sink in sinks.iter_mut() {
+        block_on(poll_fn(|cx| Pin::new(&mut *sink).poll_ready(cx))).unwrap();
+        Pin::new(&mut *sink)
+            .start_send(Arc::clone(&packet))
+            .unwrap();
+        block_on(poll_fn(|cx| Pin::new(&mut *sink).poll_flush(cx))).unwrap();
+    }
+    assert_eq!(Arc::strong_count(&packet), 1);
+    for sink in sinks {
+        assert_eq!(sink.into_inner(), expected);
+    }
+
+    let mut sink = GenericPacketSink::<_, &Packet>::new(Vec::new());
+    block_on(poll_fn(|cx| Pin::new(&mut sink).poll_ready(cx))).unwrap();
+    Pin::new(&mut sink).start_send(&*packet).unwrap();
+    block_on(poll_fn(|cx| Pin::new(&mut sink).poll_close(cx))).unwrap();
+    assert_eq!(sink.into_inner(), expected);
+
+    // Packet bodies are encodable by reference
+    let mut body = Vec::new();
+    Encodable::encode(&&publish, &mut body).unwrap();
+    assert_eq!(Encodable::encode_len(&Arc::new(publish)), body.len());
+}
+
+#[test]
+fn test_encode_header() {
+    let header = Header::new(PacketType::Publish, true, QoS::Level2, true, 321);
+    let mut data = Vec::new();
+    header.encode(&mut data).unwrap();
+    assert_eq!(data, [0b00111101, 0xC1, 0x02]);
+    assert_eq!(Header::decode(&data).unwrap(), header);
+
+    let header = Header::new(PacketType::Pubrel, false, QoS::Level0, false, 2);
+    let mut data = Vec::new();
+    header.encode(&mut data).unwrap();
+    assert_eq!(data, [0b01100010, 0x02]);
+
+    let header = Header::new(PacketType::Connect, false, QoS::Level0, false, 268435456);
+    assert!(header.encode(&mut Vec::new()).is_err());
+}
+
+#[test]
+fn test_packet_accessors() {
+    let topic_name = TopicName::try_from("a/b".to_owned()).unwrap();
+    let packet: Packet = Publish {
+        dup: false,
+        qos_pid: QosPid::Level1(Pid::try_from(10).unwrap()),
+        retain: false,
+        topic_name: topic_name.clone(),
+        payload: Bytes::new(),
+    }
+    .into();
+    assert_eq!(packet.packet_type(), PacketType::Publish);
+    assert_eq!(packet.pid(), Some(Pid::try_from(10).unwrap()));
+    assert_eq!(packet.topic_name(), Some(&topic_name));
+    assert_eq!(packet.qos(), Some(QoS::Level1));
+
+    let packet = Packet::Pubrel(Pid::try_from(11).unwrap());
+    assert_eq!(packet.packet_type(), PacketType::Pubrel);
+    assert_eq!(packet.pid(), Some(Pid::try_from(11).unwrap()));
+    assert_eq!(packet.topic_name(), None);
+    assert_eq!(packet.qos(), None);
+
+    assert_eq!(Packet::Pingreq.pid(), None);
+}
+
+#[test]
+fn test_packet_type_metadata() {
+    assert!(PacketType::Connect.is_client_to_server());
+    assert!(!PacketType::Connect.is_server_to_client());
+    assert!(PacketType::Publish.is_client_to_server());
+    assert!(PacketType::Publish.is_server_to_client());
+    assert!(!PacketType::Disconnect.is_server_to_client());
+    assert!(!PacketType::Suback.is_client_to_server());
+
+    assert!(!PacketType::Publish.requires_pid(QoS::Level0));
+    assert!(PacketType::Publish.requires_pid(QoS::Level2));
+    assert!(PacketType::Unsuback.requires_pid(QoS::Level0));
+    assert!(!PacketType::Pingreq.requires_pid(QoS::Level0));
+
+    let pid = Pid::default();
+    for packet in [
+        Packet::Pubrel(pid),
+        Packet::Puback(pid),
+        Subscribe::new(pid, vec![]).into(),
+        Packet::Disconnect,
+    ] {
+        let data = packet.encode().unwrap();
+        assert_eq!(
+            packet.packet_type().expected_flags(),
+            Some(data.as_ref()[0] & 0b1111)
+        );
+    }
+    assert_eq!(PacketType::Publish.expected_flags(), None);
+}
+
+#[test]
+fn test_validate() {
+    let mut connect = Connect::new("id", 60);
+    assert_eq!(Packet::from(connect.clone()).validate(), Ok(()));
+    connect.password = Some(Bytes::from("password"));
+    assert_eq!(
+        Packet::from(connect.clone()).validate(),
+        Err(Error::InvalidPacket("password without username"))
+    );
+    connect.protocol = Protocol::V310;
+    assert_eq!(Packet::from(connect.clone()).validate(), Ok(()));
+    for client_id in ["", "123456789012345678901234"] {
+        connect.client_id = SharedStr::from(client_id);
+        assert_eq!(
+            Packet::from(connect.clone()).validate(),
+            Err(Error::InvalidPacket(
+                "v3.1 client identifier must be 1 to 23 characters"
+            ))
+        );
+    }
+
+    let mut publish = Publish::new(
+        QosPid::Level0,
+        TopicName::try_from("a/b".to_owned()).unwrap(),
+        Bytes::new(),
+    );
+    publish.dup = true;
+    assert_eq!(
+        Packet::from(publish.clone()).validate(),
+        Err(Error::InvalidPacket("dup flag with QoS 0"))
+    );
+    publish.qos_pid = QosPid::Level1(Pid::default());
+    assert_eq!(Packet::from(publish).validate(), Ok(()));
+
+    assert_eq!(
+        Packet::from(Subscribe::new(Pid::default(), Vec::new())).validate(),
+        Err(Error::EmptySubscription)
+    );
+    assert_eq!(
+        Packet::from(Suback::new(Pid::default(), Vec::new())).validate(),
+        Err(Error::InvalidPacket("empty suback"))
+    );
+}
+
+#[test]
+fn test_validate_v310() {
+    let mut connect = Connect::new("id", 60);
+    assert_eq!(
+        Packet::from(connect.clone()).validate_v310(),
+        Err(Error::UnexpectedProtocol(Protocol::V311))
+    );
+    connect.protocol = Protocol::V310;
+    assert_eq!(Packet::from(connect).validate_v310(), Ok(()));
+
+    let connack = Connack::new(true, ConnectReturnCode::Accepted);
+    assert_eq!(
+        Packet::from(connack).validate_v310(),
+        Err(Error::InvalidPacket("session present in v3.1 connack"))
+    );
+    let connack = Connack::new(false, ConnectReturnCode::IdentifierRejected);
+    assert_eq!(Packet::from(connack).validate_v310(), Ok(()));
+
+    let pid = Pid::default();
+    let suback = Suback::new(pid, vec![QoS::Level1.into()]);
+    assert_eq!(Packet::from(suback).validate_v310(), Ok(()));
+    let suback = Suback::new(pid, vec![SubscribeReturnCode::Failure]);
+    assert_eq!(
+        Packet::from(suback).validate_v310(),
+        Err(Error::InvalidPacket("failure return code in v3.1 suback"))
+    );
+}
+
+#[test]
+fn test_return_code_description() {
+    assert!(!ConnectReturnCode::Accepted.is_error());
+    assert!(ConnectReturnCode::NotAuthorized.is_error());
+    assert_eq!(
+        ConnectReturnCode::NotAuthorized.description(),
+        "The Client is not authorized to connect."
+    );
+    assert!(!SubscribeReturnCode::MaxLevel1.is_error());
+    assert!(SubscribeReturnCode::Failure.is_error());
+}
+
+#[test]
+fn test_return_code_granted_qos() {
+    for qos in [QoS::Level0, QoS::Level1, QoS::Level2] {
+        assert_eq!(SubscribeReturnCode::granted(qos).granted_qos(), Some(qos));
+    }
+    assert_eq!(SubscribeReturnCode::Failure.granted_qos(), None);
+}
+
+#[cfg(feature = "proptest")]
+proptest::proptest! {
+    #[test]
+    fn test_strategy_packet(pkt in v3::strategy::packet()) {
+        pkt.validate().unwrap();
+        let len = pkt.encode_len().unwrap();
+        assert_encode(pkt, len);
+    }
+}
+
+#[test]
+fn test_subscribe_split() {
+    let topics: Vec<_> = (0..10)
+        .map(|i| {
+            (
+                TopicFilter::try_from(format!("topic/{i}")).unwrap(),
+                QoS::Level1,
+            )
+        })
+        .collect();
+    let mut pids = PidAllocator::new();
+    // Each filter takes 3 + 7 bytes, 3 filters per packet
+    let packets = Subscribe::split(topics.clone(), 2 + 2 + 30, || pids.alloc()).unwrap();
+    assert_eq!(
+        packets.iter().map(|p| p.topics.len()).collect::<Vec<_>>(),
+        vec![3, 3, 3, 1]
+    );
+    assert_eq!(pids.in_flight(), 4);
+    for packet in &packets {
+        assert!(Packet::Subscribe(packet.clone()).encode_len().unwrap() <= 34);
+    }
+    let all: Vec<_> = packets.into_iter().flat_map(|p| p.topics).collect();
+    assert_eq!(all, topics);
+
+    let filters: Vec<_> = topics.into_iter().map(|(filter, _)| filter).collect();
+    let packets = Unsubscribe::split(filters, 2 + 2 + 27, || pids.alloc()).unwrap();
+    assert_eq!(packets.len(), 4);
+
+    // Too small for a single filter, no pid is taken
+    let err = Subscribe::split(
+        vec![(TopicFilter::try_from("topic/0").unwrap(), QoS::Level0)],
+        13,
+        || pids.alloc(),
+    )
+    .unwrap_err();
+    assert_eq!(err, Error::PacketTooLarge(14));
+    assert_eq!(pids.in_flight(), 8);
+}
+
+#[test]
+fn test_encode_value_too_long() {
+    let mut connect = Connect::new("client", 30);
+    connect.username = Some(SharedStr::from("user"));
+    connect.password = Some(Bytes::from(vec![0; u16::MAX as usize + 1]));
+    let packet: Packet = connect.into();
+    assert_eq!(packet.encode(), Err(Error::ValueTooLong(65536)));
+    let mut data = Vec::new();
+    assert_eq!(
+        block_on(packet.encode_async(&mut data)),
+        Err(Error::ValueTooLong(65536))
+    );
+    assert_eq!(
+        packet.encode_to(&mut Vec::new()),
+        Err(Error::ValueTooLong(65536))
+    );
+}
+
+#[test]
+fn test_packet_display() {
+    let mut connect = Connect::new("client", 60);
+    connect.username = Some(SharedStr::from("user"));
+    connect.password = Some(Bytes::from_static(b"secret"));
+    let output = Packet::from(connect).to_string();
+    assert_eq!(
+        output,
+        "CONNECT protocol=v3.1.1 client_id=client keep_alive=60 clean_session username=user password=***"
+    );
+    assert!(!output.contains("secret"));
+
+    let publish = Publish {
+        dup: false,
+        retain: true,
+        qos_pid: QosPid::Level1(Pid::try_from(12).unwrap()),
+        topic_name: TopicName::try_from("a/b".to_owned()).unwrap(),
+        payload: Bytes::from(vec![0u8; 240]),
+    };
+    assert_eq!(
+        Packet::from(publish).to_string(),
+        "PUBLISH qos=1 pid=12 topic=a/b len=240 retain"
+    );
+
+    let subscribe = Subscribe::new(
+        Pid::try_from(3).unwrap(),
+        vec![
+            (
+                TopicFilter::try_from("a/+".to_owned()).unwrap(),
+                QoS::Level1,
+            ),
+            (
+                TopicFilter::try_from("b/#".to_owned()).unwrap(),
+                QoS::Level0,
+            ),
+        ],
+    );
+    assert_eq!(
+        Packet::from(subscribe).to_string(),
+        "SUBSCRIBE pid=3 topics=a/+:1,b/#:0"
+    );
+    assert_eq!(
+        Packet::Puback(Pid::try_from(12).unwrap()).to_string(),
+        "PUBACK pid=12"
+    );
+    assert_eq!(Packet::Pingreq.to_string(), "PINGREQ");
+}
+
+#[cfg(feature = "json")]
+#[test]
+fn test_packet_to_json() {
+    use serde_json::json;
+
+    let mut connect = Connect::new("client", 60);
+    connect.password = Some(Bytes::from_static(b"\x01\xab"));
+    assert_eq!(
+        Packet::from(connect).to_json(),
+        json!({
+            "type": "CONNECT",
+            "protocol": "v3.1.1",
+            "clean_session": true,
+            "keep_alive": 60,
+            "client_id": "client",
+            "last_will": null,
+            "username": null,
+            "password": "01ab",
+        })
+    );
+
+    let publish = Publish::new(
+        QosPid::Level1(Pid::try_from(12).unwrap()),
+        TopicName::try_from("a/b".to_owned()).unwrap(),
+        Bytes::from_static(b"hi"),
+    );
+    assert_eq!(
+        Packet::from(publish).to_json(),
+        json!({
+            "type": "PUBLISH",
+            "dup": false,
+            "retain": false,
+            "qos": 1,
+            "pid": 12,
+            "topic_name": "a/b",
+            "payload": "6869",
+        })
+    );
+
+    let suback = Suback::new(
+        Pid::try_from(3).unwrap(),
+        vec![SubscribeReturnCode::MaxLevel1, SubscribeReturnCode::Failure],
+    );
+    assert_eq!(
+        Packet::from(suback).to_json(),
+        json!({ "type": "SUBACK", "pid": 3, "codes": ["MaxLevel1", "Failure"] })
+    );
+    assert_eq!(Packet::Pingreq.to_json(), json!({ "type": "PINGREQ" }));
+}
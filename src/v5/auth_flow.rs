@@ -0,0 +1,275 @@
+use std::sync::Arc;
+
+use bytes::Bytes;
+use thiserror::Error;
+
+use super::{
+    Auth, AuthReasonCode, Connack, Connect, ConnectReasonCode, Disconnect, DisconnectReasonCode,
+    Packet,
+};
+
+/// The result of one step of the authentication callback.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum AuthStep {
+    /// Send the challenge to the client and wait for the next AUTH.
+    Continue(Option<Bytes>),
+    /// The client is authenticated, with the optional final data.
+    Success(Option<Bytes>),
+    /// The client is not authenticated.
+    Failure,
+}
+
+/// Errors of the enhanced authentication exchange, the connection should be
+/// closed with reason code "Protocol Error".
+#[derive(Error, Debug, Clone, PartialEq, Eq)]
+pub enum AuthFlowError {
+    /// AUTH is received but no authentication method is given in CONNECT.
+    #[error("enhanced authentication is not negotiated")]
+    NotNegotiated,
+
+    /// The authentication method of AUTH is not the one in CONNECT.
+    #[error("authentication method mismatch: `{0:?}`")]
+    MethodMismatch(Option<Arc<str>>),
+
+    /// The AUTH reason code is not allowed in current state.
+    #[error("unexpected AUTH reason code: {0:?}")]
+    UnexpectedReasonCode(AuthReasonCode),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+enum State {
+    /// No authentication in progress
+    Idle,
+    /// Authenticating before CONNACK
+    Connecting,
+    /// Re-authenticating after CONNACK
+    Reauthenticating,
+}
+
+/// Server side driver of the [enhanced authentication] exchange.
+///
+/// The `callback` is called with the authentication method and the data
+/// received from the client (in CONNECT or AUTH), like a SASL server step.
+///
+/// [enhanced authentication]: https://docs.oasis-open.org/mqtt/mqtt/v5.0/os/mqtt-v5.0-os.html#_Toc3901256
+pub struct AuthFlow<F> {
+    callback: F,
+    method: Option<Arc<str>>,
+    state: State,
+}
+
+impl<F> AuthFlow<F>
+where
+    F: FnMut(&str, Option<&Bytes>) -> AuthStep,
+{
+    pub fn new(callback: F) -> Self {
+        AuthFlow {
+            callback,
+            method: None,
+            state: State::Idle,
+        }
+    }
+
+    /// The authentication method given in CONNECT.
+    pub fn method(&self) -> Option<&Arc<str>> {
+        self.method.as_ref()
+    }
+
+    /// Whether an authentication exchange is in progress.
+    pub fn in_progress(&self) -> bool {
+        self.state != State::Idle
+    }
+
+    /// Start the exchange by a CONNECT packet, return the AUTH or CONNACK to
+    /// send. Return `None` if no authentication method is given, the enhanced
+    /// authentication is not used.
+    pub fn on_connect(&mut self, connect: &Connect) -> Option<Packet> {
+        let method = connect.properties.auth_method.clone()?;
+        self.method = Some(method);
+        self.state = State::Connecting;
+        Some(self.step(connect.properties.auth_data.as_ref()))
+    }
+
+    /// Handle a received AUTH packet, return the AUTH, CONNACK or DISCONNECT
+    /// to send.
+    pub fn on_auth(&mut self, auth: &Auth) -> Result<Packet, AuthFlowError> {
+        let method = self.method.as_ref().ok_or(AuthFlowError::NotNegotiated)?;
+        if auth.properties.auth_method.as_ref() != Some(method) {
+            return Err(AuthFlowError::MethodMismatch(
+                auth.properties.auth_method.clone(),
+            ));
+        }
+        match (self.state, auth.reason_code) {
+            (
+                State::Connecting | State::Reauthenticating,
+                AuthReasonCode::ContinueAuthentication,
+            ) => {}
+            (State::Idle, AuthReasonCode::ReAuthentication) => {
+                self.state = State::Reauthenticating;
+            }
+            (_, reason_code) => return Err(AuthFlowError::UnexpectedReasonCode(reason_code)),
+        }
+        Ok(self.step(auth.properties.auth_data.as_ref()))
+    }
+
+    fn step(&mut self, data: Option<&Bytes>) -> Packet {
+        let method = self.method.clone().expect("authentication method");
+        let reauth = self.state == State::Reauthenticating;
+        match (self.callback)(&method, data) {
+            AuthStep::Continue(data) => {
+                auth_packet(AuthReasonCode::ContinueAuthentication, method, data)
+            }
+            AuthStep::Success(data) => {
+                self.state = State::Idle;
+                if reauth {
+                    auth_packet(AuthReasonCode::Success, method, data)
+                } else {
+                    let mut connack = Connack::new(false, ConnectReasonCode::Success);
+                    connack.properties.auth_method = Some(method);
+                    connack.properties.auth_data = data;
+                    connack.into()
+                }
+            }
+            AuthStep::Failure => {
+                self.state = State::Idle;
+                if reauth {
+                    Disconnect::new(DisconnectReasonCode::NotAuthorized).into()
+                } else {
+                    Connack::new(false, ConnectReasonCode::NotAuthorized).into()
+                }
+            }
+        }
+    }
+}
+
+fn auth_packet(reason_code: AuthReasonCode, method: Arc<str>, data: Option<Bytes>) -> Packet {
+    let mut auth = Auth::new(reason_code);
+    auth.properties.auth_method = Some(method);
+    auth.properties.auth_data = data;
+    auth.into()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const METHOD: &str = "SCRAM-SHA-1";
+
+    /// Accept the client after one round of challenge, the data must be
+    /// "first" then "final".
+    fn callback(method: &str, data: Option<&Bytes>) -> AuthStep {
+        assert_eq!(method, METHOD);
+        match data.map(|data| data.as_ref()) {
+            Some(b"first") => AuthStep::Continue(Some(Bytes::from_static(b"challenge"))),
+            Some(b"final") => AuthStep::Success(None),
+            _ => AuthStep::Failure,
+        }
+    }
+
+    fn client_auth(reason_code: AuthReasonCode, data: &'static [u8]) -> Auth {
+        let mut auth = Auth::new(reason_code);
+        auth.properties.auth_method = Some(Arc::from(METHOD));
+        auth.properties.auth_data = Some(Bytes::from_static(data));
+        auth
+    }
+
+    fn connect(data: &'static [u8]) -> Connect {
+        let mut connect = Connect::new("client", 0);
+        connect.properties.auth_method = Some(Arc::from(METHOD));
+        connect.properties.auth_data = Some(Bytes::from_static(data));
+        connect
+    }
+
+    #[test]
+    fn test_connect() {
+        let mut flow = AuthFlow::new(callback);
+        let connect_plain = Connect::new("client", 0);
+        assert_eq!(flow.on_connect(&connect_plain), None);
+        assert_eq!(
+            flow.on_auth(&client_auth(
+                AuthReasonCode::ContinueAuthentication,
+                b"final"
+            )),
+            Err(AuthFlowError::NotNegotiated)
+        );
+
+        match flow.on_connect(&connect(b"first")) {
+            Some(Packet::Auth(auth)) => {
+                assert_eq!(auth.reason_code, AuthReasonCode::ContinueAuthentication);
+                assert_eq!(
+                    auth.properties.auth_data.as_deref(),
+                    Some(&b"challenge"[..])
+                );
+            }
+            packet => panic!("unexpected packet: {packet:?}"),
+        }
+        assert!(flow.in_progress());
+        match flow.on_auth(&client_auth(
+            AuthReasonCode::ContinueAuthentication,
+            b"final",
+        )) {
+            Ok(Packet::Connack(connack)) => {
+                assert_eq!(connack.reason_code, ConnectReasonCode::Success);
+                assert_eq!(connack.properties.auth_method.as_deref(), Some(METHOD));
+            }
+            packet => panic!("unexpected packet: {packet:?}"),
+        }
+        assert!(!flow.in_progress());
+
+        let mut flow = AuthFlow::new(callback);
+        assert_eq!(
+            flow.on_connect(&connect(b"bad")),
+            Some(Connack::new(false, ConnectReasonCode::NotAuthorized).into())
+        );
+    }
+
+    #[test]
+    fn test_reauthenticate() {
+        let mut flow = AuthFlow::new(callback);
+        flow.on_connect(&connect(b"final")).unwrap();
+        assert_eq!(
+            flow.on_auth(&client_auth(
+                AuthReasonCode::ContinueAuthentication,
+                b"first"
+            )),
+            Err(AuthFlowError::UnexpectedReasonCode(
+                AuthReasonCode::ContinueAuthentication
+            ))
+        );
+
+        let packet = flow
+            .on_auth(&client_auth(AuthReasonCode::ReAuthentication, b"first"))
+            .unwrap();
+        assert!(matches!(packet, Packet::Auth(_)));
+        let packet = flow
+            .on_auth(&client_auth(
+                AuthReasonCode::ContinueAuthentication,
+                b"final",
+            ))
+            .unwrap();
+        match packet {
+            Packet::Auth(auth) => assert_eq!(auth.reason_code, AuthReasonCode::Success),
+            packet => panic!("unexpected packet: {packet:?}"),
+        }
+
+        let packet = flow
+            .on_auth(&client_auth(AuthReasonCode::ReAuthentication, b"bad"))
+            .unwrap();
+        assert_eq!(
+            packet,
+            Disconnect::new(DisconnectReasonCode::NotAuthorized).into()
+        );
+    }
+
+    #[test]
+    fn test_method_mismatch() {
+        let mut flow = AuthFlow::new(callback);
+        flow.on_connect(&connect(b"first")).unwrap();
+        let mut auth = client_auth(AuthReasonCode::ContinueAuthentication, b"final");
+        auth.properties.auth_method = Some(Arc::from("PLAIN"));
+        assert_eq!(
+            flow.on_auth(&auth),
+            Err(AuthFlowError::MethodMismatch(Some(Arc::from("PLAIN"))))
+        );
+    }
+}
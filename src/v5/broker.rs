@@ -0,0 +1,397 @@
+//! Sans-IO broker (server) side protocol state machine.
+//!
+//! The [`Connection`] does no I/O: feed it the decoded packets from the
+//! client, the packets to send and the timer events, then poll the packets
+//! to send.
+
+use std::collections::{HashSet, VecDeque};
+use std::time::{Duration, Instant};
+
+use thiserror::Error;
+
+use super::{
+    Connect, ConnectReasonCode, Disconnect, DisconnectReasonCode, Packet, PacketType, SendQuota,
+};
+use crate::{Pid, QosPid};
+
+/// Protocol violations detected by the broker [`Connection`].
+#[derive(Error, Debug, Clone, PartialEq, Eq)]
+pub enum ConnectionError {
+    /// The connection is already closed.
+    #[error("connection closed")]
+    Closed,
+
+    /// The first packet is not CONNECT.
+    #[error("first packet is not CONNECT: `{0}`")]
+    FirstPacketNotConnect(PacketType),
+
+    /// A second CONNECT packet is received.
+    #[error("second CONNECT packet")]
+    SecondConnect,
+
+    /// AUTH is received but no authentication method is given in CONNECT.
+    #[error("AUTH without authentication method")]
+    AuthNotNegotiated,
+
+    /// The packet is not allowed to be sent by a client (or by a server).
+    #[error("unexpected packet: `{0}`")]
+    UnexpectedPacket(PacketType),
+
+    /// The client sent more QoS 1/2 PUBLISH than the "Receive Maximum".
+    #[error("receive maximum exceeded")]
+    ReceiveMaximumExceeded,
+
+    /// The client's "Receive Maximum" is reached, the PUBLISH can not be sent now.
+    #[error("send quota exceeded")]
+    SendQuotaExceeded,
+
+    /// No packet received in one and a half times the keep alive.
+    #[error("keep alive timeout")]
+    KeepAliveTimeout,
+}
+
+impl ConnectionError {
+    /// The reason code of the DISCONNECT packet for the violation.
+    pub fn disconnect_reason_code(&self) -> DisconnectReasonCode {
+        match self {
+            ConnectionError::ReceiveMaximumExceeded => DisconnectReasonCode::ReceiveMaximumExceeded,
+            ConnectionError::KeepAliveTimeout => DisconnectReasonCode::KeepAliveTimeout,
+            _ => DisconnectReasonCode::ProtocolError,
+        }
+    }
+}
+
+/// The connection state.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum State {
+    /// Waiting for the CONNECT packet.
+    WaitConnect,
+    /// CONNECT is received, CONNACK is not sent yet.
+    Connecting,
+    /// CONNACK with success reason code is sent.
+    Connected,
+    Disconnected,
+}
+
+/// Server side state of an MQTT v5.0 connection.
+///
+/// The received packets are validated against the packet ordering rules, the
+/// keep alive and the "Receive Maximum". On violation the DISCONNECT packet
+/// is queued (only after CONNACK is sent, otherwise the network connection
+/// should simply be closed) and the connection is closed.
+#[derive(Debug)]
+pub struct Connection {
+    state: State,
+    receive_max: u16,
+    auth: bool,
+    keep_alive: Duration,
+    last_received: Instant,
+    /// Send quota limited by the client's "Receive Maximum"
+    quota: SendQuota,
+    /// Received QoS 1/2 PUBLISH not yet acknowledged
+    incoming: HashSet<Pid>,
+    transmit: VecDeque<Packet>,
+}
+
+impl Connection {
+    /// Create a connection, `receive_max` is the "Receive Maximum" sent to
+    /// the client in CONNACK (65535 if absent).
+    pub fn new(receive_max: u16, now: Instant) -> Self {
+        Connection {
+            state: State::WaitConnect,
+            receive_max,
+            auth: false,
+            keep_alive: Duration::ZERO,
+            last_received: now,
+            quota: SendQuota::default(),
+            incoming: HashSet::new(),
+            transmit: VecDeque::new(),
+        }
+    }
+
+    pub fn state(&self) -> State {
+        self.state
+    }
+
+    /// The keep alive negotiated by CONNECT and CONNACK.
+    pub fn keep_alive(&self) -> Duration {
+        self.keep_alive
+    }
+
+    /// Handle a received packet, return the packet if the application should
+    /// handle it (PINGREQ is answered here).
+    pub fn handle_packet(
+        &mut self,
+        packet: Packet,
+        now: Instant,
+    ) -> Result<Option<Packet>, ConnectionError> {
+        let result = self.handle_packet_inner(packet, now);
+        if let Err(err) = &result {
+            self.violate(err);
+        }
+        result
+    }
+
+    /// Validate and queue a packet to send.
+    ///
+    /// Return `ConnectionError::SendQuotaExceeded` if the PUBLISH can not be
+    /// sent now, the connection is not closed in this case.
+    pub fn send(&mut self, packet: Packet) -> Result<(), ConnectionError> {
+        match (self.state, &packet) {
+            (State::Disconnected, _) => return Err(ConnectionError::Closed),
+            (State::Connecting, Packet::Connack(connack)) => {
+                if connack.reason_code == ConnectReasonCode::Success {
+                    if let Some(server_keep_alive) = connack.properties.server_keep_alive {
+                        self.keep_alive = Duration::from_secs(server_keep_alive.into());
+                    }
+                    self.state = State::Connected;
+                } else {
+                    self.state = State::Disconnected;
+                }
+            }
+            (State::Connecting | State::Connected, Packet::Auth(_)) if self.auth => {}
+            (State::Connected, Packet::Publish(publish)) => {
+                if !self.quota.on_publish(publish.qos_pid.qos()) {
+                    return Err(ConnectionError::SendQuotaExceeded);
+                }
+            }
+            (State::Connected, Packet::Puback(puback)) => {
+                self.incoming.remove(&puback.pid);
+            }
+            (State::Connected, Packet::Pubrec(pubrec)) => {
+                if pubrec.reason_code as u8 >= 0x80 {
+                    self.incoming.remove(&pubrec.pid);
+                }
+            }
+            (State::Connected, Packet::Pubcomp(pubcomp)) => {
+                self.incoming.remove(&pubcomp.pid);
+            }
+            (
+                State::Connected,
+                Packet::Pubrel(_) | Packet::Suback(_) | Packet::Unsuback(_) | Packet::Pingresp,
+            ) => {}
+            (State::Connected, Packet::Disconnect(_)) => self.state = State::Disconnected,
+            (_, packet) => return Err(ConnectionError::UnexpectedPacket(packet.packet_type())),
+        }
+        self.transmit.push_back(packet);
+        Ok(())
+    }
+
+    /// Handle the timer event, see `poll_timeout()`.
+    pub fn handle_timeout(&mut self, now: Instant) -> Result<(), ConnectionError> {
+        match self.poll_timeout() {
+            Some(timeout) if now >= timeout => {
+                let err = ConnectionError::KeepAliveTimeout;
+                self.violate(&err);
+                Err(err)
+            }
+            _ => Ok(()),
+        }
+    }
+
+    /// The next time `handle_timeout()` should be called.
+    pub fn poll_timeout(&self) -> Option<Instant> {
+        if self.state == State::Disconnected || self.keep_alive.is_zero() {
+            return None;
+        }
+        Some(self.last_received + self.keep_alive * 3 / 2)
+    }
+
+    /// The next packet to send.
+    pub fn poll_transmit(&mut self) -> Option<Packet> {
+        self.transmit.pop_front()
+    }
+
+    /// The network connection is closed.
+    pub fn handle_closed(&mut self) {
+        self.state = State::Disconnected;
+    }
+
+    fn violate(&mut self, err: &ConnectionError) {
+        if self.state == State::Connected {
+            self.transmit
+                .push_back(Disconnect::new(err.disconnect_reason_code()).into());
+        }
+        self.state = State::Disconnected;
+    }
+
+    fn handle_packet_inner(
+        &mut self,
+        packet: Packet,
+        now: Instant,
+    ) -> Result<Option<Packet>, ConnectionError> {
+        self.last_received = now;
+        match (self.state, &packet) {
+            (State::Disconnected, _) => return Err(ConnectionError::Closed),
+            (State::WaitConnect, Packet::Connect(connect)) => self.handle_connect(connect),
+            (State::WaitConnect, packet) => {
+                return Err(ConnectionError::FirstPacketNotConnect(packet.packet_type()))
+            }
+            (_, Packet::Connect(_)) => return Err(ConnectionError::SecondConnect),
+            (_, Packet::Auth(_)) if !self.auth => return Err(ConnectionError::AuthNotNegotiated),
+            (_, Packet::Publish(publish)) => match publish.qos_pid {
+                QosPid::Level0 => {}
+                QosPid::Level1(pid) | QosPid::Level2(pid) => {
+                    if !self.incoming.contains(&pid) {
+                        if self.incoming.len() >= self.receive_max as usize {
+                            return Err(ConnectionError::ReceiveMaximumExceeded);
+                        }
+                        self.incoming.insert(pid);
+                    }
+                }
+            },
+            (_, Packet::Puback(_) | Packet::Pubrec(_) | Packet::Pubcomp(_)) => {
+                self.quota.on_receive(&packet);
+            }
+            (_, Packet::Pingreq) => {
+                self.transmit.push_back(Packet::Pingresp);
+                return Ok(None);
+            }
+            (_, Packet::Disconnect(_)) => self.state = State::Disconnected,
+            (
+                _,
+                Packet::Auth(_) | Packet::Pubrel(_) | Packet::Subscribe(_) | Packet::Unsubscribe(_),
+            ) => {}
+            (_, packet) => return Err(ConnectionError::UnexpectedPacket(packet.packet_type())),
+        }
+        Ok(Some(packet))
+    }
+
+    fn handle_connect(&mut self, connect: &Connect) {
+        self.state = State::Connecting;
+        self.auth = connect.properties.auth_method.is_some();
+        self.keep_alive = Duration::from_secs(connect.keep_alive.into());
+        self.quota = SendQuota::new(connect.properties.receive_max.unwrap_or(u16::MAX));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::convert::TryFrom;
+    use std::sync::Arc;
+
+    use bytes::Bytes;
+
+    use super::*;
+    use crate::v5::{Auth, AuthReasonCode, Connack, Puback, Publish};
+    use crate::TopicName;
+
+    fn connect(keep_alive: u16) -> (Connection, Instant) {
+        let now = Instant::now();
+        let mut conn = Connection::new(1, now);
+        let mut connect = Connect::new("client", keep_alive);
+        connect.properties.receive_max = Some(1);
+        assert!(conn.handle_packet(connect.into(), now).unwrap().is_some());
+        assert_eq!(conn.state(), State::Connecting);
+        conn.send(Connack::new(false, ConnectReasonCode::Success).into())
+            .unwrap();
+        assert!(matches!(conn.poll_transmit(), Some(Packet::Connack(_))));
+        assert_eq!(conn.state(), State::Connected);
+        (conn, now)
+    }
+
+    fn publish(pid: u16) -> Packet {
+        let topic_name = TopicName::try_from("a/b".to_owned()).unwrap();
+        let qos_pid = QosPid::Level1(Pid::try_from(pid).unwrap());
+        Publish::new(qos_pid, topic_name, Bytes::new()).into()
+    }
+
+    fn assert_disconnect(conn: &mut Connection, reason_code: DisconnectReasonCode) {
+        assert_eq!(
+            conn.poll_transmit(),
+            Some(Disconnect::new(reason_code).into())
+        );
+        assert_eq!(conn.state(), State::Disconnected);
+    }
+
+    #[test]
+    fn test_first_packet_not_connect() {
+        let now = Instant::now();
+        let mut conn = Connection::new(10, now);
+        assert_eq!(
+            conn.handle_packet(Packet::Pingreq, now),
+            Err(ConnectionError::FirstPacketNotConnect(PacketType::Pingreq))
+        );
+        // No DISCONNECT before CONNACK
+        assert_eq!(conn.poll_transmit(), None);
+        assert_eq!(conn.state(), State::Disconnected);
+    }
+
+    #[test]
+    fn test_second_connect() {
+        let (mut conn, now) = connect(0);
+        assert_eq!(conn.handle_packet(Packet::Pingreq, now), Ok(None));
+        assert_eq!(conn.poll_transmit(), Some(Packet::Pingresp));
+
+        let connect = Connect::new("client", 0);
+        assert_eq!(
+            conn.handle_packet(connect.into(), now),
+            Err(ConnectionError::SecondConnect)
+        );
+        assert_disconnect(&mut conn, DisconnectReasonCode::ProtocolError);
+    }
+
+    #[test]
+    fn test_auth() {
+        let (mut conn, now) = connect(0);
+        let auth = Auth::new(AuthReasonCode::ReAuthentication);
+        assert_eq!(
+            conn.handle_packet(auth.into(), now),
+            Err(ConnectionError::AuthNotNegotiated)
+        );
+        assert_disconnect(&mut conn, DisconnectReasonCode::ProtocolError);
+
+        let mut conn = Connection::new(10, now);
+        let mut connect = Connect::new("client", 0);
+        connect.properties.auth_method = Some(Arc::from("SCRAM-SHA-1"));
+        conn.handle_packet(connect.into(), now).unwrap();
+        let auth = Auth::new(AuthReasonCode::ContinueAuthentication);
+        conn.send(auth.clone().into()).unwrap();
+        assert!(conn.handle_packet(auth.into(), now).unwrap().is_some());
+    }
+
+    #[test]
+    fn test_receive_maximum() {
+        let (mut conn, now) = connect(0);
+        conn.handle_packet(publish(1), now).unwrap();
+        // Duplicated PUBLISH is not counted
+        conn.handle_packet(publish(1), now).unwrap();
+        conn.send(Puback::new_success(Pid::try_from(1).unwrap()).into())
+            .unwrap();
+        conn.poll_transmit();
+        conn.handle_packet(publish(2), now).unwrap();
+        assert_eq!(
+            conn.handle_packet(publish(3), now),
+            Err(ConnectionError::ReceiveMaximumExceeded)
+        );
+        assert_disconnect(&mut conn, DisconnectReasonCode::ReceiveMaximumExceeded);
+    }
+
+    #[test]
+    fn test_send_quota() {
+        let (mut conn, now) = connect(0);
+        conn.send(publish(1)).unwrap();
+        assert_eq!(
+            conn.send(publish(2)),
+            Err(ConnectionError::SendQuotaExceeded)
+        );
+        conn.handle_packet(Puback::new_success(Pid::try_from(1).unwrap()).into(), now)
+            .unwrap();
+        conn.send(publish(2)).unwrap();
+        assert_eq!(conn.state(), State::Connected);
+    }
+
+    #[test]
+    fn test_keep_alive() {
+        let (mut conn, now) = connect(10);
+        let timeout = conn.poll_timeout().unwrap();
+        assert_eq!(timeout, now + Duration::from_secs(15));
+        conn.handle_timeout(now + Duration::from_secs(14)).unwrap();
+        assert_eq!(
+            conn.handle_timeout(timeout),
+            Err(ConnectionError::KeepAliveTimeout)
+        );
+        assert_disconnect(&mut conn, DisconnectReasonCode::KeepAliveTimeout);
+        assert_eq!(conn.poll_timeout(), None);
+    }
+}
@@ -0,0 +1,122 @@
+use super::{ConnackProperties, DisconnectReasonCode, Packet};
+use crate::{MATCH_ALL_CHAR, MATCH_ONE_CHAR};
+
+impl ConnackProperties {
+    /// Check an outgoing packet against the capabilities the server
+    /// advertised in CONNACK, return the reason code the server would
+    /// disconnect with if the packet is not supported.
+    ///
+    /// Absent properties mean the capability is available (or the maximum
+    /// is not limited), except Topic Alias Maximum which defaults to 0.
+    pub fn check_outgoing(&self, packet: &Packet) -> Result<(), DisconnectReasonCode> {
+        match packet {
+            Packet::Publish(publish) => {
+                if let Some(max_qos) = self.max_qos {
+                    if publish.qos_pid.qos() > max_qos {
+                        return Err(DisconnectReasonCode::QoSNotSupported);
+                    }
+                }
+                if publish.retain && self.retain_available == Some(false) {
+                    return Err(DisconnectReasonCode::RetainNotSupported);
+                }
+                if let Some(alias) = publish.properties.topic_alias {
+                    if alias == 0 || alias > self.topic_alias_max.unwrap_or(0) {
+                        return Err(DisconnectReasonCode::TopicAliasInvalid);
+                    }
+                }
+            }
+            Packet::Subscribe(subscribe) => {
+                for (filter, _) in &subscribe.topics {
+                    if filter.is_shared() && self.shared_subscription_available == Some(false) {
+                        return Err(DisconnectReasonCode::SharedSubscriptionNotSupported);
+                    }
+                    if filter.contains([MATCH_ONE_CHAR, MATCH_ALL_CHAR])
+                        && self.wildcard_subscription_available == Some(false)
+                    {
+                        return Err(DisconnectReasonCode::WildcardSubscriptionsNotSupported);
+                    }
+                }
+                if subscribe.properties.subscription_id.is_some()
+                    && self.subscription_id_available == Some(false)
+                {
+                    return Err(DisconnectReasonCode::SubscriptionIdentifiersNotSupported);
+                }
+            }
+            _ => {}
+        }
+        if let Some(max_packet_size) = self.max_packet_size {
+            let size = packet
+                .encode_len()
+                .map_err(|_| DisconnectReasonCode::PacketTooLarge)?;
+            if size > max_packet_size as usize {
+                return Err(DisconnectReasonCode::PacketTooLarge);
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use bytes::Bytes;
+
+    use super::*;
+    use crate::v5::{Publish, Subscribe, SubscriptionOptions};
+    use crate::{Pid, QoS, QosPid, TopicFilter, TopicName};
+
+    #[test]
+    fn test_check_outgoing() {
+        let topic_name = TopicName::try_from("a/b".to_owned()).unwrap();
+        let mut publish = Publish::new(
+            QosPid::Level2(Pid::default()),
+            topic_name,
+            Bytes::from_static(b"hello"),
+        );
+        publish.retain = true;
+        let mut properties = ConnackProperties::default();
+        assert_eq!(properties.check_outgoing(&publish.clone().into()), Ok(()));
+
+        properties.max_qos = Some(QoS::Level1);
+        assert_eq!(
+            properties.check_outgoing(&publish.clone().into()),
+            Err(DisconnectReasonCode::QoSNotSupported)
+        );
+        publish.qos_pid = QosPid::Level1(Pid::default());
+        properties.retain_available = Some(false);
+        assert_eq!(
+            properties.check_outgoing(&publish.clone().into()),
+            Err(DisconnectReasonCode::RetainNotSupported)
+        );
+        publish.retain = false;
+        publish.properties.topic_alias = Some(1);
+        assert_eq!(
+            properties.check_outgoing(&publish.clone().into()),
+            Err(DisconnectReasonCode::TopicAliasInvalid)
+        );
+        properties.topic_alias_max = Some(1);
+        properties.max_packet_size = Some(16);
+        assert_eq!(
+            properties.check_outgoing(&publish.clone().into()),
+            Err(DisconnectReasonCode::PacketTooLarge)
+        );
+        properties.max_packet_size = Some(64);
+        assert_eq!(properties.check_outgoing(&publish.into()), Ok(()));
+
+        let options = SubscriptionOptions::new(QoS::Level1);
+        let subscribe = |filter: &str| -> Packet {
+            let filter = TopicFilter::try_from(filter.to_owned()).unwrap();
+            Subscribe::new(Pid::default(), vec![(filter, options)]).into()
+        };
+        properties.wildcard_subscription_available = Some(false);
+        properties.shared_subscription_available = Some(false);
+        assert_eq!(properties.check_outgoing(&subscribe("a/b")), Ok(()));
+        assert_eq!(
+            properties.check_outgoing(&subscribe("a/+")),
+            Err(DisconnectReasonCode::WildcardSubscriptionsNotSupported)
+        );
+        assert_eq!(
+            properties.check_outgoing(&subscribe("$share/g/a")),
+            Err(DisconnectReasonCode::SharedSubscriptionNotSupported)
+        );
+    }
+}
@@ -0,0 +1,670 @@
+//! Sans-IO client protocol state machine.
+//!
+//! The [`Connection`] does no I/O: feed it the decoded packets and the timer
+//! events, then poll the packets to send and the application events.
+
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::time::{Duration, Instant};
+
+use thiserror::Error;
+
+use super::{
+    Connack, ConnackProperties, Connect, ConnectReasonCode, Disconnect, DisconnectReasonCode,
+    ErrorV5, InflightQueue, Packet, PacketType, Puback, Pubcomp, PubcompReasonCode, Publish,
+    Pubrec, Pubrel, SendQuota, Suback, Subscribe, SubscriptionOptions, TopicAliasManager, Unsuback,
+    Unsubscribe,
+};
+use crate::{Pid, PidAllocator, QoS, QosPid, TopicFilter};
+
+/// Errors returned by the client [`Connection`].
+#[derive(Error, Debug, Clone, PartialEq, Eq)]
+pub enum ConnectionError {
+    /// The operation requires the connection to be established.
+    #[error("not connected")]
+    NotConnected,
+
+    /// The connection is already established (or establishing).
+    #[error("already connected")]
+    AlreadyConnected,
+
+    /// The packet is not allowed in current state.
+    #[error("unexpected packet: `{0}`")]
+    UnexpectedPacket(PacketType),
+
+    /// The packet identifier of an acknowledgement is not in-flight.
+    #[error("unknown packet identifier `{1:?}` for packet `{0}`")]
+    UnknownPid(PacketType, Pid),
+
+    /// The packet is not supported by the server, see
+    /// `ConnackProperties::check_outgoing()`.
+    #[error("not supported by server: {0:?}")]
+    NotSupported(DisconnectReasonCode),
+
+    /// No PINGRESP received in time.
+    #[error("keep alive timeout")]
+    KeepAliveTimeout,
+
+    /// Error from the codec (topic alias, packet identifier, ...).
+    #[error("codec error: {0}")]
+    Codec(#[from] ErrorV5),
+}
+
+/// Events for the application.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Event {
+    /// CONNACK with success reason code is received.
+    Connected(Connack),
+    /// CONNACK with error reason code is received, the connection is closed.
+    ConnectFailed(Connack),
+    /// An application message is received.
+    Publish(Publish),
+    /// A QoS 1 PUBLISH is acknowledged.
+    Puback(Puback),
+    /// A QoS 2 PUBLISH is rejected by a PUBREC with error reason code.
+    Pubrec(Pubrec),
+    /// A QoS 2 PUBLISH is completed.
+    Pubcomp(Pubcomp),
+    Suback(Suback),
+    Unsuback(Unsuback),
+    /// DISCONNECT is received, the connection is closed.
+    Disconnected(Disconnect),
+}
+
+/// The connection state.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum State {
+    Disconnected,
+    Connecting,
+    Connected,
+}
+
+/// Client side state of an MQTT v5.0 connection and session.
+///
+/// The session state (in-flight packets) is kept across connections, the
+/// unacknowledged PUBLISH and PUBREL packets are sent again when the server
+/// resumes the session. The packets to send are marked as sent when they are
+/// queued, so `poll_transmit()` should be called after every operation.
+#[derive(Debug)]
+pub struct Connection {
+    connect: Connect,
+    state: State,
+    keep_alive: Duration,
+    last_sent: Option<Instant>,
+    ping_sent: Option<Instant>,
+    pids: PidAllocator,
+    quota: SendQuota,
+    topic_alias: TopicAliasManager,
+    /// The capabilities in last CONNACK
+    server: ConnackProperties,
+    /// In-flight outgoing PUBLISH flows in sending order
+    inflight: InflightQueue,
+    /// QoS 1/2 PUBLISH waiting for send quota
+    pending: VecDeque<Publish>,
+    /// In-flight SUBSCRIBE/UNSUBSCRIBE, the value is the ack packet type
+    subscriptions: HashMap<Pid, PacketType>,
+    /// Received QoS 2 PUBLISH waiting for PUBREL
+    incoming: HashSet<Pid>,
+    transmit: VecDeque<Packet>,
+    events: VecDeque<Event>,
+}
+
+impl Connection {
+    pub fn new(connect: Connect) -> Self {
+        Connection {
+            keep_alive: Duration::from_secs(connect.keep_alive.into()),
+            connect,
+            state: State::Disconnected,
+            last_sent: None,
+            ping_sent: None,
+            pids: PidAllocator::new(),
+            quota: SendQuota::default(),
+            topic_alias: TopicAliasManager::default(),
+            server: ConnackProperties::default(),
+            inflight: InflightQueue::new(),
+            pending: VecDeque::new(),
+            subscriptions: HashMap::new(),
+            incoming: HashSet::new(),
+            transmit: VecDeque::new(),
+            events: VecDeque::new(),
+        }
+    }
+
+    pub fn state(&self) -> State {
+        self.state
+    }
+
+    /// Start a new connection by sending the CONNECT packet. The session
+    /// state is cleared if `clean_start` is set.
+    pub fn connect(&mut self, now: Instant) -> Result<(), ConnectionError> {
+        if self.state != State::Disconnected {
+            return Err(ConnectionError::AlreadyConnected);
+        }
+        if self.connect.clean_start {
+            self.clear_session();
+        }
+        self.keep_alive = Duration::from_secs(self.connect.keep_alive.into());
+        self.ping_sent = None;
+        self.quota = SendQuota::default();
+        self.topic_alias =
+            TopicAliasManager::new(self.connect.properties.topic_alias_max.unwrap_or(0), 0);
+        self.transmit.clear();
+        self.state = State::Connecting;
+        self.send(self.connect.clone(), now);
+        Ok(())
+    }
+
+    /// Send a PUBLISH packet, the packet identifier is allocated for QoS 1/2
+    /// PUBLISH. The packet is queued if there is no send quota, and rejected
+    /// if it is not supported by the server.
+    pub fn publish(
+        &mut self,
+        mut publish: Publish,
+        now: Instant,
+    ) -> Result<Option<Pid>, ConnectionError> {
+        if self.state != State::Connected {
+            return Err(ConnectionError::NotConnected);
+        }
+        publish.dup = false;
+        self.server
+            .check_outgoing(&publish.clone().into())
+            .map_err(ConnectionError::NotSupported)?;
+        let qos = publish.qos_pid.qos();
+        if qos == QoS::Level0 {
+            self.topic_alias.apply_outbound(&mut publish);
+            self.send(publish, now);
+            return Ok(None);
+        }
+        let pid = self.pids.alloc().map_err(ErrorV5::from)?;
+        publish.qos_pid = match qos {
+            QoS::Level1 => QosPid::Level1(pid),
+            _ => QosPid::Level2(pid),
+        };
+        if self.quota.on_publish(qos) {
+            self.send_inflight(publish, now);
+        } else {
+            self.pending.push_back(publish);
+        }
+        Ok(Some(pid))
+    }
+
+    /// Send a SUBSCRIBE packet, return the allocated packet identifier.
+    pub fn subscribe(
+        &mut self,
+        topics: Vec<(TopicFilter, SubscriptionOptions)>,
+        now: Instant,
+    ) -> Result<Pid, ConnectionError> {
+        if self.state != State::Connected {
+            return Err(ConnectionError::NotConnected);
+        }
+        let mut subscribe = Subscribe::new(Pid::default(), topics);
+        self.server
+            .check_outgoing(&subscribe.clone().into())
+            .map_err(ConnectionError::NotSupported)?;
+        let pid = self.pids.alloc().map_err(ErrorV5::from)?;
+        subscribe.pid = pid;
+        self.subscriptions.insert(pid, PacketType::Suback);
+        self.send(subscribe, now);
+        Ok(pid)
+    }
+
+    /// Send an UNSUBSCRIBE packet, return the allocated packet identifier.
+    pub fn unsubscribe(
+        &mut self,
+        topics: Vec<TopicFilter>,
+        now: Instant,
+    ) -> Result<Pid, ConnectionError> {
+        if self.state != State::Connected {
+            return Err(ConnectionError::NotConnected);
+        }
+        let mut unsubscribe = Unsubscribe::new(Pid::default(), topics);
+        self.server
+            .check_outgoing(&unsubscribe.clone().into())
+            .map_err(ConnectionError::NotSupported)?;
+        let pid = self.pids.alloc().map_err(ErrorV5::from)?;
+        unsubscribe.pid = pid;
+        self.subscriptions.insert(pid, PacketType::Unsuback);
+        self.send(unsubscribe, now);
+        Ok(pid)
+    }
+
+    /// Send a DISCONNECT packet and close the connection.
+    pub fn disconnect(&mut self, disconnect: Disconnect, now: Instant) {
+        if self.state != State::Disconnected {
+            self.send(disconnect, now);
+            self.state = State::Disconnected;
+        }
+    }
+
+    /// The network connection is closed (by the peer or an I/O error).
+    pub fn handle_closed(&mut self) {
+        self.state = State::Disconnected;
+        self.transmit.clear();
+    }
+
+    /// Handle a received packet. On protocol violation a DISCONNECT packet is
+    /// queued and the connection is closed.
+    pub fn handle_packet(&mut self, packet: Packet, now: Instant) -> Result<(), ConnectionError> {
+        let result = self.handle_packet_inner(packet, now);
+        if let Err(err) = &result {
+            let reason_code = match err {
+                ConnectionError::NotConnected => return result,
+                ConnectionError::Codec(ErrorV5::InvalidTopicAlias(_))
+                | ConnectionError::Codec(ErrorV5::UnknownTopicAlias(_)) => {
+                    DisconnectReasonCode::TopicAliasInvalid
+                }
+                _ => DisconnectReasonCode::ProtocolError,
+            };
+            self.disconnect(Disconnect::new(reason_code), now);
+        }
+        result
+    }
+
+    /// Handle the timer event, see `poll_timeout()`.
+    pub fn handle_timeout(&mut self, now: Instant) -> Result<(), ConnectionError> {
+        if self.state != State::Connected || self.keep_alive.is_zero() {
+            return Ok(());
+        }
+        if let Some(ping_sent) = self.ping_sent {
+            if now >= ping_sent + self.keep_alive {
+                self.handle_closed();
+                return Err(ConnectionError::KeepAliveTimeout);
+            }
+        } else if let Some(last_sent) = self.last_sent {
+            if now >= last_sent + self.keep_alive {
+                self.send(Packet::Pingreq, now);
+                self.ping_sent = Some(now);
+            }
+        }
+        Ok(())
+    }
+
+    /// The next time `handle_timeout()` should be called.
+    pub fn poll_timeout(&self) -> Option<Instant> {
+        if self.state != State::Connected || self.keep_alive.is_zero() {
+            return None;
+        }
+        self.ping_sent
+            .or(self.last_sent)
+            .map(|instant| instant + self.keep_alive)
+    }
+
+    /// The next packet to send.
+    pub fn poll_transmit(&mut self) -> Option<Packet> {
+        self.transmit.pop_front()
+    }
+
+    /// The next application event.
+    pub fn poll_event(&mut self) -> Option<Event> {
+        self.events.pop_front()
+    }
+
+    fn handle_packet_inner(&mut self, packet: Packet, now: Instant) -> Result<(), ConnectionError> {
+        match self.state {
+            State::Disconnected => return Err(ConnectionError::NotConnected),
+            State::Connecting => {
+                return match packet {
+                    Packet::Connack(connack) => {
+                        self.handle_connack(connack, now);
+                        Ok(())
+                    }
+                    packet => Err(ConnectionError::UnexpectedPacket(packet.packet_type())),
+                };
+            }
+            State::Connected => {}
+        }
+        match packet {
+            Packet::Publish(publish) => self.handle_publish(publish, now)?,
+            Packet::Puback(puback) => {
+                if self.inflight.on_puback(puback.pid).is_none() {
+                    return Err(ConnectionError::UnknownPid(PacketType::Puback, puback.pid));
+                }
+                self.finish_inflight(puback.pid, now);
+                self.events.push_back(Event::Puback(puback));
+            }
+            Packet::Pubrec(pubrec) => {
+                let pid = pubrec.pid;
+                if !self.inflight.on_pubrec(pid) {
+                    return Err(ConnectionError::UnknownPid(PacketType::Pubrec, pid));
+                }
+                if pubrec.reason_code as u8 >= 0x80 {
+                    self.inflight.remove(pid);
+                    self.finish_inflight(pid, now);
+                    self.events.push_back(Event::Pubrec(pubrec));
+                } else {
+                    self.send(Pubrel::new_success(pid), now);
+                }
+            }
+            Packet::Pubrel(pubrel) => {
+                let pubcomp = if self.incoming.remove(&pubrel.pid) {
+                    Pubcomp::new_success(pubrel.pid)
+                } else {
+                    Pubcomp::new(pubrel.pid, PubcompReasonCode::PacketIdentifierNotFound)
+                };
+                self.send(pubcomp, now);
+            }
+            Packet::Pubcomp(pubcomp) => {
+                if !self.inflight.on_pubcomp(pubcomp.pid) {
+                    return Err(ConnectionError::UnknownPid(
+                        PacketType::Pubcomp,
+                        pubcomp.pid,
+                    ));
+                }
+                self.finish_inflight(pubcomp.pid, now);
+                self.events.push_back(Event::Pubcomp(pubcomp));
+            }
+            Packet::Suback(suback) => {
+                self.finish_subscription(PacketType::Suback, suback.pid)?;
+                self.events.push_back(Event::Suback(suback));
+            }
+            Packet::Unsuback(unsuback) => {
+                self.finish_subscription(PacketType::Unsuback, unsuback.pid)?;
+                self.events.push_back(Event::Unsuback(unsuback));
+            }
+            Packet::Pingresp => self.ping_sent = None,
+            Packet::Disconnect(disconnect) => {
+                self.handle_closed();
+                self.events.push_back(Event::Disconnected(disconnect));
+            }
+            packet => return Err(ConnectionError::UnexpectedPacket(packet.packet_type())),
+        }
+        Ok(())
+    }
+
+    fn handle_connack(&mut self, connack: Connack, now: Instant) {
+        if connack.reason_code != ConnectReasonCode::Success {
+            self.handle_closed();
+            self.events.push_back(Event::ConnectFailed(connack));
+            return;
+        }
+        self.state = State::Connected;
+        let properties = &connack.properties;
+        self.server = properties.clone();
+        self.quota = SendQuota::new(properties.receive_max.unwrap_or(u16::MAX));
+        self.topic_alias
+            .set_outbound_max(properties.topic_alias_max.unwrap_or(0));
+        if let Some(server_keep_alive) = properties.server_keep_alive {
+            self.keep_alive = Duration::from_secs(server_keep_alive.into());
+        }
+        if connack.session_present {
+            // Resend the in-flight packets in the original order
+            for packet in self.inflight.retransmit().collect::<Vec<_>>() {
+                self.quota.on_send(&packet);
+                self.send(packet, now);
+            }
+        } else {
+            self.clear_session();
+        }
+        self.events.push_back(Event::Connected(connack));
+        self.send_pending(now);
+    }
+
+    fn handle_publish(&mut self, mut publish: Publish, now: Instant) -> Result<(), ErrorV5> {
+        self.topic_alias.resolve_inbound(&mut publish)?;
+        match publish.qos_pid {
+            QosPid::Level0 => self.events.push_back(Event::Publish(publish)),
+            QosPid::Level1(pid) => {
+                self.send(Puback::new_success(pid), now);
+                self.events.push_back(Event::Publish(publish));
+            }
+            QosPid::Level2(pid) => {
+                self.send(Pubrec::new_success(pid), now);
+                // Only deliver the message once
+                if self.incoming.insert(pid) {
+                    self.events.push_back(Event::Publish(publish));
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn finish_inflight(&mut self, pid: Pid, now: Instant) {
+        self.pids.release(pid);
+        self.quota.restore();
+        self.send_pending(now);
+    }
+
+    fn finish_subscription(&mut self, typ: PacketType, pid: Pid) -> Result<(), ConnectionError> {
+        if self.subscriptions.get(&pid) != Some(&typ) {
+            return Err(ConnectionError::UnknownPid(typ, pid));
+        }
+        self.subscriptions.remove(&pid);
+        self.pids.release(pid);
+        Ok(())
+    }
+
+    fn send_pending(&mut self, now: Instant) {
+        while self.quota.can_send() {
+            let publish = match self.pending.pop_front() {
+                Some(publish) => publish,
+                None => break,
+            };
+            self.quota.on_publish(publish.qos_pid.qos());
+            self.send_inflight(publish, now);
+        }
+    }
+
+    fn send_inflight(&mut self, mut publish: Publish, now: Instant) {
+        self.inflight.push(publish.clone());
+        self.topic_alias.apply_outbound(&mut publish);
+        self.send(publish, now);
+    }
+
+    fn send<P: Into<Packet>>(&mut self, packet: P, now: Instant) {
+        self.transmit.push_back(packet.into());
+        self.last_sent = Some(now);
+    }
+
+    fn clear_session(&mut self) {
+        self.pids.clear();
+        self.inflight.clear();
+        self.pending.clear();
+        self.subscriptions.clear();
+        self.incoming.clear();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::convert::TryFrom;
+
+    use bytes::Bytes;
+
+    use super::*;
+    use crate::v5::PubrecReasonCode;
+    use crate::TopicName;
+
+    fn connect(keep_alive: u16) -> (Connection, Instant) {
+        let now = Instant::now();
+        let mut conn = Connection::new(Connect::new("client", keep_alive));
+        conn.connect(now).unwrap();
+        assert!(matches!(conn.poll_transmit(), Some(Packet::Connect(_))));
+        let mut connack = Connack::new(false, ConnectReasonCode::Success);
+        connack.properties.receive_max = Some(1);
+        conn.handle_packet(connack.into(), now).unwrap();
+        assert!(matches!(conn.poll_event(), Some(Event::Connected(_))));
+        assert_eq!(conn.state(), State::Connected);
+        (conn, now)
+    }
+
+    fn publish(qos: QoS) -> Publish {
+        let qos_pid = match qos {
+            QoS::Level0 => QosPid::Level0,
+            QoS::Level1 => QosPid::Level1(Pid::default()),
+            QoS::Level2 => QosPid::Level2(Pid::default()),
+        };
+        let topic_name = TopicName::try_from("a/b".to_owned()).unwrap();
+        Publish::new(qos_pid, topic_name, Bytes::from_static(b"data"))
+    }
+
+    #[test]
+    fn test_outgoing_publish() {
+        let (mut conn, now) = connect(0);
+        assert_eq!(conn.publish(publish(QoS::Level0), now), Ok(None));
+        assert!(matches!(conn.poll_transmit(), Some(Packet::Publish(_))));
+
+        // Receive maximum is 1, the second one is pending
+        let pid1 = conn.publish(publish(QoS::Level1), now).unwrap().unwrap();
+        let pid2 = conn.publish(publish(QoS::Level2), now).unwrap().unwrap();
+        assert_eq!(conn.poll_transmit().unwrap().pid(), Some(pid1));
+        assert_eq!(conn.poll_transmit(), None);
+
+        conn.handle_packet(Puback::new_success(pid1).into(), now)
+            .unwrap();
+        assert_eq!(
+            conn.poll_event(),
+            Some(Event::Puback(Puback::new_success(pid1)))
+        );
+        assert_eq!(conn.poll_transmit().unwrap().pid(), Some(pid2));
+
+        conn.handle_packet(Pubrec::new_success(pid2).into(), now)
+            .unwrap();
+        assert_eq!(conn.poll_transmit(), Some(Pubrel::new_success(pid2).into()));
+        conn.handle_packet(Pubcomp::new_success(pid2).into(), now)
+            .unwrap();
+        assert_eq!(
+            conn.poll_event(),
+            Some(Event::Pubcomp(Pubcomp::new_success(pid2)))
+        );
+
+        // Unknown packet identifier
+        let err = conn
+            .handle_packet(Puback::new_success(pid1).into(), now)
+            .unwrap_err();
+        assert_eq!(err, ConnectionError::UnknownPid(PacketType::Puback, pid1));
+        assert_eq!(
+            conn.poll_transmit(),
+            Some(Disconnect::new(DisconnectReasonCode::ProtocolError).into())
+        );
+        assert_eq!(conn.state(), State::Disconnected);
+    }
+
+    #[test]
+    fn test_server_capabilities() {
+        let now = Instant::now();
+        let mut conn = Connection::new(Connect::new("client", 0));
+        conn.connect(now).unwrap();
+        let mut connack = Connack::new(false, ConnectReasonCode::Success);
+        connack.properties.max_qos = Some(QoS::Level1);
+        connack.properties.wildcard_subscription_available = Some(false);
+        connack.properties.max_packet_size = Some(32);
+        conn.handle_packet(connack.into(), now).unwrap();
+        conn.poll_transmit();
+
+        assert_eq!(
+            conn.publish(publish(QoS::Level2), now),
+            Err(ConnectionError::NotSupported(
+                DisconnectReasonCode::QoSNotSupported
+            ))
+        );
+        let filter = TopicFilter::try_from("a/#".to_owned()).unwrap();
+        let options = SubscriptionOptions::new(QoS::Level1);
+        assert_eq!(
+            conn.subscribe(vec![(filter, options)], now),
+            Err(ConnectionError::NotSupported(
+                DisconnectReasonCode::WildcardSubscriptionsNotSupported
+            ))
+        );
+        let filter = TopicFilter::try_from("a".repeat(32)).unwrap();
+        assert_eq!(
+            conn.unsubscribe(vec![filter], now),
+            Err(ConnectionError::NotSupported(
+                DisconnectReasonCode::PacketTooLarge
+            ))
+        );
+        assert_eq!(conn.poll_transmit(), None);
+        assert!(conn.publish(publish(QoS::Level1), now).is_ok());
+    }
+
+    #[test]
+    fn test_incoming_publish() {
+        let (mut conn, now) = connect(0);
+        let pid = Pid::try_from(7).unwrap();
+        let mut packet = publish(QoS::Level2);
+        packet.qos_pid = QosPid::Level2(pid);
+
+        conn.handle_packet(packet.clone().into(), now).unwrap();
+        conn.handle_packet(packet.clone().into(), now).unwrap();
+        assert_eq!(conn.poll_event(), Some(Event::Publish(packet)));
+        assert_eq!(conn.poll_event(), None);
+        assert_eq!(conn.poll_transmit(), Some(Pubrec::new_success(pid).into()));
+        assert_eq!(conn.poll_transmit(), Some(Pubrec::new_success(pid).into()));
+
+        conn.handle_packet(Pubrel::new_success(pid).into(), now)
+            .unwrap();
+        assert_eq!(conn.poll_transmit(), Some(Pubcomp::new_success(pid).into()));
+    }
+
+    #[test]
+    fn test_resend_on_session_present() {
+        let (mut conn, now) = connect(0);
+        let pid = conn.publish(publish(QoS::Level2), now).unwrap().unwrap();
+        conn.poll_transmit();
+        conn.handle_closed();
+        assert_eq!(
+            conn.publish(publish(QoS::Level1), now),
+            Err(ConnectionError::NotConnected)
+        );
+
+        // Resume the session
+        conn.connect.clean_start = false;
+        conn.connect(now).unwrap();
+        conn.poll_transmit();
+        conn.handle_packet(Connack::new(true, ConnectReasonCode::Success).into(), now)
+            .unwrap();
+        match conn.poll_transmit() {
+            Some(Packet::Publish(publish)) => {
+                assert!(publish.dup);
+                assert_eq!(publish.qos_pid, QosPid::Level2(pid));
+            }
+            packet => panic!("unexpected packet: {packet:?}"),
+        }
+
+        conn.handle_packet(
+            Pubrec::new(pid, PubrecReasonCode::QuotaExceeded).into(),
+            now,
+        )
+        .unwrap();
+        assert!(matches!(conn.poll_event(), Some(Event::Connected(_))));
+        assert!(matches!(conn.poll_event(), Some(Event::Pubrec(_))));
+        assert_eq!(conn.poll_transmit(), None);
+    }
+
+    #[test]
+    fn test_keep_alive() {
+        let (mut conn, now) = connect(10);
+        let timeout = conn.poll_timeout().unwrap();
+        assert_eq!(timeout, now + Duration::from_secs(10));
+        conn.handle_timeout(timeout).unwrap();
+        assert_eq!(conn.poll_transmit(), Some(Packet::Pingreq));
+        conn.handle_packet(Packet::Pingresp, timeout).unwrap();
+
+        conn.handle_timeout(timeout + Duration::from_secs(10))
+            .unwrap();
+        assert_eq!(conn.poll_transmit(), Some(Packet::Pingreq));
+        assert_eq!(
+            conn.handle_timeout(timeout + Duration::from_secs(20)),
+            Err(ConnectionError::KeepAliveTimeout)
+        );
+        assert_eq!(conn.state(), State::Disconnected);
+    }
+
+    #[test]
+    fn test_unexpected_packet() {
+        let now = Instant::now();
+        let mut conn = Connection::new(Connect::new("client", 0));
+        assert_eq!(
+            conn.handle_packet(Packet::Pingresp, now),
+            Err(ConnectionError::NotConnected)
+        );
+        conn.connect(now).unwrap();
+        conn.poll_transmit();
+        assert_eq!(
+            conn.handle_packet(Packet::Pingresp, now),
+            Err(ConnectionError::UnexpectedPacket(PacketType::Pingresp))
+        );
+        assert_eq!(
+            conn.poll_transmit(),
+            Some(Disconnect::new(DisconnectReasonCode::ProtocolError).into())
+        );
+    }
+}
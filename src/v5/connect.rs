@@ -1,22 +1,24 @@
 use std::convert::TryFrom;
+use std::fmt;
 use std::io;
 use std::sync::Arc;
 
 use bytes::Bytes;
 use simdutf8::basic::from_utf8;
-use tokio::io::{AsyncRead, AsyncReadExt};
+use tokio::io::AsyncRead;
 
 use super::{
-    decode_properties, encode_properties, encode_properties_len, ErrorV5, Header, PacketType,
-    UserProperty,
+    decode_body_async, decode_properties, decode_properties_async, encode_properties,
+    encode_properties_len, ErrorV5, Header, PacketType, UserProperties, UserProperty,
 };
 use crate::{
-    read_bytes, read_string, read_u16, read_u8, write_bytes, write_u16, write_u8, Encodable, Error,
-    Protocol, QoS, TopicName,
+    decode_exact, read_bytes, read_exact_capped, read_string, redacted, write_bytes, write_u16,
+    write_u8, Encodable, Error, Protocol, QoS, SharedStr, SliceReader, TopicName,
 };
 
 /// Body type of CONNECT packet.
-#[derive(Debug, Clone, PartialEq, Eq)]
+#[derive(Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct Connect {
     /// The [protocol version](https://docs.oasis-open.org/mqtt/mqtt/v5.0/os/mqtt-v5.0-os.html#_Toc3901036).
     pub protocol: Protocol,
@@ -40,7 +42,7 @@ pub struct Connect {
     /// The [client identifier] (ClientID).
     ///
     /// [client identifier]: https://docs.oasis-open.org/mqtt/mqtt/v5.0/os/mqtt-v5.0-os.html#_Toc3901059
-    pub client_id: Arc<String>,
+    pub client_id: SharedStr,
 
     /// The [will] message.
     ///
@@ -48,11 +50,26 @@ pub struct Connect {
     pub last_will: Option<LastWill>,
 
     /// The [user name](https://docs.oasis-open.org/mqtt/mqtt/v5.0/os/mqtt-v5.0-os.html#_Toc3901071).
-    pub username: Option<Arc<String>>,
+    pub username: Option<SharedStr>,
 
     /// The [password](https://docs.oasis-open.org/mqtt/mqtt/v5.0/os/mqtt-v5.0-os.html#_Toc3901072).
     pub password: Option<Bytes>,
 }
+
+impl fmt::Debug for Connect {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Connect")
+            .field("protocol", &self.protocol)
+            .field("clean_start", &self.clean_start)
+            .field("keep_alive", &self.keep_alive)
+            .field("properties", &self.properties)
+            .field("client_id", &self.client_id)
+            .field("last_will", &self.last_will)
+            .field("username", &self.username)
+            .field("password", &redacted(&self.password))
+            .finish()
+    }
+}
 #[cfg(feature = "arbitrary")]
 impl<'a> arbitrary::Arbitrary<'a> for Connect {
     fn arbitrary(u: &mut arbitrary::Unstructured<'a>) -> arbitrary::Result<Self> {
@@ -70,13 +87,13 @@ impl<'a> arbitrary::Arbitrary<'a> for Connect {
 }
 
 impl Connect {
-    pub fn new(client_id: Arc<String>, keep_alive: u16) -> Self {
+    pub fn new(client_id: impl Into<SharedStr>, keep_alive: u16) -> Self {
         Connect {
             protocol: Protocol::V500,
             clean_start: true,
             keep_alive,
             properties: ConnectProperties::default(),
-            client_id,
+            client_id: client_id.into(),
             last_will: None,
             username: None,
             password: None,
@@ -87,10 +104,12 @@ impl Connect {
         reader: &mut T,
         header: Header,
     ) -> Result<Self, ErrorV5> {
-        let protocol = Protocol::decode_async(reader).await?;
-        Self::decode_with_protocol(reader, header, protocol).await
+        decode_body_async(reader, header, Self::decode_slice).await
     }
 
+    /// Decode the rest of the packet after the protocol name and level, the
+    /// fields must consume the remaining length exactly, the bytes after it
+    /// are never read.
     #[inline]
     pub async fn decode_with_protocol<T: AsyncRead + Unpin>(
         reader: &mut T,
@@ -100,32 +119,54 @@ impl Connect {
         if protocol != Protocol::V500 {
             return Err(Error::UnexpectedProtocol(protocol).into());
         }
-        let connect_flags: u8 = read_u8(reader).await?;
+        let body_len = (header.remaining_len as usize)
+            .checked_sub(protocol.encode_len())
+            .ok_or(Error::InvalidRemainingLength)?;
+        let body = read_exact_capped(reader, body_len).await?;
+        decode_exact(&body, |reader| {
+            Self::decode_fields(reader, header, protocol)
+        })
+    }
+
+    pub(crate) fn decode_slice(
+        reader: &mut SliceReader<'_>,
+        header: Header,
+    ) -> Result<Self, ErrorV5> {
+        let protocol = Protocol::new(reader.read_bytes()?, reader.read_u8()?)?;
+        if protocol != Protocol::V500 {
+            return Err(Error::UnexpectedProtocol(protocol).into());
+        }
+        Self::decode_fields(reader, header, protocol)
+    }
+
+    fn decode_fields(
+        reader: &mut SliceReader<'_>,
+        header: Header,
+        protocol: Protocol,
+    ) -> Result<Self, ErrorV5> {
+        let connect_flags = reader.read_u8()?;
         if connect_flags & 1 != 0 {
             return Err(Error::InvalidConnectFlags(connect_flags).into());
         }
-        let keep_alive = read_u16(reader).await?;
-
-        // FIXME: check remaining length
-
-        let properties = ConnectProperties::decode_async(reader, header.typ).await?;
-        let client_id = Arc::new(read_string(reader).await?);
+        let keep_alive = reader.read_u16()?;
+        let properties = ConnectProperties::decode_slice(reader, header.typ)?;
+        let client_id = SharedStr::from(reader.read_str()?.to_owned());
         let last_will = if connect_flags & 0b100 != 0 {
             let qos = QoS::from_u8((connect_flags & 0b11000) >> 3)?;
             let retain = (connect_flags & 0b00100000) != 0;
-            Some(LastWill::decode_async(reader, qos, retain).await?)
+            Some(LastWill::decode_slice(reader, qos, retain)?)
         } else if connect_flags & 0b11000 != 0 {
             return Err(Error::InvalidConnectFlags(connect_flags).into());
         } else {
             None
         };
         let username = if connect_flags & 0b10000000 != 0 {
-            Some(Arc::new(read_string(reader).await?))
+            Some(SharedStr::from(reader.read_str()?.to_owned()))
         } else {
             None
         };
         let password = if connect_flags & 0b01000000 != 0 {
-            Some(Bytes::from(read_bytes(reader).await?))
+            Some(Bytes::copy_from_slice(reader.read_bytes()?))
         } else {
             None
         };
@@ -203,7 +244,8 @@ impl Encodable for Connect {
 }
 
 /// Property list for CONNECT packet.
-#[derive(Debug, Clone, PartialEq, Eq, Default)]
+#[derive(Clone, PartialEq, Eq, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct ConnectProperties {
     /// Session Expiry Interval
     pub session_expiry_interval: Option<u32>,
@@ -218,13 +260,29 @@ pub struct ConnectProperties {
     /// Request Problem Information. If absent the default value should be true.
     pub request_problem_info: Option<bool>,
     /// User Property
-    pub user_properties: Vec<UserProperty>,
+    pub user_properties: UserProperties,
     /// Authentication Method
-    pub auth_method: Option<Arc<String>>,
+    pub auth_method: Option<Arc<str>>,
     /// Authentication Data
     pub auth_data: Option<Bytes>,
 }
 
+impl fmt::Debug for ConnectProperties {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("ConnectProperties")
+            .field("session_expiry_interval", &self.session_expiry_interval)
+            .field("receive_max", &self.receive_max)
+            .field("max_packet_size", &self.max_packet_size)
+            .field("topic_alias_max", &self.topic_alias_max)
+            .field("request_response_info", &self.request_response_info)
+            .field("request_problem_info", &self.request_problem_info)
+            .field("user_properties", &self.user_properties)
+            .field("auth_method", &self.auth_method)
+            .field("auth_data", &redacted(&self.auth_data))
+            .finish()
+    }
+}
+
 #[cfg(feature = "arbitrary")]
 impl<'a> arbitrary::Arbitrary<'a> for ConnectProperties {
     fn arbitrary(u: &mut arbitrary::Unstructured<'a>) -> arbitrary::Result<Self> {
@@ -246,6 +304,13 @@ impl ConnectProperties {
     pub async fn decode_async<T: AsyncRead + Unpin>(
         reader: &mut T,
         packet_type: PacketType,
+    ) -> Result<Self, ErrorV5> {
+        decode_properties_async(reader, |reader| Self::decode_slice(reader, packet_type)).await
+    }
+
+    pub(crate) fn decode_slice(
+        reader: &mut SliceReader<'_>,
+        packet_type: PacketType,
     ) -> Result<Self, ErrorV5> {
         let mut properties = ConnectProperties::default();
         decode_properties!(
@@ -302,6 +367,7 @@ impl Encodable for ConnectProperties {
 
 /// The will message for CONNECT packet.
 #[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct LastWill {
     pub qos: QoS,
     pub retain: bool,
@@ -324,16 +390,22 @@ impl<'a> arbitrary::Arbitrary<'a> for LastWill {
 }
 
 impl LastWill {
-    pub fn new(qos: QoS, topic_name: TopicName, payload: Bytes) -> Self {
+    pub fn new(qos: QoS, topic_name: TopicName, payload: impl Into<Bytes>) -> Self {
         LastWill {
             qos,
             retain: false,
             topic_name,
-            payload,
+            payload: payload.into(),
             properties: WillProperties::default(),
         }
     }
 
+    /// Build a QoS 0 will message which is not retained, the QoS, retain
+    /// flag and will properties are set by the builder methods.
+    pub fn builder(topic_name: TopicName, payload: Bytes) -> LastWillBuilder {
+        LastWillBuilder(LastWill::new(QoS::Level0, topic_name, payload))
+    }
+
     pub async fn decode_async<T: AsyncRead + Unpin>(
         reader: &mut T,
         qos: QoS,
@@ -353,6 +425,26 @@ impl LastWill {
             payload: Bytes::from(payload),
         })
     }
+
+    pub(crate) fn decode_slice(
+        reader: &mut SliceReader<'_>,
+        qos: QoS,
+        retain: bool,
+    ) -> Result<Self, ErrorV5> {
+        let properties = WillProperties::decode_slice(reader)?;
+        let topic_name = TopicName::try_from(reader.read_str()?.to_owned())?;
+        let payload = reader.read_bytes()?;
+        if properties.payload_is_utf8 == Some(true) && from_utf8(payload).is_err() {
+            return Err(ErrorV5::InvalidPayloadFormat);
+        }
+        Ok(LastWill {
+            qos,
+            retain,
+            properties,
+            topic_name,
+            payload: Bytes::copy_from_slice(payload),
+        })
+    }
 }
 
 impl Encodable for LastWill {
@@ -372,16 +464,78 @@ impl Encodable for LastWill {
     }
 }
 
+/// Builder of [`LastWill`], created by `LastWill::builder()`.
+#[derive(Debug, Clone)]
+pub struct LastWillBuilder(LastWill);
+
+impl LastWillBuilder {
+    pub fn qos(mut self, qos: QoS) -> Self {
+        self.0.qos = qos;
+        self
+    }
+
+    pub fn retain(mut self) -> Self {
+        self.0.retain = true;
+        self
+    }
+
+    /// Seconds to wait before publishing the will message.
+    pub fn delay_interval(mut self, seconds: u32) -> Self {
+        self.0.properties.delay_interval = Some(seconds);
+        self
+    }
+
+    pub fn payload_is_utf8(mut self, payload_is_utf8: bool) -> Self {
+        self.0.properties.payload_is_utf8 = Some(payload_is_utf8);
+        self
+    }
+
+    /// Lifetime of the will message in seconds.
+    pub fn message_expiry_interval(mut self, seconds: u32) -> Self {
+        self.0.properties.message_expiry_interval = Some(seconds);
+        self
+    }
+
+    pub fn content_type(mut self, content_type: impl Into<Arc<str>>) -> Self {
+        self.0.properties.content_type = Some(content_type.into());
+        self
+    }
+
+    pub fn response_topic(mut self, response_topic: TopicName) -> Self {
+        self.0.properties.response_topic = Some(response_topic);
+        self
+    }
+
+    pub fn correlation_data(mut self, correlation_data: Bytes) -> Self {
+        self.0.properties.correlation_data = Some(correlation_data);
+        self
+    }
+
+    /// Append a user property, it can be called multiple times.
+    pub fn user_property(mut self, name: impl Into<Arc<str>>, value: impl Into<Arc<str>>) -> Self {
+        self.0.properties.user_properties.push(UserProperty {
+            name: name.into(),
+            value: value.into(),
+        });
+        self
+    }
+
+    pub fn build(self) -> LastWill {
+        self.0
+    }
+}
+
 /// Property list for will message.
 #[derive(Debug, Clone, PartialEq, Eq, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct WillProperties {
     pub delay_interval: Option<u32>,
     pub payload_is_utf8: Option<bool>,
     pub message_expiry_interval: Option<u32>,
-    pub content_type: Option<Arc<String>>,
+    pub content_type: Option<Arc<str>>,
     pub response_topic: Option<TopicName>,
     pub correlation_data: Option<Bytes>,
-    pub user_properties: Vec<UserProperty>,
+    pub user_properties: UserProperties,
 }
 #[cfg(feature = "arbitrary")]
 impl<'a> arbitrary::Arbitrary<'a> for WillProperties {
@@ -400,6 +554,10 @@ impl<'a> arbitrary::Arbitrary<'a> for WillProperties {
 
 impl WillProperties {
     pub async fn decode_async<T: AsyncRead + Unpin>(reader: &mut T) -> Result<Self, ErrorV5> {
+        decode_properties_async(reader, Self::decode_slice).await
+    }
+
+    pub(crate) fn decode_slice(reader: &mut SliceReader<'_>) -> Result<Self, ErrorV5> {
         let mut properties = WillProperties::default();
         decode_properties!(
             LastWill,
@@ -450,6 +608,7 @@ impl Encodable for WillProperties {
 /// Body type of CONNACK packet.
 #[derive(Debug, Clone, PartialEq, Eq)]
 #[cfg_attr(feature = "arbitrary", derive(arbitrary::Arbitrary))]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct Connack {
     pub session_present: bool,
     pub reason_code: ConnectReasonCode,
@@ -464,15 +623,20 @@ impl Connack {
             properties: ConnackProperties::default(),
         }
     }
+    /// Decode the CONNACK body, the fields must consume the remaining length
+    /// exactly, the bytes after it are never read.
     pub async fn decode_async<T: AsyncRead + Unpin>(
         reader: &mut T,
         header: Header,
     ) -> Result<Self, ErrorV5> {
-        let mut payload = [0u8; 2];
-        reader
-            .read_exact(&mut payload)
-            .await
-            .map_err(|err| Error::IoError(err.kind(), err.to_string()))?;
+        decode_body_async(reader, header, Self::decode_slice).await
+    }
+
+    pub(crate) fn decode_slice(
+        reader: &mut SliceReader<'_>,
+        header: Header,
+    ) -> Result<Self, ErrorV5> {
+        let payload = reader.take(2)?;
         let session_present = match payload[0] {
             0 => false,
             1 => true,
@@ -480,7 +644,7 @@ impl Connack {
         };
         let reason_code = ConnectReasonCode::from_u8(payload[1])
             .ok_or(ErrorV5::InvalidReasonCode(header.typ, payload[1]))?;
-        let properties = ConnackProperties::decode_async(reader, header.typ).await?;
+        let properties = ConnackProperties::decode_slice(reader, header.typ)?;
         Ok(Connack {
             session_present,
             reason_code,
@@ -513,7 +677,7 @@ impl Encodable for Connack {
 /// | 131 | 0x83 | Implementation specific error | The CONNECT is valid but is not accepted by this Server.                                                 |
 /// | 132 | 0x84 | Unsupported Protocol Version  | The Server does not support the version of the MQTT protocol requested by the Client.                    |
 /// | 133 | 0x85 | Client Identifier not valid   | The Client Identifier is a valid string but is not allowed by the Server.                                |
-/// | 134 | 0x86 | Bad User Name or Password     | The Server does not accept the User Name or Password specified by the Client                             |
+/// | 134 | 0x86 | Bad User Name or Password     | The Server does not accept the User Name or Password specified by the Client.                            |
 /// | 135 | 0x87 | Not authorized                | The Client is not authorized to connect.                                                                 |
 /// | 136 | 0x88 | Server unavailable            | The MQTT Server is not available.                                                                        |
 /// | 137 | 0x89 | Server busy                   | The Server is busy. Try again later.                                                                     |
@@ -531,6 +695,7 @@ impl Encodable for Connack {
 #[repr(u8)]
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
 #[cfg_attr(feature = "arbitrary", derive(arbitrary::Arbitrary))]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum ConnectReasonCode {
     Success = 0x00,
     UnspecifiedError = 0x80,
@@ -585,30 +750,95 @@ impl ConnectReasonCode {
         };
         Some(code)
     }
+
+    /// The description of the reason code in the specification.
+    pub fn description(&self) -> &'static str {
+        match self {
+            Self::Success => "The Connection is accepted.",
+            Self::UnspecifiedError => "The Server does not wish to reveal the reason for the failure, or none of the other Reason Codes apply.",
+            Self::MalformedPacket => "Data within the CONNECT packet could not be correctly parsed.",
+            Self::ProtocolError => "Data in the CONNECT packet does not conform to this specification.",
+            Self::ImplementationSpecificError => "The CONNECT is valid but is not accepted by this Server.",
+            Self::UnsupportedProtocolVersion => "The Server does not support the version of the MQTT protocol requested by the Client.",
+            Self::ClientIdentifierNotValid => "The Client Identifier is a valid string but is not allowed by the Server.",
+            Self::BadUserNameOrPassword => "The Server does not accept the User Name or Password specified by the Client.",
+            Self::NotAuthorized => "The Client is not authorized to connect.",
+            Self::ServerUnavailable => "The MQTT Server is not available.",
+            Self::ServerBusy => "The Server is busy. Try again later.",
+            Self::Banned => "This Client has been banned by administrative action. Contact the server administrator.",
+            Self::BadAuthMethod => "The authentication method is not supported or does not match the authentication method currently in use.",
+            Self::TopicNameInvalid => "The Will Topic Name is not malformed, but is not accepted by this Server.",
+            Self::PacketTooLarge => "The CONNECT packet exceeded the maximum permissible size.",
+            Self::QuotaExceeded => "An implementation or administrative imposed limit has been exceeded.",
+            Self::PayloadFormatInvalid => "The Will Payload does not match the specified Payload Format Indicator.",
+            Self::RetainNotSupported => "The Server does not support retained messages, and Will Retain was set to 1.",
+            Self::QoSNotSupported => "The Server does not support the QoS set in Will QoS.",
+            Self::UseAnotherServer => "The Client should temporarily use another server.",
+            Self::ServerMoved => "The Client should permanently use another server.",
+            Self::ConnectionRateExceeded => "The connection rate limit has been exceeded.",
+        }
+    }
+
+    /// Reason codes less than 0x80 indicate success, the others indicate
+    /// an error.
+    pub fn is_error(&self) -> bool {
+        *self as u8 >= 0x80
+    }
 }
 
 /// Property list for CONNACK packet.
-#[derive(Debug, Clone, PartialEq, Eq, Default)]
+#[derive(Clone, PartialEq, Eq, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct ConnackProperties {
     pub session_expiry_interval: Option<u32>,
     pub receive_max: Option<u16>,
     pub max_qos: Option<QoS>,
     pub retain_available: Option<bool>,
     pub max_packet_size: Option<u32>,
-    pub assigned_client_id: Option<Arc<String>>,
+    pub assigned_client_id: Option<Arc<str>>,
     pub topic_alias_max: Option<u16>,
-    pub reason_string: Option<Arc<String>>,
-    pub user_properties: Vec<UserProperty>,
+    pub reason_string: Option<Arc<str>>,
+    pub user_properties: UserProperties,
     pub wildcard_subscription_available: Option<bool>,
     pub subscription_id_available: Option<bool>,
     pub shared_subscription_available: Option<bool>,
     pub server_keep_alive: Option<u16>,
-    pub response_info: Option<Arc<String>>,
-    pub server_reference: Option<Arc<String>>,
-    pub auth_method: Option<Arc<String>>,
+    pub response_info: Option<Arc<str>>,
+    pub server_reference: Option<Arc<str>>,
+    pub auth_method: Option<Arc<str>>,
     pub auth_data: Option<Bytes>,
 }
 
+impl fmt::Debug for ConnackProperties {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("ConnackProperties")
+            .field("session_expiry_interval", &self.session_expiry_interval)
+            .field("receive_max", &self.receive_max)
+            .field("max_qos", &self.max_qos)
+            .field("retain_available", &self.retain_available)
+            .field("max_packet_size", &self.max_packet_size)
+            .field("assigned_client_id", &self.assigned_client_id)
+            .field("topic_alias_max", &self.topic_alias_max)
+            .field("reason_string", &self.reason_string)
+            .field("user_properties", &self.user_properties)
+            .field(
+                "wildcard_subscription_available",
+                &self.wildcard_subscription_available,
+            )
+            .field("subscription_id_available", &self.subscription_id_available)
+            .field(
+                "shared_subscription_available",
+                &self.shared_subscription_available,
+            )
+            .field("server_keep_alive", &self.server_keep_alive)
+            .field("response_info", &self.response_info)
+            .field("server_reference", &self.server_reference)
+            .field("auth_method", &self.auth_method)
+            .field("auth_data", &redacted(&self.auth_data))
+            .finish()
+    }
+}
+
 #[cfg(feature = "arbitrary")]
 impl<'a> arbitrary::Arbitrary<'a> for ConnackProperties {
     fn arbitrary(u: &mut arbitrary::Unstructured<'a>) -> arbitrary::Result<Self> {
@@ -638,6 +868,13 @@ impl ConnackProperties {
     pub async fn decode_async<T: AsyncRead + Unpin>(
         reader: &mut T,
         packet_type: PacketType,
+    ) -> Result<Self, ErrorV5> {
+        decode_properties_async(reader, |reader| Self::decode_slice(reader, packet_type)).await
+    }
+
+    pub(crate) fn decode_slice(
+        reader: &mut SliceReader<'_>,
+        packet_type: PacketType,
     ) -> Result<Self, ErrorV5> {
         let mut properties = ConnackProperties::default();
         decode_properties!(
@@ -716,9 +953,129 @@ impl Encodable for ConnackProperties {
     }
 }
 
+/// The server capabilities in CONNACK properties, the absent properties are
+/// resolved to the default values of the specification.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct ConnackCapabilities {
+    /// Absent means the session expiry interval in CONNECT is used.
+    pub session_expiry_interval: Option<u32>,
+    pub receive_max: u16,
+    pub max_qos: QoS,
+    pub retain_available: bool,
+    /// Absent means no limit other than the protocol limit.
+    pub max_packet_size: Option<u32>,
+    pub topic_alias_max: u16,
+    pub wildcard_subscription_available: bool,
+    pub subscription_id_available: bool,
+    pub shared_subscription_available: bool,
+    /// Absent means the keep alive in CONNECT is used.
+    pub server_keep_alive: Option<u16>,
+}
+
+impl From<&ConnackProperties> for ConnackCapabilities {
+    fn from(properties: &ConnackProperties) -> Self {
+        ConnackCapabilities {
+            session_expiry_interval: properties.session_expiry_interval,
+            receive_max: properties.receive_max.unwrap_or(u16::MAX),
+            max_qos: properties.max_qos.unwrap_or(QoS::Level2),
+            retain_available: properties.retain_available.unwrap_or(true),
+            max_packet_size: properties.max_packet_size,
+            topic_alias_max: properties.topic_alias_max.unwrap_or(0),
+            wildcard_subscription_available: properties
+                .wildcard_subscription_available
+                .unwrap_or(true),
+            subscription_id_available: properties.subscription_id_available.unwrap_or(true),
+            shared_subscription_available: properties.shared_subscription_available.unwrap_or(true),
+            server_keep_alive: properties.server_keep_alive,
+        }
+    }
+}
+
+impl Default for ConnackCapabilities {
+    fn default() -> Self {
+        ConnackCapabilities::from(&ConnackProperties::default())
+    }
+}
+
+impl ConnackCapabilities {
+    /// The keep alive to use, `keep_alive` is the value sent in CONNECT.
+    pub fn keep_alive(&self, keep_alive: u16) -> u16 {
+        self.server_keep_alive.unwrap_or(keep_alive)
+    }
+
+    /// The session expiry interval to use, `session_expiry_interval` is the
+    /// value sent in CONNECT (0 if absent).
+    pub fn session_expiry_interval(&self, session_expiry_interval: u32) -> u32 {
+        self.session_expiry_interval
+            .unwrap_or(session_expiry_interval)
+    }
+}
+
+/// The effective settings of a connection after the CONNECT/CONNACK
+/// handshake, with the server overrides applied and the absent properties
+/// resolved to their default values.
+///
+/// The "client" values are the limits the client sent in CONNECT, which
+/// the server must respect. The "server" values are the limits the server
+/// sent in CONNACK, which the client must respect.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct NegotiatedSettings {
+    /// The client identifier assigned by the server, or the one in CONNECT.
+    pub client_id: SharedStr,
+    /// If the client identifier is assigned by the server.
+    pub client_id_assigned: bool,
+    pub session_present: bool,
+    pub keep_alive: u16,
+    pub session_expiry_interval: u32,
+    pub client_receive_max: u16,
+    pub server_receive_max: u16,
+    /// Absent means no limit other than the protocol limit.
+    pub client_max_packet_size: Option<u32>,
+    /// Absent means no limit other than the protocol limit.
+    pub server_max_packet_size: Option<u32>,
+    pub client_topic_alias_max: u16,
+    pub server_topic_alias_max: u16,
+    pub capabilities: ConnackCapabilities,
+}
+
+impl NegotiatedSettings {
+    pub fn new(connect: &Connect, connack: &Connack) -> Self {
+        let capabilities = ConnackCapabilities::from(&connack.properties);
+        let (client_id, client_id_assigned) = match &connack.properties.assigned_client_id {
+            Some(client_id) => (SharedStr::from(client_id.to_string()), true),
+            None => (connect.client_id.clone(), false),
+        };
+        let properties = &connect.properties;
+        NegotiatedSettings {
+            client_id,
+            client_id_assigned,
+            session_present: connack.session_present,
+            keep_alive: capabilities.keep_alive(connect.keep_alive),
+            session_expiry_interval: capabilities
+                .session_expiry_interval(properties.session_expiry_interval.unwrap_or(0)),
+            client_receive_max: properties.receive_max.unwrap_or(u16::MAX),
+            server_receive_max: capabilities.receive_max,
+            client_max_packet_size: properties.max_packet_size,
+            server_max_packet_size: capabilities.max_packet_size,
+            client_topic_alias_max: properties.topic_alias_max.unwrap_or(0),
+            server_topic_alias_max: capabilities.topic_alias_max,
+            capabilities,
+        }
+    }
+}
+
+impl From<(&Connect, &Connack)> for NegotiatedSettings {
+    fn from((connect, connack): (&Connect, &Connack)) -> Self {
+        NegotiatedSettings::new(connect, connack)
+    }
+}
+
 /// Body type for DISCONNECT packet.
 #[derive(Debug, Clone, PartialEq, Eq)]
 #[cfg_attr(feature = "arbitrary", derive(arbitrary::Arbitrary))]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct Disconnect {
     pub reason_code: DisconnectReasonCode,
     pub properties: DisconnectProperties,
@@ -739,19 +1096,26 @@ impl Disconnect {
     pub async fn decode_async<T: AsyncRead + Unpin>(
         reader: &mut T,
         header: Header,
+    ) -> Result<Self, ErrorV5> {
+        decode_body_async(reader, header, Self::decode_slice).await
+    }
+
+    pub(crate) fn decode_slice(
+        reader: &mut SliceReader<'_>,
+        header: Header,
     ) -> Result<Self, ErrorV5> {
         let (reason_code, properties) = if header.remaining_len == 0 {
             (DisconnectReasonCode::NormalDisconnect, Default::default())
         } else if header.remaining_len == 1 {
-            let reason_byte = read_u8(reader).await?;
+            let reason_byte = reader.read_u8()?;
             let reason_code = DisconnectReasonCode::from_u8(reason_byte)
                 .ok_or(ErrorV5::InvalidReasonCode(header.typ, reason_byte))?;
             (reason_code, Default::default())
         } else {
-            let reason_byte = read_u8(reader).await?;
+            let reason_byte = reader.read_u8()?;
             let reason_code = DisconnectReasonCode::from_u8(reason_byte)
                 .ok_or(ErrorV5::InvalidReasonCode(header.typ, reason_byte))?;
-            let properties = DisconnectProperties::decode_async(reader, header.typ).await?;
+            let properties = DisconnectProperties::decode_slice(reader, header.typ)?;
             (reason_code, properties)
         };
         Ok(Disconnect {
@@ -803,7 +1167,7 @@ impl Encodable for Disconnect {
 /// | 139 | 0x8B | Server shutting down                   | Server        | The Server is shutting down.                                                                   |
 /// | 141 | 0x8D | Keep Alive timeout                     | Server        | The Connection is closed because no packet has been received for 1.5 times the Keepalive time. |
 /// | 142 | 0x8E | Session taken over                     | Server        | Another Connection using the same ClientID has connected causing this Connection to be closed. |
-/// | 143 | 0x8F | Topic Filter invalid                   | Server        | The Topic Filter is correctly formed, but is not accepted by this Sever.                       |
+/// | 143 | 0x8F | Topic Filter invalid                   | Server        | The Topic Filter is correctly formed, but is not accepted by this Server.                       |
 /// | 144 | 0x90 | Topic Name invalid                     | Client/Server | The Topic Name is correctly formed, but is not accepted by this Client/Server.                 |
 /// | 147 | 0x93 | Receive Maximum exceeded               | Client/Server | The Client/Server has received more than Receive Maximum publication for                       |
 /// |     |      |                                        |               | which it has not sent PUBACK or PUBCOMP.                                                       |
@@ -826,6 +1190,7 @@ impl Encodable for Disconnect {
 #[repr(u8)]
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
 #[cfg_attr(feature = "arbitrary", derive(arbitrary::Arbitrary))]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum DisconnectReasonCode {
     NormalDisconnect = 0x00,
     DisconnectWithWillMessage = 0x04,
@@ -894,22 +1259,71 @@ impl DisconnectReasonCode {
         };
         Some(code)
     }
+
+    /// The description of the reason code in the specification.
+    pub fn description(&self) -> &'static str {
+        match self {
+            Self::NormalDisconnect => "Close the connection normally. Do not send the Will Message.",
+            Self::DisconnectWithWillMessage => "The Client wishes to disconnect but requires that the Server also publishes its Will Message.",
+            Self::UnspecifiedError => "The Connection is closed but the sender either does not wish to reveal the reason, or none of the other Reason Codes apply.",
+            Self::MalformedPacket => "The received packet does not conform to this specification.",
+            Self::ProtocolError => "An unexpected or out of order packet was received.",
+            Self::ImplementationSpecificError => "The packet received is valid but cannot be processed by this implementation.",
+            Self::NotAuthorized => "The request is not authorized.",
+            Self::ServerBusy => "The Server is busy and cannot continue processing requests from this Client.",
+            Self::ServerShuttingDown => "The Server is shutting down.",
+            Self::KeepAliveTimeout => "The Connection is closed because no packet has been received for 1.5 times the Keepalive time.",
+            Self::SessionTakenOver => "Another Connection using the same ClientID has connected causing this Connection to be closed.",
+            Self::TopicFilterInvalid => "The Topic Filter is correctly formed, but is not accepted by this Server.",
+            Self::TopicNameInvalid => "The Topic Name is correctly formed, but is not accepted by this Client/Server.",
+            Self::ReceiveMaximumExceeded => "The Client/Server has received more than Receive Maximum publication for which it has not sent PUBACK or PUBCOMP.",
+            Self::TopicAliasInvalid => "The Client/Server has received a PUBLISH packet containing a Topic Alias which is greater than the Maximum Topic Alias it sent in the CONNECT or CONNACK packet.",
+            Self::PacketTooLarge => "The packet size is greater than Maximum Packet Size for this Client/Server.",
+            Self::MessageRateTooHigh => "The received data rate is too high.",
+            Self::QuotaExceeded => "An implementation or administrative imposed limit has been exceeded.",
+            Self::AdministrativeAction => "The Connection is closed due to an administrative action.",
+            Self::PayloadFormatInvalid => "The payload format does not match the one specified by the Payload Format Indicator.",
+            Self::RetainNotSupported => "The Server has does not support retained messages.",
+            Self::QoSNotSupported => "The Client specified a QoS greater than the QoS specified in a Maximum QoS in the CONNACK.",
+            Self::UserAnotherServer => "The Client should temporarily change its Server.",
+            Self::ServerMoved => "The Server is moved and the Client should permanently change its server location.",
+            Self::SharedSubscriptionNotSupported => "The Server does not support Shared Subscriptions.",
+            Self::ConnectionRateExceeded => "This connection is closed because the connection rate is too high.",
+            Self::MaximumConnectTime => "The maximum connection time authorized for this connection has been exceeded.",
+            Self::SubscriptionIdentifiersNotSupported => "The Server does not support Subscription Identifiers; the subscription is not accepted.",
+            Self::WildcardSubscriptionsNotSupported => "The Server does not support Wildcard Subscriptions; the subscription is not accepted.",
+        }
+    }
+
+    /// Reason codes less than 0x80 indicate success, the others indicate
+    /// an error.
+    pub fn is_error(&self) -> bool {
+        *self as u8 >= 0x80
+    }
 }
 
 /// Property list for DISCONNECT packet.
 #[derive(Debug, Clone, PartialEq, Eq, Default)]
 #[cfg_attr(feature = "arbitrary", derive(arbitrary::Arbitrary))]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct DisconnectProperties {
     pub session_expiry_interval: Option<u32>,
-    pub reason_string: Option<Arc<String>>,
-    pub user_properties: Vec<UserProperty>,
-    pub server_reference: Option<Arc<String>>,
+    pub reason_string: Option<Arc<str>>,
+    pub user_properties: UserProperties,
+    pub server_reference: Option<Arc<str>>,
 }
 
 impl DisconnectProperties {
     pub async fn decode_async<T: AsyncRead + Unpin>(
         reader: &mut T,
         packet_type: PacketType,
+    ) -> Result<Self, ErrorV5> {
+        decode_properties_async(reader, |reader| Self::decode_slice(reader, packet_type)).await
+    }
+
+    pub(crate) fn decode_slice(
+        reader: &mut SliceReader<'_>,
+        packet_type: PacketType,
     ) -> Result<Self, ErrorV5> {
         let mut properties = DisconnectProperties::default();
         decode_properties!(
@@ -952,6 +1366,7 @@ impl Encodable for DisconnectProperties {
 /// Body type of AUTH packet .
 #[derive(Debug, Clone, PartialEq, Eq)]
 #[cfg_attr(feature = "arbitrary", derive(arbitrary::Arbitrary))]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct Auth {
     pub reason_code: AuthReasonCode,
     pub properties: AuthProperties,
@@ -972,6 +1387,13 @@ impl Auth {
     pub async fn decode_async<T: AsyncRead + Unpin>(
         reader: &mut T,
         header: Header,
+    ) -> Result<Self, ErrorV5> {
+        decode_body_async(reader, header, Self::decode_slice).await
+    }
+
+    pub(crate) fn decode_slice(
+        reader: &mut SliceReader<'_>,
+        header: Header,
     ) -> Result<Self, ErrorV5> {
         let auth = if header.remaining_len == 0 {
             Auth {
@@ -979,10 +1401,10 @@ impl Auth {
                 properties: AuthProperties::default(),
             }
         } else {
-            let reason_byte = read_u8(reader).await?;
+            let reason_byte = reader.read_u8()?;
             let reason_code = AuthReasonCode::from_u8(reason_byte)
                 .ok_or(ErrorV5::InvalidReasonCode(header.typ, reason_byte))?;
-            let properties = AuthProperties::decode_async(reader, header.typ).await?;
+            let properties = AuthProperties::decode_slice(reader, header.typ)?;
             Auth {
                 reason_code,
                 properties,
@@ -1024,6 +1446,7 @@ impl Encodable for Auth {
 #[repr(u8)]
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
 #[cfg_attr(feature = "arbitrary", derive(arbitrary::Arbitrary))]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum AuthReasonCode {
     Success = 0x00,
     ContinueAuthentication = 0x18,
@@ -1040,15 +1463,42 @@ impl AuthReasonCode {
         };
         Some(code)
     }
+
+    /// The description of the reason code in the specification.
+    pub fn description(&self) -> &'static str {
+        match self {
+            Self::Success => "Authentication is successful",
+            Self::ContinueAuthentication => "Continue the authentication with another step",
+            Self::ReAuthentication => "Initiate a re-authentication",
+        }
+    }
+
+    /// Reason codes less than 0x80 indicate success, the others indicate
+    /// an error.
+    pub fn is_error(&self) -> bool {
+        *self as u8 >= 0x80
+    }
 }
 
 /// Property list for AUTH packet.
-#[derive(Debug, Clone, PartialEq, Eq, Default)]
+#[derive(Clone, PartialEq, Eq, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct AuthProperties {
-    pub auth_method: Option<Arc<String>>,
+    pub auth_method: Option<Arc<str>>,
     pub auth_data: Option<Bytes>,
-    pub reason_string: Option<Arc<String>>,
-    pub user_properties: Vec<UserProperty>,
+    pub reason_string: Option<Arc<str>>,
+    pub user_properties: UserProperties,
+}
+
+impl fmt::Debug for AuthProperties {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("AuthProperties")
+            .field("auth_method", &self.auth_method)
+            .field("auth_data", &redacted(&self.auth_data))
+            .field("reason_string", &self.reason_string)
+            .field("user_properties", &self.user_properties)
+            .finish()
+    }
 }
 
 #[cfg(feature = "arbitrary")]
@@ -1067,6 +1517,13 @@ impl AuthProperties {
     pub async fn decode_async<T: AsyncRead + Unpin>(
         reader: &mut T,
         packet_type: PacketType,
+    ) -> Result<Self, ErrorV5> {
+        decode_properties_async(reader, |reader| Self::decode_slice(reader, packet_type)).await
+    }
+
+    pub(crate) fn decode_slice(
+        reader: &mut SliceReader<'_>,
+        packet_type: PacketType,
     ) -> Result<Self, ErrorV5> {
         let mut properties = AuthProperties::default();
         decode_properties!(
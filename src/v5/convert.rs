@@ -0,0 +1,249 @@
+use std::convert::TryFrom;
+
+use thiserror::Error;
+
+use super::{
+    PropertyId, Puback, Pubcomp, Publish, PublishProperties, Pubrec, Pubrel, Suback, Subscribe,
+    SubscribeReasonCode, SubscriptionOptions, Unsuback, Unsubscribe, UnsubscribeReasonCode,
+    UserProperty,
+};
+use crate::v3;
+use crate::Pid;
+
+/// Report of the v5.0 information dropped when converting a packet to v3.
+///
+/// Converting from v3 to v5.0 is always lossless, the other direction is
+/// lossless only when the report is empty.
+#[derive(Error, Debug, Clone, Default, PartialEq, Eq)]
+#[error("v5.0 information dropped: properties={properties:?}, reason_code={reason_code}, subscription_options={subscription_options}")]
+pub struct LossReport {
+    /// Properties which have no counterpart in v3.
+    pub properties: Vec<PropertyId>,
+    /// A failure reason code was reduced to a v3 success or failure.
+    pub reason_code: bool,
+    /// Some subscription options other than maximum QoS were not default.
+    pub subscription_options: bool,
+}
+
+impl LossReport {
+    pub fn is_empty(&self) -> bool {
+        self.properties.is_empty() && !self.reason_code && !self.subscription_options
+    }
+
+    fn check<T>(&mut self, id: PropertyId, value: &Option<T>) {
+        if value.is_some() {
+            self.properties.push(id);
+        }
+    }
+
+    fn check_user_properties(&mut self, value: &[UserProperty]) {
+        if !value.is_empty() {
+            self.properties.push(PropertyId::UserProperty);
+        }
+    }
+
+    fn into_result<T>(self, value: T) -> Result<T, LossReport> {
+        if self.is_empty() {
+            Ok(value)
+        } else {
+            Err(self)
+        }
+    }
+}
+
+impl v3::Publish {
+    pub fn into_v5(self) -> Publish {
+        Publish {
+            dup: self.dup,
+            retain: self.retain,
+            qos_pid: self.qos_pid,
+            topic_name: self.topic_name,
+            payload: self.payload,
+            properties: PublishProperties::default(),
+        }
+    }
+}
+
+impl Publish {
+    pub fn into_v3_lossy(self) -> (v3::Publish, LossReport) {
+        let mut report = LossReport::default();
+        let properties = &self.properties;
+        report.check(
+            PropertyId::PayloadFormatIndicator,
+            &properties.payload_is_utf8,
+        );
+        report.check(
+            PropertyId::MessageExpiryInterval,
+            &properties.message_expiry_interval,
+        );
+        report.check(PropertyId::ContentType, &properties.content_type);
+        report.check(PropertyId::ResponseTopic, &properties.response_topic);
+        report.check(PropertyId::CorrelationData, &properties.correlation_data);
+        report.check(
+            PropertyId::SubscriptionIdentifier,
+            &properties.subscription_id,
+        );
+        report.check(PropertyId::TopicAlias, &properties.topic_alias);
+        report.check_user_properties(&properties.user_properties);
+        let publish = v3::Publish {
+            dup: self.dup,
+            retain: self.retain,
+            qos_pid: self.qos_pid,
+            topic_name: self.topic_name,
+            payload: self.payload,
+        };
+        (publish, report)
+    }
+}
+
+impl v3::Subscribe {
+    pub fn into_v5(self) -> Subscribe {
+        let topics = self
+            .topics
+            .into_iter()
+            .map(|(filter, qos)| (filter, SubscriptionOptions::new(qos)))
+            .collect();
+        Subscribe::new(self.pid, topics)
+    }
+}
+
+impl Subscribe {
+    pub fn into_v3_lossy(self) -> (v3::Subscribe, LossReport) {
+        let mut report = LossReport::default();
+        report.check(
+            PropertyId::SubscriptionIdentifier,
+            &self.properties.subscription_id,
+        );
+        report.check_user_properties(&self.properties.user_properties);
+        let topics = self
+            .topics
+            .into_iter()
+            .map(|(filter, options)| {
+                if options != SubscriptionOptions::new(options.max_qos) {
+                    report.subscription_options = true;
+                }
+                (filter, options.max_qos)
+            })
+            .collect();
+        (v3::Subscribe::new(self.pid, topics), report)
+    }
+}
+
+impl v3::Suback {
+    pub fn into_v5(self) -> Suback {
+        let topics = self
+            .topics
+            .into_iter()
+            .map(|code| match code.granted_qos() {
+                Some(qos) => SubscribeReasonCode::granted(qos),
+                None => SubscribeReasonCode::UnspecifiedError,
+            })
+            .collect();
+        Suback::new(self.pid, topics)
+    }
+}
+
+impl Suback {
+    pub fn into_v3_lossy(self) -> (v3::Suback, LossReport) {
+        let mut report = LossReport::default();
+        report.check(PropertyId::ReasonString, &self.properties.reason_string);
+        report.check_user_properties(&self.properties.user_properties);
+        let topics = self
+            .topics
+            .into_iter()
+            .map(|code| {
+                if let Some(qos) = code.granted_qos() {
+                    return v3::SubscribeReturnCode::granted(qos);
+                }
+                // The v3 failure code is the same value as the v5
+                // unspecified error.
+                if code != SubscribeReasonCode::UnspecifiedError {
+                    report.reason_code = true;
+                }
+                v3::SubscribeReturnCode::Failure
+            })
+            .collect();
+        (v3::Suback::new(self.pid, topics), report)
+    }
+}
+
+impl v3::Unsubscribe {
+    pub fn into_v5(self) -> Unsubscribe {
+        Unsubscribe::new(self.pid, self.topics)
+    }
+}
+
+impl Unsubscribe {
+    pub fn into_v3_lossy(self) -> (v3::Unsubscribe, LossReport) {
+        let mut report = LossReport::default();
+        report.check_user_properties(&self.properties.user_properties);
+        (v3::Unsubscribe::new(self.pid, self.topics), report)
+    }
+}
+
+impl Unsuback {
+    /// Convert the packet identifier of a v3 UNSUBACK packet, all topic
+    /// filters are treated as successfully unsubscribed.
+    pub fn from_v3(pid: Pid, topics_len: usize) -> Self {
+        Unsuback::new(pid, vec![UnsubscribeReasonCode::Success; topics_len])
+    }
+
+    /// Return the packet identifier of v3 UNSUBACK packet.
+    pub fn into_v3_lossy(self) -> (Pid, LossReport) {
+        let mut report = LossReport::default();
+        report.check(PropertyId::ReasonString, &self.properties.reason_string);
+        report.check_user_properties(&self.properties.user_properties);
+        report.reason_code = self.topics.iter().any(|code| {
+            !matches!(
+                code,
+                UnsubscribeReasonCode::Success | UnsubscribeReasonCode::NoSubscriptionExisted
+            )
+        });
+        (self.pid, report)
+    }
+}
+
+// The v3 acknowledgement packets only carry a packet identifier, use
+// `new_success()` to convert them to v5.0.
+macro_rules! impl_ack_convert {
+    ($t:ident) => {
+        impl $t {
+            /// Return the packet identifier of the v3 packet.
+            pub fn into_v3_lossy(self) -> (Pid, LossReport) {
+                let mut report = LossReport::default();
+                report.check(PropertyId::ReasonString, &self.properties.reason_string);
+                report.check_user_properties(&self.properties.user_properties);
+                report.reason_code = self.reason_code as u8 >= 0x80;
+                (self.pid, report)
+            }
+        }
+    };
+}
+
+impl_ack_convert!(Puback);
+impl_ack_convert!(Pubrec);
+impl_ack_convert!(Pubrel);
+impl_ack_convert!(Pubcomp);
+
+macro_rules! impl_packet_convert {
+    ($t:ident) => {
+        impl From<v3::$t> for $t {
+            fn from(packet: v3::$t) -> Self {
+                packet.into_v5()
+            }
+        }
+
+        impl TryFrom<$t> for v3::$t {
+            type Error = LossReport;
+            fn try_from(packet: $t) -> Result<Self, LossReport> {
+                let (packet, report) = packet.into_v3_lossy();
+                report.into_result(packet)
+            }
+        }
+    };
+}
+
+impl_packet_convert!(Publish);
+impl_packet_convert!(Subscribe);
+impl_packet_convert!(Suback);
+impl_packet_convert!(Unsubscribe);
@@ -42,6 +42,15 @@ pub enum ErrorV5 {
     #[error("duplicated property: `{0}`")]
     DuplicatedProperty(PropertyId),
 
+    /// Property value is 0 where the spec forbids it (Receive Maximum and
+    /// Maximum Packet Size).
+    #[error("zero value for property `{0}`")]
+    ZeroProperty(PropertyId),
+
+    /// Subscription Identifier is 0 [MQTT-3.8.2.1.2].
+    #[error("zero subscription identifier")]
+    ZeroSubscriptionId,
+
     /// Invalid property.
     #[error("invalid property `{1}` for packet `{0}`")]
     InvalidProperty(PacketType, PropertyId),
@@ -49,15 +58,32 @@ pub enum ErrorV5 {
     /// Invalid will property (connect packet).
     #[error("invalid will property: `{0}`")]
     InvalidWillProperty(PropertyId),
+
+    /// Topic alias is 0 or exceeds the topic alias maximum.
+    #[error("invalid topic alias: `{0}`")]
+    InvalidTopicAlias(u16),
+
+    /// Topic alias is used before a topic name is assigned to it.
+    #[error("unknown topic alias: `{0}`")]
+    UnknownTopicAlias(u16),
 }
 
 impl ErrorV5 {
+    /// See `Error::is_eof()`.
     pub fn is_eof(&self) -> bool {
         match self {
             ErrorV5::Common(err) => err.is_eof(),
             _ => false,
         }
     }
+
+    /// See `Error::is_would_block()`.
+    pub fn is_would_block(&self) -> bool {
+        match self {
+            ErrorV5::Common(err) => err.is_would_block(),
+            _ => false,
+        }
+    }
 }
 
 impl From<io::Error> for ErrorV5 {
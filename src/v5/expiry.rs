@@ -0,0 +1,100 @@
+use std::time::{Duration, Instant};
+
+use super::Publish;
+
+/// A received PUBLISH packet with the instant it is received, to apply the
+/// "Message Expiry Interval" when the message is stored and forwarded later.
+/// See [MQTT 3.3.2.3.3].
+///
+/// [MQTT 3.3.2.3.3]: https://docs.oasis-open.org/mqtt/mqtt/v5.0/os/mqtt-v5.0-os.html#_Toc3901112
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ExpiringPublish {
+    pub publish: Publish,
+    pub received: Instant,
+}
+
+impl ExpiringPublish {
+    pub fn new(publish: Publish, received: Instant) -> Self {
+        ExpiringPublish { publish, received }
+    }
+
+    /// The instant the message expires, `None` if it never expires.
+    pub fn expires_at(&self) -> Option<Instant> {
+        self.publish
+            .properties
+            .message_expiry_interval
+            .map(|interval| self.received + Duration::from_secs(u64::from(interval)))
+    }
+
+    pub fn is_expired(&self, now: Instant) -> bool {
+        self.expires_at()
+            .is_some_and(|expires_at| now >= expires_at)
+    }
+
+    /// The remaining seconds of the expiry interval: the received interval
+    /// minus the whole seconds the message has been waiting. `None` if it
+    /// never expires, zero means expired.
+    pub fn remaining_secs(&self, now: Instant) -> Option<u32> {
+        let interval = self.publish.properties.message_expiry_interval?;
+        let waited = now.saturating_duration_since(self.received).as_secs();
+        Some(u64::from(interval).saturating_sub(waited) as u32)
+    }
+
+    /// The PUBLISH packet to forward at `now`, the message expiry interval is
+    /// set to the remaining seconds. Return `None` if the message is expired
+    /// and must not be forwarded.
+    pub fn to_forward(&self, now: Instant) -> Option<Publish> {
+        let mut publish = self.publish.clone();
+        if let Some(remaining) = self.remaining_secs(now) {
+            if remaining == 0 {
+                return None;
+            }
+            publish.properties.message_expiry_interval = Some(remaining);
+        }
+        Some(publish)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::convert::TryFrom;
+
+    use bytes::Bytes;
+
+    use super::*;
+    use crate::{QosPid, TopicName};
+
+    #[test]
+    fn test_expiring_publish() {
+        let topic_name = TopicName::try_from("a".to_owned()).unwrap();
+        let mut publish = Publish::new(QosPid::Level0, topic_name, Bytes::new());
+        let received = Instant::now();
+
+        // Never expires
+        let message = ExpiringPublish::new(publish.clone(), received);
+        let later = received + Duration::from_secs(3600);
+        assert_eq!(message.expires_at(), None);
+        assert!(!message.is_expired(later));
+        assert_eq!(message.to_forward(later), Some(publish.clone()));
+
+        publish.properties.message_expiry_interval = Some(60);
+        let message = ExpiringPublish::new(publish, received);
+        assert_eq!(
+            message.expires_at(),
+            Some(received + Duration::from_secs(60))
+        );
+        assert_eq!(message.remaining_secs(received), Some(60));
+
+        let now = received + Duration::from_millis(20_500);
+        assert!(!message.is_expired(now));
+        assert_eq!(message.remaining_secs(now), Some(40));
+        let forward = message.to_forward(now).unwrap();
+        assert_eq!(forward.properties.message_expiry_interval, Some(40));
+        assert_eq!(forward.payload, message.publish.payload);
+
+        let now = received + Duration::from_secs(60);
+        assert!(message.is_expired(now));
+        assert_eq!(message.remaining_secs(now), Some(0));
+        assert_eq!(message.to_forward(now), None);
+    }
+}
@@ -0,0 +1,160 @@
+use super::PropertyId;
+use crate::HexdumpWriter;
+
+/// Format MQTT v5.0 packet bytes as a hexdump annotated with the fields
+/// (fixed header, remaining length, variable header, properties and
+/// payload), for debugging interoperability issues.
+///
+/// All the packets in `data` are annotated, malformed or incomplete packets
+/// are annotated as far as possible.
+pub fn annotated_hexdump(data: &[u8]) -> String {
+    HexdumpWriter::annotate(data, annotate_body)
+}
+
+fn annotate_body(writer: &mut HexdumpWriter<'_>, control_byte: u8) -> Option<()> {
+    match control_byte >> 4 {
+        1 => {
+            writer.string("protocol name")?;
+            writer.u8("protocol level")?;
+            let flags = writer.connect_flags("clean_start")?;
+            writer.u16("keep alive")?;
+            annotate_properties(writer)?;
+            writer.string("client identifier")?;
+            if flags & 0b100 != 0 {
+                annotate_properties(writer)?;
+                writer.string("will topic")?;
+                writer.binary("will payload")?;
+            }
+            if flags & 0b10000000 != 0 {
+                writer.string("username")?;
+            }
+            if flags & 0b01000000 != 0 {
+                writer.binary("password")?;
+            }
+        }
+        2 => {
+            writer.u8("connack flags")?;
+            writer.u8("reason code")?;
+            annotate_properties(writer)?;
+        }
+        3 => {
+            writer.string("topic name")?;
+            if control_byte & 0b110 != 0 {
+                writer.u16("packet identifier")?;
+            }
+            annotate_properties(writer)?;
+            writer.rest("payload");
+        }
+        4..=7 => {
+            writer.u16("packet identifier")?;
+            if writer.remaining() > 0 {
+                writer.u8("reason code")?;
+            }
+            if writer.remaining() > 0 {
+                annotate_properties(writer)?;
+            }
+        }
+        8 => {
+            writer.u16("packet identifier")?;
+            annotate_properties(writer)?;
+            while writer.remaining() > 0 {
+                writer.string("topic filter")?;
+                writer.field(1, |bytes| {
+                    let options = bytes[0];
+                    format!(
+                        "subscription options: 0b{options:08b} max_qos={} no_local={} retain_as_published={} retain_handling={}",
+                        options & 0b11,
+                        options & 0b100 != 0,
+                        options & 0b1000 != 0,
+                        (options & 0b110000) >> 4,
+                    )
+                })?;
+            }
+        }
+        9 | 11 => {
+            writer.u16("packet identifier")?;
+            annotate_properties(writer)?;
+            while writer.remaining() > 0 {
+                writer.u8("reason code")?;
+            }
+        }
+        10 => {
+            writer.u16("packet identifier")?;
+            annotate_properties(writer)?;
+            while writer.remaining() > 0 {
+                writer.string("topic filter")?;
+            }
+        }
+        12 | 13 => {}
+        14 | 15 => {
+            if writer.remaining() > 0 {
+                writer.u8("reason code")?;
+            }
+            if writer.remaining() > 0 {
+                annotate_properties(writer)?;
+            }
+        }
+        _ => return None,
+    }
+    Some(())
+}
+
+fn annotate_properties(writer: &mut HexdumpWriter<'_>) -> Option<()> {
+    let property_len = writer.var_int("property length")? as usize;
+    let end = writer.remaining().checked_sub(property_len)?;
+    while writer.remaining() > end {
+        let byte = writer.field(1, |bytes| match PropertyId::from_u8(bytes[0]) {
+            Ok(property_id) => format!("property: {property_id}"),
+            Err(_) => format!("invalid property identifier: {:#04x}", bytes[0]),
+        })?[0];
+        annotate_property_value(writer, PropertyId::from_u8(byte).ok()?)?;
+    }
+    (writer.remaining() == end).then_some(())
+}
+
+fn annotate_property_value(writer: &mut HexdumpWriter<'_>, property_id: PropertyId) -> Option<()> {
+    match property_id {
+        PropertyId::PayloadFormatIndicator
+        | PropertyId::RequestProblemInformation
+        | PropertyId::RequestResponseInformation
+        | PropertyId::MaximumQoS
+        | PropertyId::RetainAvailable
+        | PropertyId::WildcardSubscriptionAvailable
+        | PropertyId::SubscriptionIdentifierAvailable
+        | PropertyId::SharedSubscriptionAvailable => {
+            writer.u8("value")?;
+        }
+        PropertyId::ServerKeepAlive
+        | PropertyId::ReceiveMaximum
+        | PropertyId::TopicAliasMaximum
+        | PropertyId::TopicAlias => {
+            writer.u16("value")?;
+        }
+        PropertyId::MessageExpiryInterval
+        | PropertyId::SessionExpiryInterval
+        | PropertyId::WillDelayInterval
+        | PropertyId::MaximumPacketSize => {
+            writer.u32("value")?;
+        }
+        PropertyId::SubscriptionIdentifier => {
+            writer.var_int("value")?;
+        }
+        PropertyId::ContentType
+        | PropertyId::ResponseTopic
+        | PropertyId::AssignedClientIdentifier
+        | PropertyId::AuthenticationMethod
+        | PropertyId::ResponseInformation
+        | PropertyId::ServerReference
+        | PropertyId::ReasonString => {
+            writer.string("value")?;
+        }
+        PropertyId::CorrelationData | PropertyId::AuthenticationData => {
+            writer.binary("value")?;
+        }
+        PropertyId::UserProperty => {
+            writer.string("name")?;
+            writer.string("value")?;
+        }
+    }
+    Some(())
+}
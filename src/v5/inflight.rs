@@ -0,0 +1,193 @@
+use std::collections::VecDeque;
+
+use super::{Packet, Publish, Pubrel};
+use crate::{Pid, QoS};
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Inflight {
+    /// Waiting for PUBACK or PUBREC
+    Publish(Publish),
+    /// Waiting for PUBCOMP
+    Pubrel,
+}
+
+/// The unacknowledged outgoing QoS 1/2 PUBLISH flows of a session, to send
+/// them again when the session is resumed. See [MQTT 4.4].
+///
+/// A PUBLISH is added when it is sent, and replaced by a PUBREL when the
+/// PUBREC is received. The flows are kept in sending order, so that
+/// `retransmit()` yields the packets in the same order as they were sent.
+///
+/// [MQTT 4.4]: https://docs.oasis-open.org/mqtt/mqtt/v5.0/os/mqtt-v5.0-os.html#_Toc3901238
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct InflightQueue {
+    items: VecDeque<(Pid, Inflight)>,
+}
+
+impl InflightQueue {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Number of the in-flight flows.
+    pub fn len(&self) -> usize {
+        self.items.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.items.is_empty()
+    }
+
+    pub fn contains(&self, pid: Pid) -> bool {
+        self.position(pid).is_some()
+    }
+
+    /// Add a sent QoS 1/2 PUBLISH, the PUBLISH should be the one before
+    /// applying the topic alias. Return false (and the PUBLISH is not added)
+    /// if it is QoS 0 or the packet identifier is already in-flight.
+    pub fn push(&mut self, publish: Publish) -> bool {
+        match publish.qos_pid.pid() {
+            Some(pid) if !self.contains(pid) => {
+                self.items.push_back((pid, Inflight::Publish(publish)));
+                true
+            }
+            _ => false,
+        }
+    }
+
+    /// Handle a received PUBACK, return the acknowledged QoS 1 PUBLISH.
+    pub fn on_puback(&mut self, pid: Pid) -> Option<Publish> {
+        let idx = self.position(pid)?;
+        match &self.items[idx].1 {
+            Inflight::Publish(publish) if publish.qos_pid.qos() == QoS::Level1 => {}
+            _ => return None,
+        }
+        match self.items.remove(idx) {
+            Some((_, Inflight::Publish(publish))) => Some(publish),
+            _ => None,
+        }
+    }
+
+    /// Handle a received PUBREC with success reason code, the QoS 2 PUBLISH
+    /// is replaced by a PUBREL (moved to the end, since the PUBREL is sent
+    /// now). A duplicated PUBREC is accepted. Return false if there is no
+    /// QoS 2 flow with the packet identifier.
+    ///
+    /// For a PUBREC with error reason code, the flow is completed, use
+    /// `remove()` instead.
+    pub fn on_pubrec(&mut self, pid: Pid) -> bool {
+        let Some(idx) = self.position(pid) else {
+            return false;
+        };
+        match &self.items[idx].1 {
+            Inflight::Publish(publish) if publish.qos_pid.qos() == QoS::Level2 => {
+                self.items.remove(idx);
+                self.items.push_back((pid, Inflight::Pubrel));
+                true
+            }
+            Inflight::Publish(_) => false,
+            Inflight::Pubrel => true,
+        }
+    }
+
+    /// Handle a received PUBCOMP. Return false if there is no PUBREL
+    /// waiting for it.
+    pub fn on_pubcomp(&mut self, pid: Pid) -> bool {
+        match self.position(pid) {
+            Some(idx) if self.items[idx].1 == Inflight::Pubrel => {
+                self.items.remove(idx);
+                true
+            }
+            _ => false,
+        }
+    }
+
+    /// Remove the flow of the packet identifier, return false if it is not
+    /// in-flight.
+    pub fn remove(&mut self, pid: Pid) -> bool {
+        match self.position(pid) {
+            Some(idx) => {
+                self.items.remove(idx);
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// The packets to send again when the session is resumed, in the
+    /// original order: the PUBLISH packets with DUP flag set and the PUBREL
+    /// packets of the half-completed QoS 2 flows.
+    pub fn retransmit(&self) -> impl Iterator<Item = Packet> + '_ {
+        self.items.iter().map(|(pid, inflight)| match inflight {
+            Inflight::Publish(publish) => {
+                let mut publish = publish.clone();
+                publish.dup = true;
+                publish.into()
+            }
+            Inflight::Pubrel => Pubrel::new_success(*pid).into(),
+        })
+    }
+
+    pub fn clear(&mut self) {
+        self.items.clear();
+    }
+
+    fn position(&self, pid: Pid) -> Option<usize> {
+        self.items.iter().position(|(item, _)| *item == pid)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::convert::TryFrom;
+
+    use bytes::Bytes;
+
+    use super::*;
+    use crate::{QosPid, TopicName};
+
+    fn publish(qos_pid: QosPid) -> Publish {
+        let topic_name = TopicName::try_from("a/b".to_owned()).unwrap();
+        Publish::new(qos_pid, topic_name, Bytes::from_static(b"x"))
+    }
+
+    #[test]
+    fn test_inflight_queue() {
+        let pid1 = Pid::try_from(1).unwrap();
+        let pid2 = Pid::try_from(2).unwrap();
+        let pid3 = Pid::try_from(3).unwrap();
+        let mut queue = InflightQueue::new();
+        assert!(!queue.push(publish(QosPid::Level0)));
+        assert!(queue.push(publish(QosPid::Level2(pid1))));
+        assert!(queue.push(publish(QosPid::Level1(pid2))));
+        assert!(queue.push(publish(QosPid::Level2(pid3))));
+        assert!(!queue.push(publish(QosPid::Level1(pid3))));
+        assert_eq!(queue.len(), 3);
+
+        // Wrong acknowledgements
+        assert!(queue.on_puback(pid1).is_none());
+        assert!(!queue.on_pubrec(pid2));
+        assert!(!queue.on_pubcomp(pid1));
+
+        assert!(queue.on_pubrec(pid1));
+        assert!(queue.on_pubrec(pid1));
+        let mut dup1 = publish(QosPid::Level1(pid2));
+        dup1.dup = true;
+        let mut dup2 = publish(QosPid::Level2(pid3));
+        dup2.dup = true;
+        assert_eq!(
+            queue.retransmit().collect::<Vec<_>>(),
+            vec![
+                Packet::Publish(dup1),
+                Packet::Publish(dup2),
+                Packet::Pubrel(Pubrel::new_success(pid1)),
+            ]
+        );
+
+        assert_eq!(queue.on_puback(pid2), Some(publish(QosPid::Level1(pid2))));
+        assert!(queue.on_pubcomp(pid1));
+        assert!(queue.remove(pid3));
+        assert!(!queue.remove(pid3));
+        assert!(queue.is_empty());
+    }
+}
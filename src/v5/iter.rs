@@ -0,0 +1,85 @@
+use bytes::BytesMut;
+
+use super::{ErrorV5, Header, Packet};
+use crate::decode_frame;
+
+/// Iterator of the packets decoded from a slice, see `Packet::iter_from()`.
+///
+/// Each item is a decoded packet and the number of bytes it consumed. The
+/// iteration stops at the first incomplete packet or after an error, the
+/// bytes not consumed are returned by `remaining()`.
+#[derive(Debug, Clone)]
+pub struct PacketIter<'a> {
+    buf: &'a [u8],
+    failed: bool,
+}
+
+/// Iterator of the packets decoded from a `BytesMut`, see
+/// `Packet::iter_from_bytes_mut()`.
+///
+/// The consumed bytes are split off the buffer, the payload of PUBLISH packet
+/// shares the memory of the buffer.
+#[derive(Debug)]
+pub struct BytesMutPacketIter<'a> {
+    buf: &'a mut BytesMut,
+    failed: bool,
+}
+
+impl Packet {
+    /// Decode successive packets from a slice.
+    pub fn iter_from(buf: &[u8]) -> PacketIter<'_> {
+        PacketIter { buf, failed: false }
+    }
+
+    /// Decode successive packets from a `BytesMut`, the buffer is advanced
+    /// past each decoded packet.
+    pub fn iter_from_bytes_mut(buf: &mut BytesMut) -> BytesMutPacketIter<'_> {
+        BytesMutPacketIter { buf, failed: false }
+    }
+}
+
+impl<'a> PacketIter<'a> {
+    /// The bytes not consumed yet.
+    pub fn remaining(&self) -> &'a [u8] {
+        self.buf
+    }
+}
+
+impl Iterator for PacketIter<'_> {
+    type Item = Result<(Packet, usize), ErrorV5>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.failed {
+            return None;
+        }
+        let (header, header_len) = match decode_frame::<Header>(self.buf).transpose()? {
+            Ok(value) => value,
+            Err(err) => {
+                self.failed = true;
+                return Some(Err(err));
+            }
+        };
+        let total = header_len + header.remaining_len as usize;
+        let result = Packet::decode_body_exact(header, &self.buf[header_len..total]);
+        if result.is_err() {
+            self.failed = true;
+        } else {
+            self.buf = &self.buf[total..];
+        }
+        Some(result.map(|packet| (packet, total)))
+    }
+}
+
+impl Iterator for BytesMutPacketIter<'_> {
+    type Item = Result<(Packet, usize), ErrorV5>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.failed {
+            return None;
+        }
+        let len = self.buf.len();
+        let result = Packet::decode_buf(self.buf).transpose()?;
+        self.failed = result.is_err();
+        Some(result.map(|packet| (packet, len - self.buf.len())))
+    }
+}
@@ -0,0 +1,183 @@
+use serde_json::{json, Value};
+
+use super::{decode_property_list, LastWill, Packet, Property};
+use crate::{json_hex, Encodable};
+
+impl Packet {
+    /// A structured JSON representation for protocol analysis tools and
+    /// snapshot tests, the binary fields are hex encoded.
+    ///
+    /// The layout is not the serde representation of the packet, the `type`
+    /// field is the packet type name such as `"PUBLISH"`, the properties are
+    /// listed in the wire order.
+    pub fn to_json(&self) -> Value {
+        match self {
+            Packet::Connect(connect) => json!({
+                "type": "CONNECT",
+                "protocol": connect.protocol.to_string(),
+                "clean_start": connect.clean_start,
+                "keep_alive": connect.keep_alive,
+                "properties": properties_json(&connect.properties),
+                "client_id": &*connect.client_id,
+                "last_will": connect.last_will.as_ref().map(last_will_json),
+                "username": connect.username.as_deref(),
+                "password": connect.password.as_deref().map(json_hex),
+            }),
+            Packet::Connack(connack) => json!({
+                "type": "CONNACK",
+                "session_present": connack.session_present,
+                "code": format!("{:?}", connack.reason_code),
+                "properties": properties_json(&connack.properties),
+            }),
+            Packet::Publish(publish) => json!({
+                "type": "PUBLISH",
+                "dup": publish.dup,
+                "retain": publish.retain,
+                "qos": publish.qos_pid.qos() as u8,
+                "pid": publish.qos_pid.pid().map(|pid| pid.value()),
+                "topic_name": &*publish.topic_name,
+                "properties": properties_json(&publish.properties),
+                "payload": json_hex(&publish.payload),
+            }),
+            Packet::Puback(ack) => json!({
+                "type": "PUBACK",
+                "pid": ack.pid.value(),
+                "code": format!("{:?}", ack.reason_code),
+                "properties": properties_json(&ack.properties),
+            }),
+            Packet::Pubrec(ack) => json!({
+                "type": "PUBREC",
+                "pid": ack.pid.value(),
+                "code": format!("{:?}", ack.reason_code),
+                "properties": properties_json(&ack.properties),
+            }),
+            Packet::Pubrel(ack) => json!({
+                "type": "PUBREL",
+                "pid": ack.pid.value(),
+                "code": format!("{:?}", ack.reason_code),
+                "properties": properties_json(&ack.properties),
+            }),
+            Packet::Pubcomp(ack) => json!({
+                "type": "PUBCOMP",
+                "pid": ack.pid.value(),
+                "code": format!("{:?}", ack.reason_code),
+                "properties": properties_json(&ack.properties),
+            }),
+            Packet::Subscribe(subscribe) => json!({
+                "type": "SUBSCRIBE",
+                "pid": subscribe.pid.value(),
+                "properties": properties_json(&subscribe.properties),
+                "topics": subscribe
+                    .topics
+                    .iter()
+                    .map(|(filter, options)| json!({
+                        "topic_filter": &**filter,
+                        "qos": options.max_qos as u8,
+                        "no_local": options.no_local,
+                        "retain_as_published": options.retain_as_published,
+                        "retain_handling": format!("{:?}", options.retain_handling),
+                    }))
+                    .collect::<Vec<_>>(),
+            }),
+            Packet::Suback(suback) => json!({
+                "type": "SUBACK",
+                "pid": suback.pid.value(),
+                "properties": properties_json(&suback.properties),
+                "codes": suback
+                    .topics
+                    .iter()
+                    .map(|code| format!("{code:?}"))
+                    .collect::<Vec<_>>(),
+            }),
+            Packet::Unsubscribe(unsubscribe) => json!({
+                "type": "UNSUBSCRIBE",
+                "pid": unsubscribe.pid.value(),
+                "properties": properties_json(&unsubscribe.properties),
+                "topics": unsubscribe
+                    .topics
+                    .iter()
+                    .map(|filter| &**filter)
+                    .collect::<Vec<_>>(),
+            }),
+            Packet::Unsuback(unsuback) => json!({
+                "type": "UNSUBACK",
+                "pid": unsuback.pid.value(),
+                "properties": properties_json(&unsuback.properties),
+                "codes": unsuback
+                    .topics
+                    .iter()
+                    .map(|code| format!("{code:?}"))
+                    .collect::<Vec<_>>(),
+            }),
+            Packet::Pingreq => json!({ "type": "PINGREQ" }),
+            Packet::Pingresp => json!({ "type": "PINGRESP" }),
+            Packet::Disconnect(disconnect) => json!({
+                "type": "DISCONNECT",
+                "code": format!("{:?}", disconnect.reason_code),
+                "properties": properties_json(&disconnect.properties),
+            }),
+            Packet::Auth(auth) => json!({
+                "type": "AUTH",
+                "code": format!("{:?}", auth.reason_code),
+                "properties": properties_json(&auth.properties),
+            }),
+        }
+    }
+}
+
+fn last_will_json(last_will: &LastWill) -> Value {
+    json!({
+        "qos": last_will.qos as u8,
+        "retain": last_will.retain,
+        "topic_name": &*last_will.topic_name,
+        "properties": properties_json(&last_will.properties),
+        "payload": json_hex(&last_will.payload),
+    })
+}
+
+/// The properties are encoded and decoded as a generic property list, so the
+/// property structs of all the packets share one conversion.
+fn properties_json<P: Encodable>(properties: &P) -> Value {
+    let mut data = Vec::with_capacity(properties.encode_len());
+    if properties.encode(&mut data).is_err() {
+        return Value::Null;
+    }
+    match decode_property_list(&data) {
+        Ok(list) => list.iter().map(property_json).collect(),
+        Err(_) => Value::Null,
+    }
+}
+
+fn property_json(property: &Property) -> Value {
+    let value = match property {
+        Property::PayloadFormatIndicator(value)
+        | Property::RequestProblemInformation(value)
+        | Property::RequestResponseInformation(value)
+        | Property::RetainAvailable(value)
+        | Property::WildcardSubscriptionAvailable(value)
+        | Property::SubscriptionIdentifierAvailable(value)
+        | Property::SharedSubscriptionAvailable(value) => json!(value),
+        Property::MessageExpiryInterval(value)
+        | Property::SessionExpiryInterval(value)
+        | Property::WillDelayInterval(value)
+        | Property::MaximumPacketSize(value) => json!(value),
+        Property::ServerKeepAlive(value)
+        | Property::ReceiveMaximum(value)
+        | Property::TopicAliasMaximum(value)
+        | Property::TopicAlias(value) => json!(value),
+        Property::ContentType(value)
+        | Property::AssignedClientIdentifier(value)
+        | Property::AuthenticationMethod(value)
+        | Property::ResponseInformation(value)
+        | Property::ServerReference(value)
+        | Property::ReasonString(value) => json!(&**value),
+        Property::ResponseTopic(value) => json!(&**value),
+        Property::CorrelationData(value) | Property::AuthenticationData(value) => json_hex(value),
+        Property::SubscriptionIdentifier(value) => json!(value.value()),
+        Property::MaximumQoS(qos) => json!(*qos as u8),
+        Property::UserProperty(property) => {
+            json!({ "name": &*property.name, "value": &*property.value })
+        }
+    };
+    json!({ "id": property.id().to_string(), "value": value })
+}
@@ -2,38 +2,75 @@
 //!
 //! [v5.0]: https://docs.oasis-open.org/mqtt/mqtt/v5.0/os/mqtt-v5.0-os.html
 
+pub mod broker;
+pub mod client;
+
+mod auth_flow;
+mod capabilities;
 mod connect;
+mod convert;
 mod error;
+mod expiry;
+mod hexdump;
+mod inflight;
+mod iter;
+#[cfg(feature = "json")]
+mod json;
+mod lazy;
 mod packet;
 mod poll;
+mod property;
 mod publish;
+mod send_quota;
+#[cfg(feature = "proptest")]
+pub mod strategy;
 mod subscribe;
+mod subscription;
+#[cfg(any(test, feature = "testing"))]
+pub mod testing;
+mod topic_alias;
 mod types;
 
 #[cfg(test)]
 mod tests;
 
+pub(crate) use packet::decode_body_async;
 pub(crate) use types::{
-    decode_properties, decode_property, encode_properties, encode_properties_len, encode_property,
-    encode_property_len, PropertyValue,
+    check_property_len, decode_properties, decode_properties_async, decode_property,
+    encode_properties, encode_properties_len, encode_property, encode_property_len, property_field,
+    PropertyValue,
 };
 
+pub use auth_flow::{AuthFlow, AuthFlowError, AuthStep};
 pub use connect::{
-    Auth, AuthProperties, AuthReasonCode, Connack, ConnackProperties, Connect, ConnectProperties,
-    ConnectReasonCode, Disconnect, DisconnectProperties, DisconnectReasonCode, LastWill,
-    WillProperties,
+    Auth, AuthProperties, AuthReasonCode, Connack, ConnackCapabilities, ConnackProperties, Connect,
+    ConnectProperties, ConnectReasonCode, Disconnect, DisconnectProperties, DisconnectReasonCode,
+    LastWill, LastWillBuilder, NegotiatedSettings, WillProperties,
 };
+pub use convert::LossReport;
 pub use error::ErrorV5;
+pub use expiry::ExpiringPublish;
+pub use hexdump::annotated_hexdump;
+pub use inflight::InflightQueue;
+pub use iter::{BytesMutPacketIter, PacketIter};
+pub use lazy::LazyPacket;
 pub use packet::{Header, Packet, PacketType};
-pub use poll::{PollBodyState, PollPacket, PollPacketState};
+pub use poll::{
+    OwnedPacketReader, PacketDecoder, PacketSink, PacketStream, PollBodyState, PollEncode,
+    PollPacket, PollPacketState,
+};
+pub use property::{decode_property_list, encode_property_list, Property};
 pub use publish::{
-    Puback, PubackProperties, PubackReasonCode, Pubcomp, PubcompProperties, PubcompReasonCode,
-    Publish, PublishProperties, Pubrec, PubrecProperties, PubrecReasonCode, Pubrel,
-    PubrelProperties, PubrelReasonCode,
+    AckProperties, Puback, PubackProperties, PubackReasonCode, Pubcomp, PubcompProperties,
+    PubcompReasonCode, Publish, PublishProperties, Pubrec, PubrecProperties, PubrecReasonCode,
+    Pubrel, PubrelProperties, PubrelReasonCode,
 };
+pub use send_quota::SendQuota;
 pub use subscribe::{
     RetainHandling, Suback, SubackProperties, Subscribe, SubscribeProperties, SubscribeReasonCode,
     SubscriptionOptions, Unsuback, UnsubackProperties, Unsubscribe, UnsubscribeProperties,
     UnsubscribeReasonCode,
 };
-pub use types::{PropertyId, UserProperty, VarByteInt};
+pub use subscription::{Subscription, SubscriptionTable};
+pub use topic_alias::TopicAliasManager;
+pub use types::{PropertyId, UserProperties, UserProperty, VarByteInt};
@@ -2,21 +2,26 @@ use std::convert::AsRef;
 use std::fmt;
 use std::io;
 
-use futures_lite::future::block_on;
+use bytes::{Buf, BufMut, Bytes, BytesMut};
 use tokio::io::{AsyncRead, AsyncWrite, AsyncWriteExt};
 
 use super::{
-    Auth, Connack, Connect, Disconnect, ErrorV5, Puback, Pubcomp, Publish, Pubrec, Pubrel, Suback,
-    Subscribe, Unsuback, Unsubscribe,
+    Auth, AuthReasonCode, Connack, Connect, ConnectReasonCode, Disconnect, ErrorV5, PropertyId,
+    PropertyValue, Puback, Pubcomp, Publish, Pubrec, Pubrel, Suback, Subscribe, Unsuback,
+    Unsubscribe, UserProperty, VarByteInt,
 };
 use crate::{
-    decode_raw_header, encode_packet, packet_from, total_len, Encodable, Error, QoS, QosPid,
-    VarBytes,
+    check_body_end, copy_exact, decode_exact, decode_frame, decode_raw_header,
+    decode_raw_header_len, decode_raw_header_slice, encode_packet_to, fmt_list, fmt_publish,
+    packet_from, read_exact_capped, total_len, var_int_len, write_u8, write_var_int, DecodeError,
+    DecodeLimits, Encodable, EncodedPacket, Error, Pid, PollEncodeState, PollHeader, QoS, QosPid,
+    SliceReader, TopicName, VarBytes,
 };
 
 /// MQTT v5.0 packet types.
 #[derive(Debug, Clone, PartialEq, Eq)]
 #[cfg_attr(feature = "arbitrary", derive(arbitrary::Arbitrary))]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum Packet {
     /// [MQTT 3.1](https://docs.oasis-open.org/mqtt/mqtt/v5.0/os/mqtt-v5.0-os.html#_Toc3901033)
     Connect(Connect),
@@ -56,6 +61,11 @@ impl Packet {
     /// This can be used for matching, categorising, debuging, etc. Most users
     /// will match directly on `Packet` instead.
     pub fn get_type(&self) -> PacketType {
+        self.packet_type()
+    }
+
+    /// Return the packet type variant, same as `get_type()`.
+    pub fn packet_type(&self) -> PacketType {
         match self {
             Packet::Pingreq => PacketType::Pingreq,
             Packet::Pingresp => PacketType::Pingresp,
@@ -75,26 +85,287 @@ impl Packet {
         }
     }
 
+    /// Return the packet identifier, if the packet has one (QoS 0 PUBLISH
+    /// has no packet identifier).
+    pub fn pid(&self) -> Option<Pid> {
+        match self {
+            Packet::Publish(publish) => publish.qos_pid.pid(),
+            Packet::Puback(inner) => Some(inner.pid),
+            Packet::Pubrec(inner) => Some(inner.pid),
+            Packet::Pubrel(inner) => Some(inner.pid),
+            Packet::Pubcomp(inner) => Some(inner.pid),
+            Packet::Subscribe(inner) => Some(inner.pid),
+            Packet::Suback(inner) => Some(inner.pid),
+            Packet::Unsubscribe(inner) => Some(inner.pid),
+            Packet::Unsuback(inner) => Some(inner.pid),
+            _ => None,
+        }
+    }
+
+    /// Return the topic name of a PUBLISH packet.
+    pub fn topic_name(&self) -> Option<&TopicName> {
+        match self {
+            Packet::Publish(publish) => Some(&publish.topic_name),
+            _ => None,
+        }
+    }
+
+    /// Return the QoS level of a PUBLISH packet.
+    pub fn qos(&self) -> Option<QoS> {
+        match self {
+            Packet::Publish(publish) => Some(publish.qos_pid.qos()),
+            _ => None,
+        }
+    }
+
     /// Asynchronously decode a packet from an async reader.
     pub async fn decode_async<T: AsyncRead + Unpin>(reader: &mut T) -> Result<Self, ErrorV5> {
         let header = Header::decode_async(reader).await?;
-        Ok(match header.typ {
-            PacketType::Pingreq => Packet::Pingreq,
-            PacketType::Pingresp => Packet::Pingresp,
-            PacketType::Connect => Connect::decode_async(reader, header).await?.into(),
-            PacketType::Connack => Connack::decode_async(reader, header).await?.into(),
-            PacketType::Publish => Publish::decode_async(reader, header).await?.into(),
-            PacketType::Puback => Puback::decode_async(reader, header).await?.into(),
-            PacketType::Pubrec => Pubrec::decode_async(reader, header).await?.into(),
-            PacketType::Pubrel => Pubrel::decode_async(reader, header).await?.into(),
-            PacketType::Pubcomp => Pubcomp::decode_async(reader, header).await?.into(),
-            PacketType::Subscribe => Subscribe::decode_async(reader, header).await?.into(),
-            PacketType::Suback => Suback::decode_async(reader, header).await?.into(),
-            PacketType::Unsubscribe => Unsubscribe::decode_async(reader, header).await?.into(),
-            PacketType::Unsuback => Unsuback::decode_async(reader, header).await?.into(),
-            PacketType::Disconnect => Disconnect::decode_async(reader, header).await?.into(),
-            PacketType::Auth => Auth::decode_async(reader, header).await?.into(),
-        })
+        Self::decode_body_async(reader, header).await
+    }
+
+    /// Asynchronously decode a packet from an async reader, the packet must
+    /// not exceed the limits.
+    pub async fn decode_async_with_limits<T: AsyncRead + Unpin>(
+        reader: &mut T,
+        limits: &DecodeLimits,
+    ) -> Result<Self, ErrorV5> {
+        let (typ, remaining_len, var_int_bytes) = decode_raw_header_len(reader).await?;
+        let header = Header::new_with(typ, remaining_len)?;
+        limits.check_remaining_len(remaining_len, var_int_bytes)?;
+        let packet = Self::decode_body_async(reader, header).await?;
+        packet.check_limits(limits)?;
+        Ok(packet)
+    }
+
+    /// Asynchronously decode a packet from an async reader, the payload of
+    /// PUBLISH packet is written to `writer` in chunks instead of buffered.
+    pub async fn decode_async_streaming<T, W>(
+        reader: &mut T,
+        writer: &mut W,
+    ) -> Result<Self, ErrorV5>
+    where
+        T: AsyncRead + Unpin,
+        W: AsyncWrite + Unpin,
+    {
+        let header = Header::decode_async(reader).await?;
+        if header.typ == PacketType::Publish {
+            let mut body = tokio::io::AsyncReadExt::take(reader, u64::from(header.remaining_len));
+            let result = Publish::decode_async_streaming(&mut body, header, writer).await;
+            check_body_end(body.limit(), result, ErrorV5::is_eof).map(Into::into)
+        } else {
+            Self::decode_body_async(reader, header).await
+        }
+    }
+
+    /// Read the whole body and decode it, the EOF error is only returned when
+    /// the reader ends inside the body.
+    async fn decode_body_async<T: AsyncRead + Unpin>(
+        reader: &mut T,
+        header: Header,
+    ) -> Result<Self, ErrorV5> {
+        let body = read_exact_capped(reader, header.remaining_len as usize).await?;
+        Self::decode_body_exact(header, &body)
+    }
+
+    /// Check the decoded packet against the limits, the remaining length is
+    /// not checked here.
+    pub fn check_limits(&self, limits: &DecodeLimits) -> Result<(), ErrorV5> {
+        fn check_properties<P: Encodable>(
+            limits: &DecodeLimits,
+            properties: &P,
+            user_properties: &[UserProperty],
+        ) -> Result<(), Error> {
+            limits.check_properties(properties.encode_len(), user_properties.len())?;
+            for property in user_properties {
+                limits.check_str(&property.name)?;
+                limits.check_str(&property.value)?;
+            }
+            Ok(())
+        }
+
+        match self {
+            Packet::Pingreq | Packet::Pingresp => {}
+            Packet::Connect(inner) => {
+                limits.check_str(&inner.client_id)?;
+                if let Some(username) = inner.username.as_ref() {
+                    limits.check_str(username)?;
+                }
+                if let Some(last_will) = inner.last_will.as_ref() {
+                    limits.check_str(&last_will.topic_name)?;
+                    let properties = &last_will.properties;
+                    check_properties(limits, properties, &properties.user_properties)?;
+                }
+                check_properties(limits, &inner.properties, &inner.properties.user_properties)?;
+            }
+            Packet::Connack(inner) => {
+                check_properties(limits, &inner.properties, &inner.properties.user_properties)?
+            }
+            Packet::Publish(inner) => {
+                limits.check_str(&inner.topic_name)?;
+                if let (Some(max), Some(alias)) =
+                    (limits.topic_alias_max, inner.properties.topic_alias)
+                {
+                    if alias == 0 || alias > max {
+                        return Err(ErrorV5::InvalidTopicAlias(alias));
+                    }
+                }
+                check_properties(limits, &inner.properties, &inner.properties.user_properties)?;
+            }
+            Packet::Puback(inner) => {
+                check_properties(limits, &inner.properties, &inner.properties.user_properties)?
+            }
+            Packet::Pubrec(inner) => {
+                check_properties(limits, &inner.properties, &inner.properties.user_properties)?
+            }
+            Packet::Pubrel(inner) => {
+                check_properties(limits, &inner.properties, &inner.properties.user_properties)?
+            }
+            Packet::Pubcomp(inner) => {
+                check_properties(limits, &inner.properties, &inner.properties.user_properties)?
+            }
+            Packet::Subscribe(inner) => {
+                limits.check_topics(inner.topics.len())?;
+                for (filter, _) in &inner.topics {
+                    limits.check_str(filter)?;
+                }
+                check_properties(limits, &inner.properties, &inner.properties.user_properties)?;
+            }
+            Packet::Suback(inner) => {
+                check_properties(limits, &inner.properties, &inner.properties.user_properties)?
+            }
+            Packet::Unsubscribe(inner) => {
+                limits.check_topics(inner.topics.len())?;
+                for filter in &inner.topics {
+                    limits.check_str(filter)?;
+                }
+                check_properties(limits, &inner.properties, &inner.properties.user_properties)?;
+            }
+            Packet::Unsuback(inner) => {
+                check_properties(limits, &inner.properties, &inner.properties.user_properties)?
+            }
+            Packet::Disconnect(inner) => {
+                check_properties(limits, &inner.properties, &inner.properties.user_properties)?
+            }
+            Packet::Auth(inner) => {
+                check_properties(limits, &inner.properties, &inner.properties.user_properties)?
+            }
+        }
+        Ok(())
+    }
+
+    /// Check the rules which are not enforced by the types, useful to catch
+    /// a hand-built packet which would be rejected by the peer before it is
+    /// encoded. Decoded packets already pass most of the checks.
+    ///
+    /// The checks depending on the peer (e.g. Retain Available, Topic Alias
+    /// Maximum) are left to the caller.
+    pub fn validate(&self) -> Result<(), ErrorV5> {
+        fn check_utf8(is_utf8: Option<bool>, payload: &[u8]) -> Result<(), ErrorV5> {
+            if is_utf8 == Some(true) && simdutf8::basic::from_utf8(payload).is_err() {
+                return Err(ErrorV5::InvalidPayloadFormat);
+            }
+            Ok(())
+        }
+        fn check_nonzero(
+            receive_max: Option<u16>,
+            max_packet_size: Option<u32>,
+        ) -> Result<(), ErrorV5> {
+            if let Some(value) = receive_max {
+                PropertyValue::check_nonzero(PropertyId::ReceiveMaximum, value)?;
+            }
+            if let Some(value) = max_packet_size {
+                PropertyValue::check_nonzero(PropertyId::MaximumPacketSize, value)?;
+            }
+            Ok(())
+        }
+        // [MQTT-3.8.2.1.2]
+        fn check_subscription_id(subscription_id: Option<VarByteInt>) -> Result<(), ErrorV5> {
+            if let Some(value) = subscription_id {
+                PropertyValue::check_subscription_id(value.value())?;
+            }
+            Ok(())
+        }
+
+        match self {
+            Packet::Connect(inner) => {
+                let properties = &inner.properties;
+                check_nonzero(properties.receive_max, properties.max_packet_size)?;
+                if properties.auth_data.is_some() && properties.auth_method.is_none() {
+                    return Err(Error::InvalidPacket("auth data without auth method").into());
+                }
+                if let Some(last_will) = inner.last_will.as_ref() {
+                    // [MQTT-4.7.3-1]
+                    if last_will.topic_name.is_empty() {
+                        return Err(Error::InvalidPacket("empty will topic").into());
+                    }
+                    check_utf8(last_will.properties.payload_is_utf8, &last_will.payload)?;
+                }
+            }
+            Packet::Connack(inner) => {
+                check_nonzero(
+                    inner.properties.receive_max,
+                    inner.properties.max_packet_size,
+                )?;
+                // [MQTT-3.2.2-6]
+                if inner.session_present && inner.reason_code != ConnectReasonCode::Success {
+                    return Err(
+                        Error::InvalidPacket("session present with error reason code").into(),
+                    );
+                }
+            }
+            Packet::Publish(inner) => {
+                // [MQTT-3.3.1-2]
+                if inner.dup && inner.qos_pid == QosPid::Level0 {
+                    return Err(Error::InvalidPacket("dup flag with QoS 0").into());
+                }
+                match inner.properties.topic_alias {
+                    // [MQTT-3.3.2-8]
+                    Some(0) => return Err(ErrorV5::InvalidTopicAlias(0)),
+                    Some(_) => {}
+                    None if inner.topic_name.is_empty() => {
+                        return Err(Error::InvalidPacket("empty topic name").into());
+                    }
+                    None => {}
+                }
+                check_utf8(inner.properties.payload_is_utf8, &inner.payload)?;
+                check_subscription_id(inner.properties.subscription_id)?;
+            }
+            Packet::Subscribe(inner) => {
+                check_subscription_id(inner.properties.subscription_id)?;
+                // [MQTT-3.8.3-3]
+                if inner.topics.is_empty() {
+                    return Err(Error::EmptySubscription.into());
+                }
+                // [MQTT-3.8.3-4]
+                if inner
+                    .topics
+                    .iter()
+                    .any(|(filter, options)| filter.is_shared() && options.no_local)
+                {
+                    return Err(Error::InvalidPacket("no local on shared subscription").into());
+                }
+            }
+            Packet::Suback(inner) if inner.topics.is_empty() => {
+                return Err(Error::InvalidPacket("empty suback").into());
+            }
+            // [MQTT-3.10.3-2]
+            Packet::Unsubscribe(inner) if inner.topics.is_empty() => {
+                return Err(Error::EmptySubscription.into());
+            }
+            Packet::Unsuback(inner) if inner.topics.is_empty() => {
+                return Err(Error::InvalidPacket("empty unsuback").into());
+            }
+            // [MQTT-3.15.2-1]
+            Packet::Auth(inner)
+                if inner.reason_code != AuthReasonCode::Success
+                    && inner.properties.auth_method.is_none() =>
+            {
+                return Err(Error::InvalidPacket("auth without auth method").into());
+            }
+            _ => {}
+        }
+        Ok(())
     }
 
     /// Asynchronously encode the packet to an async writer.
@@ -107,98 +378,354 @@ impl Packet {
         Ok(())
     }
 
+    /// Decode a packet from an `embedded-io-async` reader, the tokio based
+    /// methods are still available for other connections.
+    #[cfg(feature = "embedded-io")]
+    pub async fn decode_embedded<T: embedded_io_async::Read>(
+        reader: &mut T,
+    ) -> Result<Self, ErrorV5> {
+        crate::read_packet_embedded::<_, Header>(reader).await
+    }
+
+    /// Encode the packet to an `embedded-io-async` writer.
+    #[cfg(feature = "embedded-io")]
+    pub async fn encode_embedded<T: embedded_io_async::Write>(
+        &self,
+        writer: &mut T,
+    ) -> Result<(), ErrorV5> {
+        let data = self.encode()?;
+        crate::write_all_embedded(writer, data.as_ref()).await?;
+        Ok(())
+    }
+
     /// Decode a packet from some bytes. If not enough bytes to decode a packet,
     /// it will return `Ok(None)`.
-    pub fn decode(mut bytes: &[u8]) -> Result<Option<Self>, ErrorV5> {
-        match block_on(Self::decode_async(&mut bytes)) {
-            Ok(pkt) => Ok(Some(pkt)),
-            Err(ErrorV5::Common(Error::IoError(kind, info))) => {
-                if kind == io::ErrorKind::UnexpectedEof {
-                    Ok(None)
+    pub fn decode(bytes: &[u8]) -> Result<Option<Self>, ErrorV5> {
+        Self::decode_slice(bytes, None).map_err(|err| err.error)
+    }
+
+    /// Same as `decode()`, but the error contains the byte offset (from the
+    /// start of `bytes`) where the decoding failed.
+    pub fn decode_with_offset(bytes: &[u8]) -> Result<Option<Self>, DecodeError<ErrorV5>> {
+        Self::decode_slice(bytes, None)
+    }
+
+    /// Decode a packet from some bytes, the packet must not exceed the limits.
+    /// If not enough bytes to decode a packet, it will return `Ok(None)`.
+    pub fn decode_with_limits(
+        bytes: &[u8],
+        limits: &DecodeLimits,
+    ) -> Result<Option<Self>, ErrorV5> {
+        Self::decode_slice(bytes, Some(limits)).map_err(|err| err.error)
+    }
+
+    fn decode_slice(
+        bytes: &[u8],
+        limits: Option<&DecodeLimits>,
+    ) -> Result<Option<Self>, DecodeError<ErrorV5>> {
+        let error = |offset: usize, error: ErrorV5| DecodeError { offset, error };
+        let (typ, remaining_len, header_len) = match decode_raw_header_slice(bytes) {
+            Ok(Some(header)) => header,
+            Ok(None) => return Ok(None),
+            // The 4 bytes of the variable byte integer are consumed
+            Err(err) => return Err(error(5, err.into())),
+        };
+        let header = Header::new_with(typ, remaining_len).map_err(|err| error(header_len, err))?;
+        if let Some(limits) = limits {
+            limits
+                .check_remaining_len(remaining_len, header_len - 1)
+                .map_err(|err| error(header_len, err.into()))?;
+        }
+        let total = header_len + header.remaining_len as usize;
+        if bytes.len() < total {
+            return Ok(None);
+        }
+        let mut reader = SliceReader(&bytes[header_len..total]);
+        let packet = Self::decode_fields(&mut reader, header)
+            .and_then(|packet| {
+                if reader.0.is_empty() {
+                    Ok(packet)
                 } else {
-                    Err(Error::IoError(kind, info).into())
+                    Err(Error::InvalidRemainingLength.into())
                 }
-            }
-            Err(err) => Err(err),
+            })
+            .map_err(|err| error(total - reader.0.len(), err))?;
+        if let Some(limits) = limits {
+            packet
+                .check_limits(limits)
+                .map_err(|err| error(total, err))?;
+        }
+        Ok(Some(packet))
+    }
+
+    /// Decode a packet from a shared bytes buffer. The payload of PUBLISH
+    /// packet is a slice of the buffer instead of a fresh allocation, the
+    /// strings (topic name, client identifier, etc.) are still copied.
+    ///
+    /// If not enough bytes to decode a packet, it will return `Ok(None)` and
+    /// the buffer is untouched, otherwise the buffer is advanced past the
+    /// decoded packet.
+    pub fn decode_shared(buf: &mut Bytes) -> Result<Option<Self>, ErrorV5> {
+        match Self::decode_header_only(buf)? {
+            Some((header, body)) => Self::decode_body(header, body).map(Some),
+            None => Ok(None),
         }
     }
 
+    /// Decode a packet from a read buffer. The payload of PUBLISH packet
+    /// shares the memory of the buffer.
+    ///
+    /// If not enough bytes to decode a packet, it will return `Ok(None)` and
+    /// the buffer is untouched, so more data can be appended to it. Otherwise
+    /// the buffer is advanced past the packet (also when the body is
+    /// invalid).
+    pub fn decode_buf(buf: &mut BytesMut) -> Result<Option<Self>, ErrorV5> {
+        let Some((header, header_len)) = decode_frame::<Header>(buf)? else {
+            return Ok(None);
+        };
+        let total = header_len + header.remaining_len as usize;
+        let mut body = buf.split_to(total).freeze();
+        body.advance(header_len);
+        Self::decode_body(header, body).map(Some)
+    }
+
+    /// Decode the fixed header from a shared bytes buffer and return it with
+    /// the unparsed body (a slice of the buffer), so the packet can be routed
+    /// or dropped by its type or size before decoding the body by
+    /// `decode_body()`.
+    ///
+    /// If not enough bytes to decode a packet, it will return `Ok(None)` and
+    /// the buffer is untouched, otherwise the buffer is advanced past the
+    /// packet.
+    pub fn decode_header_only(buf: &mut Bytes) -> Result<Option<(Header, Bytes)>, ErrorV5> {
+        let Some((header, header_len)) = decode_frame::<Header>(buf)? else {
+            return Ok(None);
+        };
+        let total = header_len + header.remaining_len as usize;
+        let body = buf.slice(header_len..total);
+        buf.advance(total);
+        Ok(Some((header, body)))
+    }
+
+    /// Asynchronously decode the fixed header and read the unparsed body,
+    /// see `decode_header_only()`.
+    pub async fn decode_header_only_async<T: AsyncRead + Unpin>(
+        reader: &mut T,
+    ) -> Result<(Header, Bytes), ErrorV5> {
+        let header = Header::decode_async(reader).await?;
+        let mut body = Vec::new();
+        copy_exact(reader, &mut body, header.remaining_len as usize).await?;
+        Ok((header, body.into()))
+    }
+
+    /// Decode the packet body returned by `decode_header_only()`, the body
+    /// must be consumed exactly.
+    pub fn decode_body(header: Header, body: Bytes) -> Result<Self, ErrorV5> {
+        if body.len() != header.remaining_len as usize {
+            return Err(Error::InvalidRemainingLength.into());
+        }
+        if header.typ == PacketType::Publish {
+            Ok(Publish::decode_shared(body, header)?.into())
+        } else {
+            Self::decode_body_exact(header, &body)
+        }
+    }
+
+    /// Decode a fully received packet body, the payload of PUBLISH packet is
+    /// copied.
+    pub(crate) fn decode_body_exact(header: Header, body: &[u8]) -> Result<Self, ErrorV5> {
+        decode_exact(body, |reader| Self::decode_fields(reader, header))
+    }
+
+    /// Decode the body fields by the slice decoder of the packet type.
+    pub(crate) fn decode_fields(
+        reader: &mut SliceReader<'_>,
+        header: Header,
+    ) -> Result<Self, ErrorV5> {
+        if let Some(packet) = header.build_empty_packet() {
+            return Ok(packet);
+        } else if header.remaining_len == 0 {
+            return Err(Error::InvalidRemainingLength.into());
+        }
+        Ok(match header.typ {
+            PacketType::Connect => Connect::decode_slice(reader, header)?.into(),
+            PacketType::Connack => Connack::decode_slice(reader, header)?.into(),
+            PacketType::Publish => Publish::decode_slice(reader, header)?.into(),
+            PacketType::Puback => Puback::decode_slice(reader, header)?.into(),
+            PacketType::Pubrec => Pubrec::decode_slice(reader, header)?.into(),
+            PacketType::Pubrel => Pubrel::decode_slice(reader, header)?.into(),
+            PacketType::Pubcomp => Pubcomp::decode_slice(reader, header)?.into(),
+            PacketType::Subscribe => Subscribe::decode_slice(reader, header)?.into(),
+            PacketType::Suback => Suback::decode_slice(reader, header)?.into(),
+            PacketType::Unsubscribe => Unsubscribe::decode_slice(reader, header)?.into(),
+            PacketType::Unsuback => Unsuback::decode_slice(reader, header)?.into(),
+            PacketType::Disconnect => Disconnect::decode_slice(reader, header)?.into(),
+            PacketType::Auth => Auth::decode_slice(reader, header)?.into(),
+            // Only reached when the body is not empty
+            PacketType::Pingreq | PacketType::Pingresp => {
+                return Err(Error::InvalidRemainingLength.into())
+            }
+        })
+    }
+
     /// Encode the packet to a dynamic vector or fixed array.
     pub fn encode(&self) -> Result<VarBytes, Error> {
         const VOID_PACKET_REMAINING_LEN: u8 = 0;
         let data = match self {
             Packet::Pingreq => {
                 const CONTROL_BYTE: u8 = 0b11000000;
-                return Ok(VarBytes::Fixed2([CONTROL_BYTE, VOID_PACKET_REMAINING_LEN]));
+                VarBytes::Fixed2([CONTROL_BYTE, VOID_PACKET_REMAINING_LEN])
             }
             Packet::Pingresp => {
                 const CONTROL_BYTE: u8 = 0b11010000;
-                return Ok(VarBytes::Fixed2([CONTROL_BYTE, VOID_PACKET_REMAINING_LEN]));
+                VarBytes::Fixed2([CONTROL_BYTE, VOID_PACKET_REMAINING_LEN])
+            }
+            _ => {
+                // The length error (if any) is returned by encode_to()
+                let mut data = Vec::with_capacity(self.encode_len().unwrap_or_default());
+                self.encode_to(&mut data)?;
+                VarBytes::Dynamic(data)
+            }
+        };
+        Ok(data)
+    }
+
+    /// Encode the packet into a writer.
+    pub fn encode_to<W: io::Write>(&self, writer: &mut W) -> Result<(), Error> {
+        match self {
+            Packet::Pingreq | Packet::Pingresp => {
+                writer.write_all(self.encode()?.as_ref())?;
+                Ok(())
             }
             Packet::Publish(publish) => {
-                let mut control_byte: u8 = match publish.qos_pid {
-                    QosPid::Level0 => 0b00110000,
-                    QosPid::Level1(_) => 0b00110010,
-                    QosPid::Level2(_) => 0b00110100,
-                };
-                if publish.dup {
-                    control_byte |= 0b00001000;
-                }
-                if publish.retain {
-                    control_byte |= 0b00000001;
-                }
-                encode_packet(control_byte, publish)?
+                encode_packet_to(writer, publish_control_byte(publish), publish)
             }
             Packet::Connect(inner) => {
                 const CONTROL_BYTE: u8 = 0b00010000;
-                encode_packet(CONTROL_BYTE, inner)?
+                encode_packet_to(writer, CONTROL_BYTE, inner)
             }
             Packet::Connack(inner) => {
                 const CONTROL_BYTE: u8 = 0b00100000;
-                encode_packet(CONTROL_BYTE, inner)?
+                encode_packet_to(writer, CONTROL_BYTE, inner)
             }
             Packet::Puback(inner) => {
                 const CONTROL_BYTE: u8 = 0b01000000;
-                encode_packet(CONTROL_BYTE, inner)?
+                encode_packet_to(writer, CONTROL_BYTE, inner)
             }
             Packet::Pubrec(inner) => {
                 const CONTROL_BYTE: u8 = 0b01010000;
-                encode_packet(CONTROL_BYTE, inner)?
+                encode_packet_to(writer, CONTROL_BYTE, inner)
             }
             Packet::Pubrel(inner) => {
                 const CONTROL_BYTE: u8 = 0b01100010;
-                encode_packet(CONTROL_BYTE, inner)?
+                encode_packet_to(writer, CONTROL_BYTE, inner)
             }
             Packet::Pubcomp(inner) => {
                 const CONTROL_BYTE: u8 = 0b01110000;
-                encode_packet(CONTROL_BYTE, inner)?
+                encode_packet_to(writer, CONTROL_BYTE, inner)
             }
             Packet::Subscribe(inner) => {
                 const CONTROL_BYTE: u8 = 0b10000010;
-                encode_packet(CONTROL_BYTE, inner)?
+                encode_packet_to(writer, CONTROL_BYTE, inner)
             }
             Packet::Suback(inner) => {
                 const CONTROL_BYTE: u8 = 0b10010000;
-                encode_packet(CONTROL_BYTE, inner)?
+                encode_packet_to(writer, CONTROL_BYTE, inner)
             }
             Packet::Unsubscribe(inner) => {
                 const CONTROL_BYTE: u8 = 0b10100010;
-                encode_packet(CONTROL_BYTE, inner)?
+                encode_packet_to(writer, CONTROL_BYTE, inner)
             }
             Packet::Unsuback(inner) => {
                 const CONTROL_BYTE: u8 = 0b10110000;
-                encode_packet(CONTROL_BYTE, inner)?
+                encode_packet_to(writer, CONTROL_BYTE, inner)
             }
             Packet::Disconnect(inner) => {
                 const CONTROL_BYTE: u8 = 0b11100000;
-                encode_packet(CONTROL_BYTE, inner)?
+                encode_packet_to(writer, CONTROL_BYTE, inner)
             }
             Packet::Auth(inner) => {
                 const CONTROL_BYTE: u8 = 0b11110000;
-                encode_packet(CONTROL_BYTE, inner)?
+                encode_packet_to(writer, CONTROL_BYTE, inner)
             }
-        };
-        Ok(VarBytes::Dynamic(data))
+        }
+    }
+
+    /// Encode the packet into a caller-provided buffer, return the number of
+    /// bytes written. If the buffer is too small nothing is written and
+    /// `Error::IoError(WriteZero, _)` is returned, use `encode_len()` to get
+    /// the required size.
+    pub fn encode_into(&self, buf: &mut [u8]) -> Result<usize, ErrorV5> {
+        let total = self.encode_len()?;
+        if buf.len() < total {
+            return Err(Error::IoError(
+                io::ErrorKind::WriteZero,
+                format!("buffer too small: {} < {}", buf.len(), total),
+            )
+            .into());
+        }
+        self.encode_to(&mut &mut buf[..total])?;
+        Ok(total)
+    }
+
+    /// Encode the packet into a `BufMut`, return the number of bytes written.
+    /// Same as `encode_into()`, nothing is written if the remaining capacity
+    /// is not enough.
+    pub fn encode_to_buf<B: BufMut>(&self, buf: &mut B) -> Result<usize, ErrorV5> {
+        let total = self.encode_len()?;
+        if buf.remaining_mut() < total {
+            return Err(Error::IoError(
+                io::ErrorKind::WriteZero,
+                format!("buffer too small: {} < {}", buf.remaining_mut(), total),
+            )
+            .into());
+        }
+        self.encode_to(&mut buf.writer())?;
+        Ok(total)
+    }
+
+    /// Encode the packet except the PUBLISH payload, return the encoded bytes
+    /// and the payload. The payload is not copied, so the two parts can be
+    /// written with `write_vectored`. The payload is empty for other packets.
+    pub fn encode_vectored(&self) -> Result<(VarBytes, Bytes), Error> {
+        match self {
+            Packet::Publish(publish) => {
+                let remaining_len = publish.encode_len();
+                let total = total_len(remaining_len)?;
+                let mut data = Vec::with_capacity(total - publish.payload.len());
+                data.push(publish_control_byte(publish));
+                write_var_int(&mut data, remaining_len)?;
+                publish.encode_without_payload(&mut data)?;
+                Ok((VarBytes::Dynamic(data), publish.payload.clone()))
+            }
+            _ => Ok((self.encode()?, Bytes::new())),
+        }
+    }
+
+    /// Encode the packet into a state for writing it incrementally, see
+    /// `PollEncode`.
+    pub fn encode_poll_state(&self) -> Result<PollEncodeState, Error> {
+        let (data, payload) = self.encode_vectored()?;
+        Ok(PollEncodeState::new(data, payload))
+    }
+
+    /// Encode the packet once for writing it to many connections, see
+    /// `EncodedPacket`.
+    pub fn encode_shared(&self) -> Result<EncodedPacket, Error> {
+        let (data, payload) = self.encode_vectored()?;
+        Ok(EncodedPacket::new(data, payload))
+    }
+
+    /// Same as `encode_async()`, but the PUBLISH payload is not copied into
+    /// the encoded buffer (see `encode_vectored()`).
+    pub async fn encode_vectored_async<T: AsyncWrite + Unpin>(
+        &self,
+        writer: &mut T,
+    ) -> Result<(), ErrorV5> {
+        let (data, payload) = self.encode_vectored()?;
+        let mut buf = data.as_ref().chain(payload);
+        writer.write_all_buf(&mut buf).await?;
+        Ok(())
     }
 
     /// Return the total length of bytes the packet encoded into.
@@ -224,8 +751,128 @@ impl Packet {
     }
 }
 
+/// Single line summary for logging, the password and the authentication
+/// data are elided.
+impl fmt::Display for Packet {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Packet::Connect(connect) => {
+                write!(
+                    f,
+                    "CONNECT protocol={} client_id={} keep_alive={}",
+                    connect.protocol, connect.client_id, connect.keep_alive
+                )?;
+                if connect.clean_start {
+                    write!(f, " clean_start")?;
+                }
+                if let Some(last_will) = &connect.last_will {
+                    write!(
+                        f,
+                        " will={} will_qos={}",
+                        last_will.topic_name, last_will.qos as u8
+                    )?;
+                }
+                if let Some(username) = &connect.username {
+                    write!(f, " username={username}")?;
+                }
+                if connect.password.is_some() {
+                    write!(f, " password=***")?;
+                }
+                if let Some(method) = &connect.properties.auth_method {
+                    write!(f, " auth_method={method}")?;
+                }
+                if connect.properties.auth_data.is_some() {
+                    write!(f, " auth_data=***")?;
+                }
+                Ok(())
+            }
+            Packet::Connack(connack) => {
+                write!(f, "CONNACK code={:?}", connack.reason_code)?;
+                if connack.session_present {
+                    write!(f, " session_present")?;
+                }
+                Ok(())
+            }
+            Packet::Publish(publish) => fmt_publish(
+                f,
+                publish.qos_pid,
+                &publish.topic_name,
+                publish.payload.len(),
+                publish.retain,
+                publish.dup,
+            ),
+            Packet::Puback(ack) => {
+                write!(
+                    f,
+                    "PUBACK pid={} code={:?}",
+                    ack.pid.value(),
+                    ack.reason_code
+                )
+            }
+            Packet::Pubrec(ack) => {
+                write!(
+                    f,
+                    "PUBREC pid={} code={:?}",
+                    ack.pid.value(),
+                    ack.reason_code
+                )
+            }
+            Packet::Pubrel(ack) => {
+                write!(
+                    f,
+                    "PUBREL pid={} code={:?}",
+                    ack.pid.value(),
+                    ack.reason_code
+                )
+            }
+            Packet::Pubcomp(ack) => {
+                write!(
+                    f,
+                    "PUBCOMP pid={} code={:?}",
+                    ack.pid.value(),
+                    ack.reason_code
+                )
+            }
+            Packet::Subscribe(subscribe) => {
+                write!(f, "SUBSCRIBE pid={} topics=", subscribe.pid.value())?;
+                fmt_list(f, &subscribe.topics, |f, (filter, options)| {
+                    write!(f, "{filter}:{}", options.max_qos as u8)
+                })
+            }
+            Packet::Suback(suback) => {
+                write!(f, "SUBACK pid={} codes=", suback.pid.value())?;
+                fmt_list(f, &suback.topics, |f, code| write!(f, "{code:?}"))
+            }
+            Packet::Unsubscribe(unsubscribe) => {
+                write!(f, "UNSUBSCRIBE pid={} topics=", unsubscribe.pid.value())?;
+                fmt_list(f, &unsubscribe.topics, |f, filter| write!(f, "{filter}"))
+            }
+            Packet::Unsuback(unsuback) => {
+                write!(f, "UNSUBACK pid={} codes=", unsuback.pid.value())?;
+                fmt_list(f, &unsuback.topics, |f, code| write!(f, "{code:?}"))
+            }
+            Packet::Pingreq => write!(f, "PINGREQ"),
+            Packet::Pingresp => write!(f, "PINGRESP"),
+            Packet::Disconnect(disconnect) => {
+                write!(f, "DISCONNECT code={:?}", disconnect.reason_code)
+            }
+            Packet::Auth(auth) => {
+                write!(f, "AUTH code={:?}", auth.reason_code)?;
+                if let Some(method) = &auth.properties.auth_method {
+                    write!(f, " auth_method={method}")?;
+                }
+                if auth.properties.auth_data.is_some() {
+                    write!(f, " auth_data=***")?;
+                }
+                Ok(())
+            }
+        }
+    }
+}
+
 /// MQTT v5.0 packet type variant, without the associated data.
 #[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum PacketType {
     Connect,
     Connack,
@@ -244,6 +891,55 @@ pub enum PacketType {
     Auth,
 }
 
+impl PacketType {
+    /// The packet can be sent by a client.
+    pub fn is_client_to_server(&self) -> bool {
+        !matches!(
+            self,
+            PacketType::Connack | PacketType::Suback | PacketType::Unsuback | PacketType::Pingresp
+        )
+    }
+
+    /// The packet can be sent by a server, DISCONNECT and AUTH are sent in
+    /// both directions.
+    pub fn is_server_to_client(&self) -> bool {
+        !matches!(
+            self,
+            PacketType::Connect
+                | PacketType::Subscribe
+                | PacketType::Unsubscribe
+                | PacketType::Pingreq
+        )
+    }
+
+    /// The packet identifier is required in the variable header, `qos` is
+    /// the QoS of a PUBLISH packet (ignored for the other packet types).
+    pub fn requires_pid(&self, qos: QoS) -> bool {
+        match self {
+            PacketType::Publish => qos != QoS::Level0,
+            PacketType::Puback
+            | PacketType::Pubrec
+            | PacketType::Pubrel
+            | PacketType::Pubcomp
+            | PacketType::Subscribe
+            | PacketType::Suback
+            | PacketType::Unsubscribe
+            | PacketType::Unsuback => true,
+            _ => false,
+        }
+    }
+
+    /// The flags (lower 4 bits) of the first byte of the fixed header, or
+    /// `None` for PUBLISH whose flags are DUP, QoS and RETAIN.
+    pub fn expected_flags(&self) -> Option<u8> {
+        match self {
+            PacketType::Publish => None,
+            PacketType::Pubrel | PacketType::Subscribe | PacketType::Unsubscribe => Some(0b0010),
+            _ => Some(0),
+        }
+    }
+}
+
 impl fmt::Display for PacketType {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         write!(f, "{self:?}")
@@ -252,6 +948,7 @@ impl fmt::Display for PacketType {
 
 /// Fixed header type.
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct Header {
     pub typ: PacketType,
     pub dup: bool,
@@ -311,14 +1008,67 @@ impl Header {
         })
     }
 
-    pub fn decode(mut reader: &[u8]) -> Result<Self, ErrorV5> {
-        block_on(Self::decode_async(&mut reader))
+    pub fn decode(reader: &[u8]) -> Result<Self, ErrorV5> {
+        match decode_raw_header_slice(reader)? {
+            Some((typ, remaining_len, _)) => Header::new_with(typ, remaining_len),
+            None => Err(Error::from(io::Error::from(io::ErrorKind::UnexpectedEof)).into()),
+        }
     }
 
     pub async fn decode_async<T: AsyncRead + Unpin>(reader: &mut T) -> Result<Self, ErrorV5> {
         let (typ, remaining_len) = decode_raw_header(reader).await?;
         Header::new_with(typ, remaining_len)
     }
+
+    /// The first byte of the fixed header (packet type and flags).
+    pub(crate) fn control_byte(&self) -> u8 {
+        let typ: u8 = match self.typ {
+            PacketType::Connect => 1,
+            PacketType::Connack => 2,
+            PacketType::Publish => 3,
+            PacketType::Puback => 4,
+            PacketType::Pubrec => 5,
+            PacketType::Pubrel => 6,
+            PacketType::Pubcomp => 7,
+            PacketType::Subscribe => 8,
+            PacketType::Suback => 9,
+            PacketType::Unsubscribe => 10,
+            PacketType::Unsuback => 11,
+            PacketType::Pingreq => 12,
+            PacketType::Pingresp => 13,
+            PacketType::Disconnect => 14,
+            PacketType::Auth => 15,
+        };
+        let flags = match self.typ {
+            PacketType::Publish => {
+                let mut flags = (self.qos as u8) << 1;
+                if self.dup {
+                    flags |= 0b1000;
+                }
+                if self.retain {
+                    flags |= 0b0001;
+                }
+                flags
+            }
+            PacketType::Pubrel | PacketType::Subscribe | PacketType::Unsubscribe => 0b0010,
+            _ => 0,
+        };
+        (typ << 4) | flags
+    }
+}
+
+impl Encodable for Header {
+    fn encode<W: io::Write>(&self, writer: &mut W) -> io::Result<()> {
+        var_int_len(self.remaining_len as usize)?;
+        write_u8(writer, self.control_byte())?;
+        write_var_int(writer, self.remaining_len as usize)?;
+        Ok(())
+    }
+
+    fn encode_len(&self) -> usize {
+        // Invalid remaining length is rejected by encode()
+        1 + var_int_len(self.remaining_len as usize).unwrap_or(4)
+    }
 }
 
 packet_from!(
@@ -336,3 +1086,33 @@ packet_from!(
     Disconnect,
     Auth
 );
+
+#[inline]
+fn publish_control_byte(publish: &Publish) -> u8 {
+    let mut control_byte: u8 = match publish.qos_pid {
+        QosPid::Level0 => 0b00110000,
+        QosPid::Level1(_) => 0b00110010,
+        QosPid::Level2(_) => 0b00110100,
+    };
+    if publish.dup {
+        control_byte |= 0b00001000;
+    }
+    if publish.retain {
+        control_byte |= 0b00000001;
+    }
+    control_byte
+}
+
+/// Read the whole body from an async reader and decode it by the slice
+/// decoder of the packet type, the body must be consumed exactly.
+pub(crate) async fn decode_body_async<T, P>(
+    reader: &mut T,
+    header: Header,
+    decode: fn(&mut SliceReader<'_>, Header) -> Result<P, ErrorV5>,
+) -> Result<P, ErrorV5>
+where
+    T: AsyncRead + Unpin,
+{
+    let body = read_exact_capped(reader, header.remaining_len as usize).await?;
+    decode_exact(&body, |reader| decode(reader, header))
+}
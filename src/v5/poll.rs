@@ -1,10 +1,11 @@
-use futures_lite::future::block_on;
+use bytes::Bytes;
 
-use super::{
-    Auth, Connack, Connect, Disconnect, ErrorV5, Header, Packet, PacketType, Puback, Pubcomp,
-    Publish, Pubrec, Pubrel, Suback, Subscribe, Unsuback, Unsubscribe,
+use super::{Auth, Disconnect, ErrorV5, Header, Packet, PacketType};
+use crate::{
+    DecodeLimits, Error, GenericOwnedPacketReader, GenericPacketDecoder, GenericPacketSink,
+    GenericPacketStream, GenericPollBodyState, GenericPollEncode, GenericPollPacket,
+    GenericPollPacketState, PollEncodable, PollEncodeState, PollHeader, SliceReader,
 };
-use crate::{GenericPollBodyState, GenericPollPacket, GenericPollPacketState, PollHeader};
 
 impl PollHeader for Header {
     type Error = ErrorV5;
@@ -19,8 +20,8 @@ impl PollHeader for Header {
 
     fn build_empty_packet(&self) -> Option<Self::Packet> {
         let packet = match self.typ {
-            PacketType::Pingreq => Packet::Pingreq,
-            PacketType::Pingresp => Packet::Pingresp,
+            PacketType::Pingreq if self.remaining_len == 0 => Packet::Pingreq,
+            PacketType::Pingresp if self.remaining_len == 0 => Packet::Pingresp,
             PacketType::Auth if self.remaining_len == 0 => Auth::new_success().into(),
             PacketType::Disconnect if self.remaining_len == 0 => Disconnect::new_normal().into(),
             _ => return None,
@@ -29,39 +30,44 @@ impl PollHeader for Header {
     }
 
     fn block_decode(self, reader: &mut &[u8]) -> Result<Self::Packet, Self::Error> {
-        match self.typ {
-            PacketType::Connect => block_on(Connect::decode_async(reader, self)).map(Into::into),
-            PacketType::Connack => block_on(Connack::decode_async(reader, self)).map(Into::into),
-            PacketType::Publish => block_on(Publish::decode_async(reader, self)).map(Into::into),
-            PacketType::Puback => block_on(Puback::decode_async(reader, self)).map(Into::into),
-            PacketType::Pubrec => block_on(Pubrec::decode_async(reader, self)).map(Into::into),
-            PacketType::Pubrel => block_on(Pubrel::decode_async(reader, self)).map(Into::into),
-            PacketType::Pubcomp => block_on(Pubcomp::decode_async(reader, self)).map(Into::into),
-            PacketType::Subscribe => {
-                block_on(Subscribe::decode_async(reader, self)).map(Into::into)
-            }
-            PacketType::Suback => block_on(Suback::decode_async(reader, self)).map(Into::into),
-            PacketType::Unsubscribe => {
-                block_on(Unsubscribe::decode_async(reader, self)).map(Into::into)
-            }
-            PacketType::Unsuback => block_on(Unsuback::decode_async(reader, self)).map(Into::into),
-            PacketType::Disconnect => {
-                block_on(Disconnect::decode_async(reader, self)).map(Into::into)
-            }
-            PacketType::Auth => block_on(Auth::decode_async(reader, self)).map(Into::into),
-            PacketType::Pingreq | PacketType::Pingresp => unreachable!(),
-        }
+        let mut body = SliceReader(reader);
+        let result = Packet::decode_fields(&mut body, self);
+        *reader = body.0;
+        result
+    }
+
+    fn decode_shared(self, body: Bytes) -> Result<Self::Packet, Self::Error> {
+        Packet::decode_body(self, body)
     }
 
     fn remaining_len(&self) -> usize {
         self.remaining_len as usize
     }
 
+    fn control_byte(&self) -> u8 {
+        Header::control_byte(self)
+    }
+
     fn is_eof_error(err: &Self::Error) -> bool {
         err.is_eof()
     }
+
+    fn check_limits(packet: &Self::Packet, limits: &DecodeLimits) -> Result<(), Self::Error> {
+        packet.check_limits(limits)
+    }
+}
+
+impl PollEncodable for Packet {
+    fn encode_poll_state(&self) -> Result<PollEncodeState, Error> {
+        Packet::encode_poll_state(self)
+    }
 }
 
 pub type PollPacket<'a, T> = GenericPollPacket<'a, T, Header>;
 pub type PollPacketState = GenericPollPacketState<Header>;
 pub type PollBodyState = GenericPollBodyState<Header>;
+pub type PollEncode<'a, T> = GenericPollEncode<'a, T>;
+pub type PacketStream<R> = GenericPacketStream<R, Header>;
+pub type PacketSink<W> = GenericPacketSink<W, Packet>;
+pub type OwnedPacketReader<R> = GenericOwnedPacketReader<R, Header>;
+pub type PacketDecoder = GenericPacketDecoder<Header>;
@@ -0,0 +1,252 @@
+use std::io;
+use std::sync::Arc;
+
+use bytes::Bytes;
+
+use super::{check_property_len, decode_property, ErrorV5, PropertyId, UserProperty, VarByteInt};
+use crate::{
+    capacity_hint, var_int_len, write_bytes, write_u16, write_u32, write_u8, write_var_int,
+    Encodable, Error, QoS, SliceReader, TopicName,
+};
+
+/// A property with its typed value, one variant per property identifier.
+///
+/// The per-packet property structs (`ConnectProperties`,
+/// `PublishProperties`, ...) are still the main API, this type is for tooling
+/// which need to inspect or construct property lists generically.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum Property {
+    PayloadFormatIndicator(bool),
+    MessageExpiryInterval(u32),
+    ContentType(Arc<str>),
+    ResponseTopic(TopicName),
+    CorrelationData(Bytes),
+    SubscriptionIdentifier(VarByteInt),
+    SessionExpiryInterval(u32),
+    AssignedClientIdentifier(Arc<str>),
+    ServerKeepAlive(u16),
+    AuthenticationMethod(Arc<str>),
+    AuthenticationData(Bytes),
+    RequestProblemInformation(bool),
+    WillDelayInterval(u32),
+    RequestResponseInformation(bool),
+    ResponseInformation(Arc<str>),
+    ServerReference(Arc<str>),
+    ReasonString(Arc<str>),
+    ReceiveMaximum(u16),
+    TopicAliasMaximum(u16),
+    TopicAlias(u16),
+    /// Only `QoS::Level0` and `QoS::Level1` are allowed.
+    MaximumQoS(QoS),
+    RetainAvailable(bool),
+    UserProperty(UserProperty),
+    MaximumPacketSize(u32),
+    WildcardSubscriptionAvailable(bool),
+    SubscriptionIdentifierAvailable(bool),
+    SharedSubscriptionAvailable(bool),
+}
+
+impl Property {
+    /// Return the property identifier.
+    pub fn id(&self) -> PropertyId {
+        match self {
+            Property::PayloadFormatIndicator(_) => PropertyId::PayloadFormatIndicator,
+            Property::MessageExpiryInterval(_) => PropertyId::MessageExpiryInterval,
+            Property::ContentType(_) => PropertyId::ContentType,
+            Property::ResponseTopic(_) => PropertyId::ResponseTopic,
+            Property::CorrelationData(_) => PropertyId::CorrelationData,
+            Property::SubscriptionIdentifier(_) => PropertyId::SubscriptionIdentifier,
+            Property::SessionExpiryInterval(_) => PropertyId::SessionExpiryInterval,
+            Property::AssignedClientIdentifier(_) => PropertyId::AssignedClientIdentifier,
+            Property::ServerKeepAlive(_) => PropertyId::ServerKeepAlive,
+            Property::AuthenticationMethod(_) => PropertyId::AuthenticationMethod,
+            Property::AuthenticationData(_) => PropertyId::AuthenticationData,
+            Property::RequestProblemInformation(_) => PropertyId::RequestProblemInformation,
+            Property::WillDelayInterval(_) => PropertyId::WillDelayInterval,
+            Property::RequestResponseInformation(_) => PropertyId::RequestResponseInformation,
+            Property::ResponseInformation(_) => PropertyId::ResponseInformation,
+            Property::ServerReference(_) => PropertyId::ServerReference,
+            Property::ReasonString(_) => PropertyId::ReasonString,
+            Property::ReceiveMaximum(_) => PropertyId::ReceiveMaximum,
+            Property::TopicAliasMaximum(_) => PropertyId::TopicAliasMaximum,
+            Property::TopicAlias(_) => PropertyId::TopicAlias,
+            Property::MaximumQoS(_) => PropertyId::MaximumQoS,
+            Property::RetainAvailable(_) => PropertyId::RetainAvailable,
+            Property::UserProperty(_) => PropertyId::UserProperty,
+            Property::MaximumPacketSize(_) => PropertyId::MaximumPacketSize,
+            Property::WildcardSubscriptionAvailable(_) => PropertyId::WildcardSubscriptionAvailable,
+            Property::SubscriptionIdentifierAvailable(_) => {
+                PropertyId::SubscriptionIdentifierAvailable
+            }
+            Property::SharedSubscriptionAvailable(_) => PropertyId::SharedSubscriptionAvailable,
+        }
+    }
+
+    /// Decode a property (identifier and value) from the start of some
+    /// bytes, the bytes are advanced past the property.
+    pub fn decode(bytes: &mut &[u8]) -> Result<Self, ErrorV5> {
+        let mut reader = SliceReader(bytes);
+        let property = Self::decode_slice(&mut reader).map_err(eof_error)?;
+        *bytes = reader.0;
+        Ok(property)
+    }
+
+    fn decode_slice(reader: &mut SliceReader<'_>) -> Result<Self, ErrorV5> {
+        let property_id = PropertyId::from_u8(reader.read_u8()?)?;
+        macro_rules! decode_value {
+            ($($t:ident,)*) => {
+                match property_id {
+                    $(PropertyId::$t => Property::$t(decode_property!($t, reader, property_id)),)*
+                }
+            };
+        }
+        Ok(decode_value!(
+            PayloadFormatIndicator,
+            MessageExpiryInterval,
+            ContentType,
+            ResponseTopic,
+            CorrelationData,
+            SubscriptionIdentifier,
+            SessionExpiryInterval,
+            AssignedClientIdentifier,
+            ServerKeepAlive,
+            AuthenticationMethod,
+            AuthenticationData,
+            RequestProblemInformation,
+            WillDelayInterval,
+            RequestResponseInformation,
+            ResponseInformation,
+            ServerReference,
+            ReasonString,
+            ReceiveMaximum,
+            TopicAliasMaximum,
+            TopicAlias,
+            MaximumQoS,
+            RetainAvailable,
+            UserProperty,
+            MaximumPacketSize,
+            WildcardSubscriptionAvailable,
+            SubscriptionIdentifierAvailable,
+            SharedSubscriptionAvailable,
+        ))
+    }
+}
+
+impl Encodable for Property {
+    fn encode<W: io::Write>(&self, writer: &mut W) -> io::Result<()> {
+        write_u8(writer, self.id() as u8)?;
+        match self {
+            Property::PayloadFormatIndicator(value)
+            | Property::RequestProblemInformation(value)
+            | Property::RequestResponseInformation(value)
+            | Property::RetainAvailable(value)
+            | Property::WildcardSubscriptionAvailable(value)
+            | Property::SubscriptionIdentifierAvailable(value)
+            | Property::SharedSubscriptionAvailable(value) => write_u8(writer, u8::from(*value)),
+            Property::MessageExpiryInterval(value)
+            | Property::SessionExpiryInterval(value)
+            | Property::WillDelayInterval(value)
+            | Property::MaximumPacketSize(value) => write_u32(writer, *value),
+            Property::ServerKeepAlive(value)
+            | Property::ReceiveMaximum(value)
+            | Property::TopicAliasMaximum(value)
+            | Property::TopicAlias(value) => write_u16(writer, *value),
+            Property::ContentType(value)
+            | Property::AssignedClientIdentifier(value)
+            | Property::AuthenticationMethod(value)
+            | Property::ResponseInformation(value)
+            | Property::ServerReference(value)
+            | Property::ReasonString(value) => write_bytes(writer, value.as_bytes()),
+            Property::ResponseTopic(value) => write_bytes(writer, value.as_bytes()),
+            Property::CorrelationData(value) | Property::AuthenticationData(value) => {
+                write_bytes(writer, value.as_ref())
+            }
+            Property::SubscriptionIdentifier(value) => {
+                write_var_int(writer, value.value() as usize)
+            }
+            Property::MaximumQoS(value) => write_u8(writer, *value as u8),
+            Property::UserProperty(UserProperty { name, value }) => {
+                write_bytes(writer, name.as_bytes())?;
+                write_bytes(writer, value.as_bytes())
+            }
+        }
+    }
+
+    fn encode_len(&self) -> usize {
+        let value_len = match self {
+            Property::PayloadFormatIndicator(_)
+            | Property::RequestProblemInformation(_)
+            | Property::RequestResponseInformation(_)
+            | Property::RetainAvailable(_)
+            | Property::WildcardSubscriptionAvailable(_)
+            | Property::SubscriptionIdentifierAvailable(_)
+            | Property::SharedSubscriptionAvailable(_)
+            | Property::MaximumQoS(_) => 1,
+            Property::MessageExpiryInterval(_)
+            | Property::SessionExpiryInterval(_)
+            | Property::WillDelayInterval(_)
+            | Property::MaximumPacketSize(_) => 4,
+            Property::ServerKeepAlive(_)
+            | Property::ReceiveMaximum(_)
+            | Property::TopicAliasMaximum(_)
+            | Property::TopicAlias(_) => 2,
+            Property::ContentType(value)
+            | Property::AssignedClientIdentifier(value)
+            | Property::AuthenticationMethod(value)
+            | Property::ResponseInformation(value)
+            | Property::ServerReference(value)
+            | Property::ReasonString(value) => 2 + value.len(),
+            Property::ResponseTopic(value) => 2 + value.len(),
+            Property::CorrelationData(value) | Property::AuthenticationData(value) => {
+                2 + value.len()
+            }
+            Property::SubscriptionIdentifier(value) => value.encoded_len(),
+            Property::UserProperty(UserProperty { name, value }) => 4 + name.len() + value.len(),
+        };
+        1 + value_len
+    }
+}
+
+/// Decode a property list (the property length and the properties) from some
+/// bytes. The properties are returned in the wire order, no packet specific
+/// check (duplicated or not allowed property) is done.
+pub fn decode_property_list(bytes: &[u8]) -> Result<Vec<Property>, ErrorV5> {
+    let mut reader = SliceReader(bytes);
+    let property_len = reader
+        .read_var_int()
+        .map_err(ErrorV5::from)
+        .map_err(eof_error)?;
+    let mut properties = Vec::with_capacity(capacity_hint(property_len as usize, 2));
+    let start = reader.0.len();
+    while ((start - reader.0.len()) as u64) < u64::from(property_len) {
+        properties.push(Property::decode_slice(&mut reader).map_err(eof_error)?);
+        check_property_len(start - reader.0.len(), property_len)?;
+    }
+    Ok(properties)
+}
+
+/// Encode a property list (the property length and the properties) into a
+/// writer.
+pub fn encode_property_list<W: io::Write>(
+    writer: &mut W,
+    properties: &[Property],
+) -> Result<(), ErrorV5> {
+    let property_len: usize = properties.iter().map(Encodable::encode_len).sum();
+    var_int_len(property_len)?;
+    write_var_int(writer, property_len)?;
+    for property in properties {
+        property.encode(writer)?;
+    }
+    Ok(())
+}
+
+// The bytes are all the input, running out of them is an EOF.
+fn eof_error(err: ErrorV5) -> ErrorV5 {
+    match err {
+        ErrorV5::Common(Error::InvalidRemainingLength) => {
+            Error::from(io::Error::from(io::ErrorKind::UnexpectedEof)).into()
+        }
+        err => err,
+    }
+}
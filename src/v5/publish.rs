@@ -4,19 +4,20 @@ use std::sync::Arc;
 
 use bytes::Bytes;
 use simdutf8::basic::from_utf8;
-use tokio::io::{AsyncRead, AsyncReadExt};
+use tokio::io::{AsyncRead, AsyncWrite};
 
 use super::{
-    decode_properties, encode_properties, encode_properties_len, ErrorV5, Header, PacketType,
-    UserProperty, VarByteInt,
+    decode_body_async, decode_properties, decode_properties_async, encode_properties,
+    encode_properties_len, ErrorV5, Header, PacketType, UserProperties, UserProperty, VarByteInt,
 };
 use crate::{
-    read_string, read_u16, read_u8, write_bytes, write_u16, write_u8, Encodable, Error, Pid, QoS,
-    QosPid, TopicName,
+    copy_exact, decode_exact, decode_var_int, read_u16, write_bytes, write_u16, write_u8,
+    write_var_int, Encodable, Error, Pid, QoS, QosPid, SliceReader, TopicName,
 };
 
 /// Body type of PUBLISH packet.
 #[derive(Debug, Clone, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct Publish {
     pub dup: bool,
     pub retain: bool,
@@ -41,13 +42,15 @@ impl<'a> arbitrary::Arbitrary<'a> for Publish {
 }
 
 impl Publish {
-    pub fn new(qos_pid: QosPid, topic_name: TopicName, payload: Bytes) -> Self {
+    /// Create a publish packet, the payload can be `Bytes`, `Vec<u8>`,
+    /// `&'static [u8]`, `String` or `&'static str`.
+    pub fn new(qos_pid: QosPid, topic_name: TopicName, payload: impl Into<Bytes>) -> Self {
         Publish {
             dup: false,
             retain: false,
             qos_pid,
             topic_name,
-            payload,
+            payload: payload.into(),
             properties: PublishProperties::default(),
         }
     }
@@ -56,56 +59,118 @@ impl Publish {
         reader: &mut T,
         header: Header,
     ) -> Result<Self, ErrorV5> {
-        let mut remaining_len = header.remaining_len as usize;
-        let topic_name = read_string(reader).await?;
-        remaining_len = remaining_len
-            .checked_sub(2 + topic_name.len())
-            .ok_or(Error::InvalidRemainingLength)?;
-        let qos_pid = match header.qos {
-            QoS::Level0 => QosPid::Level0,
-            QoS::Level1 => {
-                remaining_len = remaining_len
-                    .checked_sub(2)
-                    .ok_or(Error::InvalidRemainingLength)?;
-                QosPid::Level1(Pid::try_from(read_u16(reader).await?)?)
-            }
-            QoS::Level2 => {
-                remaining_len = remaining_len
-                    .checked_sub(2)
-                    .ok_or(Error::InvalidRemainingLength)?;
-                QosPid::Level2(Pid::try_from(read_u16(reader).await?)?)
-            }
-        };
-        let properties = PublishProperties::decode_async(reader, header.typ).await?;
-        remaining_len = remaining_len
-            .checked_sub(properties.encode_len())
+        decode_body_async(reader, header, Self::decode_slice).await
+    }
+
+    pub(crate) fn decode_slice(
+        reader: &mut SliceReader<'_>,
+        header: Header,
+    ) -> Result<Self, ErrorV5> {
+        let (topic_name, qos_pid, properties) = Self::decode_variable_header(reader, header)?;
+        let payload = reader.take(reader.0.len())?;
+        if properties.payload_is_utf8 == Some(true) && from_utf8(payload).is_err() {
+            return Err(ErrorV5::InvalidPayloadFormat);
+        }
+        Ok(Publish {
+            dup: header.dup,
+            qos_pid,
+            retain: header.retain,
+            topic_name,
+            properties,
+            payload: Bytes::copy_from_slice(payload),
+        })
+    }
+
+    /// Decode the packet and write the payload to `writer` in chunks instead
+    /// of buffering it, the payload of returned packet is empty.
+    ///
+    /// NOTE: the payload is not checked even if it is marked as utf8.
+    pub async fn decode_async_streaming<T, W>(
+        reader: &mut T,
+        header: Header,
+        writer: &mut W,
+    ) -> Result<Self, ErrorV5>
+    where
+        T: AsyncRead + Unpin,
+        W: AsyncWrite + Unpin,
+    {
+        // Only the variable header is read into memory, it is decoded by the
+        // slice decoder.
+        let mut data = Vec::new();
+        let topic_len = read_u16(reader).await?;
+        write_u16(&mut data, topic_len)?;
+        copy_exact(reader, &mut data, topic_len as usize).await?;
+        if header.qos != QoS::Level0 {
+            copy_exact(reader, &mut data, 2).await?;
+        }
+        let (property_len, _bytes) = decode_var_int(reader).await?;
+        write_var_int(&mut data, property_len as usize)?;
+        copy_exact(reader, &mut data, property_len as usize).await?;
+        let payload_len = (header.remaining_len as usize)
+            .checked_sub(data.len())
             .ok_or(Error::InvalidRemainingLength)?;
-        let payload = if remaining_len > 0 {
-            let mut data = vec![0u8; remaining_len];
-            reader
-                .read_exact(&mut data)
-                .await
-                .map_err(|err| Error::IoError(err.kind(), err.to_string()))?;
-            if properties.payload_is_utf8 == Some(true) && from_utf8(&data).is_err() {
-                return Err(ErrorV5::InvalidPayloadFormat);
-            }
-            data
-        } else {
-            Vec::new()
-        };
+        let (topic_name, qos_pid, properties) =
+            decode_exact(&data, |reader| Self::decode_variable_header(reader, header)).map_err(
+                |err| match err {
+                    // The last property is truncated by the property length
+                    ErrorV5::Common(Error::InvalidRemainingLength) => {
+                        ErrorV5::InvalidPropertyLength(property_len)
+                    }
+                    err => err,
+                },
+            )?;
+        copy_exact(reader, writer, payload_len).await?;
         Ok(Publish {
             dup: header.dup,
             qos_pid,
             retain: header.retain,
-            topic_name: TopicName::try_from(topic_name)?,
+            topic_name,
             properties,
-            payload: Bytes::from(payload),
+            payload: Bytes::new(),
         })
     }
-}
 
-impl Encodable for Publish {
-    fn encode<W: io::Write>(&self, writer: &mut W) -> io::Result<()> {
+    /// Decode from a shared buffer which contains exactly the packet body, the
+    /// payload is a slice of the buffer.
+    pub(crate) fn decode_shared(data: Bytes, header: Header) -> Result<Self, ErrorV5> {
+        let mut reader = SliceReader(&data);
+        let (topic_name, qos_pid, properties) = Self::decode_variable_header(&mut reader, header)?;
+        let payload = data.slice(data.len() - reader.0.len()..);
+        if properties.payload_is_utf8 == Some(true) && from_utf8(&payload).is_err() {
+            return Err(ErrorV5::InvalidPayloadFormat);
+        }
+        Ok(Publish {
+            dup: header.dup,
+            qos_pid,
+            retain: header.retain,
+            topic_name,
+            properties,
+            payload,
+        })
+    }
+
+    /// Decode topic name, packet identifier and properties, the rest of the
+    /// body is the payload.
+    fn decode_variable_header(
+        reader: &mut SliceReader<'_>,
+        header: Header,
+    ) -> Result<(TopicName, QosPid, PublishProperties), ErrorV5> {
+        let topic_name = reader.read_str()?;
+        let qos_pid = match header.qos {
+            QoS::Level0 => QosPid::Level0,
+            QoS::Level1 => QosPid::Level1(reader.read_pid()?),
+            QoS::Level2 => QosPid::Level2(reader.read_pid()?),
+        };
+        let properties = PublishProperties::decode_slice(reader, header.typ)?;
+        Ok((
+            TopicName::try_from(topic_name.to_owned())?,
+            qos_pid,
+            properties,
+        ))
+    }
+
+    /// Encode the variable header (everything except the payload).
+    pub(crate) fn encode_without_payload<W: io::Write>(&self, writer: &mut W) -> io::Result<()> {
         write_bytes(writer, self.topic_name.as_bytes())?;
         match self.qos_pid {
             QosPid::Level0 => {}
@@ -114,6 +179,13 @@ impl Encodable for Publish {
             }
         }
         self.properties.encode(writer)?;
+        Ok(())
+    }
+}
+
+impl Encodable for Publish {
+    fn encode<W: io::Write>(&self, writer: &mut W) -> io::Result<()> {
+        self.encode_without_payload(writer)?;
         writer.write_all(self.payload.as_ref())?;
         Ok(())
     }
@@ -134,16 +206,17 @@ impl Encodable for Publish {
 
 /// Property list for PUBLISH packet.
 #[derive(Debug, Clone, PartialEq, Eq, Default, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct PublishProperties {
     pub payload_is_utf8: Option<bool>,
     pub message_expiry_interval: Option<u32>,
     pub topic_alias: Option<u16>,
     pub response_topic: Option<TopicName>,
     pub correlation_data: Option<Bytes>,
-    pub user_properties: Vec<UserProperty>,
+    pub user_properties: UserProperties,
     // FIXME: this is a list of identifiers
     pub subscription_id: Option<VarByteInt>,
-    pub content_type: Option<Arc<String>>,
+    pub content_type: Option<Arc<str>>,
 }
 
 #[cfg(feature = "arbitrary")]
@@ -166,6 +239,13 @@ impl PublishProperties {
     pub async fn decode_async<T: AsyncRead + Unpin>(
         reader: &mut T,
         packet_type: PacketType,
+    ) -> Result<Self, ErrorV5> {
+        decode_properties_async(reader, |reader| Self::decode_slice(reader, packet_type)).await
+    }
+
+    pub(crate) fn decode_slice(
+        reader: &mut SliceReader<'_>,
+        packet_type: PacketType,
     ) -> Result<Self, ErrorV5> {
         let mut properties = PublishProperties::default();
         decode_properties!(
@@ -219,6 +299,7 @@ impl Encodable for PublishProperties {
 /// Body type for PUBACK packet.
 #[derive(Debug, Clone, PartialEq, Eq)]
 #[cfg_attr(feature = "arbitrary", derive(arbitrary::Arbitrary))]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct Puback {
     pub pid: Pid,
     pub reason_code: PubackReasonCode,
@@ -238,23 +319,36 @@ impl Puback {
         Self::new(pid, PubackReasonCode::Success)
     }
 
+    /// Replace the reason code, such as `Puback::new_success(pid).with_reason(code)`.
+    pub fn with_reason(mut self, reason_code: PubackReasonCode) -> Self {
+        self.reason_code = reason_code;
+        self
+    }
+
     pub async fn decode_async<T: AsyncRead + Unpin>(
         reader: &mut T,
         header: Header,
     ) -> Result<Self, ErrorV5> {
-        let pid = Pid::try_from(read_u16(reader).await?)?;
+        decode_body_async(reader, header, Self::decode_slice).await
+    }
+
+    pub(crate) fn decode_slice(
+        reader: &mut SliceReader<'_>,
+        header: Header,
+    ) -> Result<Self, ErrorV5> {
+        let pid = reader.read_pid()?;
         let (reason_code, properties) = if header.remaining_len == 2 {
             (PubackReasonCode::Success, PubackProperties::default())
         } else if header.remaining_len == 3 {
-            let reason_byte = read_u8(reader).await?;
+            let reason_byte = reader.read_u8()?;
             let reason_code = PubackReasonCode::from_u8(reason_byte)
                 .ok_or(ErrorV5::InvalidReasonCode(header.typ, reason_byte))?;
             (reason_code, PubackProperties::default())
         } else {
-            let reason_byte = read_u8(reader).await?;
+            let reason_byte = reader.read_u8()?;
             let reason_code = PubackReasonCode::from_u8(reason_byte)
                 .ok_or(ErrorV5::InvalidReasonCode(header.typ, reason_byte))?;
-            let properties = PubackProperties::decode_async(reader, header.typ).await?;
+            let properties = PubackProperties::decode_slice(reader, header.typ)?;
             (reason_code, properties)
         };
         Ok(Puback {
@@ -268,11 +362,13 @@ impl Puback {
 impl Encodable for Puback {
     fn encode<W: io::Write>(&self, writer: &mut W) -> io::Result<()> {
         write_u16(writer, self.pid.value())?;
-        if self.reason_code != PubackReasonCode::Success {
-            write_u8(writer, self.reason_code as u8)?;
-            if self.properties != PubackProperties::default() {
-                self.properties.encode(writer)?;
+        if self.properties == PubackProperties::default() {
+            if self.reason_code != PubackReasonCode::Success {
+                write_u8(writer, self.reason_code as u8)?;
             }
+        } else {
+            write_u8(writer, self.reason_code as u8)?;
+            self.properties.encode(writer)?;
         }
         Ok(())
     }
@@ -290,26 +386,35 @@ impl Encodable for Puback {
     }
 }
 
-/// Property list for PUBACK packet.
+/// Property list for PUBACK, PUBREC, PUBREL and PUBCOMP packets, they are
+/// the same so acknowledgement handling code can be shared.
 #[derive(Debug, Clone, PartialEq, Eq, Default)]
 #[cfg_attr(feature = "arbitrary", derive(arbitrary::Arbitrary))]
-pub struct PubackProperties {
-    pub reason_string: Option<Arc<String>>,
-    pub user_properties: Vec<UserProperty>,
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct AckProperties {
+    pub reason_string: Option<Arc<str>>,
+    pub user_properties: UserProperties,
 }
 
-impl PubackProperties {
+impl AckProperties {
     pub async fn decode_async<T: AsyncRead + Unpin>(
         reader: &mut T,
         packet_type: PacketType,
     ) -> Result<Self, ErrorV5> {
-        let mut properties = PubackProperties::default();
+        decode_properties_async(reader, |reader| Self::decode_slice(reader, packet_type)).await
+    }
+
+    pub(crate) fn decode_slice(
+        reader: &mut SliceReader<'_>,
+        packet_type: PacketType,
+    ) -> Result<Self, ErrorV5> {
+        let mut properties = AckProperties::default();
         decode_properties!(packet_type, properties, reader, ReasonString,);
         Ok(properties)
     }
 }
 
-impl Encodable for PubackProperties {
+impl Encodable for AckProperties {
     fn encode<W: io::Write>(&self, writer: &mut W) -> io::Result<()> {
         encode_properties!(self, writer, ReasonString,);
         Ok(())
@@ -321,6 +426,9 @@ impl Encodable for PubackProperties {
     }
 }
 
+/// Property list for PUBACK packet.
+pub type PubackProperties = AckProperties;
+
 /// Reason code for PUBACK packet.
 ///
 /// | Dec |  Hex | Reason Code name              | Description                                                                                                        |
@@ -340,6 +448,7 @@ impl Encodable for PubackProperties {
 #[repr(u8)]
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
 #[cfg_attr(feature = "arbitrary", derive(arbitrary::Arbitrary))]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum PubackReasonCode {
     Success = 0x00,
     NoMatchingSubscribers = 0x10,
@@ -368,11 +477,33 @@ impl PubackReasonCode {
         };
         Some(code)
     }
+
+    /// The description of the reason code in the specification.
+    pub fn description(&self) -> &'static str {
+        match self {
+            Self::Success => "The message is accepted. Publication of the QoS 1 message proceeds.",
+            Self::NoMatchingSubscribers => "The message is accepted but there are no subscribers. This is sent only by the Server. If the Server knows that there are no matching subscribers, it MAY use this Reason Code instead of 0x00 (Success).",
+            Self::UnspecifiedError => "The receiver does not accept the publish but either does not want to reveal the reason, or it does not match one of the other values.",
+            Self::ImplementationSpecificError => "The PUBLISH is valid but the receiver is not willing to accept it.",
+            Self::NotAuthorized => "The PUBLISH is not authorized.",
+            Self::TopicNameInvalid => "The Topic Name is not malformed, but is not accepted by this Client or Server.",
+            Self::PacketIdentifierInUse => "The Packet Identifier is already in use. This might indicate a mismatch in the Session State between the Client and Server.",
+            Self::QuotaExceeded => "An implementation or administrative imposed limit has been exceeded.",
+            Self::PayloadFormatInvalid => "The payload format does not match the specified Payload Format Indicator.",
+        }
+    }
+
+    /// Reason codes less than 0x80 indicate success, the others indicate
+    /// an error.
+    pub fn is_error(&self) -> bool {
+        *self as u8 >= 0x80
+    }
 }
 
 /// Body type for PUBREC packet.
 #[derive(Debug, Clone, PartialEq, Eq)]
 #[cfg_attr(feature = "arbitrary", derive(arbitrary::Arbitrary))]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct Pubrec {
     pub pid: Pid,
     pub reason_code: PubrecReasonCode,
@@ -392,23 +523,36 @@ impl Pubrec {
         Self::new(pid, PubrecReasonCode::Success)
     }
 
+    /// Replace the reason code, such as `Pubrec::new_success(pid).with_reason(code)`.
+    pub fn with_reason(mut self, reason_code: PubrecReasonCode) -> Self {
+        self.reason_code = reason_code;
+        self
+    }
+
     pub async fn decode_async<T: AsyncRead + Unpin>(
         reader: &mut T,
         header: Header,
     ) -> Result<Self, ErrorV5> {
-        let pid = Pid::try_from(read_u16(reader).await?)?;
+        decode_body_async(reader, header, Self::decode_slice).await
+    }
+
+    pub(crate) fn decode_slice(
+        reader: &mut SliceReader<'_>,
+        header: Header,
+    ) -> Result<Self, ErrorV5> {
+        let pid = reader.read_pid()?;
         let (reason_code, properties) = if header.remaining_len == 2 {
             (PubrecReasonCode::Success, PubrecProperties::default())
         } else if header.remaining_len == 3 {
-            let reason_byte = read_u8(reader).await?;
+            let reason_byte = reader.read_u8()?;
             let reason_code = PubrecReasonCode::from_u8(reason_byte)
                 .ok_or(ErrorV5::InvalidReasonCode(header.typ, reason_byte))?;
             (reason_code, PubrecProperties::default())
         } else {
-            let reason_byte = read_u8(reader).await?;
+            let reason_byte = reader.read_u8()?;
             let reason_code = PubrecReasonCode::from_u8(reason_byte)
                 .ok_or(ErrorV5::InvalidReasonCode(header.typ, reason_byte))?;
-            let properties = PubrecProperties::decode_async(reader, header.typ).await?;
+            let properties = PubrecProperties::decode_slice(reader, header.typ)?;
             (reason_code, properties)
         };
         Ok(Pubrec {
@@ -422,11 +566,13 @@ impl Pubrec {
 impl Encodable for Pubrec {
     fn encode<W: io::Write>(&self, writer: &mut W) -> io::Result<()> {
         write_u16(writer, self.pid.value())?;
-        if self.reason_code != PubrecReasonCode::Success {
-            write_u8(writer, self.reason_code as u8)?;
-            if self.properties != PubrecProperties::default() {
-                self.properties.encode(writer)?;
+        if self.properties == PubrecProperties::default() {
+            if self.reason_code != PubrecReasonCode::Success {
+                write_u8(writer, self.reason_code as u8)?;
             }
+        } else {
+            write_u8(writer, self.reason_code as u8)?;
+            self.properties.encode(writer)?;
         }
         Ok(())
     }
@@ -445,35 +591,7 @@ impl Encodable for Pubrec {
 }
 
 /// Property list for PUBREC packet.
-#[derive(Debug, Clone, PartialEq, Eq, Default)]
-#[cfg_attr(feature = "arbitrary", derive(arbitrary::Arbitrary))]
-pub struct PubrecProperties {
-    pub reason_string: Option<Arc<String>>,
-    pub user_properties: Vec<UserProperty>,
-}
-
-impl PubrecProperties {
-    pub async fn decode_async<T: AsyncRead + Unpin>(
-        reader: &mut T,
-        packet_type: PacketType,
-    ) -> Result<Self, ErrorV5> {
-        let mut properties = PubrecProperties::default();
-        decode_properties!(packet_type, properties, reader, ReasonString,);
-        Ok(properties)
-    }
-}
-
-impl Encodable for PubrecProperties {
-    fn encode<W: io::Write>(&self, writer: &mut W) -> io::Result<()> {
-        encode_properties!(self, writer, ReasonString,);
-        Ok(())
-    }
-    fn encode_len(&self) -> usize {
-        let mut len = 0;
-        encode_properties_len!(self, len, ReasonString,);
-        len
-    }
-}
+pub type PubrecProperties = AckProperties;
 
 /// Reason code for PUBREC packet.
 ///
@@ -494,6 +612,7 @@ impl Encodable for PubrecProperties {
 #[repr(u8)]
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
 #[cfg_attr(feature = "arbitrary", derive(arbitrary::Arbitrary))]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum PubrecReasonCode {
     Success = 0x00,
     NoMatchingSubscribers = 0x10,
@@ -522,11 +641,33 @@ impl PubrecReasonCode {
         };
         Some(code)
     }
+
+    /// The description of the reason code in the specification.
+    pub fn description(&self) -> &'static str {
+        match self {
+            Self::Success => "The message is accepted. Publication of the QoS 2 message proceeds.",
+            Self::NoMatchingSubscribers => "The message is accepted but there are no subscribers. This is sent only by the Server. If the Server knows that there are no matching subscribers, it MAY use this Reason Code instead of 0x00 (Success).",
+            Self::UnspecifiedError => "The receiver does not accept the publish but either does not want to reveal the reason, or it does not match one of the other values.",
+            Self::ImplementationSpecificError => "The PUBLISH is valid but the receiver is not willing to accept it.",
+            Self::NotAuthorized => "The PUBLISH is not authorized.",
+            Self::TopicNameInvalid => "The Topic Name is not malformed, but is not accepted by this Client or Server.",
+            Self::PacketIdentifierInUse => "The Packet Identifier is already in use. This might indicate a mismatch in the Session State between the Client and Server.",
+            Self::QuotaExceeded => "An implementation or administrative imposed limit has been exceeded.",
+            Self::PayloadFormatInvalid => "The payload format does not match the specified Payload Format Indicator.",
+        }
+    }
+
+    /// Reason codes less than 0x80 indicate success, the others indicate
+    /// an error.
+    pub fn is_error(&self) -> bool {
+        *self as u8 >= 0x80
+    }
 }
 
 /// Body type for PUBREL packet.
 #[derive(Debug, Clone, PartialEq, Eq)]
 #[cfg_attr(feature = "arbitrary", derive(arbitrary::Arbitrary))]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct Pubrel {
     pub pid: Pid,
     pub reason_code: PubrelReasonCode,
@@ -546,23 +687,36 @@ impl Pubrel {
         Self::new(pid, PubrelReasonCode::Success)
     }
 
+    /// Replace the reason code, such as `Pubrel::new_success(pid).with_reason(code)`.
+    pub fn with_reason(mut self, reason_code: PubrelReasonCode) -> Self {
+        self.reason_code = reason_code;
+        self
+    }
+
     pub async fn decode_async<T: AsyncRead + Unpin>(
         reader: &mut T,
         header: Header,
     ) -> Result<Self, ErrorV5> {
-        let pid = Pid::try_from(read_u16(reader).await?)?;
+        decode_body_async(reader, header, Self::decode_slice).await
+    }
+
+    pub(crate) fn decode_slice(
+        reader: &mut SliceReader<'_>,
+        header: Header,
+    ) -> Result<Self, ErrorV5> {
+        let pid = reader.read_pid()?;
         let (reason_code, properties) = if header.remaining_len == 2 {
             (PubrelReasonCode::Success, PubrelProperties::default())
         } else if header.remaining_len == 3 {
-            let reason_byte = read_u8(reader).await?;
+            let reason_byte = reader.read_u8()?;
             let reason_code = PubrelReasonCode::from_u8(reason_byte)
                 .ok_or(ErrorV5::InvalidReasonCode(header.typ, reason_byte))?;
             (reason_code, PubrelProperties::default())
         } else {
-            let reason_byte = read_u8(reader).await?;
+            let reason_byte = reader.read_u8()?;
             let reason_code = PubrelReasonCode::from_u8(reason_byte)
                 .ok_or(ErrorV5::InvalidReasonCode(header.typ, reason_byte))?;
-            let properties = PubrelProperties::decode_async(reader, header.typ).await?;
+            let properties = PubrelProperties::decode_slice(reader, header.typ)?;
             (reason_code, properties)
         };
         Ok(Pubrel {
@@ -576,11 +730,13 @@ impl Pubrel {
 impl Encodable for Pubrel {
     fn encode<W: io::Write>(&self, writer: &mut W) -> io::Result<()> {
         write_u16(writer, self.pid.value())?;
-        if self.reason_code != PubrelReasonCode::Success {
-            write_u8(writer, self.reason_code as u8)?;
-            if self.properties != PubrelProperties::default() {
-                self.properties.encode(writer)?;
+        if self.properties == PubrelProperties::default() {
+            if self.reason_code != PubrelReasonCode::Success {
+                write_u8(writer, self.reason_code as u8)?;
             }
+        } else {
+            write_u8(writer, self.reason_code as u8)?;
+            self.properties.encode(writer)?;
         }
         Ok(())
     }
@@ -599,35 +755,7 @@ impl Encodable for Pubrel {
 }
 
 /// Property list for PUBREL packet.
-#[derive(Debug, Clone, PartialEq, Eq, Default)]
-#[cfg_attr(feature = "arbitrary", derive(arbitrary::Arbitrary))]
-pub struct PubrelProperties {
-    pub reason_string: Option<Arc<String>>,
-    pub user_properties: Vec<UserProperty>,
-}
-
-impl PubrelProperties {
-    pub async fn decode_async<T: AsyncRead + Unpin>(
-        reader: &mut T,
-        packet_type: PacketType,
-    ) -> Result<Self, ErrorV5> {
-        let mut properties = PubrelProperties::default();
-        decode_properties!(packet_type, properties, reader, ReasonString,);
-        Ok(properties)
-    }
-}
-
-impl Encodable for PubrelProperties {
-    fn encode<W: io::Write>(&self, writer: &mut W) -> io::Result<()> {
-        encode_properties!(self, writer, ReasonString,);
-        Ok(())
-    }
-    fn encode_len(&self) -> usize {
-        let mut len = 0;
-        encode_properties_len!(self, len, ReasonString,);
-        len
-    }
-}
+pub type PubrelProperties = AckProperties;
 
 /// Reason code for PUBREL packet.
 ///
@@ -639,6 +767,7 @@ impl Encodable for PubrelProperties {
 #[repr(u8)]
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
 #[cfg_attr(feature = "arbitrary", derive(arbitrary::Arbitrary))]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum PubrelReasonCode {
     Success = 0x00,
     PacketIdentifierNotFound = 0x92,
@@ -653,11 +782,26 @@ impl PubrelReasonCode {
         };
         Some(code)
     }
+
+    /// The description of the reason code in the specification.
+    pub fn description(&self) -> &'static str {
+        match self {
+            Self::Success => "Message released.",
+            Self::PacketIdentifierNotFound => "The Packet Identifier is not known. This is not an error during recovery, but at other times indicates a mismatch between the Session State on the Client and Server.",
+        }
+    }
+
+    /// Reason codes less than 0x80 indicate success, the others indicate
+    /// an error.
+    pub fn is_error(&self) -> bool {
+        *self as u8 >= 0x80
+    }
 }
 
 /// Body type for PUBCOMP packet.
 #[derive(Debug, Clone, PartialEq, Eq)]
 #[cfg_attr(feature = "arbitrary", derive(arbitrary::Arbitrary))]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct Pubcomp {
     pub pid: Pid,
     pub reason_code: PubcompReasonCode,
@@ -677,23 +821,36 @@ impl Pubcomp {
         Self::new(pid, PubcompReasonCode::Success)
     }
 
+    /// Replace the reason code, such as `Pubcomp::new_success(pid).with_reason(code)`.
+    pub fn with_reason(mut self, reason_code: PubcompReasonCode) -> Self {
+        self.reason_code = reason_code;
+        self
+    }
+
     pub async fn decode_async<T: AsyncRead + Unpin>(
         reader: &mut T,
         header: Header,
     ) -> Result<Self, ErrorV5> {
-        let pid = Pid::try_from(read_u16(reader).await?)?;
+        decode_body_async(reader, header, Self::decode_slice).await
+    }
+
+    pub(crate) fn decode_slice(
+        reader: &mut SliceReader<'_>,
+        header: Header,
+    ) -> Result<Self, ErrorV5> {
+        let pid = reader.read_pid()?;
         let (reason_code, properties) = if header.remaining_len == 2 {
             (PubcompReasonCode::Success, PubcompProperties::default())
         } else if header.remaining_len == 3 {
-            let reason_byte = read_u8(reader).await?;
+            let reason_byte = reader.read_u8()?;
             let reason_code = PubcompReasonCode::from_u8(reason_byte)
                 .ok_or(ErrorV5::InvalidReasonCode(header.typ, reason_byte))?;
             (reason_code, PubcompProperties::default())
         } else {
-            let reason_byte = read_u8(reader).await?;
+            let reason_byte = reader.read_u8()?;
             let reason_code = PubcompReasonCode::from_u8(reason_byte)
                 .ok_or(ErrorV5::InvalidReasonCode(header.typ, reason_byte))?;
-            let properties = PubcompProperties::decode_async(reader, header.typ).await?;
+            let properties = PubcompProperties::decode_slice(reader, header.typ)?;
             (reason_code, properties)
         };
         Ok(Pubcomp {
@@ -707,11 +864,13 @@ impl Pubcomp {
 impl Encodable for Pubcomp {
     fn encode<W: io::Write>(&self, writer: &mut W) -> io::Result<()> {
         write_u16(writer, self.pid.value())?;
-        if self.reason_code != PubcompReasonCode::Success {
-            write_u8(writer, self.reason_code as u8)?;
-            if self.properties != PubcompProperties::default() {
-                self.properties.encode(writer)?;
+        if self.properties == PubcompProperties::default() {
+            if self.reason_code != PubcompReasonCode::Success {
+                write_u8(writer, self.reason_code as u8)?;
             }
+        } else {
+            write_u8(writer, self.reason_code as u8)?;
+            self.properties.encode(writer)?;
         }
         Ok(())
     }
@@ -730,35 +889,7 @@ impl Encodable for Pubcomp {
 }
 
 /// Property list for PUBCOMP packet.
-#[derive(Debug, Clone, PartialEq, Eq, Default)]
-#[cfg_attr(feature = "arbitrary", derive(arbitrary::Arbitrary))]
-pub struct PubcompProperties {
-    pub reason_string: Option<Arc<String>>,
-    pub user_properties: Vec<UserProperty>,
-}
-
-impl PubcompProperties {
-    pub async fn decode_async<T: AsyncRead + Unpin>(
-        reader: &mut T,
-        packet_type: PacketType,
-    ) -> Result<Self, ErrorV5> {
-        let mut properties = PubcompProperties::default();
-        decode_properties!(packet_type, properties, reader, ReasonString,);
-        Ok(properties)
-    }
-}
-
-impl Encodable for PubcompProperties {
-    fn encode<W: io::Write>(&self, writer: &mut W) -> io::Result<()> {
-        encode_properties!(self, writer, ReasonString,);
-        Ok(())
-    }
-    fn encode_len(&self) -> usize {
-        let mut len = 0;
-        encode_properties_len!(self, len, ReasonString,);
-        len
-    }
-}
+pub type PubcompProperties = AckProperties;
 
 /// Reason code for PUBCOMP packet.
 ///
@@ -770,6 +901,7 @@ impl Encodable for PubcompProperties {
 #[repr(u8)]
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
 #[cfg_attr(feature = "arbitrary", derive(arbitrary::Arbitrary))]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum PubcompReasonCode {
     Success = 0x00,
     PacketIdentifierNotFound = 0x92,
@@ -784,4 +916,18 @@ impl PubcompReasonCode {
         };
         Some(code)
     }
+
+    /// The description of the reason code in the specification.
+    pub fn description(&self) -> &'static str {
+        match self {
+            Self::Success => "Packet Identifier released. Publication of QoS 2 message is complete.",
+            Self::PacketIdentifierNotFound => "The Packet Identifier is not known. This is not an error during recovery, but at other times indicates a mismatch between the Session State on the Client and Server.",
+        }
+    }
+
+    /// Reason codes less than 0x80 indicate success, the others indicate
+    /// an error.
+    pub fn is_error(&self) -> bool {
+        *self as u8 >= 0x80
+    }
 }
@@ -0,0 +1,125 @@
+use super::Packet;
+use crate::QoS;
+
+/// Send quota of QoS 1/2 PUBLISH packets, limited by the "Receive Maximum"
+/// of the peer. See [MQTT 4.9].
+///
+/// The quota is decremented when a QoS 1/2 PUBLISH is sent, and incremented
+/// when a PUBACK, PUBCOMP or PUBREC with an error reason code is received.
+///
+/// [MQTT 4.9]: https://docs.oasis-open.org/mqtt/mqtt/v5.0/os/mqtt-v5.0-os.html#_Toc3901251
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct SendQuota {
+    receive_max: u16,
+    quota: u16,
+}
+
+impl Default for SendQuota {
+    /// The default "Receive Maximum" is 65535.
+    fn default() -> Self {
+        SendQuota::new(u16::MAX)
+    }
+}
+
+impl SendQuota {
+    pub fn new(receive_max: u16) -> Self {
+        SendQuota {
+            receive_max,
+            quota: receive_max,
+        }
+    }
+
+    pub fn receive_max(&self) -> u16 {
+        self.receive_max
+    }
+
+    /// The number of QoS 1/2 PUBLISH packets can be sent now.
+    pub fn quota(&self) -> u16 {
+        self.quota
+    }
+
+    /// Whether another QoS 1/2 PUBLISH packet can be sent.
+    pub fn can_send(&self) -> bool {
+        self.quota > 0
+    }
+
+    /// Update the quota by a PUBLISH packet to send. Return false (and the
+    /// quota is not changed) if it is a QoS 1/2 PUBLISH and there is no quota
+    /// left, the packet should not be sent.
+    pub fn on_publish(&mut self, qos: QoS) -> bool {
+        if qos == QoS::Level0 {
+            return true;
+        }
+        if self.quota == 0 {
+            return false;
+        }
+        self.quota -= 1;
+        true
+    }
+
+    /// Update the quota by a packet to send, see `on_publish()`. Packets other
+    /// than PUBLISH are always allowed.
+    pub fn on_send(&mut self, packet: &Packet) -> bool {
+        match packet {
+            Packet::Publish(publish) => self.on_publish(publish.qos_pid.qos()),
+            _ => true,
+        }
+    }
+
+    /// Update the quota by a received packet, the quota is restored by PUBACK,
+    /// PUBCOMP and PUBREC with an error reason code.
+    pub fn on_receive(&mut self, packet: &Packet) {
+        let restore = match packet {
+            Packet::Puback(_) | Packet::Pubcomp(_) => true,
+            Packet::Pubrec(pubrec) => pubrec.reason_code as u8 >= 0x80,
+            _ => false,
+        };
+        if restore {
+            self.restore();
+        }
+    }
+
+    /// Restore one quota when a QoS 1/2 PUBLISH flow is finished.
+    pub fn restore(&mut self) {
+        if self.quota < self.receive_max {
+            self.quota += 1;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::convert::TryFrom;
+
+    use bytes::Bytes;
+
+    use super::*;
+    use crate::v5::{Puback, Publish, Pubrec, PubrecReasonCode};
+    use crate::{Pid, QosPid, TopicName};
+
+    #[test]
+    fn test_send_quota() {
+        let pid = Pid::try_from(1).unwrap();
+        let topic_name = TopicName::try_from("a".to_owned()).unwrap();
+        let qos0: Packet = Publish::new(QosPid::Level0, topic_name.clone(), Bytes::new()).into();
+        let qos1: Packet = Publish::new(QosPid::Level1(pid), topic_name, Bytes::new()).into();
+
+        let mut quota = SendQuota::new(2);
+        assert!(quota.on_send(&qos1));
+        assert!(quota.on_send(&qos1));
+        assert!(!quota.can_send());
+        assert!(!quota.on_send(&qos1));
+        assert!(quota.on_send(&qos0));
+        assert_eq!(quota.quota(), 0);
+
+        quota.on_receive(&Pubrec::new_success(pid).into());
+        assert_eq!(quota.quota(), 0);
+        quota.on_receive(&Pubrec::new(pid, PubrecReasonCode::QuotaExceeded).into());
+        assert_eq!(quota.quota(), 1);
+        quota.on_receive(&Puback::new_success(pid).into());
+        assert_eq!(quota.quota(), 2);
+        // Never exceed the receive maximum
+        quota.on_receive(&Puback::new_success(pid).into());
+        assert_eq!(quota.quota(), 2);
+    }
+}
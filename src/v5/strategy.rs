@@ -0,0 +1,567 @@
+//! `proptest` strategies of MQTT v5.0 packets and property lists.
+//!
+//! The generated packets pass `Packet::validate()`, see
+//! [`crate::strategy`] for the common types. Topic aliases are never
+//! generated since they depend on the connection state.
+
+use std::fmt::Debug;
+use std::sync::Arc;
+
+use bytes::Bytes;
+use proptest::prelude::*;
+
+use super::{
+    Auth, AuthProperties, AuthReasonCode, Connack, ConnackProperties, Connect, ConnectProperties,
+    ConnectReasonCode, Disconnect, DisconnectProperties, DisconnectReasonCode, LastWill, Packet,
+    Puback, PubackProperties, PubackReasonCode, Pubcomp, PubcompProperties, PubcompReasonCode,
+    Publish, PublishProperties, Pubrec, PubrecProperties, PubrecReasonCode, Pubrel,
+    PubrelProperties, PubrelReasonCode, RetainHandling, Suback, SubackProperties, Subscribe,
+    SubscribeProperties, SubscribeReasonCode, SubscriptionOptions, Unsuback, UnsubackProperties,
+    Unsubscribe, UnsubscribeProperties, UnsubscribeReasonCode, UserProperties, UserProperty,
+    VarByteInt, WillProperties,
+};
+use crate::strategy::{client_id, payload, pid, qos, qos_pid, topic_filter, topic_name};
+use crate::{Protocol, QoS, SharedStr};
+
+/// Any reason code accepted by `from_u8`.
+fn reason_code<T: Clone + Debug + 'static>(
+    from_u8: fn(u8) -> Option<T>,
+) -> impl Strategy<Value = T> {
+    prop::sample::select((0..=u8::MAX).filter_map(from_u8).collect::<Vec<_>>())
+}
+
+fn utf8_string() -> impl Strategy<Value = Arc<str>> {
+    "[ -~]{0,16}".prop_map(Arc::from)
+}
+
+fn non_zero_u16() -> impl Strategy<Value = u16> {
+    1..=u16::MAX
+}
+
+fn non_zero_u32() -> impl Strategy<Value = u32> {
+    1..=u32::MAX
+}
+
+fn subscription_id() -> impl Strategy<Value = VarByteInt> {
+    (1..268435456u32).prop_map(|value| VarByteInt::try_from(value).expect("valid var int"))
+}
+
+/// Clear the payload format indicator if the payload is not UTF-8.
+fn payload_format(is_utf8: Option<bool>, payload: &Bytes) -> Option<bool> {
+    if is_utf8 == Some(true) && std::str::from_utf8(payload).is_err() {
+        Some(false)
+    } else {
+        is_utf8
+    }
+}
+
+pub fn user_properties() -> impl Strategy<Value = UserProperties> {
+    prop::collection::vec((utf8_string(), utf8_string()), 0..3).prop_map(|pairs| {
+        pairs
+            .into_iter()
+            .map(|(name, value)| UserProperty { name, value })
+            .collect()
+    })
+}
+
+pub fn connect_properties() -> impl Strategy<Value = ConnectProperties> {
+    (
+        prop::option::of(any::<u32>()),
+        prop::option::of(non_zero_u16()),
+        prop::option::of(non_zero_u32()),
+        prop::option::of(any::<u16>()),
+        prop::option::of(any::<bool>()),
+        prop::option::of(any::<bool>()),
+        user_properties(),
+        prop::option::of((utf8_string(), prop::option::of(payload()))),
+    )
+        .prop_map(
+            |(
+                session_expiry_interval,
+                receive_max,
+                max_packet_size,
+                topic_alias_max,
+                request_response_info,
+                request_problem_info,
+                user_properties,
+                auth,
+            )| {
+                let (auth_method, auth_data) = match auth {
+                    Some((method, data)) => (Some(method), data),
+                    None => (None, None),
+                };
+                ConnectProperties {
+                    session_expiry_interval,
+                    receive_max,
+                    max_packet_size,
+                    topic_alias_max,
+                    request_response_info,
+                    request_problem_info,
+                    user_properties,
+                    auth_method,
+                    auth_data,
+                }
+            },
+        )
+}
+
+pub fn will_properties() -> impl Strategy<Value = WillProperties> {
+    (
+        prop::option::of(any::<u32>()),
+        prop::option::of(any::<bool>()),
+        prop::option::of(any::<u32>()),
+        prop::option::of(utf8_string()),
+        prop::option::of(topic_name()),
+        prop::option::of(payload()),
+        user_properties(),
+    )
+        .prop_map(
+            |(
+                delay_interval,
+                payload_is_utf8,
+                message_expiry_interval,
+                content_type,
+                response_topic,
+                correlation_data,
+                user_properties,
+            )| WillProperties {
+                delay_interval,
+                payload_is_utf8,
+                message_expiry_interval,
+                content_type,
+                response_topic,
+                correlation_data,
+                user_properties,
+            },
+        )
+}
+
+pub fn last_will() -> impl Strategy<Value = LastWill> {
+    (
+        qos(),
+        any::<bool>(),
+        topic_name(),
+        payload(),
+        will_properties(),
+    )
+        .prop_map(|(qos, retain, topic_name, payload, mut properties)| {
+            properties.payload_is_utf8 = payload_format(properties.payload_is_utf8, &payload);
+            LastWill {
+                qos,
+                retain,
+                topic_name,
+                payload,
+                properties,
+            }
+        })
+}
+
+pub fn connect() -> impl Strategy<Value = Connect> {
+    (
+        client_id(),
+        any::<bool>(),
+        any::<u16>(),
+        connect_properties(),
+        prop::option::of(last_will()),
+        prop::option::of("[a-zA-Z0-9]{0,8}"),
+        prop::option::of(payload()),
+    )
+        .prop_map(
+            |(client_id, clean_start, keep_alive, properties, last_will, username, password)| {
+                Connect {
+                    protocol: Protocol::V500,
+                    clean_start,
+                    keep_alive,
+                    properties,
+                    client_id,
+                    last_will,
+                    username: username.map(SharedStr::from),
+                    password,
+                }
+            },
+        )
+}
+
+pub fn connack_properties() -> impl Strategy<Value = ConnackProperties> {
+    let limits = (
+        prop::option::of(any::<u32>()),
+        prop::option::of(non_zero_u16()),
+        prop::option::of(prop_oneof![Just(QoS::Level0), Just(QoS::Level1)]),
+        prop::option::of(non_zero_u32()),
+        prop::option::of(any::<u16>()),
+        prop::option::of(any::<u16>()),
+    );
+    let available = (
+        prop::option::of(any::<bool>()),
+        prop::option::of(any::<bool>()),
+        prop::option::of(any::<bool>()),
+        prop::option::of(any::<bool>()),
+    );
+    let strings = (
+        prop::option::of(utf8_string()),
+        prop::option::of(utf8_string()),
+        prop::option::of(utf8_string()),
+        prop::option::of(utf8_string()),
+        prop::option::of((utf8_string(), prop::option::of(payload()))),
+    );
+    (limits, available, strings, user_properties()).prop_map(
+        |(limits, available, strings, user_properties)| {
+            let (
+                session_expiry_interval,
+                receive_max,
+                max_qos,
+                max_packet_size,
+                topic_alias_max,
+                server_keep_alive,
+            ) = limits;
+            let (
+                retain_available,
+                wildcard_subscription_available,
+                subscription_id_available,
+                shared_subscription_available,
+            ) = available;
+            let (assigned_client_id, reason_string, response_info, server_reference, auth) =
+                strings;
+            let (auth_method, auth_data) = match auth {
+                Some((method, data)) => (Some(method), data),
+                None => (None, None),
+            };
+            ConnackProperties {
+                session_expiry_interval,
+                receive_max,
+                max_qos,
+                retain_available,
+                max_packet_size,
+                assigned_client_id,
+                topic_alias_max,
+                reason_string,
+                user_properties,
+                wildcard_subscription_available,
+                subscription_id_available,
+                shared_subscription_available,
+                server_keep_alive,
+                response_info,
+                server_reference,
+                auth_method,
+                auth_data,
+            }
+        },
+    )
+}
+
+pub fn connack() -> impl Strategy<Value = Connack> {
+    (
+        any::<bool>(),
+        reason_code(ConnectReasonCode::from_u8),
+        connack_properties(),
+    )
+        .prop_map(|(session_present, reason_code, properties)| Connack {
+            // [MQTT-3.2.2-6]
+            session_present: session_present && reason_code == ConnectReasonCode::Success,
+            reason_code,
+            properties,
+        })
+}
+
+pub fn publish_properties() -> impl Strategy<Value = PublishProperties> {
+    (
+        prop::option::of(any::<bool>()),
+        prop::option::of(any::<u32>()),
+        prop::option::of(topic_name()),
+        prop::option::of(payload()),
+        user_properties(),
+        prop::option::of(subscription_id()),
+        prop::option::of(utf8_string()),
+    )
+        .prop_map(
+            |(
+                payload_is_utf8,
+                message_expiry_interval,
+                response_topic,
+                correlation_data,
+                user_properties,
+                subscription_id,
+                content_type,
+            )| PublishProperties {
+                payload_is_utf8,
+                message_expiry_interval,
+                topic_alias: None,
+                response_topic,
+                correlation_data,
+                user_properties,
+                subscription_id,
+                content_type,
+            },
+        )
+}
+
+pub fn publish() -> impl Strategy<Value = Publish> {
+    (
+        any::<bool>(),
+        any::<bool>(),
+        qos_pid(),
+        topic_name(),
+        payload(),
+        publish_properties(),
+    )
+        .prop_map(
+            |(dup, retain, qos_pid, topic_name, payload, mut properties)| {
+                properties.payload_is_utf8 = payload_format(properties.payload_is_utf8, &payload);
+                Publish {
+                    // [MQTT-3.3.1-2]
+                    dup: dup && qos_pid.qos() != QoS::Level0,
+                    retain,
+                    qos_pid,
+                    topic_name,
+                    payload,
+                    properties,
+                }
+            },
+        )
+}
+
+pub fn puback() -> impl Strategy<Value = Puback> {
+    (
+        pid(),
+        reason_code(PubackReasonCode::from_u8),
+        prop::option::of(utf8_string()),
+        user_properties(),
+    )
+        .prop_map(
+            |(pid, reason_code, reason_string, user_properties)| Puback {
+                pid,
+                reason_code,
+                properties: PubackProperties {
+                    reason_string,
+                    user_properties,
+                },
+            },
+        )
+}
+
+pub fn pubrec() -> impl Strategy<Value = Pubrec> {
+    (
+        pid(),
+        reason_code(PubrecReasonCode::from_u8),
+        prop::option::of(utf8_string()),
+        user_properties(),
+    )
+        .prop_map(
+            |(pid, reason_code, reason_string, user_properties)| Pubrec {
+                pid,
+                reason_code,
+                properties: PubrecProperties {
+                    reason_string,
+                    user_properties,
+                },
+            },
+        )
+}
+
+pub fn pubrel() -> impl Strategy<Value = Pubrel> {
+    (
+        pid(),
+        reason_code(PubrelReasonCode::from_u8),
+        prop::option::of(utf8_string()),
+        user_properties(),
+    )
+        .prop_map(
+            |(pid, reason_code, reason_string, user_properties)| Pubrel {
+                pid,
+                reason_code,
+                properties: PubrelProperties {
+                    reason_string,
+                    user_properties,
+                },
+            },
+        )
+}
+
+pub fn pubcomp() -> impl Strategy<Value = Pubcomp> {
+    (
+        pid(),
+        reason_code(PubcompReasonCode::from_u8),
+        prop::option::of(utf8_string()),
+        user_properties(),
+    )
+        .prop_map(
+            |(pid, reason_code, reason_string, user_properties)| Pubcomp {
+                pid,
+                reason_code,
+                properties: PubcompProperties {
+                    reason_string,
+                    user_properties,
+                },
+            },
+        )
+}
+
+pub fn subscription_options() -> impl Strategy<Value = SubscriptionOptions> {
+    (
+        qos(),
+        any::<bool>(),
+        any::<bool>(),
+        reason_code(RetainHandling::from_u8),
+    )
+        .prop_map(
+            |(max_qos, no_local, retain_as_published, retain_handling)| SubscriptionOptions {
+                max_qos,
+                no_local,
+                retain_as_published,
+                retain_handling,
+            },
+        )
+}
+
+pub fn subscribe_properties() -> impl Strategy<Value = SubscribeProperties> {
+    (prop::option::of(subscription_id()), user_properties()).prop_map(
+        |(subscription_id, user_properties)| SubscribeProperties {
+            subscription_id,
+            user_properties,
+        },
+    )
+}
+
+pub fn subscribe() -> impl Strategy<Value = Subscribe> {
+    let topic = (topic_filter(), subscription_options()).prop_map(|(filter, mut options)| {
+        // [MQTT-3.8.3-4]
+        options.no_local = options.no_local && !filter.is_shared();
+        (filter, options)
+    });
+    (
+        pid(),
+        subscribe_properties(),
+        prop::collection::vec(topic, 1..4),
+    )
+        .prop_map(|(pid, properties, topics)| Subscribe {
+            pid,
+            properties,
+            topics,
+        })
+}
+
+pub fn suback() -> impl Strategy<Value = Suback> {
+    (
+        pid(),
+        prop::option::of(utf8_string()),
+        user_properties(),
+        prop::collection::vec(reason_code(SubscribeReasonCode::from_u8), 1..4),
+    )
+        .prop_map(|(pid, reason_string, user_properties, topics)| Suback {
+            pid,
+            properties: SubackProperties {
+                reason_string,
+                user_properties,
+            },
+            topics,
+        })
+}
+
+pub fn unsubscribe() -> impl Strategy<Value = Unsubscribe> {
+    (
+        pid(),
+        user_properties(),
+        prop::collection::vec(topic_filter(), 1..4),
+    )
+        .prop_map(|(pid, user_properties, topics)| Unsubscribe {
+            pid,
+            properties: UnsubscribeProperties { user_properties },
+            topics,
+        })
+}
+
+pub fn unsuback() -> impl Strategy<Value = Unsuback> {
+    (
+        pid(),
+        prop::option::of(utf8_string()),
+        user_properties(),
+        prop::collection::vec(reason_code(UnsubscribeReasonCode::from_u8), 1..4),
+    )
+        .prop_map(|(pid, reason_string, user_properties, topics)| Unsuback {
+            pid,
+            properties: UnsubackProperties {
+                reason_string,
+                user_properties,
+            },
+            topics,
+        })
+}
+
+pub fn disconnect() -> impl Strategy<Value = Disconnect> {
+    (
+        reason_code(DisconnectReasonCode::from_u8),
+        prop::option::of(any::<u32>()),
+        prop::option::of(utf8_string()),
+        user_properties(),
+        prop::option::of(utf8_string()),
+    )
+        .prop_map(
+            |(
+                reason_code,
+                session_expiry_interval,
+                reason_string,
+                user_properties,
+                server_reference,
+            )| {
+                Disconnect {
+                    reason_code,
+                    properties: DisconnectProperties {
+                        session_expiry_interval,
+                        reason_string,
+                        user_properties,
+                        server_reference,
+                    },
+                }
+            },
+        )
+}
+
+pub fn auth() -> impl Strategy<Value = Auth> {
+    (
+        reason_code(AuthReasonCode::from_u8),
+        prop::option::of((utf8_string(), prop::option::of(payload()))),
+        prop::option::of(utf8_string()),
+        user_properties(),
+    )
+        .prop_map(|(reason_code, auth, reason_string, user_properties)| {
+            let (auth_method, auth_data) = match auth {
+                Some((method, data)) => (Some(method), data),
+                None => (None, None),
+            };
+            Auth {
+                // [MQTT-3.15.2-1]
+                reason_code: if auth_method.is_some() {
+                    reason_code
+                } else {
+                    AuthReasonCode::Success
+                },
+                properties: AuthProperties {
+                    auth_method,
+                    auth_data,
+                    reason_string,
+                    user_properties,
+                },
+            }
+        })
+}
+
+/// Any valid packet.
+pub fn packet() -> impl Strategy<Value = Packet> {
+    prop_oneof![
+        connect().prop_map(Packet::Connect),
+        connack().prop_map(Packet::Connack),
+        publish().prop_map(Packet::Publish),
+        puback().prop_map(Packet::Puback),
+        pubrec().prop_map(Packet::Pubrec),
+        pubrel().prop_map(Packet::Pubrel),
+        pubcomp().prop_map(Packet::Pubcomp),
+        subscribe().prop_map(Packet::Subscribe),
+        suback().prop_map(Packet::Suback),
+        unsubscribe().prop_map(Packet::Unsubscribe),
+        unsuback().prop_map(Packet::Unsuback),
+        Just(Packet::Pingreq),
+        Just(Packet::Pingresp),
+        disconnect().prop_map(Packet::Disconnect),
+        auth().prop_map(Packet::Auth),
+    ]
+}
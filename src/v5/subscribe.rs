@@ -5,17 +5,18 @@ use std::sync::Arc;
 use tokio::io::AsyncRead;
 
 use super::{
-    decode_properties, encode_properties, encode_properties_len, ErrorV5, Header, PacketType,
-    PropertyId, PropertyValue, UserProperty, VarByteInt,
+    decode_body_async, decode_properties, decode_properties_async, encode_properties,
+    encode_properties_len, ErrorV5, Header, PacketType, UserProperties, UserProperty, VarByteInt,
 };
 use crate::{
-    decode_var_int, read_string, read_u16, read_u8, write_bytes, write_u16, write_u8, Encodable,
-    Error, Pid, QoS, TopicFilter,
+    capacity_hint, split_by_packet_size, write_bytes, write_u16, write_u8, Encodable, Error, Pid,
+    QoS, SliceReader, TopicFilter,
 };
 
 /// Body type for SUBSCRIBE packet.
 #[derive(Debug, Clone, PartialEq, Eq, Hash)]
 #[cfg_attr(feature = "arbitrary", derive(arbitrary::Arbitrary))]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct Subscribe {
     pub pid: Pid,
     pub properties: SubscribeProperties,
@@ -31,43 +32,59 @@ impl Subscribe {
         }
     }
 
+    /// Split `topics` into packets not larger than `max_packet_size`
+    /// (include header), each packet has a copy of `properties` and takes a
+    /// packet identifier from `next_pid` (such as `PidAllocator::alloc()`).
+    /// A topic filter which can not fit in a packet is reported as
+    /// `Error::PacketTooLarge`.
+    pub fn split<F>(
+        properties: SubscribeProperties,
+        topics: Vec<(TopicFilter, SubscriptionOptions)>,
+        max_packet_size: usize,
+        mut next_pid: F,
+    ) -> Result<Vec<Self>, ErrorV5>
+    where
+        F: FnMut() -> Result<Pid, Error>,
+    {
+        let base_len = 2 + properties.encode_len();
+        split_by_packet_size(topics, base_len, max_packet_size, |(filter, _)| {
+            3 + filter.len()
+        })?
+        .into_iter()
+        .map(|topics| {
+            Ok(Subscribe {
+                pid: next_pid()?,
+                properties: properties.clone(),
+                topics,
+            })
+        })
+        .collect()
+    }
+
     pub async fn decode_async<T: AsyncRead + Unpin>(
         reader: &mut T,
         header: Header,
     ) -> Result<Self, ErrorV5> {
-        let mut remaining_len = header.remaining_len as usize;
-        let pid = Pid::try_from(read_u16(reader).await?)?;
-        let properties = SubscribeProperties::decode_async(reader, header.typ).await?;
-        remaining_len = remaining_len
-            .checked_sub(2 + properties.encode_len())
-            .ok_or(Error::InvalidRemainingLength)?;
-        if remaining_len == 0 {
+        decode_body_async(reader, header, Self::decode_slice).await
+    }
+
+    pub(crate) fn decode_slice(
+        reader: &mut SliceReader<'_>,
+        header: Header,
+    ) -> Result<Self, ErrorV5> {
+        let pid = reader.read_pid()?;
+        let properties = SubscribeProperties::decode_slice(reader, header.typ)?;
+        if reader.0.is_empty() {
             return Err(Error::EmptySubscription.into());
         }
-        let mut topics = Vec::new();
-        while remaining_len > 0 {
-            let topic_filter = TopicFilter::try_from(read_string(reader).await?)?;
-            let options = {
-                let opt_byte = read_u8(reader).await?;
-                if opt_byte & 0b11000000 > 0 {
-                    return Err(ErrorV5::InvalidSubscriptionOption(opt_byte));
-                }
-                let max_qos = QoS::from_u8(opt_byte & 0b11)
-                    .map_err(|_| ErrorV5::InvalidSubscriptionOption(opt_byte))?;
-                let no_local = opt_byte & 0b100 == 0b100;
-                let retain_as_published = opt_byte & 0b1000 == 0b1000;
-                let retain_handling = RetainHandling::from_u8((opt_byte & 0b110000) >> 4)
-                    .ok_or(ErrorV5::InvalidSubscriptionOption(opt_byte))?;
-                SubscriptionOptions {
-                    max_qos,
-                    no_local,
-                    retain_as_published,
-                    retain_handling,
-                }
-            };
-            remaining_len = remaining_len
-                .checked_sub(3 + topic_filter.len())
-                .ok_or(Error::InvalidRemainingLength)?;
+        let mut topics = Vec::with_capacity(capacity_hint(reader.0.len(), 4));
+        while !reader.0.is_empty() {
+            let topic_filter = TopicFilter::try_from(reader.read_str()?.to_owned())?;
+            let options = SubscriptionOptions::from_u8(reader.read_u8()?)?;
+            // [MQTT-3.8.3-4]
+            if topic_filter.is_shared() && options.no_local {
+                return Err(Error::InvalidPacket("no local on shared subscription").into());
+            }
             topics.push((topic_filter, options));
         }
         Ok(Subscribe {
@@ -102,15 +119,23 @@ impl Encodable for Subscribe {
 /// Property list for SUBSCRIBE packet.
 #[derive(Debug, Clone, PartialEq, Eq, Default, Hash)]
 #[cfg_attr(feature = "arbitrary", derive(arbitrary::Arbitrary))]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct SubscribeProperties {
     pub subscription_id: Option<VarByteInt>,
-    pub user_properties: Vec<UserProperty>,
+    pub user_properties: UserProperties,
 }
 
 impl SubscribeProperties {
     pub async fn decode_async<T: AsyncRead + Unpin>(
         reader: &mut T,
         packet_type: PacketType,
+    ) -> Result<Self, ErrorV5> {
+        decode_properties_async(reader, |reader| Self::decode_slice(reader, packet_type)).await
+    }
+
+    pub(crate) fn decode_slice(
+        reader: &mut SliceReader<'_>,
+        packet_type: PacketType,
     ) -> Result<Self, ErrorV5> {
         let mut properties = SubscribeProperties::default();
         decode_properties!(packet_type, properties, reader, SubscriptionIdentifier,);
@@ -133,6 +158,7 @@ impl Encodable for SubscribeProperties {
 /// Subscription options.
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
 #[cfg_attr(feature = "arbitrary", derive(arbitrary::Arbitrary))]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct SubscriptionOptions {
     pub max_qos: QoS,
     pub no_local: bool,
@@ -161,12 +187,37 @@ impl SubscriptionOptions {
         byte |= (self.retain_handling as u8) << 4;
         byte
     }
+
+    /// The inverse of `to_u8()`, the reserved bits, QoS 3 and retain
+    /// handling 3 are rejected as in decoding.
+    pub fn from_u8(value: u8) -> Result<Self, ErrorV5> {
+        if value & 0b11000000 > 0 {
+            return Err(ErrorV5::InvalidSubscriptionOption(value));
+        }
+        let max_qos =
+            QoS::from_u8(value & 0b11).map_err(|_| ErrorV5::InvalidSubscriptionOption(value))?;
+        let retain_handling = RetainHandling::from_u8((value & 0b110000) >> 4)
+            .ok_or(ErrorV5::InvalidSubscriptionOption(value))?;
+        Ok(SubscriptionOptions {
+            max_qos,
+            no_local: value & 0b100 == 0b100,
+            retain_as_published: value & 0b1000 == 0b1000,
+            retain_handling,
+        })
+    }
+}
+
+impl Default for SubscriptionOptions {
+    fn default() -> Self {
+        Self::new(QoS::Level0)
+    }
 }
 
 /// Retain handling type.
 #[repr(u8)]
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
 #[cfg_attr(feature = "arbitrary", derive(arbitrary::Arbitrary))]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum RetainHandling {
     SendAtSubscribe = 0,
     SendAtSubscribeIfNotExist = 1,
@@ -188,6 +239,7 @@ impl RetainHandling {
 /// Body type for SUBACK packet.
 #[derive(Debug, Clone, PartialEq, Eq)]
 #[cfg_attr(feature = "arbitrary", derive(arbitrary::Arbitrary))]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct Suback {
     pub pid: Pid,
     pub properties: SubackProperties,
@@ -207,19 +259,21 @@ impl Suback {
         reader: &mut T,
         header: Header,
     ) -> Result<Self, ErrorV5> {
-        let mut remaining_len = header.remaining_len as usize;
-        let pid = Pid::try_from(read_u16(reader).await?)?;
-        let properties = SubackProperties::decode_async(reader, header.typ).await?;
-        remaining_len = remaining_len
-            .checked_sub(2 + properties.encode_len())
-            .ok_or(Error::InvalidRemainingLength)?;
-        let mut topics = Vec::new();
-        while remaining_len > 0 {
-            let value = read_u8(reader).await?;
+        decode_body_async(reader, header, Self::decode_slice).await
+    }
+
+    pub(crate) fn decode_slice(
+        reader: &mut SliceReader<'_>,
+        header: Header,
+    ) -> Result<Self, ErrorV5> {
+        let pid = reader.read_pid()?;
+        let properties = SubackProperties::decode_slice(reader, header.typ)?;
+        let mut topics = Vec::with_capacity(reader.0.len());
+        while !reader.0.is_empty() {
+            let value = reader.read_u8()?;
             let code = SubscribeReasonCode::from_u8(value)
                 .ok_or(ErrorV5::InvalidReasonCode(header.typ, value))?;
             topics.push(code);
-            remaining_len -= 1;
         }
         Ok(Suback {
             pid,
@@ -247,15 +301,23 @@ impl Encodable for Suback {
 /// Property list for SUBACK packet.
 #[derive(Debug, Clone, PartialEq, Eq, Default)]
 #[cfg_attr(feature = "arbitrary", derive(arbitrary::Arbitrary))]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct SubackProperties {
-    pub reason_string: Option<Arc<String>>,
-    pub user_properties: Vec<UserProperty>,
+    pub reason_string: Option<Arc<str>>,
+    pub user_properties: UserProperties,
 }
 
 impl SubackProperties {
     pub async fn decode_async<T: AsyncRead + Unpin>(
         reader: &mut T,
         packet_type: PacketType,
+    ) -> Result<Self, ErrorV5> {
+        decode_properties_async(reader, |reader| Self::decode_slice(reader, packet_type)).await
+    }
+
+    pub(crate) fn decode_slice(
+        reader: &mut SliceReader<'_>,
+        packet_type: PacketType,
     ) -> Result<Self, ErrorV5> {
         let mut properties = SubackProperties::default();
         decode_properties!(packet_type, properties, reader, ReasonString,);
@@ -295,6 +357,7 @@ impl Encodable for SubackProperties {
 #[repr(u8)]
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
 #[cfg_attr(feature = "arbitrary", derive(arbitrary::Arbitrary))]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum SubscribeReasonCode {
     GrantedQoS0 = 0x00,
     GrantedQoS1 = 0x01,
@@ -311,6 +374,25 @@ pub enum SubscribeReasonCode {
 }
 
 impl SubscribeReasonCode {
+    /// The success reason code of the granted QoS.
+    pub fn granted(qos: QoS) -> Self {
+        match qos {
+            QoS::Level0 => Self::GrantedQoS0,
+            QoS::Level1 => Self::GrantedQoS1,
+            QoS::Level2 => Self::GrantedQoS2,
+        }
+    }
+
+    /// The granted QoS, or `None` if the subscription is not accepted.
+    pub fn granted_qos(&self) -> Option<QoS> {
+        match self {
+            Self::GrantedQoS0 => Some(QoS::Level0),
+            Self::GrantedQoS1 => Some(QoS::Level1),
+            Self::GrantedQoS2 => Some(QoS::Level2),
+            _ => None,
+        }
+    }
+
     pub fn from_u8(value: u8) -> Option<Self> {
         let code = match value {
             0x00 => Self::GrantedQoS0,
@@ -329,11 +411,36 @@ impl SubscribeReasonCode {
         };
         Some(code)
     }
+
+    /// The description of the reason code in the specification.
+    pub fn description(&self) -> &'static str {
+        match self {
+            Self::GrantedQoS0 => "The subscription is accepted and the maximum QoS sent will be QoS 0. This might be a lower QoS than was requested.",
+            Self::GrantedQoS1 => "The subscription is accepted and the maximum QoS sent will be QoS 1. This might be a lower QoS than was requested.",
+            Self::GrantedQoS2 => "The subscription is accepted and any received QoS will be sent to this subscription.",
+            Self::UnspecifiedError => "The subscription is not accepted and the Server either does not wish to reveal the reason or none of the other Reason Codes apply.",
+            Self::ImplementationSpecificError => "The SUBSCRIBE is valid but the Server does not accept it.",
+            Self::NotAuthorized => "The Client is not authorized to make this subscription.",
+            Self::TopicFilterInvalid => "The Topic Filter is correctly formed but is not allowed for this Client.",
+            Self::PacketIdentifierInUse => "The specified Packet Identifier is already in use.",
+            Self::QuotaExceeded => "An implementation or administrative imposed limit has been exceeded.",
+            Self::SharedSubscriptionNotSupported => "The Server does not support Shared Subscriptions for this Client.",
+            Self::SubscriptionIdentifiersNotSupported => "The Server does not support Subscription Identifiers; the subscription is not accepted.",
+            Self::WildcardSubscriptionsNotSupported => "The Server does not support Wildcard Subscriptions; the subscription is not accepted.",
+        }
+    }
+
+    /// Reason codes less than 0x80 indicate success, the others indicate
+    /// an error.
+    pub fn is_error(&self) -> bool {
+        *self as u8 >= 0x80
+    }
 }
 
 /// Body type for UNSUBSCRIBE packet.
 #[derive(Debug, Clone, PartialEq, Eq, Hash)]
 #[cfg_attr(feature = "arbitrary", derive(arbitrary::Arbitrary))]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct Unsubscribe {
     pub pid: Pid,
     pub properties: UnsubscribeProperties,
@@ -349,41 +456,48 @@ impl Unsubscribe {
         }
     }
 
+    /// Same as `Subscribe::split()`, but for UNSUBSCRIBE packets.
+    pub fn split<F>(
+        properties: UnsubscribeProperties,
+        topics: Vec<TopicFilter>,
+        max_packet_size: usize,
+        mut next_pid: F,
+    ) -> Result<Vec<Self>, ErrorV5>
+    where
+        F: FnMut() -> Result<Pid, Error>,
+    {
+        let base_len = 2 + properties.encode_len();
+        split_by_packet_size(topics, base_len, max_packet_size, |filter| 2 + filter.len())?
+            .into_iter()
+            .map(|topics| {
+                Ok(Unsubscribe {
+                    pid: next_pid()?,
+                    properties: properties.clone(),
+                    topics,
+                })
+            })
+            .collect()
+    }
+
     pub async fn decode_async<T: AsyncRead + Unpin>(
         reader: &mut T,
         header: Header,
     ) -> Result<Self, ErrorV5> {
-        let mut remaining_len = header.remaining_len as usize;
-        let pid = Pid::try_from(read_u16(reader).await?)?;
-        let (property_len, property_len_bytes) = decode_var_int(reader).await?;
-        let mut properties = UnsubscribeProperties::default();
-        let mut len = 0;
-        while property_len as usize > len {
-            let property_id = PropertyId::from_u8(read_u8(reader).await?)?;
-            match property_id {
-                PropertyId::UserProperty => {
-                    let property = PropertyValue::decode_user_property(reader).await?;
-                    len += 1 + 4 + property.name.len() + property.value.len();
-                    properties.user_properties.push(property);
-                }
-                _ => return Err(ErrorV5::InvalidProperty(header.typ, property_id)),
-            }
-        }
-        if property_len as usize != len {
-            return Err(ErrorV5::InvalidPropertyLength(property_len));
-        }
-        remaining_len = remaining_len
-            .checked_sub(2 + property_len_bytes + len)
-            .ok_or(Error::InvalidRemainingLength)?;
-        if remaining_len == 0 {
+        decode_body_async(reader, header, Self::decode_slice).await
+    }
+
+    pub(crate) fn decode_slice(
+        reader: &mut SliceReader<'_>,
+        header: Header,
+    ) -> Result<Self, ErrorV5> {
+        let pid = reader.read_pid()?;
+        let properties = UnsubscribeProperties::decode_slice(reader, header.typ)?;
+        if reader.0.is_empty() {
             return Err(Error::EmptySubscription.into());
         }
-        let mut topics = Vec::new();
-        while remaining_len > 0 {
-            let topic_filter = TopicFilter::try_from(read_string(reader).await?)?;
-            remaining_len = remaining_len
-                .checked_sub(2 + topic_filter.len())
-                .ok_or(Error::InvalidRemainingLength)?;
+        let mut topics = Vec::with_capacity(capacity_hint(reader.0.len(), 3));
+        while !reader.0.is_empty() {
+            let topic_filter = TopicFilter::try_from(reader.read_str()?.to_owned())?;
             topics.push(topic_filter);
         }
         Ok(Unsubscribe {
@@ -419,14 +533,22 @@ impl Encodable for Unsubscribe {
 /// Property list for UNSUBSCRIBE packet.
 #[derive(Debug, Clone, PartialEq, Eq, Default, Hash)]
 #[cfg_attr(feature = "arbitrary", derive(arbitrary::Arbitrary))]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct UnsubscribeProperties {
-    pub user_properties: Vec<UserProperty>,
+    pub user_properties: UserProperties,
 }
 
 impl UnsubscribeProperties {
     pub async fn decode_async<T: AsyncRead + Unpin>(
         reader: &mut T,
         packet_type: PacketType,
+    ) -> Result<Self, ErrorV5> {
+        decode_properties_async(reader, |reader| Self::decode_slice(reader, packet_type)).await
+    }
+
+    pub(crate) fn decode_slice(
+        reader: &mut SliceReader<'_>,
+        packet_type: PacketType,
     ) -> Result<Self, ErrorV5> {
         let mut properties = UnsubscribeProperties::default();
         decode_properties!(packet_type, properties, reader,);
@@ -448,13 +570,16 @@ impl Encodable for UnsubscribeProperties {
 
 impl From<Vec<UserProperty>> for UnsubscribeProperties {
     fn from(user_properties: Vec<UserProperty>) -> UnsubscribeProperties {
-        UnsubscribeProperties { user_properties }
+        UnsubscribeProperties {
+            user_properties: user_properties.into_iter().collect(),
+        }
     }
 }
 
 /// Body type for UNSUBACK packet.
 #[derive(Debug, Clone, PartialEq, Eq)]
 #[cfg_attr(feature = "arbitrary", derive(arbitrary::Arbitrary))]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct Unsuback {
     pub pid: Pid,
     pub properties: UnsubackProperties,
@@ -474,19 +599,21 @@ impl Unsuback {
         reader: &mut T,
         header: Header,
     ) -> Result<Self, ErrorV5> {
-        let mut remaining_len = header.remaining_len as usize;
-        let pid = Pid::try_from(read_u16(reader).await?)?;
-        let properties = UnsubackProperties::decode_async(reader, header.typ).await?;
-        remaining_len = remaining_len
-            .checked_sub(2 + properties.encode_len())
-            .ok_or(Error::InvalidRemainingLength)?;
-        let mut topics = Vec::new();
-        while remaining_len > 0 {
-            let value = read_u8(reader).await?;
+        decode_body_async(reader, header, Self::decode_slice).await
+    }
+
+    pub(crate) fn decode_slice(
+        reader: &mut SliceReader<'_>,
+        header: Header,
+    ) -> Result<Self, ErrorV5> {
+        let pid = reader.read_pid()?;
+        let properties = UnsubackProperties::decode_slice(reader, header.typ)?;
+        let mut topics = Vec::with_capacity(reader.0.len());
+        while !reader.0.is_empty() {
+            let value = reader.read_u8()?;
             let code = UnsubscribeReasonCode::from_u8(value)
                 .ok_or(ErrorV5::InvalidReasonCode(header.typ, value))?;
             topics.push(code);
-            remaining_len -= 1;
         }
         Ok(Unsuback {
             pid,
@@ -514,15 +641,23 @@ impl Encodable for Unsuback {
 /// Property list for UNSUBACK packet.
 #[derive(Debug, Clone, PartialEq, Eq, Default)]
 #[cfg_attr(feature = "arbitrary", derive(arbitrary::Arbitrary))]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct UnsubackProperties {
-    pub reason_string: Option<Arc<String>>,
-    pub user_properties: Vec<UserProperty>,
+    pub reason_string: Option<Arc<str>>,
+    pub user_properties: UserProperties,
 }
 
 impl UnsubackProperties {
     pub async fn decode_async<T: AsyncRead + Unpin>(
         reader: &mut T,
         packet_type: PacketType,
+    ) -> Result<Self, ErrorV5> {
+        decode_properties_async(reader, |reader| Self::decode_slice(reader, packet_type)).await
+    }
+
+    pub(crate) fn decode_slice(
+        reader: &mut SliceReader<'_>,
+        packet_type: PacketType,
     ) -> Result<Self, ErrorV5> {
         let mut properties = UnsubackProperties::default();
         decode_properties!(packet_type, properties, reader, ReasonString,);
@@ -557,6 +692,7 @@ impl Encodable for UnsubackProperties {
 #[repr(u8)]
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
 #[cfg_attr(feature = "arbitrary", derive(arbitrary::Arbitrary))]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum UnsubscribeReasonCode {
     Success = 0x00,
     NoSubscriptionExisted = 0x11,
@@ -581,4 +717,23 @@ impl UnsubscribeReasonCode {
         };
         Some(code)
     }
+
+    /// The description of the reason code in the specification.
+    pub fn description(&self) -> &'static str {
+        match self {
+            Self::Success => "The subscription is deleted.",
+            Self::NoSubscriptionExisted => "No matching Topic Filter is being used by the Client.",
+            Self::UnspecifiedError => "The unsubscribe could not be completed and the Server either does not wish to reveal the reason or none of the other Reason Codes apply.",
+            Self::ImplementationSpecificError => "The UNSUBSCRIBE is valid but the Server does not accept it.",
+            Self::NotAuthorized => "The Client is not authorized to unsubscribe.",
+            Self::TopicFilterInvalid => "The Topic Filter is correctly formed but is not allowed for this Client.",
+            Self::PacketIdentifierInUse => "The specified Packet Identifier is already in use.",
+        }
+    }
+
+    /// Reason codes less than 0x80 indicate success, the others indicate
+    /// an error.
+    pub fn is_error(&self) -> bool {
+        *self as u8 >= 0x80
+    }
 }
@@ -0,0 +1,188 @@
+use std::collections::HashMap;
+
+use super::{
+    Publish, Suback, Subscribe, SubscriptionOptions, Unsuback, Unsubscribe, UnsubscribeReasonCode,
+    VarByteInt,
+};
+use crate::{Pid, TopicFilter, TopicTree};
+
+/// A subscription granted by the server.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Subscription {
+    /// The options of the SUBSCRIBE, `max_qos` is the granted QoS.
+    pub options: SubscriptionOptions,
+    pub subscription_id: Option<VarByteInt>,
+}
+
+/// The subscriptions of a client, to dispatch the received PUBLISH packets
+/// to the local subscriptions.
+///
+/// The SUBSCRIBE and UNSUBSCRIBE packets are recorded when sent, and applied
+/// when the SUBACK and UNSUBACK with the same packet identifier is received.
+#[derive(Debug, Clone, Default)]
+pub struct SubscriptionTable {
+    tree: TopicTree<Subscription>,
+    pending_subscribe: HashMap<Pid, Subscribe>,
+    pending_unsubscribe: HashMap<Pid, Vec<TopicFilter>>,
+}
+
+impl SubscriptionTable {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Number of granted subscriptions.
+    pub fn len(&self) -> usize {
+        self.tree.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.tree.is_empty()
+    }
+
+    pub fn get(&self, filter: &TopicFilter) -> Option<&Subscription> {
+        self.tree.get(filter)
+    }
+
+    /// Record a SUBSCRIBE packet to send.
+    pub fn on_subscribe(&mut self, subscribe: &Subscribe) {
+        self.pending_subscribe
+            .insert(subscribe.pid, subscribe.clone());
+    }
+
+    /// Apply a received SUBACK, the filters with granted QoS are added (or
+    /// replaced). Return false if no SUBSCRIBE is recorded for the packet
+    /// identifier.
+    pub fn on_suback(&mut self, suback: &Suback) -> bool {
+        let Some(subscribe) = self.pending_subscribe.remove(&suback.pid) else {
+            return false;
+        };
+        let subscription_id = subscribe.properties.subscription_id;
+        for ((filter, mut options), code) in subscribe.topics.into_iter().zip(&suback.topics) {
+            let Some(max_qos) = code.granted_qos() else {
+                continue;
+            };
+            options.max_qos = max_qos;
+            self.tree.insert(
+                filter,
+                Subscription {
+                    options,
+                    subscription_id,
+                },
+            );
+        }
+        true
+    }
+
+    /// Record an UNSUBSCRIBE packet to send.
+    pub fn on_unsubscribe(&mut self, unsubscribe: &Unsubscribe) {
+        self.pending_unsubscribe
+            .insert(unsubscribe.pid, unsubscribe.topics.clone());
+    }
+
+    /// Apply a received UNSUBACK, the filters are removed unless the reason
+    /// code is an error. Return false if no UNSUBSCRIBE is recorded for the
+    /// packet identifier.
+    pub fn on_unsuback(&mut self, unsuback: &Unsuback) -> bool {
+        let Some(filters) = self.pending_unsubscribe.remove(&unsuback.pid) else {
+            return false;
+        };
+        for (filter, code) in filters.iter().zip(&unsuback.topics) {
+            if matches!(
+                code,
+                UnsubscribeReasonCode::Success | UnsubscribeReasonCode::NoSubscriptionExisted
+            ) {
+                self.tree.remove(filter);
+            }
+        }
+        true
+    }
+
+    /// Find the subscriptions matching a received PUBLISH. The topic alias
+    /// must be resolved before (see `TopicAliasManager`).
+    pub fn matches(&self, publish: &Publish) -> Vec<(&TopicFilter, &Subscription)> {
+        self.tree.matches(&publish.topic_name)
+    }
+
+    /// Drop all the subscriptions, such as when the server has no session
+    /// for the client (session present is false in CONNACK).
+    pub fn clear(&mut self) {
+        *self = Self::default();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::convert::TryFrom;
+
+    use bytes::Bytes;
+
+    use super::*;
+    use crate::v5::SubscribeReasonCode;
+    use crate::{QoS, QosPid, TopicName};
+
+    fn filter(value: &str) -> TopicFilter {
+        TopicFilter::try_from(value.to_owned()).unwrap()
+    }
+
+    fn matched(table: &SubscriptionTable, topic: &str) -> Vec<String> {
+        let topic_name = TopicName::try_from(topic.to_owned()).unwrap();
+        let publish = Publish::new(QosPid::Level0, topic_name, Bytes::new());
+        let mut filters: Vec<String> = table
+            .matches(&publish)
+            .into_iter()
+            .map(|(filter, _)| filter.to_string())
+            .collect();
+        filters.sort();
+        filters
+    }
+
+    #[test]
+    fn test_subscription_table() {
+        let pid1 = Pid::try_from(1).unwrap();
+        let pid2 = Pid::try_from(2).unwrap();
+        let mut table = SubscriptionTable::new();
+
+        let mut subscribe = Subscribe::new(
+            pid1,
+            vec![
+                (filter("a/+"), SubscriptionOptions::new(QoS::Level2)),
+                (filter("a/#"), SubscriptionOptions::new(QoS::Level1)),
+                (filter("denied"), SubscriptionOptions::new(QoS::Level1)),
+            ],
+        );
+        subscribe.properties.subscription_id = Some(VarByteInt::from(7u8));
+        table.on_subscribe(&subscribe);
+        assert!(table.is_empty());
+        assert!(!table.on_suback(&Suback::new(pid2, vec![])));
+        assert!(table.on_suback(&Suback::new(
+            pid1,
+            vec![
+                SubscribeReasonCode::GrantedQoS1,
+                SubscribeReasonCode::GrantedQoS1,
+                SubscribeReasonCode::NotAuthorized,
+            ],
+        )));
+        assert_eq!(table.len(), 2);
+        let subscription = table.get(&filter("a/+")).unwrap();
+        assert_eq!(subscription.options.max_qos, QoS::Level1);
+        assert_eq!(subscription.subscription_id, Some(VarByteInt::from(7u8)));
+        assert_eq!(matched(&table, "a/b"), vec!["a/#", "a/+"]);
+        assert_eq!(matched(&table, "a/b/c"), vec!["a/#"]);
+        assert!(matched(&table, "denied").is_empty());
+
+        table.on_unsubscribe(&Unsubscribe::new(pid2, vec![filter("a/+"), filter("a/#")]));
+        assert!(table.on_unsuback(&Unsuback::new(
+            pid2,
+            vec![
+                UnsubscribeReasonCode::Success,
+                UnsubscribeReasonCode::NotAuthorized,
+            ],
+        )));
+        assert!(!table.on_unsuback(&Unsuback::new(pid2, vec![])));
+        assert_eq!(matched(&table, "a/b"), vec!["a/#"]);
+
+        table.clear();
+        assert!(table.is_empty());
+    }
+}
@@ -0,0 +1,120 @@
+use std::convert::TryFrom;
+use std::sync::Arc;
+
+use bytes::Bytes;
+
+use crate::v3;
+use crate::v5::*;
+use crate::*;
+
+#[test]
+fn test_v5_convert_publish() {
+    let v3_publish = v3::Publish::new(
+        QosPid::Level1(Pid::try_from(3).unwrap()),
+        TopicName::try_from("a/b".to_owned()).unwrap(),
+        Bytes::from("hello"),
+    );
+    let publish = v3_publish.clone().into_v5();
+    assert_eq!(publish.properties, PublishProperties::default());
+    assert_eq!(
+        v3::Publish::try_from(publish.clone()),
+        Ok(v3_publish.clone())
+    );
+
+    let mut publish = publish;
+    publish.properties.topic_alias = Some(2);
+    publish.properties.user_properties = [UserProperty {
+        name: Arc::from("k"),
+        value: Arc::from("v"),
+    }]
+    .into_iter()
+    .collect();
+    let (converted, report) = publish.clone().into_v3_lossy();
+    assert_eq!(converted, v3_publish);
+    assert_eq!(
+        report.properties,
+        vec![PropertyId::TopicAlias, PropertyId::UserProperty]
+    );
+    assert!(!report.is_empty());
+    assert_eq!(v3::Publish::try_from(publish), Err(report));
+}
+
+#[test]
+fn test_v5_convert_subscribe() {
+    let filter = TopicFilter::try_from("a/+".to_owned()).unwrap();
+    let pid = Pid::try_from(5).unwrap();
+    let v3_subscribe = v3::Subscribe::new(pid, vec![(filter.clone(), QoS::Level2)]);
+    let subscribe = Subscribe::from(v3_subscribe.clone());
+    assert_eq!(
+        subscribe.topics,
+        vec![(filter.clone(), SubscriptionOptions::new(QoS::Level2))]
+    );
+    assert_eq!(
+        v3::Subscribe::try_from(subscribe.clone()),
+        Ok(v3_subscribe.clone())
+    );
+
+    let mut subscribe = subscribe;
+    subscribe.topics[0].1.no_local = true;
+    let (converted, report) = subscribe.into_v3_lossy();
+    assert_eq!(converted, v3_subscribe);
+    assert!(report.properties.is_empty());
+    assert!(report.subscription_options);
+}
+
+#[test]
+fn test_v5_convert_suback() {
+    let pid = Pid::try_from(5).unwrap();
+    let v3_suback = v3::Suback::new(
+        pid,
+        vec![
+            v3::SubscribeReturnCode::MaxLevel1,
+            v3::SubscribeReturnCode::Failure,
+        ],
+    );
+    let suback = v3_suback.clone().into_v5();
+    assert_eq!(
+        suback.topics,
+        vec![
+            SubscribeReasonCode::GrantedQoS1,
+            SubscribeReasonCode::UnspecifiedError
+        ]
+    );
+    assert_eq!(v3::Suback::try_from(suback), Ok(v3_suback.clone()));
+
+    let suback = Suback::new(
+        pid,
+        vec![
+            SubscribeReasonCode::GrantedQoS1,
+            SubscribeReasonCode::NotAuthorized,
+        ],
+    );
+    let (converted, report) = suback.into_v3_lossy();
+    assert_eq!(converted, v3_suback);
+    assert!(report.reason_code);
+}
+
+#[test]
+fn test_v5_convert_acks() {
+    let pid = Pid::try_from(7).unwrap();
+    let (converted, report) = Puback::new_success(pid).into_v3_lossy();
+    assert_eq!(converted, pid);
+    assert!(report.is_empty());
+    let (converted, report) = Pubrel::new_success(pid).into_v3_lossy();
+    assert_eq!(converted, pid);
+    assert!(report.is_empty());
+
+    let mut pubrec = Pubrec::new(pid, PubrecReasonCode::NotAuthorized);
+    pubrec.properties.reason_string = Some(Arc::from("denied"));
+    let (converted, report) = pubrec.into_v3_lossy();
+    assert_eq!(converted, pid);
+    assert_eq!(report.properties, vec![PropertyId::ReasonString]);
+    assert!(report.reason_code);
+
+    let unsuback = Unsuback::new(pid, vec![UnsubscribeReasonCode::NoSubscriptionExisted]);
+    let (converted, report) = unsuback.into_v3_lossy();
+    assert_eq!(converted, pid);
+    assert!(report.is_empty());
+    let unsuback = Unsuback::new(pid, vec![UnsubscribeReasonCode::NotAuthorized]);
+    assert!(unsuback.into_v3_lossy().1.reason_code);
+}
@@ -1,4 +1,4 @@
-use bytes::Bytes;
+use bytes::{Bytes, BytesMut};
 use std::sync::Arc;
 
 use futures_lite::future::block_on;
@@ -58,7 +58,7 @@ fn test_v5_header_firstbyte() {
 #[test]
 fn test_v5_header_len() {
     use PacketType::*;
-    for (bytes, res) in vec![
+    for (bytes, res) in [
         (
             vec![1 << 4, 0],
             Ok(Header::new(Connect, false, Level0, false, 0)),
@@ -87,6 +87,7 @@ fn test_v5_header_len() {
         let slice_buf = bytes.as_slice();
         assert_eq!(res, Header::decode(slice_buf));
     }
+    assert!(Header::decode(&[1 << 4, 0x80]).unwrap_err().is_eof());
 }
 
 #[test]
@@ -125,7 +126,7 @@ fn test_v5_decode_connect() {
             clean_start: false,
             keep_alive: 10,
             properties: Default::default(),
-            client_id: Arc::new("test".to_string()),
+            client_id: SharedStr::from("test"),
             last_will: None,
             username: None,
             password: Some(Bytes::from(vec![b'm', b'q', b't'])),
@@ -146,7 +147,15 @@ fn test_v5_decode_connect() {
         0x00, 0x04, b't', b'e', b's', b't', // client_id
         0x00, 0x03, b'm', b'q', // password with invalid length
     ];
-    assert_eq!(Ok(None), Packet::decode(data));
+    assert_eq!(
+        Err(Error::InvalidRemainingLength.into()),
+        Packet::decode(data)
+    );
+    assert_eq!(
+        block_on(Packet::decode_async(&mut &data[..])),
+        Err(Error::InvalidRemainingLength.into())
+    );
+    assert_eq!(Ok(None), Packet::decode(&data[..data.len() - 1]));
     assert_eq!(
         block_on(PollPacket::new(&mut Default::default(), &mut data)).unwrap_err(),
         Error::InvalidRemainingLength.into()
@@ -234,7 +243,6 @@ fn test_v5_decode_connect() {
 
 #[test]
 fn test_v5_decode_connack() {
-    // FIXME: check remaining length in Packet::decode_async()
     let mut data: &[u8] = &[0b00100000, 3, 0x00, 0x84, 0x00];
     assert_eq!(
         Packet::decode(data).unwrap().unwrap(),
@@ -267,7 +275,7 @@ fn test_v5_decode_connack() {
             reason_code: ConnectReasonCode::UnsupportedProtocolVersion,
             properties: ConnackProperties {
                 max_qos: Some(QoS::Level1),
-                reason_string: Some(Arc::new("abc".to_string())),
+                reason_string: Some(Arc::from("abc")),
                 ..Default::default()
             },
         })
@@ -279,6 +287,33 @@ fn test_v5_decode_connack() {
             .2,
     );
 
+    // The properties exceed the remaining length, the next packet is not read
+    let data: &[u8] = &[
+        0b00100000, 5, 0x00, 0x00, 0x03, 0x24, 0x01, // CONNACK
+        0xd0, 0x00, // PINGRESP
+    ];
+    let mut reader = data;
+    assert_eq!(
+        block_on(Packet::decode_async(&mut reader)).unwrap_err(),
+        Error::InvalidRemainingLength.into()
+    );
+    assert_eq!(reader, &[0xd0, 0x00]);
+    assert_eq!(
+        Packet::decode(data).unwrap_err(),
+        Error::InvalidRemainingLength.into()
+    );
+
+    // Bytes left after the properties
+    let mut data: &[u8] = &[0b00100000, 4, 0x00, 0x00, 0x00, 0x00];
+    assert_eq!(
+        Packet::decode(data).unwrap_err(),
+        Error::InvalidRemainingLength.into()
+    );
+    assert_eq!(
+        Packet::decode(data).unwrap_err(),
+        block_on(PollPacket::new(&mut Default::default(), &mut data)).unwrap_err()
+    );
+
     for byte0 in [2, 3, 4, 128u8] {
         let mut data: &[u8] = &[0b00100000, 2, byte0, 0x84, 0x00];
         assert_eq!(
@@ -325,6 +360,80 @@ fn test_v5_decode_connack() {
         Packet::decode(data).unwrap_err(),
         block_on(PollPacket::new(&mut Default::default(), &mut data)).unwrap_err()
     );
+
+    let mut data: &[u8] = &[
+        0b00100000, // packet type
+        6,          // remaining length
+        0x00,       // session_present
+        0x00,       // reason code
+        0x03,       // property length
+        0x21, 0x00, 0x00, // receive maximum
+    ];
+    assert_eq!(
+        Packet::decode(data).unwrap_err(),
+        ErrorV5::ZeroProperty(PropertyId::ReceiveMaximum),
+    );
+    assert_eq!(
+        Packet::decode(data).unwrap_err(),
+        block_on(PollPacket::new(&mut Default::default(), &mut data)).unwrap_err()
+    );
+
+    let data: &[u8] = &[
+        0b00100000, // packet type
+        8,          // remaining length
+        0x00,       // session_present
+        0x00,       // reason code
+        0x05,       // property length
+        0x27, 0x00, 0x00, 0x00, 0x00, // maximum packet size
+    ];
+    assert_eq!(
+        Packet::decode(data).unwrap_err(),
+        ErrorV5::ZeroProperty(PropertyId::MaximumPacketSize),
+    );
+    assert_eq!(
+        decode_property_list(&data[4..]),
+        Err(ErrorV5::ZeroProperty(PropertyId::MaximumPacketSize))
+    );
+}
+
+#[test]
+fn test_v5_zero_subscription_id() {
+    let mut data: &[u8] = &[
+        0b10000010, // packet type
+        9,          // remaining length
+        0x00, 0x01, // pid
+        0x02, // property length
+        0x0B, 0x00, // subscription identifier
+        0x00, 0x01, b'a', // topic filter
+        0x01, // subscription options
+    ];
+    assert_eq!(
+        Packet::decode(data).unwrap_err(),
+        ErrorV5::ZeroSubscriptionId
+    );
+    assert_eq!(
+        decode_property_list(&data[4..]),
+        Err(ErrorV5::ZeroSubscriptionId)
+    );
+    assert_eq!(
+        Packet::decode(data).unwrap_err(),
+        block_on(PollPacket::new(&mut Default::default(), &mut data)).unwrap_err()
+    );
+
+    let mut subscribe = Subscribe::new(
+        Pid::try_from(1).unwrap(),
+        vec![(
+            TopicFilter::try_from("a".to_owned()).unwrap(),
+            SubscriptionOptions::new(QoS::Level1),
+        )],
+    );
+    subscribe.properties.subscription_id = Some(VarByteInt::from(0u8));
+    assert_eq!(
+        Packet::from(subscribe.clone()).validate(),
+        Err(ErrorV5::ZeroSubscriptionId)
+    );
+    subscribe.properties.subscription_id = Some(VarByteInt::from(1u8));
+    assert_eq!(Packet::from(subscribe).validate(), Ok(()));
 }
 
 #[test]
@@ -432,7 +541,7 @@ fn test_v5_decode_auth() {
         Packet::Auth(Auth {
             reason_code: AuthReasonCode::ContinueAuthentication,
             properties: AuthProperties {
-                reason_string: Some(Arc::new("xy".to_string())),
+                reason_string: Some(Arc::from("xy")),
                 ..Default::default()
             },
         })
@@ -736,6 +845,79 @@ fn test_v5_decode_publish() {
     );
 }
 
+#[test]
+fn test_v5_decode_buf() {
+    let data: &[u8] = &[
+        0b00110010, 15, 0x00, 0x03, b'a', b'/', b'b', 0, 10, // topic + pid
+        2, 0x01, 0x01, // properties: payload is utf8
+        b'h', b'e', b'l', b'l', b'o', //
+        0b11000000, 0b00000000,
+    ];
+
+    let mut buf = BytesMut::from(&data[..12]);
+    assert_eq!(Packet::decode_buf(&mut buf), Ok(None));
+    assert_eq!(buf.as_ref(), &data[..12]);
+    buf.extend_from_slice(&data[12..]);
+    match Packet::decode_buf(&mut buf).unwrap().unwrap() {
+        Packet::Publish(p) => {
+            assert_eq!(&*p.topic_name, "a/b");
+            assert_eq!(p.properties.payload_is_utf8, Some(true));
+            assert_eq!(p.payload.as_ref(), b"hello");
+        }
+        other => panic!("Failed decode: {:?}", other),
+    }
+    assert_eq!(Packet::decode_buf(&mut buf), Ok(Some(Packet::Pingreq)));
+    assert!(buf.is_empty());
+}
+
+#[test]
+fn test_v5_decode_shared_publish() {
+    let data: &[u8] = &[
+        0b00110010, 15, 0x00, 0x03, b'a', b'/', b'b', 0, 10, // topic + pid
+        2, 0x01, 0x01, // properties: payload is utf8
+        b'h', b'e', b'l', b'l', b'o', //
+        0b11000000, 0b00000000,
+    ];
+
+    let mut buf = Bytes::copy_from_slice(&data[..12]);
+    assert_eq!(Packet::decode_shared(&mut buf), Ok(None));
+    assert_eq!(buf.as_ref(), &data[..12]);
+
+    let mut buf = Bytes::copy_from_slice(data);
+    let base = buf.as_ptr() as usize;
+    match Packet::decode_shared(&mut buf).unwrap().unwrap() {
+        Packet::Publish(p) => {
+            assert_eq!(p.qos_pid, QosPid::Level1(Pid::try_from(10).unwrap()));
+            assert_eq!(&*p.topic_name, "a/b");
+            assert_eq!(p.properties.payload_is_utf8, Some(true));
+            assert_eq!(p.payload.as_ref(), b"hello");
+            assert_eq!(p.payload.as_ptr() as usize, base + 12);
+        }
+        other => panic!("Failed decode: {:?}", other),
+    }
+    assert_eq!(Packet::decode_shared(&mut buf), Ok(Some(Packet::Pingreq)));
+    assert!(buf.is_empty());
+}
+
+#[test]
+fn test_v5_decode_publish_streaming() {
+    let mut publish = Publish::new(
+        QosPid::Level0,
+        TopicName::try_from("a/b".to_owned()).unwrap(),
+        Bytes::from(vec![7u8; 20000]),
+    );
+    publish.properties.message_expiry_interval = Some(30);
+    let data = Packet::Publish(publish.clone()).encode().unwrap();
+
+    let mut reader = data.as_ref();
+    let mut sink = Vec::new();
+    let packet = block_on(Packet::decode_async_streaming(&mut reader, &mut sink)).unwrap();
+    assert!(reader.is_empty());
+    assert_eq!(sink, publish.payload);
+    publish.payload = Bytes::new();
+    assert_eq!(packet, Packet::Publish(publish));
+}
+
 #[test]
 fn test_v5_decode_puback() {
     let mut data: &[u8] = &[
@@ -756,8 +938,8 @@ fn test_v5_decode_puback() {
             pid: Pid::try_from(0x1122).unwrap(),
             reason_code: PubackReasonCode::NotAuthorized,
             properties: PubackProperties {
-                reason_string: Some(Arc::new("e".to_string())),
-                user_properties: Vec::new(),
+                reason_string: Some(Arc::from("e")),
+                user_properties: UserProperties::new(),
             },
         })
     );
@@ -832,8 +1014,8 @@ fn test_v5_decode_pubrec() {
             pid: Pid::try_from(0x1122).unwrap(),
             reason_code: PubrecReasonCode::NotAuthorized,
             properties: PubrecProperties {
-                reason_string: Some(Arc::new("e".to_string())),
-                user_properties: Vec::new(),
+                reason_string: Some(Arc::from("e")),
+                user_properties: UserProperties::new(),
             },
         })
     );
@@ -907,8 +1089,8 @@ fn test_v5_decode_pubrel() {
             pid: Pid::try_from(0x1122).unwrap(),
             reason_code: PubrelReasonCode::PacketIdentifierNotFound,
             properties: PubrelProperties {
-                reason_string: Some(Arc::new("e".to_string())),
-                user_properties: Vec::new(),
+                reason_string: Some(Arc::from("e")),
+                user_properties: UserProperties::new(),
             },
         })
     );
@@ -982,8 +1164,8 @@ fn test_v5_decode_pubcomp() {
             pid: Pid::try_from(0x1122).unwrap(),
             reason_code: PubcompReasonCode::PacketIdentifierNotFound,
             properties: PubcompProperties {
-                reason_string: Some(Arc::new("e".to_string())),
-                user_properties: Vec::new(),
+                reason_string: Some(Arc::from("e")),
+                user_properties: UserProperties::new(),
             },
         })
     );
@@ -1038,6 +1220,104 @@ fn test_v5_decode_pubcomp() {
     );
 }
 
+#[test]
+fn test_v5_decode_with_limits() {
+    let data: &[u8] = &[
+        0b01000000, // PUBACK
+        11,         // remaining length
+        0x00,       // packet identifier = 10
+        0x0A, 0x00, // reason code = success
+        7,    // properties.len = 7
+        0x26, // user property: "a" = "b"
+        0x00, 0x01, b'a', 0x00, 0x01, b'b',
+    ];
+    let limits = DecodeLimits::default();
+    assert_eq!(
+        Packet::decode_with_limits(data, &limits),
+        Packet::decode(data)
+    );
+
+    let limits = DecodeLimits {
+        max_user_properties: 0,
+        ..Default::default()
+    };
+    assert_eq!(
+        Packet::decode_with_limits(data, &limits),
+        Err(Error::DecodeLimitExceeded("max_user_properties").into())
+    );
+
+    let limits = DecodeLimits {
+        max_properties_len: 7,
+        ..Default::default()
+    };
+    assert_eq!(
+        Packet::decode_with_limits(data, &limits),
+        Err(Error::DecodeLimitExceeded("max_properties_len").into())
+    );
+    let mut reader = data;
+    assert_eq!(
+        block_on(PollPacket::new(&mut Default::default(), &mut reader).with_limits(limits))
+            .unwrap_err(),
+        Error::DecodeLimitExceeded("max_properties_len").into()
+    );
+}
+
+#[test]
+fn test_v5_decode_topic_alias_max() {
+    // QoS 0 PUBLISH with topic name "a" and topic alias = 0/3
+    let mut data = vec![0b00110000, 7, 0, 1, b'a', 3, 0x23, 0, 0];
+    let limits = DecodeLimits {
+        topic_alias_max: Some(2),
+        ..Default::default()
+    };
+    assert!(Packet::decode(&data).is_ok());
+    assert_eq!(
+        Packet::decode_with_limits(&data, &limits),
+        Err(ErrorV5::InvalidTopicAlias(0))
+    );
+    data[8] = 3;
+    assert_eq!(
+        Packet::decode_with_limits(&data, &limits),
+        Err(ErrorV5::InvalidTopicAlias(3))
+    );
+    data[8] = 2;
+    assert!(Packet::decode_with_limits(&data, &limits).is_ok());
+}
+
+#[test]
+fn test_v5_poll_max_packet_size() {
+    use futures_lite::StreamExt;
+
+    // PUBLISH header with remaining length = 200, the body is not sent
+    let data: &[u8] = &[0b00110000, 0xC8, 0x01];
+    let mut reader = data;
+    let mut state = PollPacketState::default();
+    assert_eq!(
+        block_on(PollPacket::new(&mut state, &mut reader).with_max_packet_size(128)).unwrap_err(),
+        Error::PacketTooLarge(203).into()
+    );
+    assert!(reader.is_empty());
+
+    let mut data: &[u8] = &[0b11000000, 0b00000000];
+    let mut state = PollPacketState::default();
+    assert_eq!(
+        block_on(PollPacket::new(&mut state, &mut data).with_max_packet_size(2))
+            .unwrap()
+            .2,
+        Packet::Pingreq
+    );
+
+    // The limit is kept after the state is reset for the next packet
+    let data = vec![0b11000000, 0b00000000, 0b00110000, 0xC8, 0x01];
+    let mut stream = PacketStream::new(data.as_slice()).with_max_packet_size(128);
+    assert_eq!(block_on(stream.next()), Some(Ok(Packet::Pingreq)));
+    assert_eq!(
+        block_on(stream.next()),
+        Some(Err(Error::PacketTooLarge(203).into()))
+    );
+    assert_eq!(block_on(stream.next()), None);
+}
+
 #[test]
 fn test_v5_decode_subscribe() {
     let mut data: &[u8] = &[
@@ -1060,8 +1340,8 @@ fn test_v5_decode_subscribe() {
         Packet::Subscribe(Subscribe {
             pid: Pid::try_from(0x1122).unwrap(),
             properties: SubscribeProperties {
-                subscription_id: Some(VarByteInt::try_from(16383).unwrap()),
-                user_properties: Vec::new(),
+                subscription_id: Some(VarByteInt::try_from(16383u32).unwrap()),
+                user_properties: UserProperties::new(),
             },
             topics: vec![(
                 TopicFilter::try_from("/+".to_string()).unwrap(),
@@ -1116,6 +1396,30 @@ fn test_v5_decode_subscribe() {
             .2,
     );
 
+    // [MQTT-3.8.3-4] no local on shared subscription
+    let data = [
+        &[
+            8 << 4 | 2, // packet type
+            16,         // remaining length
+            0x11,       // packet identifier = 0x1122
+            0x22,
+            0x00, // properties.len = 0
+            0x00, // topic filter = "$share/g/a"
+            0x0A,
+        ][..],
+        b"$share/g/a",
+        &[0b00000100], // options = no_local=true
+    ]
+    .concat();
+    assert_eq!(
+        Packet::decode(&data).unwrap_err(),
+        Error::InvalidPacket("no local on shared subscription").into()
+    );
+    assert_eq!(
+        Packet::decode(&data).unwrap_err(),
+        block_on(PollPacket::new(&mut Default::default(), &mut &data[..])).unwrap_err()
+    );
+
     for opt_byte in [
         0b01000000, // reserved bits
         0b00000011, // max_qos=3
@@ -1180,8 +1484,8 @@ fn test_v5_decode_suback() {
         Packet::Suback(Suback {
             pid: Pid::try_from(0x1122).unwrap(),
             properties: SubackProperties {
-                reason_string: Some(Arc::new("e".to_string())),
-                user_properties: Vec::new(),
+                reason_string: Some(Arc::from("e")),
+                user_properties: UserProperties::new(),
             },
             topics: vec![
                 SubscribeReasonCode::ImplementationSpecificError,
@@ -1253,12 +1557,12 @@ fn test_v5_decode_unsubscribe() {
             pid: Pid::try_from(0x1122).unwrap(),
             properties: vec![
                 UserProperty {
-                    name: Arc::new("k1".to_string()),
-                    value: Arc::new("v1".to_string()),
+                    name: Arc::from("k1"),
+                    value: Arc::from("v1"),
                 },
                 UserProperty {
-                    name: Arc::new("k2".to_string()),
-                    value: Arc::new("v2".to_string()),
+                    name: Arc::from("k2"),
+                    value: Arc::from("v2"),
                 },
             ]
             .into(),
@@ -1330,8 +1634,8 @@ fn test_v5_decode_unsuback() {
         Packet::Unsuback(Unsuback {
             pid: Pid::try_from(0x1122).unwrap(),
             properties: UnsubackProperties {
-                reason_string: Some(Arc::new("e".to_string())),
-                user_properties: Vec::new(),
+                reason_string: Some(Arc::from("e")),
+                user_properties: UserProperties::new(),
             },
             topics: vec![
                 UnsubscribeReasonCode::Success,
@@ -1405,3 +1709,553 @@ fn test_v5_decode_pingresp() {
             .2,
     );
 }
+
+#[test]
+fn test_v5_decode_empty_packet_with_body() {
+    // The packets without body, followed by a junk body
+    for control_byte in [0b1100_0000, 0b1101_0000] {
+        let data = [control_byte, 2, 0xaa, 0xbb];
+        let err = ErrorV5::from(Error::InvalidRemainingLength);
+        assert_eq!(Packet::decode(&data), Err(err.clone()));
+        assert_eq!(
+            Packet::decode_shared(&mut Bytes::copy_from_slice(&data)),
+            Err(err.clone())
+        );
+        assert_eq!(
+            Packet::decode_buf(&mut BytesMut::from(&data[..])),
+            Err(err.clone())
+        );
+        assert_eq!(Packet::iter_from(&data).next(), Some(Err(err.clone())));
+        assert_eq!(
+            Packet::iter_from_bytes_mut(&mut BytesMut::from(&data[..])).next(),
+            Some(Err(err.clone()))
+        );
+        let (header, body) = Packet::decode_header_only(&mut Bytes::copy_from_slice(&data))
+            .unwrap()
+            .unwrap();
+        assert_eq!(Packet::decode_body(header, body), Err(err.clone()));
+        assert_eq!(PacketDecoder::new().feed(&data), Err(err.clone()));
+        let mut reader = &data[..];
+        assert_eq!(
+            block_on(PollPacket::new(&mut Default::default(), &mut reader)).unwrap_err(),
+            err
+        );
+    }
+}
+
+#[test]
+fn test_v5_decode_with_offset() {
+    // PUBACK with invalid reason code
+    let data = [0b0100_0000, 3, 0, 1, 0x01];
+    let err = Packet::decode_with_offset(&data).unwrap_err();
+    assert_eq!(
+        err.error,
+        ErrorV5::InvalidReasonCode(PacketType::Puback, 0x01)
+    );
+    assert_eq!(err.offset, 5);
+    assert_eq!(err.to_string(), format!("{} (at byte offset 5)", err.error));
+}
+
+#[test]
+fn test_v5_decode_slice_same_as_async() {
+    let topic = TopicName::try_from("a/b".to_owned()).unwrap();
+    let mut connect = Connect::new(SharedStr::from("id"), 30);
+    connect.properties.receive_max = Some(10);
+    connect.last_will = Some(LastWill::new(
+        Level1,
+        topic.clone(),
+        Bytes::from_static(b"x"),
+    ));
+    let mut publish = Publish::new(
+        QosPid::Level1(Pid::try_from(3).unwrap()),
+        topic,
+        Bytes::from_static(b"hi"),
+    );
+    publish.properties.payload_is_utf8 = Some(true);
+    publish.properties.topic_alias = Some(1);
+    let mut disconnect = Disconnect::new(DisconnectReasonCode::ServerBusy);
+    disconnect.properties.reason_string = Some(Arc::from("r"));
+    let packets: Vec<Packet> = vec![
+        connect.into(),
+        Connack::new(false, ConnectReasonCode::Success).into(),
+        publish.into(),
+        Puback::new(Pid::try_from(3).unwrap(), PubackReasonCode::Success).into(),
+        Subscribe::new(
+            Pid::try_from(4).unwrap(),
+            vec![(
+                TopicFilter::try_from("a/+".to_owned()).unwrap(),
+                SubscriptionOptions::new(Level1),
+            )],
+        )
+        .into(),
+        Unsubscribe::new(
+            Pid::try_from(5).unwrap(),
+            vec![TopicFilter::try_from("a/#".to_owned()).unwrap()],
+        )
+        .into(),
+        disconnect.into(),
+        Packet::Pingreq,
+    ];
+    let decode_async = |data: &[u8]| match block_on(Packet::decode_async(&mut &data[..])) {
+        Ok(packet) => Ok(Some(packet)),
+        Err(err) if err.is_eof() => Ok(None),
+        Err(err) => Err(err),
+    };
+    for packet in packets {
+        let data = packet.encode().unwrap().as_ref().to_vec();
+        assert_eq!(Packet::decode(&data), Ok(Some(packet)));
+        for idx in 0..data.len() {
+            for value in [0, 1, 2, 0x7f, 0x80, 0xff, data[idx] ^ 1] {
+                let mut data = data.clone();
+                data[idx] = value;
+                for len in [data.len() - 1, data.len(), data.len() + 1] {
+                    data.resize(len, 0);
+                    let expected = decode_async(&data);
+                    assert_eq!(Packet::decode(&data), expected, "data: {:?}", data);
+                    assert_eq!(
+                        Packet::decode_with_offset(&data).map_err(|err| err.error),
+                        expected,
+                        "data: {:?}",
+                        data
+                    );
+                }
+            }
+        }
+    }
+}
+
+#[test]
+fn test_v5_decode_property_list() {
+    let properties = PublishProperties {
+        payload_is_utf8: Some(true),
+        message_expiry_interval: Some(60),
+        topic_alias: Some(3),
+        response_topic: Some(TopicName::try_from("a/b".to_owned()).unwrap()),
+        correlation_data: Some(Bytes::from_static(b"id")),
+        user_properties: [UserProperty {
+            name: Arc::from("k"),
+            value: Arc::from("v"),
+        }]
+        .into_iter()
+        .collect(),
+        subscription_id: Some(VarByteInt::try_from(321u32).unwrap()),
+        content_type: Some(Arc::from("json")),
+    };
+    let mut data = Vec::new();
+    properties.encode(&mut data).unwrap();
+
+    let list = decode_property_list(&data).unwrap();
+    assert_eq!(list.len(), 8);
+    assert!(list.contains(&Property::PayloadFormatIndicator(true)));
+    assert!(list.contains(&Property::TopicAlias(3)));
+    assert!(list.contains(&Property::SubscriptionIdentifier(
+        VarByteInt::try_from(321u32).unwrap()
+    )));
+    assert_eq!(
+        list.last(),
+        Some(&Property::UserProperty(UserProperty {
+            name: Arc::from("k"),
+            value: Arc::from("v"),
+        }))
+    );
+
+    let mut encoded = Vec::new();
+    encode_property_list(&mut encoded, &list).unwrap();
+    assert_eq!(encoded, data);
+
+    assert!(decode_property_list(&[3, 0x01, 0x01]).unwrap_err().is_eof());
+    // Property length mismatch
+    assert_eq!(
+        decode_property_list(&[1, 0x23, 0x00, 0x01]),
+        Err(ErrorV5::InvalidPropertyLength(1))
+    );
+    assert_eq!(
+        decode_property_list(&[2, 0x24, 0x02]),
+        Err(ErrorV5::InvalidByteProperty(PropertyId::MaximumQoS, 2))
+    );
+
+    // Same validations as the per-packet property structs
+    let mut bytes: &[u8] = &[0x21, 0x00, 0x05, 0x23];
+    assert_eq!(
+        Property::decode(&mut bytes),
+        Ok(Property::ReceiveMaximum(5))
+    );
+    assert_eq!(bytes, &[0x23]);
+    assert!(Property::decode(&mut bytes).unwrap_err().is_eof());
+    assert_eq!(
+        Property::decode(&mut &[0x21, 0x00, 0x00][..]),
+        Err(ErrorV5::ZeroProperty(PropertyId::ReceiveMaximum))
+    );
+    assert_eq!(
+        Property::decode(&mut &[0x0b, 0x00][..]),
+        Err(ErrorV5::ZeroSubscriptionId)
+    );
+}
+
+#[test]
+fn test_v5_decode_header_only() {
+    // PUBACK (pid 1) then PINGREQ
+    let data = [0b0100_0000, 2, 0, 1, 0b1100_0000, 0];
+    let mut buf = Bytes::copy_from_slice(&data);
+    let (header, body) = Packet::decode_header_only(&mut buf).unwrap().unwrap();
+    assert_eq!(header.typ, PacketType::Puback);
+    assert_eq!(
+        Packet::decode_body(header, body).unwrap(),
+        Puback::new_success(Pid::try_from(1).unwrap()).into()
+    );
+    let (header, body) = Packet::decode_header_only(&mut buf).unwrap().unwrap();
+    assert_eq!(Packet::decode_body(header, body).unwrap(), Packet::Pingreq);
+    assert!(buf.is_empty());
+
+    let mut reader = &data[..3];
+    assert!(block_on(Packet::decode_header_only_async(&mut reader))
+        .unwrap_err()
+        .is_eof());
+}
+
+#[test]
+fn test_v5_lazy_packet() {
+    // QoS 2 PUBLISH with packet identifier 10 and a topic alias property
+    let mut data = vec![0b0011_0100, 12, 0, 3, b'a', b'/', b'b', 0, 10];
+    data.extend([3, 0x23, 0, 1, b'x']);
+    let mut buf = Bytes::copy_from_slice(&data);
+    let packet = LazyPacket::decode(&mut buf).unwrap().unwrap();
+    assert_eq!(packet.topic_name().unwrap(), Some("a/b"));
+    assert_eq!(packet.pid().unwrap(), Some(Pid::try_from(10).unwrap()));
+    let publish = match packet.into_packet().unwrap() {
+        Packet::Publish(publish) => publish,
+        packet => panic!("unexpected packet: {packet:?}"),
+    };
+    assert_eq!(publish.properties.topic_alias, Some(1));
+    assert_eq!(publish.payload.as_ref(), b"x");
+
+    // Truncated body
+    let packet = LazyPacket::new(
+        Header::new(PacketType::Publish, false, Level1, false, 3),
+        Bytes::from_static(&[0, 1, b'a']),
+    );
+    assert_eq!(
+        packet.pid(),
+        Err(ErrorV5::Common(Error::InvalidRemainingLength))
+    );
+}
+
+#[test]
+fn test_v5_packet_iter() {
+    // PUBLISH, PINGRESP
+    let data = [0b0011_0000, 5, 0, 1, b'a', 0, b'x', 0b1101_0000, 0];
+    let results: Vec<_> = Packet::iter_from(&data).collect();
+    assert_eq!(results.len(), 2);
+    let (packet, len) = results[0].clone().unwrap();
+    assert_eq!(len, 7);
+    assert_eq!(packet.topic_name().map(|name| &**name), Some("a"));
+    assert_eq!(results[1], Ok((Packet::Pingresp, 2)));
+
+    let mut buf = bytes::BytesMut::from(&data[..]);
+    let mut iter = Packet::iter_from_bytes_mut(&mut buf);
+    assert_eq!(iter.next().unwrap().unwrap().1, 7);
+    assert_eq!(iter.next(), Some(Ok((Packet::Pingresp, 2))));
+    assert_eq!(iter.next(), None);
+    assert!(buf.is_empty());
+}
+
+#[test]
+fn test_v5_packet_stream_sink() {
+    use std::pin::Pin;
+
+    use futures_lite::{future::poll_fn, StreamExt};
+    use futures_sink::Sink;
+
+    let packets: Vec<Packet> = vec![
+        Publish::new(
+            QosPid::Level1(Pid::try_from(10).unwrap()),
+            TopicName::try_from("a/b").unwrap(),
+            Bytes::from_static(b"hello world"),
+        )
+        .into(),
+        Packet::Pingresp,
+    ];
+    let mut sink = PacketSink::new(Vec::new());
+    for packet in packets.clone() {
+        block_on(poll_fn(|cx| Pin::new(&mut sink).poll_ready(cx))).unwrap();
+        Pin::new(&mut sink).start_send(packet).unwrap();
+    }
+    block_on(poll_fn(|cx| Pin::new(&mut sink).poll_flush(cx))).unwrap();
+
+    let data = sink.into_inner();
+    let stream = PacketStream::new(MockBuffer::new(data).chunk_size(2));
+    let polled: Vec<_> = block_on(stream.try_collect::<_, _, Vec<_>>()).unwrap();
+    assert_eq!(polled, packets);
+}
+
+#[test]
+fn test_v5_poll_packet_pool() {
+    let packet: Packet = Publish::new(
+        QosPid::Level1(Pid::try_from(10).unwrap()),
+        TopicName::try_from("a/b").unwrap(),
+        Bytes::from_static(b"hello world"),
+    )
+    .into();
+    let data = packet.encode().unwrap().as_ref().to_vec();
+    let pool = ScratchBufferPool::new(4, 1024);
+
+    // The body buffer is taken from the pool and returned when the payload
+    // is dropped, also when the body is received by partial reads.
+    let mut reader = MockBuffer::new([data.clone(), data.clone()].concat())
+        .chunk_size(3)
+        .interleave_pending(true);
+    let handle = reader.handle();
+    for _ in 0..2 {
+        let mut state = PollPacketState::default();
+        let (total, buf, polled) =
+            block_on(PollPacket::new(&mut state, &mut reader).with_scratch_pool(&pool)).unwrap();
+        assert_eq!(total, data.len());
+        assert!(buf.is_empty());
+        assert_eq!(polled, packet);
+        assert_eq!(pool.idle(), 0);
+        drop(polled);
+        assert_eq!(pool.idle(), 1);
+    }
+    assert_eq!(handle.position(), data.len() * 2);
+    assert!(handle.reads().iter().all(|size| *size <= 3));
+}
+
+#[cfg(feature = "embedded-io")]
+#[test]
+fn test_v5_embedded_io() {
+    let packet: Packet = Publish::new(
+        QosPid::Level1(Pid::try_from(10).unwrap()),
+        TopicName::try_from("a/b").unwrap(),
+        Bytes::from_static(b"hello world"),
+    )
+    .into();
+    let mut data = Vec::new();
+    block_on(packet.encode_embedded(&mut data)).unwrap();
+    block_on(Packet::Pingresp.encode_async(&mut data)).unwrap();
+
+    // Both backends in the same build
+    let mut reader = &data[..];
+    assert_eq!(
+        block_on(Packet::decode_embedded(&mut reader)).unwrap(),
+        packet
+    );
+    assert_eq!(
+        block_on(Packet::decode_async(&mut reader)).unwrap(),
+        Packet::Pingresp
+    );
+
+    let mut reader = &data[..data.len() - 3];
+    assert!(block_on(Packet::decode_embedded(&mut reader))
+        .unwrap_err()
+        .is_eof());
+}
+
+#[test]
+fn test_v5_owned_packet_reader() {
+    // Completion based reader, which owns the buffer while reading
+    struct ChunkReader(Vec<u8>);
+    impl OwnedRead for ChunkReader {
+        async fn read_owned(&mut self, mut buf: Vec<u8>) -> (std::io::Result<usize>, Vec<u8>) {
+            let size = self.0.len().min(3);
+            buf.extend(self.0.drain(..size));
+            (Ok(size), buf)
+        }
+    }
+
+    let packet: Packet = Publish::new(
+        QosPid::Level1(Pid::try_from(10).unwrap()),
+        TopicName::try_from("a/b").unwrap(),
+        Bytes::from_static(b"hello world"),
+    )
+    .into();
+    let mut data = packet.encode().unwrap().as_ref().to_vec();
+    data.extend_from_slice(&[0b1101_0000, 0]);
+
+    let mut reader = OwnedPacketReader::new(ChunkReader(data.clone()));
+    assert_eq!(block_on(reader.read_packet()).unwrap(), Some(packet));
+    assert_eq!(
+        block_on(reader.read_packet()).unwrap(),
+        Some(Packet::Pingresp)
+    );
+    assert_eq!(block_on(reader.read_packet()).unwrap(), None);
+
+    // EOF in the middle of a packet
+    let mut reader = OwnedPacketReader::new(ChunkReader(data[..5].to_vec()));
+    assert!(block_on(reader.read_packet()).unwrap_err().is_eof());
+    assert_eq!(reader.buffered(), &data[..5]);
+}
+
+#[test]
+fn test_v5_connack_capabilities() {
+    let capabilities = ConnackCapabilities::default();
+    assert_eq!(capabilities.receive_max, 65535);
+    assert_eq!(capabilities.max_qos, QoS::Level2);
+    assert!(capabilities.retain_available);
+    assert_eq!(capabilities.max_packet_size, None);
+    assert_eq!(capabilities.topic_alias_max, 0);
+    assert!(capabilities.wildcard_subscription_available);
+    assert!(capabilities.subscription_id_available);
+    assert!(capabilities.shared_subscription_available);
+    assert_eq!(capabilities.keep_alive(30), 30);
+    assert_eq!(capabilities.session_expiry_interval(60), 60);
+
+    let properties = ConnackProperties {
+        receive_max: Some(10),
+        max_qos: Some(QoS::Level1),
+        retain_available: Some(false),
+        topic_alias_max: Some(5),
+        shared_subscription_available: Some(false),
+        server_keep_alive: Some(10),
+        session_expiry_interval: Some(0),
+        ..Default::default()
+    };
+    let capabilities = ConnackCapabilities::from(&properties);
+    assert_eq!(capabilities.receive_max, 10);
+    assert_eq!(capabilities.max_qos, QoS::Level1);
+    assert!(!capabilities.retain_available);
+    assert_eq!(capabilities.topic_alias_max, 5);
+    assert!(capabilities.wildcard_subscription_available);
+    assert!(!capabilities.shared_subscription_available);
+    assert_eq!(capabilities.keep_alive(30), 10);
+    assert_eq!(capabilities.session_expiry_interval(60), 0);
+}
+
+#[test]
+fn test_v5_negotiated_settings() {
+    let mut connect = Connect::new("", 60);
+    connect.properties.session_expiry_interval = Some(300);
+    connect.properties.receive_max = Some(20);
+    connect.properties.topic_alias_max = Some(8);
+    let mut connack = Connack::new(false, ConnectReasonCode::Success);
+    let settings = NegotiatedSettings::new(&connect, &connack);
+    assert_eq!(&*settings.client_id, "");
+    assert!(!settings.client_id_assigned);
+    assert_eq!(settings.keep_alive, 60);
+    assert_eq!(settings.session_expiry_interval, 300);
+    assert_eq!(settings.client_receive_max, 20);
+    assert_eq!(settings.server_receive_max, 65535);
+    assert_eq!(settings.client_topic_alias_max, 8);
+    assert_eq!(settings.server_topic_alias_max, 0);
+    assert_eq!(settings.server_max_packet_size, None);
+
+    connack.properties.assigned_client_id = Some("auto-1".into());
+    connack.properties.server_keep_alive = Some(30);
+    connack.properties.session_expiry_interval = Some(0);
+    connack.properties.receive_max = Some(5);
+    connack.properties.max_packet_size = Some(1024);
+    let settings = NegotiatedSettings::from((&connect, &connack));
+    assert_eq!(&*settings.client_id, "auto-1");
+    assert!(settings.client_id_assigned);
+    assert_eq!(settings.keep_alive, 30);
+    assert_eq!(settings.session_expiry_interval, 0);
+    assert_eq!(settings.server_receive_max, 5);
+    assert_eq!(settings.server_max_packet_size, Some(1024));
+}
+
+#[test]
+fn test_v5_annotated_hexdump() {
+    let mut publish = Publish::new(
+        QosPid::Level1(Pid::try_from(10).unwrap()),
+        TopicName::try_from("a/b".to_owned()).unwrap(),
+        Bytes::from(vec![7u8; 20]),
+    );
+    publish.properties.topic_alias = Some(3);
+    publish.properties.user_properties.push(UserProperty {
+        name: Arc::from("k"),
+        value: Arc::from("v"),
+    });
+    let data = Packet::from(publish).encode().unwrap();
+    let expected = [
+        "000000  32                                               PUBLISH flags=0b0010",
+        "000001  26                                               remaining length: 38",
+        "000002  00 03 61 2f 62                                   topic name: \"a/b\"",
+        "000007  00 0a                                            packet identifier: 10",
+        "000009  0a                                               property length: 10",
+        "00000a  23                                               property: TopicAlias",
+        "00000b  00 03                                            value: 3",
+        "00000d  26                                               property: UserProperty",
+        "00000e  00 01 6b                                         name: \"k\"",
+        "000011  00 01 76                                         value: \"v\"",
+        "000014  07 07 07 07 07 07 07 07 07 07 07 07 07 07 07 07  payload: 20 bytes",
+        "000024  07 07 07 07",
+    ];
+    assert_eq!(
+        annotated_hexdump(data.as_ref()).lines().collect::<Vec<_>>(),
+        expected
+    );
+
+    // Invalid property identifier in PUBACK
+    let data: &[u8] = &[0b01000000, 6, 0, 10, 0x80, 2, 0x7f, 0];
+    let output = annotated_hexdump(data);
+    assert!(output.contains("invalid property identifier: 0x7f"));
+    assert!(output.contains("malformed: 1 bytes"));
+}
+
+#[test]
+fn test_v5_connect_remaining_length() {
+    let body: &[u8] = &[
+        0x00, 0x04, b'M', b'Q', b'T', b'T', 0x05, 0b01000000, // +password
+        0x00, 0x0a, // keepalive 10 sec
+        0x00, // properties
+        0x00, 0x04, b't', b'e', b's', b't', // client_id
+        0x00, 0x02, b'm', b'q', // password
+    ];
+    let next_packet: &[u8] = &[0xc0, 0x00];
+
+    // The password exceeds the remaining length, the next packet is not read
+    let header = Header::new(PacketType::Connect, false, Level0, false, 20);
+    let reader = [body, next_packet].concat();
+    let mut reader = &reader[..];
+    assert_eq!(
+        block_on(Connect::decode_async(&mut reader, header)),
+        Err(Error::InvalidRemainingLength.into())
+    );
+    assert_eq!(reader, &[b'q', 0xc0, 0x00]);
+
+    // Bytes left in the remaining length
+    let header = Header::new(PacketType::Connect, false, Level0, false, 22);
+    let reader = [body, &[0x00]].concat();
+    assert_eq!(
+        block_on(Connect::decode_async(&mut &reader[..], header)),
+        Err(Error::InvalidRemainingLength.into())
+    );
+
+    let header = Header::new(PacketType::Connect, false, Level0, false, 21);
+    let connect = block_on(Connect::decode_async(&mut &body[..], header)).unwrap();
+    assert_eq!(connect.password, Some(Bytes::from_static(b"mq")));
+
+    // The reader ends inside the packet
+    let header = Header::new(PacketType::Connect, false, Level0, false, 21);
+    assert!(block_on(Connect::decode_async(&mut &body[..20], header))
+        .unwrap_err()
+        .is_eof());
+}
+
+#[test]
+fn test_v5_property_length_overflow() {
+    // Message expiry interval (5 bytes) exceeds the property length
+    let data: &[u8] = &[0x02, 0x02, 0x00, 0x00, 0x00, 0x3c];
+    assert_eq!(
+        decode_property_list(data),
+        Err(ErrorV5::InvalidPropertyLength(2))
+    );
+    assert_eq!(
+        block_on(PublishProperties::decode_async(
+            &mut &data[..],
+            PacketType::Publish
+        )),
+        Err(ErrorV5::InvalidPropertyLength(2))
+    );
+
+    // The max property length is rejected by the packet length
+    let data: &[u8] = &[
+        0b00110000, 8, // PUBLISH
+        0x00, 0x01, b'a', // topic name
+        0xff, 0xff, 0xff, 0x7f, // property length
+        0x01, // payload format indicator
+    ];
+    assert_eq!(
+        Packet::decode(data).unwrap_err(),
+        Error::InvalidRemainingLength.into()
+    );
+}
@@ -1,7 +1,8 @@
+use std::io;
 use std::mem;
 use std::sync::Arc;
 
-use bytes::Bytes;
+use bytes::{Bytes, BytesMut};
 use futures_lite::future::block_on;
 
 use crate::v5::*;
@@ -15,8 +16,31 @@ fn assert_encode(pkt: Packet, len: usize) {
     assert_eq!(pkt.encode_len().unwrap(), len);
     assert_eq!(data_async.len(), len);
 
-    let decoded_pkt = Packet::decode(&data_async).unwrap().unwrap();
-    assert_eq!(pkt, decoded_pkt);
+    let mut buf = vec![0u8; len + 1];
+    assert_eq!(pkt.encode_into(&mut buf).unwrap(), len);
+    assert_eq!(&buf[..len], &data_async);
+    let mut bytes_mut = BytesMut::new();
+    assert_eq!(pkt.encode_to_buf(&mut bytes_mut).unwrap(), len);
+    assert_eq!(&bytes_mut[..], &data_async);
+
+    let (head, payload) = pkt.encode_vectored().unwrap();
+    assert_eq!([head.as_ref(), &payload[..]].concat(), data_async);
+    let mut data_vectored = Vec::new();
+    block_on(pkt.encode_vectored_async(&mut data_vectored)).unwrap();
+    assert_eq!(data_vectored, data_async);
+    let mut state = pkt.encode_poll_state().unwrap();
+    let mut data_polled = Vec::new();
+    let total = block_on(PollEncode::new(&mut state, &mut data_polled)).unwrap();
+    assert_eq!(total, len);
+    assert_eq!(data_polled, data_async);
+
+    let header = Header::decode(&data_async).unwrap();
+    let mut header_data = Vec::new();
+    header.encode(&mut header_data).unwrap();
+    assert_eq!(header_data.len(), header.encode_len());
+    assert_eq!(header_data, &data_async[..header_len(len)]);
+
+    assert_eq!(testing::assert_round_trip(&pkt), data_async);
 
     let mut data = &data_async[..];
     let (total, buf, polled_pkt) =
@@ -25,6 +49,17 @@ fn assert_encode(pkt: Packet, len: usize) {
     assert_eq!(total, len);
     assert_eq!(buf_ref, &data_async[header_len(total)..]);
     assert_eq!(pkt, polled_pkt);
+
+    let mut shared = Bytes::from(data_async);
+    let shared_pkt = Packet::decode_shared(&mut shared).unwrap().unwrap();
+    assert!(shared.is_empty());
+    assert_eq!(pkt, shared_pkt);
+
+    #[cfg(feature = "serde")]
+    {
+        let json = serde_json::to_string(&pkt).unwrap();
+        assert_eq!(pkt, serde_json::from_str::<Packet>(&json).unwrap());
+    }
 }
 
 #[test]
@@ -51,14 +86,14 @@ fn test_v5_encode_connect() {
             ..Default::default()
         },
         // 2 + 11 = 13
-        client_id: Arc::new("client no.1".to_string()),
+        client_id: SharedStr::from("client no.1"),
         // 8 + 5 + 4 = 17
         last_will: Some(LastWill {
             qos: QoS::Level1,
             retain: false,
             // 1 + 1 + 2 + 4 = 8
             properties: WillProperties {
-                content_type: Some(Arc::new("text".to_string())),
+                content_type: Some(Arc::from("text")),
                 ..Default::default()
             },
             // 2 + 3 = 5
@@ -67,7 +102,7 @@ fn test_v5_encode_connect() {
             payload: Bytes::from(vec![0u8, 1u8]),
         }),
         // 2 + 6 = 8
-        username: Some(Arc::new("nahida".to_string())),
+        username: Some(SharedStr::from("nahida")),
         // 2 + 2 = 4
         password: Some(Bytes::from(vec![3u8, 4u8])),
     };
@@ -89,7 +124,7 @@ fn test_v5_encode_connect() {
     assert_encode(packet.clone().into(), len);
 
     let packet_large = Connect {
-        client_id: Arc::new("a".repeat(128).to_string()),
+        client_id: SharedStr::from("a".repeat(128)),
         ..packet
     };
     let len = [
@@ -117,12 +152,14 @@ fn test_v5_encode_connack() {
         // 1 + 6 + 14 = 21
         properties: ConnackProperties {
             // 1 + 2 + 3 = 6
-            auth_method: Some(Arc::new("tls".to_string())),
+            auth_method: Some(Arc::from("tls")),
             // 1 + 4 + 9 = 14
-            user_properties: vec![UserProperty {
-                name: Arc::new("name".to_string()),
-                value: Arc::new("value".to_string()),
-            }],
+            user_properties: [UserProperty {
+                name: Arc::from("name"),
+                value: Arc::from("value"),
+            }]
+            .into_iter()
+            .collect(),
             ..Default::default()
         },
     };
@@ -149,7 +186,7 @@ fn test_v5_encode_disconnect() {
             // 1 + 4 = 5
             session_expiry_interval: Some(456),
             // 1 + 2 + 4 = 7
-            server_reference: Some(Arc::new("http".to_string())),
+            server_reference: Some(Arc::from("http")),
             ..Default::default()
         },
     };
@@ -186,7 +223,7 @@ fn test_v5_encode_disconnect() {
         properties: DisconnectProperties {
             session_expiry_interval: None,
             reason_string: None,
-            user_properties: Vec::new(),
+            user_properties: UserProperties::new(),
             server_reference: None,
         },
     };
@@ -201,7 +238,7 @@ fn test_v5_encode_auth() {
         // 1 + 8 = 9
         properties: AuthProperties {
             // 1 + 2 + 5 = 8
-            reason_string: Some(Arc::new("error".to_string())),
+            reason_string: Some(Arc::from("error")),
             ..Default::default()
         },
     };
@@ -228,6 +265,28 @@ fn test_v5_encode_auth() {
     assert_encode(packet2.into(), 2);
 }
 
+#[test]
+fn test_v5_debug_redacted() {
+    let mut connect = Connect::new(SharedStr::from("client"), 30);
+    connect.username = Some(SharedStr::from("user"));
+    connect.password = Some(Bytes::from_static(b"secret"));
+    connect.properties.auth_data = Some(Bytes::from_static(b"token"));
+    let output = format!("{connect:?}");
+    assert!(output.contains(r#"username: Some("user")"#));
+    assert!(output.contains("password: Some([REDACTED len=6])"));
+    assert!(output.contains("auth_data: Some([REDACTED len=5])"));
+    assert!(!output.contains("secret") && !output.contains("token"));
+
+    let mut auth = Auth::new(AuthReasonCode::ContinueAuthentication);
+    auth.properties.auth_data = Some(Bytes::from_static(b"token"));
+    let output = format!("{auth:?}");
+    assert!(output.contains("auth_data: Some([REDACTED len=5])"));
+    assert!(!output.contains("token"));
+
+    let output = format!("{:?}", ConnackProperties::default());
+    assert!(output.contains("auth_data: None"));
+}
+
 #[test]
 fn test_v5_encode_publish() {
     let packet = Publish {
@@ -286,6 +345,22 @@ fn test_v5_encode_publish() {
     assert_encode(packet3.clone().into(), len);
 }
 
+#[test]
+fn test_v5_publish_new_payload() {
+    let qos_pid = QosPid::Level1(Pid::try_from(10).unwrap());
+    let topic_name = TopicName::try_from("a/b").unwrap();
+    let expected = Publish::new(qos_pid, topic_name.clone(), Bytes::from_static(b"hello"));
+    assert_eq!(expected.properties, PublishProperties::default());
+    for packet in [
+        Publish::new(qos_pid, topic_name.clone(), b"hello".to_vec()),
+        Publish::new(qos_pid, topic_name.clone(), &b"hello"[..]),
+        Publish::new(qos_pid, topic_name.clone(), "hello"),
+        Publish::new(qos_pid, topic_name.clone(), String::from("hello")),
+    ] {
+        assert_eq!(packet, expected);
+    }
+}
+
 #[test]
 fn test_v5_encode_puback() {
     let packet = Puback {
@@ -296,20 +371,22 @@ fn test_v5_encode_puback() {
         // 1 + 7 + 27 = 35
         properties: PubackProperties {
             // 1 + 2 + 4 = 7
-            reason_string: Some(Arc::new("auth".to_string())),
+            reason_string: Some(Arc::from("auth")),
             // 13 + 14 = 27
-            user_properties: vec![
+            user_properties: [
                 // 1 + 4 + 9 = 14
                 UserProperty {
-                    name: Arc::new("name".to_string()),
-                    value: Arc::new("value".to_string()),
+                    name: Arc::from("name"),
+                    value: Arc::from("value"),
                 },
                 // 1 + 4 + 8 = 13
                 UserProperty {
-                    name: Arc::new("key".to_string()),
-                    value: Arc::new("value".to_string()),
+                    name: Arc::from("key"),
+                    value: Arc::from("value"),
                 },
-            ],
+            ]
+            .into_iter()
+            .collect(),
         },
     };
     let len = [
@@ -360,20 +437,22 @@ fn test_v5_encode_pubrec() {
         // 1 + 7 + 27 = 35
         properties: PubrecProperties {
             // 1 + 2 + 4 = 7
-            reason_string: Some(Arc::new("auth".to_string())),
+            reason_string: Some(Arc::from("auth")),
             // 13 + 14 = 27
-            user_properties: vec![
+            user_properties: [
                 // 1 + 4 + 9 = 14
                 UserProperty {
-                    name: Arc::new("name".to_string()),
-                    value: Arc::new("value".to_string()),
+                    name: Arc::from("name"),
+                    value: Arc::from("value"),
                 },
                 // 1 + 4 + 8 = 13
                 UserProperty {
-                    name: Arc::new("key".to_string()),
-                    value: Arc::new("value".to_string()),
+                    name: Arc::from("key"),
+                    value: Arc::from("value"),
                 },
-            ],
+            ]
+            .into_iter()
+            .collect(),
         },
     };
     let len = [
@@ -424,20 +503,22 @@ fn test_v5_encode_pubrel() {
         // 1 + 7 + 27 = 35
         properties: PubrelProperties {
             // 1 + 2 + 4 = 7
-            reason_string: Some(Arc::new("auth".to_string())),
+            reason_string: Some(Arc::from("auth")),
             // 13 + 14 = 27
-            user_properties: vec![
+            user_properties: [
                 // 1 + 4 + 9 = 14
                 UserProperty {
-                    name: Arc::new("name".to_string()),
-                    value: Arc::new("value".to_string()),
+                    name: Arc::from("name"),
+                    value: Arc::from("value"),
                 },
                 // 1 + 4 + 8 = 13
                 UserProperty {
-                    name: Arc::new("key".to_string()),
-                    value: Arc::new("value".to_string()),
+                    name: Arc::from("key"),
+                    value: Arc::from("value"),
                 },
-            ],
+            ]
+            .into_iter()
+            .collect(),
         },
     };
     let len = [
@@ -488,20 +569,22 @@ fn test_v5_encode_pubcomp() {
         // 1 + 7 + 27 = 35
         properties: PubcompProperties {
             // 1 + 2 + 4 = 7
-            reason_string: Some(Arc::new("auth".to_string())),
+            reason_string: Some(Arc::from("auth")),
             // 13 + 14 = 27
-            user_properties: vec![
+            user_properties: [
                 // 1 + 4 + 9 = 14
                 UserProperty {
-                    name: Arc::new("name".to_string()),
-                    value: Arc::new("value".to_string()),
+                    name: Arc::from("name"),
+                    value: Arc::from("value"),
                 },
                 // 1 + 4 + 8 = 13
                 UserProperty {
-                    name: Arc::new("key".to_string()),
-                    value: Arc::new("value".to_string()),
+                    name: Arc::from("key"),
+                    value: Arc::from("value"),
                 },
-            ],
+            ]
+            .into_iter()
+            .collect(),
         },
     };
     let len = [
@@ -542,6 +625,75 @@ fn test_v5_encode_pubcomp() {
     assert_encode(packet2.into(), len);
 }
 
+#[test]
+fn test_v5_ack_properties() {
+    let properties = AckProperties {
+        reason_string: Some(Arc::from("quota")),
+        user_properties: [UserProperty {
+            name: Arc::from("key"),
+            value: Arc::from("value"),
+        }]
+        .into_iter()
+        .collect(),
+    };
+    let pid = Pid::try_from(10).unwrap();
+    let pubrec = Pubrec {
+        pid,
+        reason_code: PubrecReasonCode::QuotaExceeded,
+        properties: properties.clone(),
+    };
+    // Reply with the properties of the received PUBREC
+    let pubrel = Pubrel {
+        pid,
+        reason_code: PubrelReasonCode::Success,
+        properties: pubrec.properties.clone(),
+    };
+    let pubrec_data = Packet::from(pubrec).encode().unwrap();
+    let pubrel_data = Packet::from(pubrel).encode().unwrap();
+    assert_eq!(pubrec_data.as_ref()[0], 0b0101_0000);
+    assert_eq!(pubrel_data.as_ref()[0], 0b0110_0010);
+    assert_eq!(pubrec_data.as_ref()[1..4], pubrel_data.as_ref()[1..4]);
+    assert_eq!(pubrec_data.as_ref()[5..], pubrel_data.as_ref()[5..]);
+    match Packet::decode(pubrel_data.as_ref()).unwrap().unwrap() {
+        Packet::Pubrel(pubrel) => assert_eq!(pubrel.properties, properties),
+        packet => panic!("invalid packet: {:?}", packet),
+    }
+}
+
+#[test]
+fn test_v5_ack_constructors() {
+    let pid = Pid::try_from(3u16).unwrap();
+    let puback = Puback::new_success(pid).with_reason(PubackReasonCode::NoMatchingSubscribers);
+    assert_eq!(
+        puback,
+        Puback::new(pid, PubackReasonCode::NoMatchingSubscribers)
+    );
+    assert_eq!(
+        Packet::from(puback).encode().unwrap().as_ref(),
+        &[0b0100_0000, 3, 0, 3, 0x10]
+    );
+    // Success with empty properties is encoded without the reason code
+    assert_eq!(
+        Packet::from(Pubcomp::new_success(pid))
+            .encode()
+            .unwrap()
+            .as_ref(),
+        &[0b0111_0000, 2, 0, 3]
+    );
+    assert_eq!(
+        Pubrel::new_success(pid)
+            .with_reason(PubrelReasonCode::PacketIdentifierNotFound)
+            .reason_code,
+        PubrelReasonCode::PacketIdentifierNotFound
+    );
+    assert_eq!(
+        Pubrec::new_success(pid)
+            .with_reason(PubrecReasonCode::QuotaExceeded)
+            .properties,
+        AckProperties::default()
+    );
+}
+
 #[test]
 fn test_v5_encode_subscribe() {
     let packet = Subscribe {
@@ -550,8 +702,8 @@ fn test_v5_encode_subscribe() {
         // 1 + 3 = 4
         properties: SubscribeProperties {
             // 1 + 2 = 3
-            subscription_id: Some(VarByteInt::try_from(3344).unwrap()),
-            user_properties: Vec::new(),
+            subscription_id: Some(VarByteInt::try_from(3344u32).unwrap()),
+            user_properties: UserProperties::new(),
         },
         // 5 + 1 = 6
         topics: vec![(
@@ -585,8 +737,8 @@ fn test_v5_encode_suback() {
         // 1 + 7 = 8
         properties: SubackProperties {
             // 1 + 2 + 4 = 7
-            reason_string: Some(Arc::new("warn".to_string())),
-            user_properties: Vec::new(),
+            reason_string: Some(Arc::from("warn")),
+            user_properties: UserProperties::new(),
         },
         // 1
         topics: vec![SubscribeReasonCode::GrantedQoS2],
@@ -610,8 +762,8 @@ fn test_v5_encode_unsubscribe() {
         properties: vec![
             // 1 + 4 + 9 = 14
             UserProperty {
-                name: Arc::new("name".to_string()),
-                value: Arc::new("value".to_string()),
+                name: Arc::from("name"),
+                value: Arc::from("value"),
             },
         ]
         .into(),
@@ -639,13 +791,13 @@ fn test_v5_encode_unsubscribe() {
         properties: vec![
             // 1 + 4 + 9 = 14
             UserProperty {
-                name: Arc::new("name".to_string()),
-                value: Arc::new("value".to_string()),
+                name: Arc::from("name"),
+                value: Arc::from("value"),
             },
             // 1 + 4 + 8 = 13
             UserProperty {
-                name: Arc::new("key".to_string()),
-                value: Arc::new("value".to_string()),
+                name: Arc::from("key"),
+                value: Arc::from("value"),
             },
         ]
         .into(),
@@ -676,8 +828,8 @@ fn test_v5_encode_unsuback() {
         // 1 + 7 = 8
         properties: UnsubackProperties {
             // 1 + 2 + 4 = 7
-            reason_string: Some(Arc::new("warn".to_string())),
-            user_properties: Vec::new(),
+            reason_string: Some(Arc::from("warn")),
+            user_properties: UserProperties::new(),
         },
         // 1
         topics: vec![UnsubscribeReasonCode::UnspecifiedError],
@@ -692,3 +844,442 @@ fn test_v5_encode_unsuback() {
     .sum();
     assert_encode(packet.into(), len);
 }
+
+#[test]
+fn test_var_byte_int() {
+    for (value, len) in [
+        (0u32, 1),
+        (127, 1),
+        (128, 2),
+        (16383, 2),
+        (16384, 3),
+        (2097152, 4),
+    ] {
+        let var_int = VarByteInt::try_from(value).unwrap();
+        assert_eq!(var_int.encoded_len(), len);
+        assert_eq!(var_int_len(value as usize).unwrap(), len);
+    }
+    assert_eq!(VarByteInt::MAX.value(), 268435455);
+    assert_eq!(VarByteInt::MAX.encoded_len(), 4);
+    assert!(VarByteInt::try_from(VarByteInt::MAX.value() + 1).is_err());
+
+    let one = VarByteInt::from(1u8);
+    assert_eq!(
+        VarByteInt::from(300u16).checked_add(one),
+        Some(VarByteInt::from(301u16))
+    );
+    assert_eq!(
+        VarByteInt::MAX.checked_add(VarByteInt::default()),
+        Some(VarByteInt::MAX)
+    );
+    assert_eq!(VarByteInt::MAX.checked_add(one), None);
+    assert_eq!(u32::from(VarByteInt::from(u16::MAX)), 65535);
+    assert_eq!(VarByteInt::from(42u8).to_string(), "42");
+}
+
+#[test]
+fn test_encode_into_small_buffer() {
+    let packet: Packet = Disconnect::new_normal().into();
+    let len = packet.encode_len().unwrap();
+    let mut buf = vec![0u8; len - 1];
+    let err = packet.encode_into(&mut buf).unwrap_err();
+    assert!(matches!(
+        err,
+        ErrorV5::Common(Error::IoError(io::ErrorKind::WriteZero, _))
+    ));
+
+    let mut buf = [0u8; 1];
+    let err = packet.encode_to_buf(&mut &mut buf[..]).unwrap_err();
+    assert!(matches!(
+        err,
+        ErrorV5::Common(Error::IoError(io::ErrorKind::WriteZero, _))
+    ));
+}
+
+#[test]
+fn test_packet_accessors() {
+    let topic_name = TopicName::try_from("a/b".to_owned()).unwrap();
+    let packet: Packet = Publish {
+        dup: false,
+        qos_pid: QosPid::Level2(Pid::try_from(10).unwrap()),
+        retain: false,
+        topic_name: topic_name.clone(),
+        payload: Bytes::new(),
+        properties: Default::default(),
+    }
+    .into();
+    assert_eq!(packet.packet_type(), PacketType::Publish);
+    assert_eq!(packet.pid(), Some(Pid::try_from(10).unwrap()));
+    assert_eq!(packet.topic_name(), Some(&topic_name));
+    assert_eq!(packet.qos(), Some(QoS::Level2));
+
+    let packet: Packet = Puback::new_success(Pid::try_from(11).unwrap()).into();
+    assert_eq!(packet.packet_type(), PacketType::Puback);
+    assert_eq!(packet.pid(), Some(Pid::try_from(11).unwrap()));
+    assert_eq!(packet.topic_name(), None);
+    assert_eq!(packet.qos(), None);
+
+    let packet: Packet = Disconnect::new_normal().into();
+    assert_eq!(packet.pid(), None);
+}
+
+#[test]
+fn test_packet_type_metadata() {
+    for typ in [PacketType::Disconnect, PacketType::Auth, PacketType::Pubrel] {
+        assert!(typ.is_client_to_server());
+        assert!(typ.is_server_to_client());
+    }
+    assert!(!PacketType::Connack.is_client_to_server());
+    assert!(!PacketType::Pingreq.is_server_to_client());
+
+    assert!(PacketType::Publish.requires_pid(QoS::Level1));
+    assert!(!PacketType::Publish.requires_pid(QoS::Level0));
+    assert!(PacketType::Suback.requires_pid(QoS::Level0));
+    assert!(!PacketType::Auth.requires_pid(QoS::Level0));
+
+    let pid = Pid::default();
+    for packet in [
+        Pubrel::new_success(pid).into(),
+        Unsubscribe::new(pid, vec![]).into(),
+        Packet::from(Auth::new_success()),
+        Packet::Pingresp,
+    ] {
+        let data = packet.encode().unwrap();
+        assert_eq!(
+            packet.packet_type().expected_flags(),
+            Some(data.as_ref()[0] & 0b1111)
+        );
+    }
+    assert_eq!(PacketType::Publish.expected_flags(), None);
+}
+
+#[test]
+fn test_validate() {
+    let mut connect = Connect::new("id", 60);
+    assert_eq!(Packet::from(connect.clone()).validate(), Ok(()));
+    connect.properties.receive_max = Some(0);
+    assert_eq!(
+        Packet::from(connect.clone()).validate(),
+        Err(ErrorV5::ZeroProperty(PropertyId::ReceiveMaximum))
+    );
+    connect.properties.receive_max = None;
+    connect.properties.auth_data = Some(Bytes::from("data"));
+    assert_eq!(
+        Packet::from(connect).validate(),
+        Err(Error::InvalidPacket("auth data without auth method").into())
+    );
+
+    let connack = Connack::new(true, ConnectReasonCode::NotAuthorized);
+    assert_eq!(
+        Packet::from(connack).validate(),
+        Err(Error::InvalidPacket("session present with error reason code").into())
+    );
+
+    let topic_name = TopicName::try_from(String::new()).unwrap();
+    let payload = Bytes::from_static(b"\xff");
+    let mut publish = Publish::new(QosPid::Level0, topic_name, payload);
+    assert_eq!(
+        Packet::from(publish.clone()).validate(),
+        Err(Error::InvalidPacket("empty topic name").into())
+    );
+    publish.properties.topic_alias = Some(1);
+    assert_eq!(Packet::from(publish.clone()).validate(), Ok(()));
+    publish.properties.payload_is_utf8 = Some(true);
+    assert_eq!(
+        Packet::from(publish).validate(),
+        Err(ErrorV5::InvalidPayloadFormat)
+    );
+
+    let mut options = SubscriptionOptions::new(QoS::Level1);
+    options.no_local = true;
+    let filter = TopicFilter::try_from("$share/group/a/b".to_owned()).unwrap();
+    let subscribe = Subscribe::new(Pid::default(), vec![(filter, options)]);
+    assert_eq!(
+        Packet::from(subscribe).validate(),
+        Err(Error::InvalidPacket("no local on shared subscription").into())
+    );
+
+    let auth = Auth::new(AuthReasonCode::ContinueAuthentication);
+    assert_eq!(
+        Packet::from(auth).validate(),
+        Err(Error::InvalidPacket("auth without auth method").into())
+    );
+    assert_eq!(Packet::from(Auth::new_success()).validate(), Ok(()));
+}
+
+#[test]
+fn test_reason_code_description() {
+    assert!(!ConnectReasonCode::Success.is_error());
+    assert!(ConnectReasonCode::Banned.is_error());
+    assert_eq!(
+        DisconnectReasonCode::ReceiveMaximumExceeded.description(),
+        "The Client/Server has received more than Receive Maximum publication for \
+         which it has not sent PUBACK or PUBCOMP."
+    );
+    assert!(!PubackReasonCode::NoMatchingSubscribers.is_error());
+    assert!(PubrelReasonCode::PacketIdentifierNotFound.is_error());
+    assert!(!SubscribeReasonCode::GrantedQoS2.is_error());
+    assert!(SubscribeReasonCode::QuotaExceeded.is_error());
+    assert!(!AuthReasonCode::ContinueAuthentication.is_error());
+}
+
+#[test]
+fn test_subscribe_reason_code_granted_qos() {
+    for qos in [QoS::Level0, QoS::Level1, QoS::Level2] {
+        let code = SubscribeReasonCode::granted(qos);
+        assert!(!code.is_error());
+        assert_eq!(code.granted_qos(), Some(qos));
+    }
+    assert_eq!(SubscribeReasonCode::GrantedQoS1 as u8, 1);
+    assert_eq!(SubscribeReasonCode::NotAuthorized.granted_qos(), None);
+}
+
+#[test]
+fn test_user_properties() {
+    assert_eq!(mem::size_of::<UserProperties>(), mem::size_of::<usize>());
+    let property = UserProperty {
+        name: Arc::from("k"),
+        value: Arc::from("v"),
+    };
+    let mut properties = UserProperties::new();
+    assert!(properties.is_empty());
+    properties.push(property.clone());
+    assert_eq!(properties.len(), 1);
+    assert_eq!(properties, UserProperties::from([property.clone()]));
+    assert_eq!(format!("{properties:?}"), format!("{:?}", [&property]));
+    assert_eq!(properties.pop(), Some(property.clone()));
+    assert_eq!(properties, UserProperties::default());
+    assert_eq!(UserProperties::from(Vec::new()), UserProperties::new());
+
+    let properties: UserProperties = vec![property.clone(); 3].into_iter().collect();
+    assert_eq!(properties.len(), 3);
+    assert_eq!(Vec::from(properties), vec![property; 3]);
+}
+
+#[test]
+fn test_last_will_builder() {
+    let topic_name = TopicName::try_from("a/b").unwrap();
+    let payload = Bytes::from_static(b"offline");
+    assert_eq!(
+        LastWill::builder(topic_name.clone(), payload.clone()).build(),
+        LastWill::new(QoS::Level0, topic_name.clone(), payload.clone())
+    );
+
+    let last_will = LastWill::builder(topic_name.clone(), payload.clone())
+        .qos(QoS::Level1)
+        .retain()
+        .delay_interval(30)
+        .message_expiry_interval(3600)
+        .content_type("text/plain")
+        .payload_is_utf8(true)
+        .user_property("k", "v")
+        .build();
+    let expected = LastWill {
+        qos: QoS::Level1,
+        retain: true,
+        topic_name: topic_name.clone(),
+        payload,
+        properties: WillProperties {
+            delay_interval: Some(30),
+            payload_is_utf8: Some(true),
+            message_expiry_interval: Some(3600),
+            content_type: Some(Arc::from("text/plain")),
+            user_properties: [UserProperty {
+                name: Arc::from("k"),
+                value: Arc::from("v"),
+            }]
+            .into_iter()
+            .collect(),
+            ..Default::default()
+        },
+    };
+    assert_eq!(last_will, expected);
+
+    let mut connect = Connect::new("client", 30);
+    connect.last_will = Some(last_will);
+    let packet: Packet = connect.into();
+    let len = packet.encode_len().unwrap();
+    assert_encode(packet, len);
+}
+
+#[test]
+fn test_subscription_options_from_u8() {
+    let options = SubscriptionOptions::default();
+    assert_eq!(options, SubscriptionOptions::new(QoS::Level0));
+    assert_eq!(options.to_u8(), 0b1000);
+    for value in 0..=u8::MAX {
+        match SubscriptionOptions::from_u8(value) {
+            Ok(options) => assert_eq!(options.to_u8(), value),
+            Err(err) => {
+                assert_eq!(err, ErrorV5::InvalidSubscriptionOption(value));
+                assert!(
+                    value & 0b11000000 > 0 || value & 0b11 == 3 || value & 0b110000 == 0b110000
+                );
+            }
+        }
+    }
+    let options = SubscriptionOptions::from_u8(0b101110).unwrap();
+    assert_eq!(options.max_qos, QoS::Level2);
+    assert!(options.no_local);
+    assert!(options.retain_as_published);
+    assert_eq!(options.retain_handling, RetainHandling::DoNotSend);
+}
+
+#[cfg(feature = "proptest")]
+proptest::proptest! {
+    #[test]
+    fn test_strategy_packet(pkt in v5::strategy::packet()) {
+        pkt.validate().unwrap();
+        let len = pkt.encode_len().unwrap();
+        assert_encode(pkt, len);
+    }
+}
+
+#[test]
+fn test_v5_subscribe_split() {
+    let topics: Vec<_> = (0..10)
+        .map(|i| {
+            let filter = TopicFilter::try_from(format!("topic/{i}")).unwrap();
+            (filter, SubscriptionOptions::new(QoS::Level1))
+        })
+        .collect();
+    let properties = SubscribeProperties {
+        subscription_id: Some(VarByteInt::try_from(3u32).unwrap()),
+        ..Default::default()
+    };
+    let mut pids = PidAllocator::new();
+    // The properties take 3 bytes, each filter takes 3 + 7 bytes
+    let packets = Subscribe::split(properties.clone(), topics.clone(), 2 + 5 + 30, || {
+        pids.alloc()
+    })
+    .unwrap();
+    assert_eq!(packets.len(), 4);
+    for packet in &packets {
+        assert_eq!(packet.properties, properties);
+        assert!(Packet::Subscribe(packet.clone()).encode_len().unwrap() <= 37);
+    }
+    let all: Vec<_> = packets.into_iter().flat_map(|p| p.topics).collect();
+    assert_eq!(all, topics);
+
+    let filters: Vec<_> = topics.into_iter().map(|(filter, _)| filter).collect();
+    let packets =
+        Unsubscribe::split(Default::default(), filters, 2 + 3 + 9, || pids.alloc()).unwrap();
+    assert_eq!(packets.len(), 10);
+    let err = Unsubscribe::split(
+        Default::default(),
+        vec![TopicFilter::try_from("topic/0").unwrap()],
+        13,
+        || pids.alloc(),
+    )
+    .unwrap_err();
+    assert_eq!(err, ErrorV5::Common(Error::PacketTooLarge(14)));
+}
+
+#[test]
+fn test_v5_encode_value_too_long() {
+    let mut publish = Publish::new(
+        QosPid::Level0,
+        TopicName::try_from("a/b").unwrap(),
+        Bytes::new(),
+    );
+    publish.properties.user_properties.push(UserProperty {
+        name: "name".into(),
+        value: "v".repeat(u16::MAX as usize + 1).into(),
+    });
+    let packet: Packet = publish.into();
+    assert_eq!(packet.encode(), Err(Error::ValueTooLong(65536)));
+
+    // The total length of the properties exceeds 268,435,455
+    let value: Arc<str> = "v".repeat(u16::MAX as usize).into();
+    let mut publish = Publish::new(
+        QosPid::Level0,
+        TopicName::try_from("a/b").unwrap(),
+        Bytes::new(),
+    );
+    for _ in 0..4200 {
+        publish.properties.user_properties.push(UserProperty {
+            name: "name".into(),
+            value: value.clone(),
+        });
+    }
+    let packet: Packet = publish.into();
+    assert_eq!(packet.encode_len(), Err(Error::InvalidVarByteInt.into()));
+    assert_eq!(packet.encode(), Err(Error::InvalidVarByteInt));
+}
+
+#[test]
+fn test_v5_packet_display() {
+    let mut connect = Connect::new("client", 60);
+    connect.password = Some(Bytes::from_static(b"secret"));
+    connect.properties.auth_method = Some(Arc::from("SCRAM-SHA-1"));
+    connect.properties.auth_data = Some(Bytes::from_static(b"nonce"));
+    let output = Packet::from(connect).to_string();
+    assert_eq!(
+        output,
+        "CONNECT protocol=v5.0 client_id=client keep_alive=60 clean_start password=*** auth_method=SCRAM-SHA-1 auth_data=***"
+    );
+    assert!(!output.contains("secret") && !output.contains("nonce"));
+
+    let publish = Publish::new(
+        QosPid::Level2(Pid::try_from(7).unwrap()),
+        TopicName::try_from("a/b".to_owned()).unwrap(),
+        Bytes::from_static(b"hello"),
+    );
+    assert_eq!(
+        Packet::from(publish).to_string(),
+        "PUBLISH qos=2 pid=7 topic=a/b len=5"
+    );
+    assert_eq!(
+        Packet::from(Puback::new_success(Pid::try_from(7).unwrap())).to_string(),
+        "PUBACK pid=7 code=Success"
+    );
+    assert_eq!(
+        Packet::from(Disconnect::new_normal()).to_string(),
+        "DISCONNECT code=NormalDisconnect"
+    );
+}
+
+#[cfg(feature = "json")]
+#[test]
+fn test_v5_packet_to_json() {
+    use serde_json::json;
+
+    let mut publish = Publish::new(
+        QosPid::Level0,
+        TopicName::try_from("a/b".to_owned()).unwrap(),
+        Bytes::from_static(b"hi"),
+    );
+    publish.properties.correlation_data = Some(Bytes::from_static(b"\xff"));
+    publish.properties.user_properties.push(UserProperty {
+        name: Arc::from("k"),
+        value: Arc::from("v"),
+    });
+    assert_eq!(
+        Packet::from(publish).to_json(),
+        json!({
+            "type": "PUBLISH",
+            "dup": false,
+            "retain": false,
+            "qos": 0,
+            "pid": null,
+            "topic_name": "a/b",
+            "properties": [
+                { "id": "CorrelationData", "value": "ff" },
+                { "id": "UserProperty", "value": { "name": "k", "value": "v" } },
+            ],
+            "payload": "6869",
+        })
+    );
+
+    let mut puback = Puback::new(Pid::try_from(7).unwrap(), PubackReasonCode::QuotaExceeded);
+    puback.properties.reason_string = Some(Arc::from("full"));
+    assert_eq!(
+        Packet::from(puback).to_json(),
+        json!({
+            "type": "PUBACK",
+            "pid": 7,
+            "code": "QuotaExceeded",
+            "properties": [{ "id": "ReasonString", "value": "full" }],
+        })
+    );
+}
@@ -0,0 +1,253 @@
+use std::collections::HashMap;
+use std::convert::TryFrom;
+
+use tokio::io::AsyncWrite;
+
+use super::{ErrorV5, Packet, Publish};
+use crate::{TopicName, VarBytes};
+
+/// Track topic aliases of a network connection.
+///
+/// Inbound aliases are registered by the peer, the maximum is the "Topic
+/// Alias Maximum" we sent to the peer. Outbound aliases are allocated by us,
+/// the maximum is the "Topic Alias Maximum" the peer sent to us. Topic
+/// aliases only live within a network connection, create a new manager (or
+/// call `clear()`) when reconnecting.
+#[derive(Debug, Clone, Default)]
+pub struct TopicAliasManager {
+    inbound_max: u16,
+    inbound: HashMap<u16, TopicName>,
+    outbound_max: u16,
+    outbound: HashMap<TopicName, u16>,
+}
+
+impl TopicAliasManager {
+    pub fn new(inbound_max: u16, outbound_max: u16) -> Self {
+        TopicAliasManager {
+            inbound_max,
+            outbound_max,
+            ..Default::default()
+        }
+    }
+
+    pub fn inbound_max(&self) -> u16 {
+        self.inbound_max
+    }
+
+    pub fn outbound_max(&self) -> u16 {
+        self.outbound_max
+    }
+
+    /// Update the outbound maximum (e.g. after CONNACK is received), the
+    /// allocated aliases which exceed the new maximum are dropped.
+    pub fn set_outbound_max(&mut self, outbound_max: u16) {
+        self.outbound_max = outbound_max;
+        self.outbound.retain(|_, alias| *alias <= outbound_max);
+    }
+
+    /// Remove all the inbound and outbound aliases.
+    pub fn clear(&mut self) {
+        self.inbound.clear();
+        self.outbound.clear();
+    }
+
+    /// Resolve the topic alias of a received PUBLISH packet.
+    ///
+    /// If the topic name is empty it is replaced by the topic name assigned
+    /// to the alias, otherwise the topic name is assigned to the alias.
+    pub fn resolve_inbound(&mut self, publish: &mut Publish) -> Result<(), ErrorV5> {
+        let alias = match publish.properties.topic_alias {
+            Some(alias) => alias,
+            None => return Ok(()),
+        };
+        if alias == 0 || alias > self.inbound_max {
+            return Err(ErrorV5::InvalidTopicAlias(alias));
+        }
+        if publish.topic_name.is_empty() {
+            let topic_name = self
+                .inbound
+                .get(&alias)
+                .ok_or(ErrorV5::UnknownTopicAlias(alias))?;
+            publish.topic_name = topic_name.clone();
+        } else {
+            self.inbound.insert(alias, publish.topic_name.clone());
+        }
+        Ok(())
+    }
+
+    /// Apply a topic alias to a PUBLISH packet to send.
+    ///
+    /// If the topic name already has an alias, the topic name is replaced by
+    /// an empty string. Otherwise a new alias is allocated if there is any
+    /// left, and the topic name is sent along with it. The packet is not
+    /// changed if the topic alias is already set.
+    pub fn apply_outbound(&mut self, publish: &mut Publish) {
+        if publish.properties.topic_alias.is_some() || publish.topic_name.is_empty() {
+            return;
+        }
+        if let Some(alias) = self.outbound.get(&publish.topic_name) {
+            publish.properties.topic_alias = Some(*alias);
+            publish.topic_name = TopicName::try_from(String::new()).expect("empty topic name");
+        } else if self.outbound.len() < self.outbound_max as usize {
+            let alias = self.outbound.len() as u16 + 1;
+            self.outbound.insert(publish.topic_name.clone(), alias);
+            publish.properties.topic_alias = Some(alias);
+        }
+    }
+
+    /// Encode a packet to send, a PUBLISH packet is encoded with the topic
+    /// alias applied by `apply_outbound()`. The given packet is not changed.
+    pub fn encode_outbound(&mut self, packet: &Packet) -> Result<VarBytes, ErrorV5> {
+        match packet {
+            Packet::Publish(publish) => {
+                let mut publish = publish.clone();
+                self.apply_outbound(&mut publish);
+                Ok(Packet::Publish(publish).encode()?)
+            }
+            _ => Ok(packet.encode()?),
+        }
+    }
+
+    /// Same as `encode_outbound()`, but write the packet to an async writer.
+    pub async fn encode_outbound_async<T: AsyncWrite + Unpin>(
+        &mut self,
+        packet: &Packet,
+        writer: &mut T,
+    ) -> Result<(), ErrorV5> {
+        match packet {
+            Packet::Publish(publish) => {
+                let mut publish = publish.clone();
+                self.apply_outbound(&mut publish);
+                Packet::Publish(publish).encode_async(writer).await
+            }
+            _ => packet.encode_async(writer).await,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use bytes::Bytes;
+
+    use super::*;
+    use crate::QosPid;
+
+    fn publish(topic_name: &str, topic_alias: Option<u16>) -> Publish {
+        let mut publish = Publish::new(
+            QosPid::Level0,
+            TopicName::try_from(topic_name.to_owned()).unwrap(),
+            Bytes::new(),
+        );
+        publish.properties.topic_alias = topic_alias;
+        publish
+    }
+
+    #[test]
+    fn test_inbound() {
+        let mut manager = TopicAliasManager::new(2, 0);
+
+        let mut packet = publish("a/b", None);
+        manager.resolve_inbound(&mut packet).unwrap();
+        assert_eq!(&*packet.topic_name, "a/b");
+
+        let mut packet = publish("a/b", Some(1));
+        manager.resolve_inbound(&mut packet).unwrap();
+        let mut packet = publish("", Some(1));
+        manager.resolve_inbound(&mut packet).unwrap();
+        assert_eq!(&*packet.topic_name, "a/b");
+
+        // Re-assign the alias
+        let mut packet = publish("c", Some(1));
+        manager.resolve_inbound(&mut packet).unwrap();
+        let mut packet = publish("", Some(1));
+        manager.resolve_inbound(&mut packet).unwrap();
+        assert_eq!(&*packet.topic_name, "c");
+
+        assert_eq!(
+            manager.resolve_inbound(&mut publish("", Some(2))),
+            Err(ErrorV5::UnknownTopicAlias(2))
+        );
+        assert_eq!(
+            manager.resolve_inbound(&mut publish("a", Some(0))),
+            Err(ErrorV5::InvalidTopicAlias(0))
+        );
+        assert_eq!(
+            manager.resolve_inbound(&mut publish("a", Some(3))),
+            Err(ErrorV5::InvalidTopicAlias(3))
+        );
+    }
+
+    #[test]
+    fn test_outbound() {
+        let mut manager = TopicAliasManager::new(0, 2);
+
+        let mut packet = publish("a", None);
+        manager.apply_outbound(&mut packet);
+        assert_eq!(
+            (&*packet.topic_name, packet.properties.topic_alias),
+            ("a", Some(1))
+        );
+        let mut packet = publish("a", None);
+        manager.apply_outbound(&mut packet);
+        assert_eq!(
+            (&*packet.topic_name, packet.properties.topic_alias),
+            ("", Some(1))
+        );
+
+        let mut packet = publish("b", None);
+        manager.apply_outbound(&mut packet);
+        assert_eq!(
+            (&*packet.topic_name, packet.properties.topic_alias),
+            ("b", Some(2))
+        );
+
+        // No alias left
+        let mut packet = publish("c", None);
+        manager.apply_outbound(&mut packet);
+        assert_eq!(
+            (&*packet.topic_name, packet.properties.topic_alias),
+            ("c", None)
+        );
+
+        manager.set_outbound_max(1);
+        let mut packet = publish("b", None);
+        manager.apply_outbound(&mut packet);
+        assert_eq!(
+            (&*packet.topic_name, packet.properties.topic_alias),
+            ("b", None)
+        );
+        let mut packet = publish("a", None);
+        manager.apply_outbound(&mut packet);
+        assert_eq!(
+            (&*packet.topic_name, packet.properties.topic_alias),
+            ("", Some(1))
+        );
+    }
+
+    #[test]
+    fn test_encode_outbound() {
+        let mut manager = TopicAliasManager::new(0, 1);
+        let packet = Packet::Publish(publish("a/long/topic/name", None));
+
+        let first = manager.encode_outbound(&packet).unwrap();
+        let second = manager.encode_outbound(&packet).unwrap();
+        // The topic name is replaced by the alias
+        assert!(second.as_ref().len() < first.as_ref().len());
+        let decoded = Packet::decode(second.as_ref()).unwrap().unwrap();
+        let Packet::Publish(decoded) = decoded else {
+            panic!("not a publish packet");
+        };
+        assert_eq!(
+            (&*decoded.topic_name, decoded.properties.topic_alias),
+            ("", Some(1))
+        );
+
+        let mut data = Vec::new();
+        futures_lite::future::block_on(manager.encode_outbound_async(&packet, &mut data)).unwrap();
+        assert_eq!(data, second.as_ref());
+        assert_eq!(
+            manager.encode_outbound(&Packet::Pingreq).unwrap().as_ref(),
+            Packet::Pingreq.encode().unwrap().as_ref()
+        );
+    }
+}
@@ -1,12 +1,14 @@
 use std::convert::TryFrom;
 use std::fmt;
+use std::hash::{Hash, Hasher};
+use std::ops::{Deref, DerefMut};
 use std::sync::Arc;
 
 use bytes::Bytes;
 use tokio::io::AsyncRead;
 
 use super::ErrorV5;
-use crate::{read_bytes, read_string, read_u16, read_u32, read_u8, Error, TopicName};
+use crate::{decode_var_int, read_exact_capped, write_var_int, Error, QoS, SliceReader, TopicName};
 
 /// [Property identifier](https://docs.oasis-open.org/mqtt/mqtt/v5.0/os/mqtt-v5.0-os.html#_Toc3901027)
 ///
@@ -44,6 +46,7 @@ use crate::{read_bytes, read_string, read_u16, read_u32, read_u8, Error, TopicNa
 /// |  42 | 0x2A | Shared Subscription Available     | Byte                  | CONNACK                                         |
 #[repr(u8)]
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum PropertyId {
     PayloadFormatIndicator = 0x01,
     MessageExpiryInterval = 0x02,
@@ -121,114 +124,253 @@ pub(crate) struct PropertyValue;
 
 impl PropertyValue {
     #[inline]
-    pub(crate) async fn decode_bool<T: AsyncRead + Unpin>(
-        reader: &mut T,
+    pub(crate) fn decode_bool(
+        reader: &mut SliceReader<'_>,
         property_id: PropertyId,
-        target: &mut Option<bool>,
-    ) -> Result<(), ErrorV5> {
-        if target.is_some() {
-            return Err(ErrorV5::DuplicatedProperty(property_id));
-        }
-        let value = read_u8(reader).await?;
+    ) -> Result<bool, ErrorV5> {
+        let value = reader.read_u8()?;
         if value > 1 {
             Err(ErrorV5::InvalidByteProperty(property_id, value))
         } else {
-            *target = Some(value == 1);
-            Ok(())
+            Ok(value == 1)
         }
     }
 
     #[inline]
-    pub(crate) async fn decode_u16<T: AsyncRead + Unpin>(
-        reader: &mut T,
+    pub(crate) fn decode_max_qos(
+        reader: &mut SliceReader<'_>,
         property_id: PropertyId,
-        target: &mut Option<u16>,
-    ) -> Result<(), ErrorV5> {
-        if target.is_some() {
-            return Err(ErrorV5::DuplicatedProperty(property_id));
+    ) -> Result<QoS, ErrorV5> {
+        let value = reader.read_u8()?;
+        if value > 1 {
+            Err(ErrorV5::InvalidByteProperty(property_id, value))
+        } else {
+            Ok(QoS::from_u8(value).expect("0/1 qos"))
         }
-        *target = Some(read_u16(reader).await?);
-        Ok(())
     }
 
     #[inline]
-    pub(crate) async fn decode_u32<T: AsyncRead + Unpin>(
-        reader: &mut T,
-        property_id: PropertyId,
-        target: &mut Option<u32>,
-    ) -> Result<(), ErrorV5> {
-        if target.is_some() {
-            return Err(ErrorV5::DuplicatedProperty(property_id));
-        }
-        *target = Some(read_u32(reader).await?);
-        Ok(())
+    pub(crate) fn decode_string(reader: &mut SliceReader<'_>) -> Result<Arc<str>, ErrorV5> {
+        Ok(Arc::from(reader.read_str()?))
     }
 
     #[inline]
-    pub(crate) async fn decode_string<T: AsyncRead + Unpin>(
-        reader: &mut T,
-        property_id: PropertyId,
-        target: &mut Option<Arc<String>>,
-    ) -> Result<(), ErrorV5> {
-        if target.is_some() {
-            return Err(ErrorV5::DuplicatedProperty(property_id));
-        }
-        *target = Some(Arc::new(read_string(reader).await?));
-        Ok(())
+    pub(crate) fn decode_topic_name(reader: &mut SliceReader<'_>) -> Result<TopicName, ErrorV5> {
+        TopicName::try_from(reader.read_str()?.to_owned()).map_err(|err| match err {
+            Error::InvalidTopicName(_) => ErrorV5::InvalidResponseTopic,
+            err => err.into(),
+        })
     }
 
     #[inline]
-    pub(crate) async fn decode_topic_name<T: AsyncRead + Unpin>(
-        reader: &mut T,
-        property_id: PropertyId,
-        target: &mut Option<TopicName>,
-    ) -> Result<(), ErrorV5> {
-        if target.is_some() {
-            return Err(ErrorV5::DuplicatedProperty(property_id));
-        }
-        let content = read_string(reader).await?;
-        *target = Some(TopicName::try_from(content)?);
-        Ok(())
+    pub(crate) fn decode_bytes(reader: &mut SliceReader<'_>) -> Result<Bytes, ErrorV5> {
+        Ok(Bytes::copy_from_slice(reader.read_bytes()?))
+    }
+
+    #[inline]
+    pub(crate) fn decode_subscription_id(
+        reader: &mut SliceReader<'_>,
+    ) -> Result<VarByteInt, ErrorV5> {
+        let value = reader.read_var_int()?;
+        Self::check_subscription_id(value)?;
+        VarByteInt::try_from(value)
     }
 
     #[inline]
-    pub(crate) async fn decode_bytes<T: AsyncRead + Unpin>(
-        reader: &mut T,
+    pub(crate) fn decode_user_property(
+        reader: &mut SliceReader<'_>,
+    ) -> Result<UserProperty, ErrorV5> {
+        let name = Self::decode_string(reader)?;
+        let value = Self::decode_string(reader)?;
+        Ok(UserProperty { name, value })
+    }
+
+    /// Receive Maximum and Maximum Packet Size must not be 0.
+    #[inline]
+    pub(crate) fn check_nonzero<V: Default + PartialEq>(
         property_id: PropertyId,
-        target: &mut Option<Bytes>,
-    ) -> Result<(), ErrorV5> {
-        if target.is_some() {
-            return Err(ErrorV5::DuplicatedProperty(property_id));
+        value: V,
+    ) -> Result<V, ErrorV5> {
+        if value == V::default() {
+            Err(ErrorV5::ZeroProperty(property_id))
+        } else {
+            Ok(value)
         }
-        *target = Some(Bytes::from(read_bytes(reader).await?));
-        Ok(())
     }
 
+    /// Subscription Identifier must not be 0.
     #[inline]
-    pub(crate) async fn decode_user_property<T: AsyncRead + Unpin>(
-        reader: &mut T,
-    ) -> Result<UserProperty, ErrorV5> {
-        let name = read_string(reader).await?;
-        let value = read_string(reader).await?;
-        Ok(UserProperty {
-            name: Arc::new(name),
-            value: Arc::new(value),
-        })
+    pub(crate) fn check_subscription_id(value: u32) -> Result<(), ErrorV5> {
+        if value == 0 {
+            Err(ErrorV5::ZeroSubscriptionId)
+        } else {
+            Ok(())
+        }
+    }
+}
+
+/// The user properties of a property list.
+///
+/// Most packets carry no user property, so the properties are stored as
+/// `Option<Box<Vec<UserProperty>>>`: nothing is allocated when it is empty
+/// and the field is only one pointer wide (a `Vec` is three). It derefs to
+/// `[UserProperty]` and has the `Vec` methods used to build the list.
+#[derive(Clone, Default)]
+// The box is intended, it makes the field one pointer wide
+#[allow(clippy::box_collection)]
+pub struct UserProperties(Option<Box<Vec<UserProperty>>>);
+
+impl UserProperties {
+    pub const fn new() -> Self {
+        UserProperties(None)
+    }
+
+    pub fn push(&mut self, property: UserProperty) {
+        self.0.get_or_insert_with(Default::default).push(property);
+    }
+
+    pub fn pop(&mut self) -> Option<UserProperty> {
+        let properties = self.0.as_mut()?;
+        let property = properties.pop();
+        if properties.is_empty() {
+            self.0 = None;
+        }
+        property
+    }
+
+    pub fn clear(&mut self) {
+        self.0 = None;
+    }
+
+    pub fn into_vec(self) -> Vec<UserProperty> {
+        self.0.map(|properties| *properties).unwrap_or_default()
+    }
+}
+
+impl Deref for UserProperties {
+    type Target = [UserProperty];
+    fn deref(&self) -> &[UserProperty] {
+        self.0.as_deref().map_or(&[], Vec::as_slice)
+    }
+}
+
+impl DerefMut for UserProperties {
+    fn deref_mut(&mut self) -> &mut [UserProperty] {
+        self.0.as_deref_mut().map_or(&mut [], Vec::as_mut_slice)
+    }
+}
+
+impl fmt::Debug for UserProperties {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Debug::fmt(&**self, f)
+    }
+}
+
+impl PartialEq for UserProperties {
+    fn eq(&self, other: &Self) -> bool {
+        **self == **other
+    }
+}
+
+impl Eq for UserProperties {}
+
+impl Hash for UserProperties {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        (**self).hash(state);
+    }
+}
+
+impl From<Vec<UserProperty>> for UserProperties {
+    fn from(properties: Vec<UserProperty>) -> Self {
+        if properties.is_empty() {
+            UserProperties(None)
+        } else {
+            UserProperties(Some(Box::new(properties)))
+        }
+    }
+}
+
+impl<const N: usize> From<[UserProperty; N]> for UserProperties {
+    fn from(properties: [UserProperty; N]) -> Self {
+        Vec::from(properties).into()
+    }
+}
+
+impl From<UserProperties> for Vec<UserProperty> {
+    fn from(properties: UserProperties) -> Self {
+        properties.into_vec()
+    }
+}
+
+impl FromIterator<UserProperty> for UserProperties {
+    fn from_iter<I: IntoIterator<Item = UserProperty>>(iter: I) -> Self {
+        Vec::from_iter(iter).into()
+    }
+}
+
+impl Extend<UserProperty> for UserProperties {
+    fn extend<I: IntoIterator<Item = UserProperty>>(&mut self, iter: I) {
+        for property in iter {
+            self.push(property);
+        }
+    }
+}
+
+impl IntoIterator for UserProperties {
+    type Item = UserProperty;
+    type IntoIter = std::vec::IntoIter<UserProperty>;
+    fn into_iter(self) -> Self::IntoIter {
+        self.into_vec().into_iter()
+    }
+}
+
+impl<'a> IntoIterator for &'a UserProperties {
+    type Item = &'a UserProperty;
+    type IntoIter = std::slice::Iter<'a, UserProperty>;
+    fn into_iter(self) -> Self::IntoIter {
+        self.iter()
+    }
+}
+
+#[cfg(feature = "serde")]
+impl serde::Serialize for UserProperties {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        (**self).serialize(serializer)
+    }
+}
+
+#[cfg(feature = "serde")]
+impl<'de> serde::Deserialize<'de> for UserProperties {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        Vec::<UserProperty>::deserialize(deserializer).map(Self::from)
+    }
+}
+
+#[cfg(feature = "arbitrary")]
+impl<'a> arbitrary::Arbitrary<'a> for UserProperties {
+    fn arbitrary(u: &mut arbitrary::Unstructured<'a>) -> arbitrary::Result<Self> {
+        Ok(Vec::<UserProperty>::arbitrary(u)?.into())
     }
 }
 
 /// User Property is a UTF-8 String Pair.
 #[derive(Debug, Clone, PartialEq, Eq, Hash)]
 #[cfg_attr(feature = "arbitrary", derive(arbitrary::Arbitrary))]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct UserProperty {
     /// The name of the user property.
-    pub name: Arc<String>,
+    pub name: Arc<str>,
     /// The value of the user property.
-    pub value: Arc<String>,
+    pub value: Arc<str>,
 }
 
 /// Variable Byte Integer
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default, PartialOrd, Ord)]
+#[cfg_attr(
+    feature = "serde",
+    derive(serde::Serialize, serde::Deserialize),
+    serde(try_from = "u32")
+)]
 pub struct VarByteInt(u32);
 
 #[cfg(feature = "arbitrary")]
@@ -240,9 +382,53 @@ impl<'a> arbitrary::Arbitrary<'a> for VarByteInt {
 }
 
 impl VarByteInt {
+    /// The max value can be encoded (268,435,455).
+    pub const MAX: VarByteInt = VarByteInt(268435455);
+
     pub fn value(self) -> u32 {
         self.0
     }
+
+    /// Number of bytes of the encoded value (1 to 4).
+    pub fn encoded_len(self) -> usize {
+        match self.0 {
+            0..=127 => 1,
+            128..=16383 => 2,
+            16384..=2097151 => 3,
+            _ => 4,
+        }
+    }
+
+    /// Return `None` if the sum exceeds `VarByteInt::MAX`.
+    pub fn checked_add(self, other: VarByteInt) -> Option<VarByteInt> {
+        // Both values are less than 2^28, can't overflow
+        let value = self.0 + other.0;
+        (value <= Self::MAX.0).then_some(VarByteInt(value))
+    }
+}
+
+impl fmt::Display for VarByteInt {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+impl From<u8> for VarByteInt {
+    fn from(value: u8) -> Self {
+        VarByteInt(u32::from(value))
+    }
+}
+
+impl From<u16> for VarByteInt {
+    fn from(value: u16) -> Self {
+        VarByteInt(u32::from(value))
+    }
+}
+
+impl From<VarByteInt> for u32 {
+    fn from(value: VarByteInt) -> u32 {
+        value.0
+    }
 }
 
 impl TryFrom<u32> for VarByteInt {
@@ -256,270 +442,252 @@ impl TryFrom<u32> for VarByteInt {
     }
 }
 
+/// Decode the value of a property, all the per-property validations are here.
 macro_rules! decode_property {
-    (PayloadFormatIndicator, $properties:expr, $reader:expr, $property_id:expr) => {
-        crate::v5::PropertyValue::decode_bool(
-            $reader,
-            $property_id,
-            &mut $properties.payload_is_utf8,
-        )
-        .await?;
+    (PayloadFormatIndicator, $reader:expr, $property_id:expr) => {
+        crate::v5::PropertyValue::decode_bool($reader, $property_id)?
     };
-    (MessageExpiryInterval, $properties:expr, $reader:expr, $property_id:expr) => {
-        crate::v5::PropertyValue::decode_u32(
-            $reader,
-            $property_id,
-            &mut $properties.message_expiry_interval,
-        )
-        .await?;
+    (MessageExpiryInterval, $reader:expr, $property_id:expr) => {
+        $reader.read_u32()?
     };
-    (ContentType, $properties:expr, $reader:expr, $property_id:expr) => {
-        crate::v5::PropertyValue::decode_string(
-            $reader,
-            $property_id,
-            &mut $properties.content_type,
-        )
-        .await?;
+    (ContentType, $reader:expr, $property_id:expr) => {
+        crate::v5::PropertyValue::decode_string($reader)?
     };
-    (ResponseTopic, $properties:expr, $reader:expr, $property_id:expr) => {
-        crate::v5::PropertyValue::decode_topic_name(
-            $reader,
-            $property_id,
-            &mut $properties.response_topic,
-        )
-        .await
-        .map_err(|err| match err {
-            crate::v5::ErrorV5::Common(crate::Error::InvalidTopicName(_)) => {
-                crate::v5::ErrorV5::InvalidResponseTopic
-            }
-            err => err,
-        })?;
+    (ResponseTopic, $reader:expr, $property_id:expr) => {
+        crate::v5::PropertyValue::decode_topic_name($reader)?
     };
-    (CorrelationData, $properties:expr, $reader:expr, $property_id:expr) => {
-        crate::v5::PropertyValue::decode_bytes(
-            $reader,
-            $property_id,
-            &mut $properties.correlation_data,
-        )
-        .await?;
+    (CorrelationData, $reader:expr, $property_id:expr) => {
+        crate::v5::PropertyValue::decode_bytes($reader)?
     };
-    (SubscriptionIdentifier, $properties:expr, $reader:expr, $property_id:expr) => {
-        if $properties.subscription_id.is_some() {
-            return Err(crate::v5::ErrorV5::DuplicatedProperty($property_id));
-        }
-        let (value, _bytes) = crate::decode_var_int($reader).await?;
-        $properties.subscription_id = Some(crate::v5::VarByteInt::try_from(value)?);
+    (SubscriptionIdentifier, $reader:expr, $property_id:expr) => {
+        crate::v5::PropertyValue::decode_subscription_id($reader)?
     };
-    (SessionExpiryInterval, $properties:expr, $reader:expr, $property_id:expr) => {
-        crate::v5::PropertyValue::decode_u32(
-            $reader,
-            $property_id,
-            &mut $properties.session_expiry_interval,
-        )
-        .await?;
+    (SessionExpiryInterval, $reader:expr, $property_id:expr) => {
+        $reader.read_u32()?
     };
-    (AssignedClientIdentifier, $properties:expr, $reader:expr, $property_id:expr) => {
-        crate::v5::PropertyValue::decode_string(
-            $reader,
-            $property_id,
-            &mut $properties.assigned_client_id,
-        )
-        .await?;
+    (AssignedClientIdentifier, $reader:expr, $property_id:expr) => {
+        crate::v5::PropertyValue::decode_string($reader)?
     };
-    (ServerKeepAlive, $properties:expr, $reader:expr, $property_id:expr) => {
-        crate::v5::PropertyValue::decode_u16(
-            $reader,
-            $property_id,
-            &mut $properties.server_keep_alive,
-        )
-        .await?;
+    (ServerKeepAlive, $reader:expr, $property_id:expr) => {
+        $reader.read_u16()?
     };
-    (AuthenticationMethod, $properties:expr, $reader:expr, $property_id:expr) => {
-        crate::v5::PropertyValue::decode_string(
-            $reader,
-            $property_id,
-            &mut $properties.auth_method,
-        )
-        .await?;
+    (AuthenticationMethod, $reader:expr, $property_id:expr) => {
+        crate::v5::PropertyValue::decode_string($reader)?
     };
-    (AuthenticationData, $properties:expr, $reader:expr, $property_id:expr) => {
-        crate::v5::PropertyValue::decode_bytes($reader, $property_id, &mut $properties.auth_data)
-            .await?;
+    (AuthenticationData, $reader:expr, $property_id:expr) => {
+        crate::v5::PropertyValue::decode_bytes($reader)?
     };
-    (RequestProblemInformation, $properties:expr, $reader:expr, $property_id:expr) => {
-        crate::v5::PropertyValue::decode_bool(
-            $reader,
-            $property_id,
-            &mut $properties.request_problem_info,
-        )
-        .await?;
+    (RequestProblemInformation, $reader:expr, $property_id:expr) => {
+        crate::v5::PropertyValue::decode_bool($reader, $property_id)?
     };
-    (WillDelayInterval, $properties:expr, $reader:expr, $property_id:expr) => {
-        crate::v5::PropertyValue::decode_u32(
-            $reader,
-            $property_id,
-            &mut $properties.delay_interval,
-        )
-        .await?;
+    (WillDelayInterval, $reader:expr, $property_id:expr) => {
+        $reader.read_u32()?
     };
-    (RequestResponseInformation, $properties:expr, $reader:expr, $property_id:expr) => {
-        crate::v5::PropertyValue::decode_bool(
-            $reader,
-            $property_id,
-            &mut $properties.request_response_info,
-        )
-        .await?;
+    (RequestResponseInformation, $reader:expr, $property_id:expr) => {
+        crate::v5::PropertyValue::decode_bool($reader, $property_id)?
     };
-    (ResponseInformation, $properties:expr, $reader:expr, $property_id:expr) => {
-        crate::v5::PropertyValue::decode_string(
-            $reader,
-            $property_id,
-            &mut $properties.response_info,
-        )
-        .await?;
+    (ResponseInformation, $reader:expr, $property_id:expr) => {
+        crate::v5::PropertyValue::decode_string($reader)?
     };
-    (ServerReference, $properties:expr, $reader:expr, $property_id:expr) => {
-        crate::v5::PropertyValue::decode_string(
-            $reader,
-            $property_id,
-            &mut $properties.server_reference,
-        )
-        .await?;
+    (ServerReference, $reader:expr, $property_id:expr) => {
+        crate::v5::PropertyValue::decode_string($reader)?
     };
-    (ReasonString, $properties:expr, $reader:expr, $property_id:expr) => {
-        crate::v5::PropertyValue::decode_string(
-            $reader,
-            $property_id,
-            &mut $properties.reason_string,
-        )
-        .await?;
+    (ReasonString, $reader:expr, $property_id:expr) => {
+        crate::v5::PropertyValue::decode_string($reader)?
     };
-    (ReceiveMaximum, $properties:expr, $reader:expr, $property_id:expr) => {
-        crate::v5::PropertyValue::decode_u16($reader, $property_id, &mut $properties.receive_max)
-            .await?;
+    (ReceiveMaximum, $reader:expr, $property_id:expr) => {
+        crate::v5::PropertyValue::check_nonzero($property_id, $reader.read_u16()?)?
     };
-    (TopicAliasMaximum, $properties:expr, $reader:expr, $property_id:expr) => {
-        crate::v5::PropertyValue::decode_u16(
-            $reader,
-            $property_id,
-            &mut $properties.topic_alias_max,
-        )
-        .await?;
+    (TopicAliasMaximum, $reader:expr, $property_id:expr) => {
+        $reader.read_u16()?
     };
-    (TopicAlias, $properties:expr, $reader:expr, $property_id:expr) => {
-        crate::v5::PropertyValue::decode_u16($reader, $property_id, &mut $properties.topic_alias)
-            .await?;
+    (TopicAlias, $reader:expr, $property_id:expr) => {
+        $reader.read_u16()?
     };
-    (MaximumQoS, $properties:expr, $reader:expr, $property_id:expr) => {
-        if $properties.max_qos.is_some() {
-            return Err(crate::v5::ErrorV5::DuplicatedProperty($property_id));
-        }
-        let value = crate::read_u8($reader).await?;
-        if value > 1 {
-            return Err(crate::v5::ErrorV5::InvalidByteProperty($property_id, value));
-        } else {
-            $properties.max_qos = Some(crate::QoS::from_u8(value).expect("0/1 qos"));
-        }
+    (MaximumQoS, $reader:expr, $property_id:expr) => {
+        crate::v5::PropertyValue::decode_max_qos($reader, $property_id)?
     };
-    (RetainAvailable, $properties:expr, $reader:expr, $property_id:expr) => {
-        crate::v5::PropertyValue::decode_bool(
-            $reader,
-            $property_id,
-            &mut $properties.retain_available,
-        )
-        .await?;
+    (RetainAvailable, $reader:expr, $property_id:expr) => {
+        crate::v5::PropertyValue::decode_bool($reader, $property_id)?
     };
-    (UserProperty, $properties:expr, $reader:expr, $property_id:expr) => {
-        let user_property = crate::v5::PropertyValue::decode_user_property($reader).await?;
-        $properties.user_properties.push(user_property);
+    (UserProperty, $reader:expr, $property_id:expr) => {
+        crate::v5::PropertyValue::decode_user_property($reader)?
     };
-    (MaximumPacketSize, $properties:expr, $reader:expr, $property_id:expr) => {
-        crate::v5::PropertyValue::decode_u32(
-            $reader,
-            $property_id,
-            &mut $properties.max_packet_size,
-        )
-        .await?;
+    (MaximumPacketSize, $reader:expr, $property_id:expr) => {
+        crate::v5::PropertyValue::check_nonzero($property_id, $reader.read_u32()?)?
     };
-    (WildcardSubscriptionAvailable, $properties:expr, $reader:expr, $property_id:expr) => {
-        crate::v5::PropertyValue::decode_bool(
-            $reader,
-            $property_id,
-            &mut $properties.wildcard_subscription_available,
-        )
-        .await?;
+    (WildcardSubscriptionAvailable, $reader:expr, $property_id:expr) => {
+        crate::v5::PropertyValue::decode_bool($reader, $property_id)?
     };
-    (SubscriptionIdentifierAvailable, $properties:expr, $reader:expr, $property_id:expr) => {
-        crate::v5::PropertyValue::decode_bool(
-            $reader,
-            $property_id,
-            &mut $properties.subscription_id_available,
-        )
-        .await?;
+    (SubscriptionIdentifierAvailable, $reader:expr, $property_id:expr) => {
+        crate::v5::PropertyValue::decode_bool($reader, $property_id)?
     };
-    (SharedSubscriptionAvailable, $properties:expr, $reader:expr, $property_id:expr) => {
-        crate::v5::PropertyValue::decode_bool(
-            $reader,
-            $property_id,
-            &mut $properties.shared_subscription_available,
-        )
-        .await?;
+    (SharedSubscriptionAvailable, $reader:expr, $property_id:expr) => {
+        crate::v5::PropertyValue::decode_bool($reader, $property_id)?
+    };
+}
+
+/// The field of a property struct which stores the property.
+macro_rules! property_field {
+    (PayloadFormatIndicator, $properties:expr) => {
+        $properties.payload_is_utf8
+    };
+    (MessageExpiryInterval, $properties:expr) => {
+        $properties.message_expiry_interval
+    };
+    (ContentType, $properties:expr) => {
+        $properties.content_type
+    };
+    (ResponseTopic, $properties:expr) => {
+        $properties.response_topic
+    };
+    (CorrelationData, $properties:expr) => {
+        $properties.correlation_data
+    };
+    (SubscriptionIdentifier, $properties:expr) => {
+        $properties.subscription_id
+    };
+    (SessionExpiryInterval, $properties:expr) => {
+        $properties.session_expiry_interval
+    };
+    (AssignedClientIdentifier, $properties:expr) => {
+        $properties.assigned_client_id
+    };
+    (ServerKeepAlive, $properties:expr) => {
+        $properties.server_keep_alive
+    };
+    (AuthenticationMethod, $properties:expr) => {
+        $properties.auth_method
+    };
+    (AuthenticationData, $properties:expr) => {
+        $properties.auth_data
+    };
+    (RequestProblemInformation, $properties:expr) => {
+        $properties.request_problem_info
+    };
+    (WillDelayInterval, $properties:expr) => {
+        $properties.delay_interval
+    };
+    (RequestResponseInformation, $properties:expr) => {
+        $properties.request_response_info
+    };
+    (ResponseInformation, $properties:expr) => {
+        $properties.response_info
+    };
+    (ServerReference, $properties:expr) => {
+        $properties.server_reference
+    };
+    (ReasonString, $properties:expr) => {
+        $properties.reason_string
+    };
+    (ReceiveMaximum, $properties:expr) => {
+        $properties.receive_max
+    };
+    (TopicAliasMaximum, $properties:expr) => {
+        $properties.topic_alias_max
+    };
+    (TopicAlias, $properties:expr) => {
+        $properties.topic_alias
+    };
+    (MaximumQoS, $properties:expr) => {
+        $properties.max_qos
+    };
+    (RetainAvailable, $properties:expr) => {
+        $properties.retain_available
+    };
+    (MaximumPacketSize, $properties:expr) => {
+        $properties.max_packet_size
+    };
+    (WildcardSubscriptionAvailable, $properties:expr) => {
+        $properties.wildcard_subscription_available
     };
+    (SubscriptionIdentifierAvailable, $properties:expr) => {
+        $properties.subscription_id_available
+    };
+    (SharedSubscriptionAvailable, $properties:expr) => {
+        $properties.shared_subscription_available
+    };
+}
+
+/// Fail as soon as the decoded length exceeds the declared property length.
+#[inline]
+pub(crate) fn check_property_len(len: usize, property_len: u32) -> Result<(), ErrorV5> {
+    if len as u64 > u64::from(property_len) {
+        Err(ErrorV5::InvalidPropertyLength(property_len))
+    } else {
+        Ok(())
+    }
+}
+
+/// Decode a property list (with its length prefix) from an async reader by
+/// reading it into memory and decoding it with the slice decoder.
+pub(crate) async fn decode_properties_async<T, P, F>(
+    reader: &mut T,
+    decode: F,
+) -> Result<P, ErrorV5>
+where
+    T: AsyncRead + Unpin,
+    F: FnOnce(&mut SliceReader<'_>) -> Result<P, ErrorV5>,
+{
+    let (property_len, _bytes) = decode_var_int(reader).await?;
+    let mut data = Vec::new();
+    write_var_int(&mut data, property_len as usize)?;
+    data.extend_from_slice(&read_exact_capped(reader, property_len as usize).await?);
+    decode(&mut SliceReader(&data)).map_err(|err| match err {
+        // The last property is truncated by the property length
+        ErrorV5::Common(Error::InvalidRemainingLength) => {
+            ErrorV5::InvalidPropertyLength(property_len)
+        }
+        err => err,
+    })
 }
 
 macro_rules! decode_properties {
-    (LastWill, $properties:expr, $reader:expr, $($t:ident,)*) => {
-        let (property_len, _bytes) = crate::decode_var_int($reader).await?;
-        let mut len = 0;
-        while property_len as usize > len {
-            let property_id = crate::v5::PropertyId::from_u8(crate::read_u8($reader).await?)?;
+    (@list $properties:expr, $reader:expr, $invalid:expr, $($t:ident,)*) => {
+        let property_len = $reader.read_var_int()?;
+        let start = $reader.0.len();
+        while ((start - $reader.0.len()) as u64) < u64::from(property_len) {
+            let property_id = crate::v5::PropertyId::from_u8($reader.read_u8()?)?;
             match property_id {
                 $(
                     crate::v5::PropertyId::$t => {
-                        crate::v5::decode_property!($t, $properties, $reader, property_id);
-                        crate::v5::encode_property_len!($t, $properties, len);
+                        if crate::v5::property_field!($t, $properties).is_some() {
+                            return Err(crate::v5::ErrorV5::DuplicatedProperty(property_id));
+                        }
+                        crate::v5::property_field!($t, $properties) =
+                            Some(crate::v5::decode_property!($t, $reader, property_id));
                     }
                 )*
-                    crate::v5::PropertyId::UserProperty => {
-                        crate::v5::decode_property!(UserProperty, $properties, $reader, property_id);
-                        let last = $properties.user_properties.last().expect("user property exists");
-                        len += 1 + 4 + last.name.len() + last.value.len();
-                    }
-                    _ => return Err(crate::v5::ErrorV5::InvalidWillProperty(property_id)),
+                crate::v5::PropertyId::UserProperty => {
+                    let user_property = crate::v5::decode_property!(UserProperty, $reader, property_id);
+                    $properties.user_properties.push(user_property);
+                }
+                _ => return Err(($invalid)(property_id)),
             }
+            crate::v5::check_property_len(start - $reader.0.len(), property_len)?;
         }
-        if property_len as usize != len {
-            return Err(crate::v5::ErrorV5::InvalidPropertyLength(property_len));
-        }
+    };
+    (LastWill, $properties:expr, $reader:expr, $($t:ident,)*) => {
+        crate::v5::decode_properties!(
+            @list $properties,
+            $reader,
+            crate::v5::ErrorV5::InvalidWillProperty,
+            $($t,)*
+        );
     };
     ($packet_type:expr, $properties:expr, $reader:expr, $($t:ident,)*) => {
-        let (property_len, _bytes) = crate::decode_var_int($reader).await?;
-        let mut len = 0;
-        while property_len as usize > len {
-            let property_id = crate::v5::PropertyId::from_u8(crate::read_u8($reader).await?)?;
-            match property_id {
-                $(
-                    crate::v5::PropertyId::$t => {
-                        crate::v5::decode_property!($t, $properties, $reader, property_id);
-                        crate::v5::encode_property_len!($t, $properties, len);
-                    }
-                )*
-                    crate::v5::PropertyId::UserProperty => {
-                        crate::v5::decode_property!(UserProperty, $properties, $reader, property_id);
-                        let last = $properties.user_properties.last().expect("user property exists");
-                        len += 1 + 4 + last.name.len() + last.value.len();
-                    }
-                _ => return Err(crate::v5::ErrorV5::InvalidProperty($packet_type, property_id)),
-            }
-        }
-        if property_len as usize != len {
-            return Err(crate::v5::ErrorV5::InvalidPropertyLength(property_len));
-        }
+        crate::v5::decode_properties!(
+            @list $properties,
+            $reader,
+            |property_id| crate::v5::ErrorV5::InvalidProperty($packet_type, property_id),
+            $($t,)*
+        );
     };
 }
 
 pub(crate) use decode_properties;
 pub(crate) use decode_property;
+pub(crate) use property_field;
 
 macro_rules! encode_property {
     (PayloadFormatIndicator, $properties:expr, $writer: expr) => {
@@ -766,8 +934,7 @@ macro_rules! encode_property_len {
     };
     (SubscriptionIdentifier, $properties:expr, $property_len:expr) => {
         if let Some(value) = $properties.subscription_id {
-            $property_len += 1 + crate::var_int_len(value.value() as usize)
-                .expect("subscription id exceed 268,435,455");
+            $property_len += 1 + value.encoded_len();
         }
     };
     (SessionExpiryInterval, $properties:expr, $property_len:expr) => {
@@ -880,7 +1047,9 @@ macro_rules! encode_properties_len {
             .iter()
             .map(|property| 4 + property.name.len() + property.value.len())
             .sum::<usize>();
-        $len += property_len + crate::var_int_len(property_len).expect("total properties length exceed 268,435,455");
+        // Too long properties make the remaining length invalid, which is
+        // rejected by encode()
+        $len += property_len + crate::var_int_len(property_len).unwrap_or(4);
     };
     ($properties:expr, $len:expr, $($t:ident,)+) => {
         // Every properties have user property
@@ -893,7 +1062,9 @@ macro_rules! encode_properties_len {
             crate::v5::encode_property_len!($t, $properties, property_len);
         )+
 
-            $len += property_len + crate::var_int_len(property_len).expect("total properties length exceed 268,435,455");
+            // Too long properties make the remaining length invalid, which is
+        // rejected by encode()
+        $len += property_len + crate::var_int_len(property_len).unwrap_or(4);
     };
 }
 